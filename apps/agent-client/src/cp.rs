@@ -0,0 +1,338 @@
+//! Copy mode: transfer single files to and from the agent.
+//!
+//! Remote paths are written as `remote:PATH`, e.g. `remote:X:\tools\fix.cmd`.
+//!
+//! Files larger than one chunk are uploaded through a resumable upload:
+//! failed chunks are retried, and running the same `cp` again after an
+//! interruption continues where the previous attempt stopped.
+
+use reqwest::header::CONTENT_LENGTH;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use winpe_agent_core::{
+    ApiError, FileUploadResponse, ReceivedRange, UploadCreateRequest, UploadFinalizeRequest,
+    UploadInfo,
+};
+
+/// Prefix marking a path on the agent.
+const REMOTE_PREFIX: &str = "remote:";
+
+/// Size of one resumable upload chunk; smaller files are sent in one request.
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Attempts to send one chunk before giving up.
+const CHUNK_ATTEMPTS: u32 = 8;
+
+/// Options for the cp subcommand.
+pub struct CpOptions<'a> {
+    pub source: &'a str,
+    pub dest: &'a str,
+    /// Replace an existing remote file.
+    pub force: bool,
+}
+
+pub async fn run(
+    base_url: &str,
+    token: Option<&str>,
+    opts: CpOptions<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+    let url = format!("{}/api/v1/files", base_url);
+
+    match (remote_path(opts.source), remote_path(opts.dest)) {
+        (None, Some(remote)) => {
+            let local = Path::new(opts.source);
+            let remote = remote_target(remote, local);
+            let api = Api::new(base_url, token);
+            let size = upload(&api, local, &remote, opts.force, None).await?;
+            eprintln!("Uploaded {} bytes to {}", size, remote);
+        }
+        (Some(remote), None) => {
+            let local = local_target(Path::new(opts.dest), remote);
+            let mut request = client.get(&url).query(&[("path", remote)]);
+            if let Some(t) = token {
+                request = request.bearer_auth(t);
+            }
+            let mut response = request.send().await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await?;
+                return Err(format!("Download failed ({}): {}", status, body).into());
+            }
+
+            let mut file = tokio::fs::File::create(&local)
+                .await
+                .map_err(|e| format!("{}: {}", local.display(), e))?;
+            let mut size = 0u64;
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+                size += chunk.len() as u64;
+            }
+            file.flush().await?;
+            eprintln!("Downloaded {} bytes to {}", size, local.display());
+        }
+        _ => return Err("Exactly one of SOURCE and DEST must be remote:PATH".into()),
+    }
+    Ok(())
+}
+
+fn remote_path(arg: &str) -> Option<&str> {
+    arg.strip_prefix(REMOTE_PREFIX)
+}
+
+/// Remote destination; a path ending in a separator names a directory.
+fn remote_target(remote: &str, local: &Path) -> String {
+    match local.file_name() {
+        Some(name) if remote.ends_with(['/', '\\']) => {
+            format!("{}{}", remote, name.to_string_lossy())
+        }
+        _ => remote.to_string(),
+    }
+}
+
+/// Local destination; an existing directory receives the remote file name.
+fn local_target(local: &Path, remote: &str) -> PathBuf {
+    let name = remote.rsplit(['/', '\\']).next().unwrap_or(remote);
+    if local.is_dir() && !name.is_empty() {
+        local.join(name)
+    } else {
+        local.to_path_buf()
+    }
+}
+
+/// Upload `local` to `remote`, returning the number of bytes sent.
+///
+/// Files larger than one chunk go through a resumable upload. `mtime`, in
+/// seconds since the Unix epoch, becomes the remote modification time.
+pub(crate) async fn upload(
+    api: &Api<'_>,
+    local: &Path,
+    remote: &str,
+    overwrite: bool,
+    mtime: Option<u64>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let file = tokio::fs::File::open(local)
+        .await
+        .map_err(|e| format!("{}: {}", local.display(), e))?;
+    let size = file.metadata().await?.len();
+    if size > CHUNK_SIZE as u64 {
+        return upload_resumable(api, file, size, remote, overwrite, mtime).await;
+    }
+
+    let mut request = api
+        .request(reqwest::Method::PUT, "/api/v1/files")
+        .query(&[("path", remote)])
+        .header(CONTENT_LENGTH, size)
+        .body(file);
+    if overwrite {
+        request = request.query(&[("overwrite", "true")]);
+    }
+    if let Some(mtime) = mtime {
+        request = request.query(&[("mtime", mtime)]);
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await?;
+        return Err(format!("Upload failed ({}): {}", status, body).into());
+    }
+    Ok(size)
+}
+
+/// Minimal JSON API client for the files and uploads APIs.
+pub(crate) struct Api<'a> {
+    client: reqwest::Client,
+    base_url: &'a str,
+    token: Option<&'a str>,
+}
+
+impl<'a> Api<'a> {
+    pub(crate) fn new(base_url: &'a str, token: Option<&'a str>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url,
+            token,
+        }
+    }
+
+    pub(crate) fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path));
+        match self.token {
+            Some(t) => request.bearer_auth(t),
+            None => request,
+        }
+    }
+
+    /// Send a request and decode its JSON response, turning API errors
+    /// into readable messages.
+    pub(crate) async fn send<R: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<R, Box<dyn std::error::Error>> {
+        let response = request.send().await?;
+        if response.status().is_success() {
+            return Ok(response.json().await?);
+        }
+        let status = response.status();
+        let body = response.text().await?;
+        match serde_json::from_str::<ApiError>(&body) {
+            Ok(err) => Err(err.error.message.into()),
+            Err(_) => Err(format!("Request failed ({}): {}", status, body).into()),
+        }
+    }
+}
+
+/// Upload through a resumable upload, continuing an earlier one for the
+/// same destination and size if there is one.
+async fn upload_resumable(
+    api: &Api<'_>,
+    mut file: tokio::fs::File,
+    size: u64,
+    remote: &str,
+    force: bool,
+    mtime: Option<u64>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let existing: Vec<UploadInfo> = api
+        .send(
+            api.request(reqwest::Method::GET, "/api/v1/uploads")
+                .query(&[("path", remote)]),
+        )
+        .await?;
+    let mut upload = match existing
+        .into_iter()
+        .rev()
+        .find(|u| u.size == size && u.overwrite == force)
+    {
+        Some(upload) => {
+            eprintln!(
+                "Resuming upload {}: {} of {} bytes already sent",
+                upload.id, upload.received_bytes, size
+            );
+            upload
+        }
+        None => {
+            let req = UploadCreateRequest {
+                path: remote.to_string(),
+                size,
+                overwrite: force,
+                mode: None,
+                mtime,
+            };
+            api.send(
+                api.request(reqwest::Method::POST, "/api/v1/uploads")
+                    .json(&req),
+            )
+            .await?
+        }
+    };
+
+    // Read the whole file once, hashing it and sending what is missing
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut offset = 0u64;
+    while offset < size {
+        let len = (size - offset).min(CHUNK_SIZE as u64) as usize;
+        file.read_exact(&mut buf[..len]).await?;
+        hasher.update(&buf[..len]);
+        upload = send_chunk(api, upload, offset, &buf[..len]).await?;
+        offset += len as u64;
+    }
+
+    let req = UploadFinalizeRequest {
+        sha256: format!("{:x}", hasher.finalize()),
+    };
+    let done: FileUploadResponse = api
+        .send(
+            api.request(
+                reqwest::Method::POST,
+                &format!("/api/v1/uploads/{}/finalize", upload.id),
+            )
+            .json(&req),
+        )
+        .await
+        .map_err(|e| format!("{} (run again to resend)", e))?;
+    Ok(done.size)
+}
+
+/// Send the parts of `data` (at `offset`) the server has not received,
+/// retrying with backoff when the connection fails.
+async fn send_chunk(
+    api: &Api<'_>,
+    mut upload: UploadInfo,
+    offset: u64,
+    data: &[u8],
+) -> Result<UploadInfo, Box<dyn std::error::Error>> {
+    let path = format!("/api/v1/uploads/{}", upload.id);
+    let mut attempt = 0;
+    loop {
+        let missing = missing(&upload.received, offset, offset + data.len() as u64);
+        let Some(&ReceivedRange { start, end }) = missing.first() else {
+            return Ok(upload);
+        };
+
+        let part = data[(start - offset) as usize..(end - offset) as usize].to_vec();
+        let request = api
+            .request(reqwest::Method::PUT, &path)
+            .query(&[("offset", start)])
+            .body(part);
+        match api.send::<UploadInfo>(request).await {
+            Ok(info) => {
+                upload = info;
+                eprint!("\rSent {} of {} bytes", upload.received_bytes, upload.size);
+                if upload.received_bytes == upload.size {
+                    eprintln!();
+                }
+                attempt = 0;
+            }
+            Err(e) => {
+                attempt += 1;
+                if attempt >= CHUNK_ATTEMPTS {
+                    eprintln!();
+                    return Err(format!("{} (run again to resume)", e).into());
+                }
+                let delay = Duration::from_secs(1 << attempt.min(5));
+                eprintln!(
+                    "\nChunk at {} failed: {}; retrying in {:?}",
+                    start, e, delay
+                );
+                tokio::time::sleep(delay).await;
+                // Part of the chunk may have arrived before the failure
+                if let Ok(info) = api.send(api.request(reqwest::Method::GET, &path)).await {
+                    upload = info;
+                }
+            }
+        }
+    }
+}
+
+/// Ranges within `start..end` not covered by the sorted `received` ranges.
+fn missing(received: &[ReceivedRange], start: u64, end: u64) -> Vec<ReceivedRange> {
+    let mut gaps = Vec::new();
+    let mut cursor = start;
+    for range in received {
+        if range.end <= cursor {
+            continue;
+        }
+        if range.start >= end {
+            break;
+        }
+        if range.start > cursor {
+            gaps.push(ReceivedRange {
+                start: cursor,
+                end: range.start,
+            });
+        }
+        cursor = range.end;
+        if cursor >= end {
+            break;
+        }
+    }
+    if cursor < end {
+        gaps.push(ReceivedRange { start: cursor, end });
+    }
+    gaps
+}
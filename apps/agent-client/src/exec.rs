@@ -1,18 +1,35 @@
 //! exec mode: Execute a single command via Automation API.
 
+use base64::Engine;
 use std::collections::HashMap;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use winpe_agent_core::{ExecRequest, ExecResponse, Shell};
 
+/// Options for the exec subcommand.
+pub struct ExecOptions<'a> {
+    pub shell: &'a str,
+    pub cwd: Option<&'a str>,
+    pub timeout: u64,
+    pub json_output: bool,
+    /// Pipe the local stdin to the remote process.
+    pub stdin: bool,
+    pub command: &'a [String],
+}
+
 pub async fn run(
     base_url: &str,
     token: Option<&str>,
-    shell: &str,
-    cwd: Option<&str>,
-    timeout: u64,
-    json_output: bool,
-    command: &[String],
+    opts: ExecOptions<'_>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let ExecOptions {
+        shell,
+        cwd,
+        timeout,
+        json_output,
+        stdin,
+        command,
+    } = opts;
+
     if command.is_empty() {
         return Err("No command specified".into());
     }
@@ -20,19 +37,35 @@ pub async fn run(
     let shell_enum = match shell.to_lowercase().as_str() {
         "cmd" => Shell::Cmd,
         "powershell" | "pwsh" => Shell::Powershell,
+        "sh" => Shell::Sh,
+        "bash" => Shell::Bash,
+        "direct" | "none" => Shell::Direct,
         _ => return Err(format!("Unknown shell: {}", shell).into()),
     };
 
     let (cmd, args) = command.split_first().unwrap();
 
+    // Sent as base64 so binary input survives unchanged
+    let stdin_base64 = if stdin {
+        let mut input = Vec::new();
+        io::stdin().read_to_end(&mut input)?;
+        Some(base64::engine::general_purpose::STANDARD.encode(input))
+    } else {
+        None
+    };
+
     let req = ExecRequest {
         shell: shell_enum,
         command: cmd.clone(),
         args: args.to_vec(),
         cwd: cwd.map(String::from),
         env: HashMap::new(),
-        timeout_ms: timeout,
+        timeout_ms: Some(timeout),
         encoding: "utf-8".to_string(),
+        max_output_bytes: None,
+        stdin: None,
+        stdin_base64,
+        stdin_stream: false,
     };
 
     let client = reqwest::Client::new();
@@ -64,6 +97,12 @@ pub async fn run(
         if !result.stderr.is_empty() {
             io::stderr().write_all(result.stderr.as_bytes())?;
         }
+        if result.stdout_truncated || result.stderr_truncated {
+            eprintln!(
+                "warning: output truncated by server (stdout {} bytes, stderr {} bytes total)",
+                result.stdout_bytes, result.stderr_bytes
+            );
+        }
     }
 
     // Exit with the remote exit code
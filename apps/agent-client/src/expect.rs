@@ -0,0 +1,311 @@
+//! Expect mode: drive an interactive session with a send/expect script.
+//!
+//! Script lines (blank lines and lines starting with `#` are ignored):
+//!
+//! - `send TEXT` — type TEXT; `\r`, `\n`, `\t`, `\e`, `\\` and `\xHH` are unescaped
+//! - `sendline TEXT` — like `send`, followed by Enter (`\r`)
+//! - `expect REGEX` — wait until REGEX matches output after the previous match
+//! - `timeout SECONDS` — timeout for the following `expect` lines
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use winpe_agent_core::{
+    ApiError, SessionCreateRequest, SessionCreateResponse, SessionExpectRequest,
+    SessionExpectResponse, SessionInputRequest, SessionInputResponse, Shell,
+};
+
+/// Options for the expect subcommand.
+pub struct ExpectOptions<'a> {
+    pub script: &'a str,
+    /// Existing session to use instead of creating one.
+    pub session: Option<&'a str>,
+    pub shell: &'a str,
+    pub program: Option<&'a str>,
+    /// Default timeout for `expect` lines, in seconds.
+    pub timeout: u64,
+    /// Keep a created session after the script ends.
+    pub keep: bool,
+}
+
+enum Step {
+    Send(String),
+    Expect(String),
+    Timeout(u64),
+}
+
+pub async fn run(
+    base_url: &str,
+    token: Option<&str>,
+    opts: ExpectOptions<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(opts.script)
+        .map_err(|e| format!("Failed to read {}: {}", opts.script, e))?;
+    let steps = parse_script(&text)?;
+
+    let api = Api {
+        client: reqwest::Client::new(),
+        base_url,
+        token,
+    };
+
+    // Offset to search from; a new session is searched from its first byte
+    let (session_id, created, mut offset) = match opts.session {
+        Some(id) => (id.to_string(), false, None),
+        None => {
+            let shell = match opts.shell.to_lowercase().as_str() {
+                "cmd" => Shell::Cmd,
+                "powershell" | "pwsh" => Shell::Powershell,
+                "sh" => Shell::Sh,
+                "bash" => Shell::Bash,
+                _ => return Err(format!("Unknown shell: {}", opts.shell).into()),
+            };
+            let req = SessionCreateRequest {
+                shell,
+                program: opts.program.map(String::from),
+                args: Vec::new(),
+                cwd: None,
+                env: HashMap::new(),
+                cols: 120,
+                rows: 30,
+                idle_timeout_sec: 600,
+                scrollback_bytes: 256 * 1024,
+                record: false,
+                record_input: false,
+                init: winpe_agent_core::SessionInit { force_utf8: true },
+            };
+            let session: SessionCreateResponse = api.post("/api/v1/sessions", &req).await?;
+            eprintln!("Session created: {}", session.id);
+            (session.id, true, Some(0))
+        }
+    };
+
+    let result = run_steps(&api, &session_id, &steps, opts.timeout, &mut offset).await;
+
+    if created && !opts.keep {
+        let mut request = api
+            .client
+            .delete(format!("{}/api/v1/sessions/{}", base_url, session_id));
+        if let Some(t) = token {
+            request = request.bearer_auth(t);
+        }
+        let _ = request.send().await;
+    }
+    result
+}
+
+async fn run_steps(
+    api: &Api<'_>,
+    session_id: &str,
+    steps: &[Step],
+    default_timeout: u64,
+    offset: &mut Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut timeout_secs = default_timeout;
+    for step in steps {
+        match step {
+            Step::Timeout(secs) => timeout_secs = *secs,
+            Step::Send(data) => {
+                let req = SessionInputRequest { data: data.clone() };
+                let sent: SessionInputResponse = api
+                    .post(&format!("/api/v1/sessions/{}/input", session_id), &req)
+                    .await?;
+                // Without an earlier match, search output caused by this input
+                offset.get_or_insert(sent.offset);
+            }
+            Step::Expect(pattern) => {
+                let req = SessionExpectRequest {
+                    pattern: pattern.clone(),
+                    since: *offset,
+                    timeout_ms: timeout_secs * 1000,
+                };
+                let found: SessionExpectResponse = api
+                    .post(&format!("/api/v1/sessions/{}/expect", session_id), &req)
+                    .await?;
+                let mut stdout = io::stdout();
+                stdout.write_all(found.before.as_bytes())?;
+                stdout.write_all(found.matched.as_bytes())?;
+                stdout.flush()?;
+                *offset = Some(found.offset);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse a script, reporting the first invalid line.
+fn parse_script(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let line = line.trim_start();
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let step = match command {
+            "send" => Step::Send(unescape(arg)),
+            "sendline" => Step::Send(unescape(arg) + "\r"),
+            "expect" if !arg.is_empty() => Step::Expect(arg.to_string()),
+            "timeout" => Step::Timeout(
+                arg.trim()
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid timeout '{}'", index + 1, arg))?,
+            ),
+            _ => return Err(format!("line {}: invalid command '{}'", index + 1, line)),
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+/// Expand backslash escapes; unknown escapes are kept as written.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('e') => out.push('\x1b'),
+            Some('\\') => out.push('\\'),
+            Some('x') => {
+                let hex: String = chars.clone().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && byte.is_ascii() => {
+                        out.push(char::from(byte));
+                        chars.nth(1);
+                    }
+                    _ => out.push_str("\\x"),
+                }
+            }
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Minimal JSON API client.
+struct Api<'a> {
+    client: reqwest::Client,
+    base_url: &'a str,
+    token: Option<&'a str>,
+}
+
+impl Api<'_> {
+    /// POST a JSON body, turning API errors into readable messages.
+    async fn post<T: serde::Serialize, R: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<R, Box<dyn std::error::Error>> {
+        let mut request = self
+            .client
+            .post(format!("{}{}", self.base_url, path))
+            .json(body);
+        if let Some(t) = self.token {
+            request = request.bearer_auth(t);
+        }
+
+        let response = request.send().await?;
+        if response.status().is_success() {
+            return Ok(response.json().await?);
+        }
+
+        let status = response.status();
+        let body = response.text().await?;
+        match serde_json::from_str::<ApiError>(&body) {
+            Ok(err) => {
+                // Show what the session printed while we were waiting
+                if let Some(output) = err
+                    .error
+                    .details
+                    .extra
+                    .get("output")
+                    .and_then(|v| v.as_str())
+                {
+                    let _ = io::stdout().write_all(output.as_bytes());
+                    println!();
+                }
+                Err(err.error.message.into())
+            }
+            Err(_) => Err(format!("Request failed ({}): {}", status, body).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(steps: &[Step]) -> Vec<String> {
+        steps
+            .iter()
+            .map(|step| match step {
+                Step::Send(text) => format!("send {:?}", text),
+                Step::Expect(pattern) => format!("expect {}", pattern),
+                Step::Timeout(secs) => format!("timeout {}", secs),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_script_lines() {
+        let script = "# select the disk\r\n\
+                      \n\
+                      timeout 30\r\n\
+                      sendline diskpart\n\
+                      expect DISKPART> $\n\
+                      \x20\x20send list disk\\r\n\
+                      expect Disk (\\d+)\n";
+        assert_eq!(
+            describe(&parse_script(script).unwrap()),
+            [
+                "timeout 30",
+                r#"send "diskpart\r""#,
+                "expect DISKPART> $",
+                r#"send "list disk\r""#,
+                r"expect Disk (\d+)",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_invalid_line() {
+        let error = |script| parse_script(script).err().unwrap();
+        assert_eq!(
+            error("send a\nexpect\n"),
+            "line 2: invalid command 'expect'"
+        );
+        assert_eq!(error("\n\ntimeout soon"), "line 3: invalid timeout 'soon'");
+        assert_eq!(error("sned x"), "line 1: invalid command 'sned x'");
+        assert!(parse_script("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unescapes_known_escapes() {
+        assert_eq!(unescape(r"a\r\n\tb"), "a\r\n\tb");
+        assert_eq!(unescape(r"\e[2J\\"), "\x1b[2J\\");
+        assert_eq!(unescape(r"\x41\x1b\x7f"), "A\x1b\x7f");
+        assert_eq!(unescape("plain é"), "plain é");
+    }
+
+    #[test]
+    fn keeps_unknown_and_malformed_escapes() {
+        assert_eq!(unescape(r"\q\d+"), r"\q\d+");
+        // Only two hex digits of ASCII are taken
+        assert_eq!(unescape(r"\x80"), r"\x80");
+        assert_eq!(unescape(r"\xZZ"), r"\xZZ");
+        assert_eq!(unescape(r"\x4"), r"\x4");
+        assert_eq!(unescape(r"\x414"), "A4");
+        assert_eq!(unescape("end\\"), "end\\");
+    }
+}
@@ -0,0 +1,186 @@
+//! Filesystem modes: `ls`, `rm`, `mv` and `mkdir` on the agent's host.
+
+use winpe_agent_core::{
+    ApiError, DeleteRequest, DirListing, FileEntry, FileKind, GlobResponse, MkdirRequest,
+    RenameRequest,
+};
+
+/// Options for the ls subcommand.
+pub struct LsOptions<'a> {
+    /// Directory to list, or a glob pattern.
+    pub path: &'a str,
+    /// Show kind, size and modification time.
+    pub long: bool,
+    /// List subdirectories too.
+    pub recursive: bool,
+    /// Levels to descend when recursive.
+    pub depth: Option<u32>,
+    /// Include hidden entries.
+    pub all: bool,
+}
+
+pub async fn ls(
+    base_url: &str,
+    token: Option<&str>,
+    opts: LsOptions<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+
+    let (entries, truncated) = if opts.path.contains(['*', '?', '[']) {
+        let request = client
+            .get(format!("{}/api/v1/fs/glob", base_url))
+            .query(&[("pattern", opts.path)]);
+        let matches: GlobResponse = send(request, token).await?;
+        (matches.entries, matches.truncated)
+    } else {
+        let mut request = client
+            .get(format!("{}/api/v1/fs/list", base_url))
+            .query(&[("path", opts.path)]);
+        if opts.recursive {
+            request = request.query(&[("recursive", "true")]);
+        }
+        if let Some(depth) = opts.depth {
+            request = request.query(&[("depth", depth)]);
+        }
+        let listing: DirListing = send(request, token).await?;
+        (listing.entries, listing.truncated)
+    };
+
+    // Depth of a hidden directory whose contents are being skipped
+    let mut hidden_depth: Option<u32> = None;
+    for entry in &entries {
+        let depth = entry.depth.unwrap_or(0);
+        if hidden_depth.is_some_and(|d| depth > d) {
+            continue;
+        }
+        hidden_depth = None;
+        if entry.hidden && !opts.all {
+            hidden_depth = Some(depth);
+            continue;
+        }
+
+        // Recursive listings and glob matches show full paths
+        let name = if opts.recursive || entry.depth.is_none() {
+            &entry.path
+        } else {
+            &entry.name
+        };
+        let suffix = if entry.kind == FileKind::Dir { "/" } else { "" };
+        if opts.long {
+            println!(
+                "{} {:>12}  {}  {}{}",
+                kind_char(entry),
+                entry.size,
+                entry
+                    .modified
+                    .as_deref()
+                    .map_or("-", |m| &m[..m.len().min(19)]),
+                name,
+                suffix
+            );
+        } else {
+            println!("{}{}", name, suffix);
+        }
+    }
+    if truncated {
+        eprintln!("(listing truncated)");
+    }
+    Ok(())
+}
+
+fn kind_char(entry: &FileEntry) -> char {
+    match entry.kind {
+        FileKind::File => '-',
+        FileKind::Dir => 'd',
+        FileKind::Symlink => 'l',
+        FileKind::Other => '?',
+    }
+}
+
+pub async fn rm(
+    base_url: &str,
+    token: Option<&str>,
+    paths: &[String],
+    recursive: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+    for path in paths {
+        let req = DeleteRequest {
+            path: path.clone(),
+            recursive,
+        };
+        let request = client
+            .post(format!("{}/api/v1/fs/delete", base_url))
+            .json(&req);
+        check(request, token).await?;
+    }
+    Ok(())
+}
+
+pub async fn mv(
+    base_url: &str,
+    token: Option<&str>,
+    from: &str,
+    to: &str,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let req = RenameRequest {
+        from: from.to_string(),
+        to: to.to_string(),
+        overwrite: force,
+    };
+    let request = reqwest::Client::new()
+        .post(format!("{}/api/v1/fs/rename", base_url))
+        .json(&req);
+    let _: FileEntry = send(request, token).await?;
+    Ok(())
+}
+
+pub async fn mkdir(
+    base_url: &str,
+    token: Option<&str>,
+    paths: &[String],
+    parents: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+    for path in paths {
+        let req = MkdirRequest {
+            path: path.clone(),
+            parents,
+        };
+        let request = client
+            .post(format!("{}/api/v1/fs/mkdir", base_url))
+            .json(&req);
+        let _: FileEntry = send(request, token).await?;
+    }
+    Ok(())
+}
+
+/// Send a request and decode its JSON response.
+async fn send<R: serde::de::DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+    token: Option<&str>,
+) -> Result<R, Box<dyn std::error::Error>> {
+    Ok(check(request, token).await?.json().await?)
+}
+
+/// Send a request, turning API errors into readable messages.
+async fn check(
+    mut request: reqwest::RequestBuilder,
+    token: Option<&str>,
+) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    if let Some(t) = token {
+        request = request.bearer_auth(t);
+    }
+    let response = request.send().await?;
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let body = response.text().await?;
+    match serde_json::from_str::<ApiError>(&body) {
+        Ok(err) => Err(err.error.message.into()),
+        Err(_) => Err(format!("Request failed ({}): {}", status, body).into()),
+    }
+}
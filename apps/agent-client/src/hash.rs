@@ -0,0 +1,214 @@
+//! Hash mode: hash files on the agent, optionally checking a manifest.
+//!
+//! Manifests use the `sha256sum` format: one `HASH  PATH` line per file,
+//! with absolute agent paths. Lines starting with `#` are ignored.
+
+use std::collections::HashMap;
+use winpe_agent_core::{ApiError, HashAlgorithm, HashRequest, HashResult};
+
+/// Options for the hash subcommand.
+pub struct HashOptions<'a> {
+    /// Absolute paths or glob patterns on the agent.
+    pub paths: &'a [String],
+    pub algorithms: &'a [String],
+    /// Manifest of expected hashes to compare against.
+    pub manifest: Option<&'a str>,
+}
+
+pub async fn run(
+    base_url: &str,
+    token: Option<&str>,
+    opts: HashOptions<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let algorithms = opts
+        .algorithms
+        .iter()
+        .map(|a| parse_algorithm(a))
+        .collect::<Result<Vec<_>, _>>()?;
+    let manifest = match opts.manifest {
+        Some(file) => {
+            if algorithms.len() != 1 {
+                return Err("--manifest takes exactly one --algorithm".into());
+            }
+            let text = std::fs::read_to_string(file)
+                .map_err(|e| format!("Failed to read {}: {}", file, e))?;
+            Some(parse_manifest(&text)?)
+        }
+        None => None,
+    };
+
+    // Without explicit paths, check everything in the manifest
+    let mut paths: Vec<String> = opts.paths.to_vec();
+    if paths.is_empty()
+        && let Some(manifest) = &manifest
+    {
+        paths = manifest.iter().map(|(path, _)| path.clone()).collect();
+    }
+    if paths.is_empty() {
+        return Err("No paths to hash".into());
+    }
+
+    // Plain paths go in one request; each pattern needs its own
+    let (patterns, plain): (Vec<String>, Vec<String>) =
+        paths.into_iter().partition(|p| p.contains(['*', '?', '[']));
+    let mut requests = Vec::new();
+    if !plain.is_empty() {
+        requests.push(HashRequest {
+            paths: plain,
+            pattern: None,
+            algorithms: algorithms.clone(),
+        });
+    }
+    for pattern in patterns {
+        requests.push(HashRequest {
+            paths: Vec::new(),
+            pattern: Some(pattern),
+            algorithms: algorithms.clone(),
+        });
+    }
+
+    let expected: Option<HashMap<&str, &str>> = manifest
+        .as_ref()
+        .map(|m| m.iter().map(|(p, h)| (p.as_str(), h.as_str())).collect());
+    let mut report = Report::default();
+    let client = reqwest::Client::new();
+    for req in &requests {
+        let mut request = client
+            .post(format!("{}/api/v1/files/hash", base_url))
+            .json(req);
+        if let Some(t) = token {
+            request = request.bearer_auth(t);
+        }
+        let mut response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            return match serde_json::from_str::<ApiError>(&body) {
+                Ok(err) => Err(err.error.message.into()),
+                Err(_) => Err(format!("Request failed ({}): {}", status, body).into()),
+            };
+        }
+
+        // Results arrive as they are computed, one JSON object per line
+        let mut pending = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            pending.extend_from_slice(&chunk);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let result: HashResult = serde_json::from_slice(&line)?;
+                match &expected {
+                    Some(expected) => report.check(&result, expected, algorithms[0]),
+                    None => print_result(&result, &algorithms),
+                }
+            }
+        }
+    }
+
+    if expected.is_some() {
+        eprintln!(
+            "Checked {} files: {} ok, {} mismatched, {} failed",
+            report.ok + report.mismatched + report.failed,
+            report.ok,
+            report.mismatched,
+            report.failed
+        );
+        if report.mismatched + report.failed > 0 {
+            return Err("Verification failed".into());
+        }
+    }
+    Ok(())
+}
+
+fn parse_algorithm(name: &str) -> Result<HashAlgorithm, String> {
+    match name.to_lowercase().replace('-', "").as_str() {
+        "sha256" => Ok(HashAlgorithm::Sha256),
+        "sha1" => Ok(HashAlgorithm::Sha1),
+        "md5" => Ok(HashAlgorithm::Md5),
+        "blake3" => Ok(HashAlgorithm::Blake3),
+        _ => Err(format!("Unknown algorithm: {}", name)),
+    }
+}
+
+/// Parse `HASH  PATH` lines into (path, lowercase hash) pairs.
+fn parse_manifest(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let (hash, path) = line
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("manifest line {}: expected HASH  PATH", index + 1))?;
+        // `*` marks binary mode in sha256sum output
+        let path = path.trim_start().trim_start_matches('*');
+        if !hash.chars().all(|c| c.is_ascii_hexdigit()) || path.is_empty() {
+            return Err(format!("manifest line {}: expected HASH  PATH", index + 1));
+        }
+        entries.push((path.to_string(), hash.to_ascii_lowercase()));
+    }
+    Ok(entries)
+}
+
+/// Print a result: `HASH  PATH` for one algorithm, `ALGO (PATH) = HASH` for several.
+fn print_result(result: &HashResult, algorithms: &[HashAlgorithm]) {
+    if let Some(error) = &result.error {
+        eprintln!("{}: {}", result.path, error);
+        return;
+    }
+    for algorithm in algorithms {
+        let Some(hash) = result.hashes.get(algorithm.as_str()) else {
+            continue;
+        };
+        if algorithms.len() == 1 {
+            println!("{}  {}", hash, result.path);
+        } else {
+            println!(
+                "{} ({}) = {}",
+                algorithm.as_str().to_uppercase(),
+                result.path,
+                hash
+            );
+        }
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    ok: usize,
+    mismatched: usize,
+    failed: usize,
+}
+
+impl Report {
+    /// Compare a result with the manifest, printing anything that is off.
+    fn check(
+        &mut self,
+        result: &HashResult,
+        expected: &HashMap<&str, &str>,
+        algorithm: HashAlgorithm,
+    ) {
+        if let Some(error) = &result.error {
+            println!("FAILED    {}: {}", result.path, error);
+            self.failed += 1;
+            return;
+        }
+        let actual = result
+            .hashes
+            .get(algorithm.as_str())
+            .map_or("", String::as_str);
+        match expected.get(result.path.as_str()) {
+            Some(&hash) if hash == actual => self.ok += 1,
+            Some(&hash) => {
+                println!("MISMATCH  {}", result.path);
+                println!("  expected {}", hash);
+                println!("  actual   {}", actual);
+                self.mismatched += 1;
+            }
+            None => {
+                println!("UNLISTED  {}  {}", actual, result.path);
+            }
+        }
+    }
+}
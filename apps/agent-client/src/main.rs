@@ -1,11 +1,23 @@
 //! winpe-agent-client: CLI client for WinPE Agent.
 //!
-//! Supports three modes:
+//! Supports these modes:
 //! - `exec`: Execute a single command
 //! - `tui`: Interactive TUI terminal
+//! - `expect`: Run a send/expect script in a terminal session
+//! - `cp`: Copy files to and from the agent
+//! - `ls`, `rm`, `mv`, `mkdir`: Browse and rearrange files on the agent
+//! - `pull-dir`: Download a directory tree as an archive
+//! - `hash`: Hash files on the agent and check them against a manifest
+//! - `sync`: Mirror a local directory onto the agent
 //! - `web`: Open browser to web UI
 
+mod cp;
 mod exec;
+mod expect;
+mod fs;
+mod hash;
+mod pull;
+mod sync;
 mod tui;
 mod web;
 
@@ -32,7 +44,7 @@ struct Cli {
 enum Commands {
     /// Execute a single command via Automation API
     Exec {
-        /// Shell to use (cmd or powershell)
+        /// Shell to use (cmd, powershell, sh, bash or direct)
         #[arg(long, default_value = "cmd")]
         shell: String,
 
@@ -48,6 +60,10 @@ enum Commands {
         #[arg(long)]
         json: bool,
 
+        /// Send local stdin to the command
+        #[arg(long)]
+        stdin: bool,
+
         /// Command and arguments
         #[arg(trailing_var_arg = true, required = true)]
         command: Vec<String>,
@@ -55,7 +71,7 @@ enum Commands {
 
     /// Open interactive TUI terminal
     Tui {
-        /// Shell to use (cmd or powershell)
+        /// Shell to use (cmd, powershell, sh or bash)
         #[arg(long, default_value = "cmd")]
         shell: String,
 
@@ -66,12 +82,193 @@ enum Commands {
         /// Terminal rows
         #[arg(long, default_value = "30")]
         rows: u16,
+
+        /// Working directory
+        #[arg(long)]
+        cwd: Option<String>,
+
+        /// Extra environment variable (repeatable)
+        #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+
+        /// Program to run instead of the shell
+        #[arg(long)]
+        program: Option<String>,
+
+        /// Arguments for the program
+        #[arg(trailing_var_arg = true, requires = "program")]
+        args: Vec<String>,
+    },
+
+    /// Run a send/expect script in a terminal session
+    Expect {
+        /// Script file (send, sendline, expect and timeout lines)
+        script: String,
+
+        /// Use an existing session instead of creating one
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Shell for a new session (cmd, powershell, sh or bash)
+        #[arg(long, default_value = "cmd")]
+        shell: String,
+
+        /// Program to run in a new session instead of the shell
+        #[arg(long)]
+        program: Option<String>,
+
+        /// Default expect timeout in seconds
+        #[arg(long, default_value = "30")]
+        timeout: u64,
+
+        /// Keep the created session when the script ends
+        #[arg(long)]
+        keep: bool,
+    },
+
+    /// Copy a file to or from the agent (remote paths are remote:PATH)
+    Cp {
+        /// Source: local path or remote:PATH
+        source: String,
+
+        /// Destination: local path or remote:PATH (a trailing separator names a directory)
+        dest: String,
+
+        /// Replace an existing remote file
+        #[arg(long, short)]
+        force: bool,
+    },
+
+    /// List a remote directory, or paths matching a glob pattern
+    Ls {
+        /// Absolute directory path or glob pattern (e.g. X:\Windows\*.log)
+        path: String,
+
+        /// Show kind, size and modification time
+        #[arg(long, short)]
+        long: bool,
+
+        /// List subdirectories recursively
+        #[arg(long, short = 'R')]
+        recursive: bool,
+
+        /// Levels to descend with --recursive
+        #[arg(long, requires = "recursive")]
+        depth: Option<u32>,
+
+        /// Show hidden entries
+        #[arg(long, short)]
+        all: bool,
+    },
+
+    /// Delete remote files or directories
+    Rm {
+        /// Absolute paths to delete
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Delete directories and their contents
+        #[arg(long, short)]
+        recursive: bool,
+    },
+
+    /// Move or rename a remote file or directory
+    Mv {
+        /// Absolute source path
+        from: String,
+
+        /// Absolute destination path
+        to: String,
+
+        /// Replace an existing file at the destination
+        #[arg(long, short)]
+        force: bool,
+    },
+
+    /// Create remote directories
+    Mkdir {
+        /// Absolute directory paths
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Create missing parents; existing directories are not an error
+        #[arg(long, short)]
+        parents: bool,
+    },
+
+    /// Download a remote directory tree as a zip or tar.gz archive
+    PullDir {
+        /// Absolute directory path on the agent
+        remote: String,
+
+        /// Archive file to write, or directory to save it in
+        local: Option<String>,
+
+        /// Archive format (zip or tar.gz)
+        #[arg(long, default_value = "zip")]
+        format: String,
+
+        /// Only include files matching this glob (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Leave out files and directories matching this glob (repeatable)
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Fail if the files total more than this many bytes
+        #[arg(long)]
+        max_bytes: Option<u64>,
+    },
+
+    /// Hash remote files, optionally checking them against a manifest
+    Hash {
+        /// Absolute paths or glob patterns (defaults to the manifest's paths)
+        #[arg(required_unless_present = "manifest")]
+        paths: Vec<String>,
+
+        /// Algorithm: sha256, sha1, md5 or blake3 (repeatable)
+        #[arg(long = "algorithm", short, default_value = "sha256")]
+        algorithms: Vec<String>,
+
+        /// Manifest of `HASH  PATH` lines to verify; mismatches are printed
+        #[arg(long)]
+        manifest: Option<String>,
+    },
+
+    /// Mirror a local directory onto the agent, sending only changed files
+    Sync {
+        /// Local source directory
+        local: String,
+
+        /// Remote destination directory (absolute)
+        remote: String,
+
+        /// Delete remote files and directories that do not exist locally
+        #[arg(long)]
+        delete: bool,
+
+        /// Show what would change without changing anything
+        #[arg(long, short = 'n')]
+        dry_run: bool,
+
+        /// Compare contents by SHA-256 instead of size and modification time
+        #[arg(long, short)]
+        checksum: bool,
     },
 
     /// Open browser to web UI
     Web,
 }
 
+/// Parse a `KEY=VALUE` environment assignment.
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
@@ -82,21 +279,171 @@ async fn main() {
             cwd,
             timeout,
             json,
+            stdin,
             command,
         } => {
             exec::run(
                 &cli.url,
                 cli.token.as_deref(),
-                &shell,
-                cwd.as_deref(),
-                timeout,
-                json,
-                &command,
+                exec::ExecOptions {
+                    shell: &shell,
+                    cwd: cwd.as_deref(),
+                    timeout,
+                    json_output: json,
+                    stdin,
+                    command: &command,
+                },
+            )
+            .await
+        }
+        Commands::Tui {
+            shell,
+            cols,
+            rows,
+            cwd,
+            env,
+            program,
+            args,
+        } => {
+            tui::run(
+                &cli.url,
+                cli.token.as_deref(),
+                tui::TuiOptions {
+                    shell: &shell,
+                    cols,
+                    rows,
+                    cwd: cwd.as_deref(),
+                    env: env.into_iter().collect(),
+                    program: program.as_deref(),
+                    args: &args,
+                },
+            )
+            .await
+        }
+        Commands::Expect {
+            script,
+            session,
+            shell,
+            program,
+            timeout,
+            keep,
+        } => {
+            expect::run(
+                &cli.url,
+                cli.token.as_deref(),
+                expect::ExpectOptions {
+                    script: &script,
+                    session: session.as_deref(),
+                    shell: &shell,
+                    program: program.as_deref(),
+                    timeout,
+                    keep,
+                },
+            )
+            .await
+        }
+        Commands::Cp {
+            source,
+            dest,
+            force,
+        } => {
+            cp::run(
+                &cli.url,
+                cli.token.as_deref(),
+                cp::CpOptions {
+                    source: &source,
+                    dest: &dest,
+                    force,
+                },
             )
             .await
         }
-        Commands::Tui { shell, cols, rows } => {
-            tui::run(&cli.url, cli.token.as_deref(), &shell, cols, rows).await
+        Commands::Ls {
+            path,
+            long,
+            recursive,
+            depth,
+            all,
+        } => {
+            fs::ls(
+                &cli.url,
+                cli.token.as_deref(),
+                fs::LsOptions {
+                    path: &path,
+                    long,
+                    recursive,
+                    depth,
+                    all,
+                },
+            )
+            .await
+        }
+        Commands::Rm { paths, recursive } => {
+            fs::rm(&cli.url, cli.token.as_deref(), &paths, recursive).await
+        }
+        Commands::Mv { from, to, force } => {
+            fs::mv(&cli.url, cli.token.as_deref(), &from, &to, force).await
+        }
+        Commands::Mkdir { paths, parents } => {
+            fs::mkdir(&cli.url, cli.token.as_deref(), &paths, parents).await
+        }
+        Commands::PullDir {
+            remote,
+            local,
+            format,
+            include,
+            exclude,
+            max_bytes,
+        } => {
+            pull::run(
+                &cli.url,
+                cli.token.as_deref(),
+                pull::PullDirOptions {
+                    remote: &remote,
+                    local: local.as_deref(),
+                    format: &format,
+                    include: &include,
+                    exclude: &exclude,
+                    max_bytes,
+                },
+            )
+            .await
+        }
+        Commands::Hash {
+            paths,
+            algorithms,
+            manifest,
+        } => {
+            hash::run(
+                &cli.url,
+                cli.token.as_deref(),
+                hash::HashOptions {
+                    paths: &paths,
+                    algorithms: &algorithms,
+                    manifest: manifest.as_deref(),
+                },
+            )
+            .await
+        }
+        Commands::Sync {
+            local,
+            remote,
+            delete,
+            dry_run,
+            checksum,
+        } => {
+            sync::run(
+                &cli.url,
+                cli.token.as_deref(),
+                sync::SyncOptions {
+                    local: &local,
+                    remote: &remote,
+                    delete,
+                    dry_run,
+                    checksum,
+                },
+            )
+            .await
         }
         Commands::Web => web::run(&cli.url),
     };
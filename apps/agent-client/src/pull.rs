@@ -0,0 +1,80 @@
+//! Pull-dir mode: download a remote directory tree as one archive.
+
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use winpe_agent_core::ApiError;
+
+/// Options for the pull-dir subcommand.
+pub struct PullDirOptions<'a> {
+    /// Absolute directory path on the agent.
+    pub remote: &'a str,
+    /// Archive file or directory to save into; defaults to the working directory.
+    pub local: Option<&'a str>,
+    /// `zip` or `tar.gz`.
+    pub format: &'a str,
+    pub include: &'a [String],
+    pub exclude: &'a [String],
+    /// Refuse archives with more file data than this.
+    pub max_bytes: Option<u64>,
+}
+
+pub async fn run(
+    base_url: &str,
+    token: Option<&str>,
+    opts: PullDirOptions<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut request = reqwest::Client::new()
+        .get(format!("{}/api/v1/archive", base_url))
+        .query(&[("path", opts.remote), ("format", opts.format)]);
+    if !opts.include.is_empty() {
+        request = request.query(&[("include", opts.include.join(","))]);
+    }
+    if !opts.exclude.is_empty() {
+        request = request.query(&[("exclude", opts.exclude.join(","))]);
+    }
+    if let Some(max_bytes) = opts.max_bytes {
+        request = request.query(&[("max_bytes", max_bytes)]);
+    }
+    if let Some(t) = token {
+        request = request.bearer_auth(t);
+    }
+
+    let mut response = request.send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await?;
+        return match serde_json::from_str::<ApiError>(&body) {
+            Ok(err) => Err(err.error.message.into()),
+            Err(_) => Err(format!("Download failed ({}): {}", status, body).into()),
+        };
+    }
+
+    let local = local_target(opts.local, opts.remote, opts.format);
+    let mut file = tokio::fs::File::create(&local)
+        .await
+        .map_err(|e| format!("{}: {}", local.display(), e))?;
+    let mut size = 0u64;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+        size += chunk.len() as u64;
+    }
+    file.flush().await?;
+    eprintln!("Saved {} bytes to {}", size, local.display());
+    Ok(())
+}
+
+/// Archive path; a directory (or no path) receives `<remote name>.<format>`.
+fn local_target(local: Option<&str>, remote: &str, format: &str) -> PathBuf {
+    let name = remote
+        .trim_end_matches(['/', '\\'])
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(':');
+    let name = format!("{}.{}", if name.is_empty() { "root" } else { name }, format);
+    match local {
+        Some(local) if Path::new(local).is_dir() => Path::new(local).join(name),
+        Some(local) => PathBuf::from(local),
+        None => PathBuf::from(name),
+    }
+}
@@ -0,0 +1,526 @@
+//! Sync mode: mirror a local directory into a directory on the agent.
+//!
+//! Both sides are described by manifests (size, modification time and,
+//! with `--checksum`, SHA-256) and only new or changed files are sent.
+//! Uploads carry the local modification time, so files sent by an earlier
+//! sync compare equal on the next one.
+
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use winpe_agent_core::{ApiError, DeleteRequest, DirManifest, ManifestEntry, MkdirRequest};
+
+use crate::cp::{self, Api};
+
+/// Modification times this close count as equal; FAT keeps 2-second steps.
+const MTIME_TOLERANCE_SECS: u64 = 2;
+
+/// Options for the sync subcommand.
+pub struct SyncOptions<'a> {
+    pub local: &'a str,
+    pub remote: &'a str,
+    /// Remove remote files and directories missing locally.
+    pub delete: bool,
+    /// Print what would change without changing anything.
+    pub dry_run: bool,
+    /// Compare file contents by SHA-256 instead of modification time.
+    pub checksum: bool,
+}
+
+/// A change to make on the agent.
+enum Action<'a> {
+    Delete { path: &'a str, dir: bool },
+    Mkdir(&'a str),
+    Upload { file: &'a ManifestEntry, new: bool },
+}
+
+pub async fn run(
+    base_url: &str,
+    token: Option<&str>,
+    opts: SyncOptions<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let local_root = PathBuf::from(opts.local);
+    if !local_root.is_dir() {
+        return Err(format!("{}: Not a directory", local_root.display()).into());
+    }
+    let checksum = opts.checksum;
+    let local = {
+        let root = local_root.clone();
+        tokio::task::spawn_blocking(move || local_manifest(&root, checksum)).await??
+    };
+
+    let api = Api::new(base_url, token);
+    let remote = remote_manifest(&api, opts.remote, checksum).await?;
+    let actions = plan(&local, &remote, opts.delete, checksum);
+
+    let unchanged = local.files.len()
+        - actions
+            .iter()
+            .filter(|a| matches!(a, Action::Upload { .. }))
+            .count();
+    let (mut uploaded, mut bytes, mut deleted, mut failed) = (0usize, 0u64, 0usize, 0usize);
+    for action in &actions {
+        let (label, relative) = match action {
+            Action::Delete { path, .. } => ("delete", *path),
+            Action::Mkdir(path) => ("mkdir ", *path),
+            Action::Upload { file, new: true } => ("new   ", file.path.as_str()),
+            Action::Upload { file, new: false } => ("update", file.path.as_str()),
+        };
+        println!("{}  {}", label, relative);
+        if opts.dry_run {
+            continue;
+        }
+
+        let target = remote_join(opts.remote, relative);
+        let result = match action {
+            Action::Delete { dir, .. } => {
+                let req = DeleteRequest {
+                    path: target,
+                    recursive: *dir,
+                };
+                send_empty(&api, "/api/v1/fs/delete", &req).await
+            }
+            Action::Mkdir(_) => {
+                let req = MkdirRequest {
+                    path: target,
+                    parents: true,
+                };
+                send_empty(&api, "/api/v1/fs/mkdir", &req).await
+            }
+            Action::Upload { file, .. } => {
+                let source = local_root.join(&file.path);
+                cp::upload(&api, &source, &target, true, file.mtime)
+                    .await
+                    .map(|size| bytes += size)
+            }
+        };
+        match result {
+            Ok(()) => match action {
+                Action::Delete { .. } => deleted += 1,
+                Action::Upload { .. } => uploaded += 1,
+                Action::Mkdir(_) => {}
+            },
+            Err(e) => {
+                eprintln!("error: {}: {}", relative, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if opts.dry_run {
+        let uploads = local.files.len() - unchanged;
+        let deletes = actions
+            .iter()
+            .filter(|a| matches!(a, Action::Delete { .. }))
+            .count();
+        eprintln!(
+            "Dry run: {} to upload, {} to delete, {} unchanged",
+            uploads, deletes, unchanged
+        );
+        return Ok(());
+    }
+    eprintln!(
+        "Uploaded {} files ({} bytes), deleted {}, {} unchanged",
+        uploaded, bytes, deleted, unchanged
+    );
+    if failed > 0 {
+        return Err(format!("{} operations failed", failed).into());
+    }
+    Ok(())
+}
+
+/// Work out the changes that make `remote` match `local`, in the order to
+/// apply them: deletions first, so a file can replace a directory of the
+/// same name and the other way round.
+fn plan<'a>(
+    local: &'a DirManifest,
+    remote: &'a DirManifest,
+    delete: bool,
+    checksum: bool,
+) -> Vec<Action<'a>> {
+    let local_dirs: HashSet<&str> = local.dirs.iter().map(String::as_str).collect();
+    let local_files: HashSet<&str> = local.files.iter().map(|f| f.path.as_str()).collect();
+    let remote_dirs: HashSet<&str> = remote.dirs.iter().map(String::as_str).collect();
+    let remote_files: HashMap<&str, &ManifestEntry> =
+        remote.files.iter().map(|f| (f.path.as_str(), f)).collect();
+
+    let mut actions = Vec::new();
+    if delete {
+        // Only the topmost of a deleted tree needs a request
+        let mut deleted: Vec<&str> = Vec::new();
+        let covered = |deleted: &[&str], path: &str| {
+            deleted
+                .iter()
+                .any(|d| path.strip_prefix(d).is_some_and(|r| r.starts_with('/')))
+        };
+        for dir in &remote.dirs {
+            if !local_dirs.contains(dir.as_str()) && !covered(&deleted, dir) {
+                deleted.push(dir);
+                actions.push(Action::Delete {
+                    path: dir,
+                    dir: true,
+                });
+            }
+        }
+        for file in &remote.files {
+            if !local_files.contains(file.path.as_str()) && !covered(&deleted, &file.path) {
+                actions.push(Action::Delete {
+                    path: &file.path,
+                    dir: false,
+                });
+            }
+        }
+    }
+
+    // Uploads create parent directories; only empty ones need a mkdir
+    let parents: HashSet<&str> = local
+        .dirs
+        .iter()
+        .map(String::as_str)
+        .chain(local.files.iter().map(|f| f.path.as_str()))
+        .filter_map(|path| path.rsplit_once('/').map(|(parent, _)| parent))
+        .collect();
+    for dir in &local.dirs {
+        if !remote_dirs.contains(dir.as_str()) && !parents.contains(dir.as_str()) {
+            actions.push(Action::Mkdir(dir));
+        }
+    }
+
+    for file in &local.files {
+        match remote_files.get(file.path.as_str()) {
+            None => actions.push(Action::Upload { file, new: true }),
+            Some(theirs) if changed(file, theirs, checksum) => {
+                actions.push(Action::Upload { file, new: false })
+            }
+            Some(_) => {}
+        }
+    }
+    actions
+}
+
+fn changed(ours: &ManifestEntry, theirs: &ManifestEntry, checksum: bool) -> bool {
+    if ours.size != theirs.size {
+        return true;
+    }
+    if checksum {
+        return ours.hash.is_none() || ours.hash != theirs.hash;
+    }
+    match (ours.mtime, theirs.mtime) {
+        (Some(a), Some(b)) => a.abs_diff(b) > MTIME_TOLERANCE_SECS,
+        _ => true,
+    }
+}
+
+/// Fetch the remote manifest; a missing directory has no entries.
+async fn remote_manifest(
+    api: &Api<'_>,
+    remote: &str,
+    checksum: bool,
+) -> Result<DirManifest, Box<dyn std::error::Error>> {
+    let mut request = api
+        .request(reqwest::Method::GET, "/api/v1/fs/manifest")
+        .query(&[("path", remote)]);
+    if checksum {
+        request = request.query(&[("hash", "sha256")]);
+    }
+    let response = request.send().await?;
+    match response.status() {
+        status if status.is_success() => Ok(response.json().await?),
+        StatusCode::NOT_FOUND => Ok(DirManifest {
+            path: remote.to_string(),
+            dirs: Vec::new(),
+            files: Vec::new(),
+        }),
+        status => {
+            let body = response.text().await?;
+            match serde_json::from_str::<ApiError>(&body) {
+                Ok(err) => Err(err.error.message.into()),
+                Err(_) => Err(format!("Request failed ({}): {}", status, body).into()),
+            }
+        }
+    }
+}
+
+/// POST a JSON request whose response body is not needed.
+async fn send_empty<T: serde::Serialize>(
+    api: &Api<'_>,
+    path: &str,
+    body: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = api
+        .request(reqwest::Method::POST, path)
+        .json(body)
+        .send()
+        .await?;
+    if response.status().is_success() {
+        return Ok(());
+    }
+    let status = response.status();
+    let body = response.text().await?;
+    match serde_json::from_str::<ApiError>(&body) {
+        Ok(err) => Err(err.error.message.into()),
+        Err(_) => Err(format!("Request failed ({}): {}", status, body).into()),
+    }
+}
+
+/// Join a `/`-separated relative path onto a remote directory, using the
+/// remote directory's own separator.
+fn remote_join(root: &str, relative: &str) -> String {
+    let separator = if root.contains('\\') || !root.contains('/') {
+        '\\'
+    } else {
+        '/'
+    };
+    let root = root.trim_end_matches(['/', '\\']);
+    let relative: String = relative
+        .chars()
+        .map(|c| if c == '/' { separator } else { c })
+        .collect();
+    format!("{}{}{}", root, separator, relative)
+}
+
+/// Describe the local tree the way the server describes the remote one.
+///
+/// Symlinks to files are followed; symlinked directories are skipped.
+fn local_manifest(root: &Path, checksum: bool) -> std::io::Result<DirManifest> {
+    let mut manifest = DirManifest {
+        path: root.display().to_string(),
+        dirs: Vec::new(),
+        files: Vec::new(),
+    };
+    walk(root, "", checksum, &mut manifest)?;
+    Ok(manifest)
+}
+
+fn walk(
+    dir: &Path,
+    parent: &str,
+    checksum: bool,
+    manifest: &mut DirManifest,
+) -> std::io::Result<()> {
+    let mut children = std::fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let path = child.path();
+        let name = child.file_name().to_string_lossy().into_owned();
+        let relative = if parent.is_empty() {
+            name
+        } else {
+            format!("{}/{}", parent, name)
+        };
+
+        let file_type = child.file_type()?;
+        if file_type.is_dir() {
+            manifest.dirs.push(relative.clone());
+            walk(&path, &relative, checksum, manifest)?;
+            continue;
+        }
+        // Broken symlinks and special files are skipped
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        manifest.files.push(ManifestEntry {
+            hash: if checksum {
+                Some(sha256_file(&path)?)
+            } else {
+                None
+            },
+            path: relative,
+            size: metadata.len(),
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+        });
+    }
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64, mtime: u64) -> ManifestEntry {
+        ManifestEntry {
+            path: path.to_string(),
+            size,
+            mtime: Some(mtime),
+            hash: None,
+        }
+    }
+
+    fn manifest(dirs: &[&str], files: Vec<ManifestEntry>) -> DirManifest {
+        DirManifest {
+            path: String::new(),
+            dirs: dirs.iter().map(|d| d.to_string()).collect(),
+            files,
+        }
+    }
+
+    /// The planned actions as `"<action> <path>"`, in order.
+    fn describe(actions: &[Action]) -> Vec<String> {
+        actions
+            .iter()
+            .map(|action| match action {
+                Action::Delete { path, dir: true } => format!("rmdir {}", path),
+                Action::Delete { path, dir: false } => format!("rm {}", path),
+                Action::Mkdir(path) => format!("mkdir {}", path),
+                Action::Upload { file, new: true } => format!("new {}", file.path),
+                Action::Upload { file, new: false } => format!("update {}", file.path),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deletes_only_topmost_missing_paths_and_before_uploads() {
+        let local = manifest(&["keep"], vec![file("keep/a", 1, 100), file("b", 1, 100)]);
+        let remote = manifest(
+            &["keep", "old", "old/deeper", "old/deeper/deepest"],
+            vec![
+                file("keep/a", 1, 100),
+                file("keep/stale", 1, 100),
+                file("old/x", 1, 100),
+                file("old/deeper/y", 1, 100),
+                // Shares a prefix with a deleted directory without being inside it
+                file("older", 1, 100),
+            ],
+        );
+        assert_eq!(
+            describe(&plan(&local, &remote, true, false)),
+            ["rmdir old", "rm keep/stale", "rm older", "new b"]
+        );
+        // Without --delete nothing is removed
+        assert_eq!(describe(&plan(&local, &remote, false, false)), ["new b"]);
+    }
+
+    #[test]
+    fn file_and_directory_swap_places() {
+        let local = manifest(&["was_file"], vec![file("was_dir", 1, 100)]);
+        let remote = manifest(
+            &["was_dir"],
+            vec![file("was_dir/inner", 1, 100), file("was_file", 1, 100)],
+        );
+        assert_eq!(
+            describe(&plan(&local, &remote, true, false)),
+            [
+                "rmdir was_dir",
+                "rm was_file",
+                "mkdir was_file",
+                "new was_dir"
+            ]
+        );
+    }
+
+    #[test]
+    fn mkdir_only_for_empty_directories() {
+        let local = manifest(
+            &["full", "full/empty", "parent", "parent/child", "lonely"],
+            vec![file("full/f", 1, 100), file("parent/child/f", 1, 100)],
+        );
+        let remote = manifest(&["lonely"], Vec::new());
+        assert_eq!(
+            describe(&plan(&local, &remote, false, false)),
+            ["mkdir full/empty", "new full/f", "new parent/child/f"]
+        );
+    }
+
+    #[test]
+    fn mtime_within_tolerance_is_unchanged() {
+        let ours = file("f", 10, 1000);
+        assert!(!changed(&ours, &file("f", 10, 1000), false));
+        assert!(!changed(&ours, &file("f", 10, 998), false));
+        assert!(!changed(&ours, &file("f", 10, 1002), false));
+        assert!(changed(&ours, &file("f", 10, 997), false));
+        assert!(changed(&ours, &file("f", 10, 1003), false));
+        // A different size is a change whatever the time
+        assert!(changed(&ours, &file("f", 11, 1000), false));
+        // So is a missing time
+        let mut unknown = file("f", 10, 1000);
+        unknown.mtime = None;
+        assert!(changed(&ours, &unknown, false));
+    }
+
+    #[test]
+    fn checksum_mode_ignores_mtime() {
+        let hashed = |mtime, hash: Option<&str>| ManifestEntry {
+            hash: hash.map(String::from),
+            ..file("f", 10, mtime)
+        };
+        assert!(!changed(
+            &hashed(1000, Some("aa")),
+            &hashed(5000, Some("aa")),
+            true
+        ));
+        assert!(changed(
+            &hashed(1000, Some("aa")),
+            &hashed(1000, Some("bb")),
+            true
+        ));
+        // Unreadable on either side counts as changed
+        assert!(changed(&hashed(1000, None), &hashed(1000, None), true));
+        assert!(changed(
+            &hashed(1000, Some("aa")),
+            &hashed(1000, None),
+            true
+        ));
+
+        let local = manifest(&[], vec![hashed(1000, Some("aa"))]);
+        let remote = manifest(&[], vec![hashed(1000, Some("bb"))]);
+        assert_eq!(describe(&plan(&local, &remote, false, true)), ["update f"]);
+        assert!(plan(&local, &remote, false, false).is_empty());
+    }
+
+    #[test]
+    fn remote_join_uses_the_remote_separator() {
+        assert_eq!(remote_join(r"C:\dest", "a/b.txt"), r"C:\dest\a\b.txt");
+        assert_eq!(remote_join(r"C:\dest\", "a/b.txt"), r"C:\dest\a\b.txt");
+        assert_eq!(remote_join("C:", "a"), r"C:\a");
+        assert_eq!(remote_join("/srv/dest", "a/b.txt"), "/srv/dest/a/b.txt");
+        assert_eq!(remote_join("/srv/dest/", "a"), "/srv/dest/a");
+        // Mixed roots follow the backslash
+        assert_eq!(remote_join(r"C:/dest\sub", "a/b"), r"C:/dest\sub\a\b");
+    }
+
+    #[test]
+    fn local_manifest_lists_parents_first() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("b/c")).unwrap();
+        std::fs::write(dir.path().join("b/c/f.txt"), b"hello").unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"x").unwrap();
+
+        let manifest = local_manifest(dir.path(), true).unwrap();
+        assert_eq!(manifest.dirs, ["b", "b/c"]);
+        let files: Vec<_> = manifest
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), f.size))
+            .collect();
+        assert_eq!(files, [("a.txt", 1), ("b/c/f.txt", 5)]);
+        assert_eq!(
+            manifest.files[1].hash.as_deref(),
+            Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")
+        );
+        assert!(manifest.files[0].mtime.is_some());
+        assert!(
+            local_manifest(dir.path(), false).unwrap().files[0]
+                .hash
+                .is_none()
+        );
+    }
+}
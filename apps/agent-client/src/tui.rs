@@ -23,24 +23,51 @@
 use crossterm::{
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+    terminal::{
+        EnterAlternateScreen, LeaveAlternateScreen, SetTitle, disable_raw_mode, enable_raw_mode,
+    },
 };
 use futures_util::{SinkExt, StreamExt};
 use std::collections::HashMap;
 use std::io::{self, Write};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use winpe_agent_core::{SessionCreateRequest, SessionCreateResponse, Shell};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{Message, client::IntoClientRequest, http::header::AUTHORIZATION},
+};
+use winpe_agent_core::{SessionCreateRequest, SessionCreateResponse, Shell, WsServerMessage};
+
+/// Options for the tui subcommand.
+pub struct TuiOptions<'a> {
+    pub shell: &'a str,
+    pub cols: u16,
+    pub rows: u16,
+    pub cwd: Option<&'a str>,
+    pub env: HashMap<String, String>,
+    /// Program to run instead of the shell.
+    pub program: Option<&'a str>,
+    pub args: &'a [String],
+}
 
 pub async fn run(
     base_url: &str,
     token: Option<&str>,
-    shell: &str,
-    cols: u16,
-    rows: u16,
+    opts: TuiOptions<'_>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let TuiOptions {
+        shell,
+        cols,
+        rows,
+        cwd,
+        env,
+        program,
+        args,
+    } = opts;
+
     let shell_enum = match shell.to_lowercase().as_str() {
         "cmd" => Shell::Cmd,
         "powershell" | "pwsh" => Shell::Powershell,
+        "sh" => Shell::Sh,
+        "bash" => Shell::Bash,
         _ => return Err(format!("Unknown shell: {}", shell).into()),
     };
 
@@ -48,11 +75,16 @@ pub async fn run(
     let client = reqwest::Client::new();
     let req = SessionCreateRequest {
         shell: shell_enum,
-        cwd: None,
-        env: HashMap::new(),
+        program: program.map(str::to_string),
+        args: args.to_vec(),
+        cwd: cwd.map(str::to_string),
+        env,
         cols,
         rows,
         idle_timeout_sec: 600,
+        scrollback_bytes: 256 * 1024,
+        record: false,
+        record_input: false,
         init: winpe_agent_core::SessionInit { force_utf8: true },
     };
 
@@ -78,15 +110,23 @@ pub async fn run(
         .replace("https://", "wss://");
     let full_ws_url = format!("{}{}", ws_url, session.ws_url);
 
-    let (ws_stream, _) = connect_async(&full_ws_url).await?;
+    let mut ws_request = full_ws_url.into_client_request()?;
+    if let Some(t) = token {
+        ws_request
+            .headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {}", t).parse()?);
+    }
+
+    let (ws_stream, _) = connect_async(ws_request).await?;
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
     // Enable raw mode
     enable_raw_mode()?;
     execute!(io::stdout(), EnterAlternateScreen)?;
 
-    // Spawn task to read from WebSocket and print to terminal
-    let output_handle = tokio::spawn(async move {
+    // Spawn task to read from WebSocket and print to terminal; yields the exit code
+    let mut output_handle = tokio::spawn(async move {
+        let mut exit_code = None;
         while let Some(msg) = ws_receiver.next().await {
             match msg {
                 Ok(Message::Binary(data)) => {
@@ -94,10 +134,21 @@ pub async fn run(
                     let _ = stdout.write_all(&data);
                     let _ = stdout.flush();
                 }
-                Ok(Message::Text(text)) => {
-                    // Control messages (pong, etc.)
-                    eprintln!("\r\n[Server]: {}", text);
-                }
+                Ok(Message::Text(text)) => match serde_json::from_str(&text) {
+                    Ok(WsServerMessage::Exit { code }) => exit_code = Some(code),
+                    Ok(WsServerMessage::Title { title }) => {
+                        let _ = execute!(io::stdout(), SetTitle(title));
+                    }
+                    Ok(WsServerMessage::Lagged { dropped_chunks }) => {
+                        eprint!("\r\n[Output dropped: {} chunks]\r\n", dropped_chunks);
+                    }
+                    Ok(WsServerMessage::Error { message }) => {
+                        eprint!("\r\n[Server error]: {}\r\n", message);
+                    }
+                    // Acknowledgements need no action; ignore unknown messages
+                    Ok(WsServerMessage::Pong { .. } | WsServerMessage::Resized { .. }) | Err(_) => {
+                    }
+                },
                 Ok(Message::Close(_)) => {
                     break;
                 }
@@ -108,10 +159,16 @@ pub async fn run(
                 _ => {}
             }
         }
+        exit_code
     });
 
-    // Main input loop
+    // Main input loop; ends on Ctrl+D or when the server closes the connection
+    let mut exit_code = None;
     loop {
+        if output_handle.is_finished() {
+            exit_code = (&mut output_handle).await.ok().flatten();
+            break;
+        }
         if event::poll(std::time::Duration::from_millis(100))? {
             match event::read()? {
                 Event::Key(KeyEvent {
@@ -193,7 +250,10 @@ pub async fn run(
     disable_raw_mode()?;
     execute!(io::stdout(), LeaveAlternateScreen)?;
 
-    eprintln!("Session ended");
+    match exit_code {
+        Some(code) => eprintln!("Session ended (exit code {})", code),
+        None => eprintln!("Session ended"),
+    }
     Ok(())
 }
 
@@ -0,0 +1,154 @@
+//! Archive API endpoint for downloading directory trees.
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use winpe_agent_core::{ApiError, ErrorCode};
+
+use super::files::{error, fs_error};
+use crate::config::ServerConfig;
+use crate::files::{self, archive};
+use crate::stream::ChannelWriter;
+
+#[derive(Clone)]
+struct ArchiveState {
+    /// Server-wide cap on the bytes of file data in one archive.
+    max_archive_bytes: u64,
+}
+
+/// Create archive router.
+pub fn router(config: &ServerConfig) -> Router {
+    Router::new()
+        .route("/archive", get(download))
+        .with_state(ArchiveState {
+            max_archive_bytes: config.max_archive_bytes,
+        })
+}
+
+#[derive(Deserialize)]
+struct ArchiveQuery {
+    path: String,
+    /// `zip` (default) or `tar.gz`.
+    format: Option<String>,
+    /// Comma-separated globs of files to include.
+    include: Option<String>,
+    /// Comma-separated globs of files and directories to leave out.
+    exclude: Option<String>,
+    /// Cap on the bytes of file data; may lower the server limit, not raise it.
+    max_bytes: Option<u64>,
+}
+
+/// GET /api/v1/archive?path=
+///
+/// Streams an archive of a directory tree as it is read.
+async fn download(
+    State(state): State<ArchiveState>,
+    Query(query): Query<ArchiveQuery>,
+) -> Response {
+    let path = match files::resolve(&query.path) {
+        Ok(path) => path,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    let format = match archive::Format::parse(query.format.as_deref().unwrap_or("zip")) {
+        Some(format) => format,
+        None => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                "format must be zip or tar.gz",
+            );
+        }
+    };
+    let filter = match archive::Filter::parse(query.include.as_deref(), query.exclude.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    let max_bytes = query
+        .max_bytes
+        .map_or(state.max_archive_bytes, |n| n.min(state.max_archive_bytes));
+
+    match tokio::fs::metadata(&path).await {
+        Ok(metadata) if !metadata.is_dir() => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                format!("{}: Not a directory", path.display()),
+            );
+        }
+        Ok(_) => {}
+        Err(e) => return fs_error(e, &path),
+    }
+
+    let collected = {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || archive::collect(&path, &filter)).await
+    };
+    let tree = match collected {
+        Ok(Ok(tree)) => tree,
+        Ok(Err(e)) => return fs_error(e, &path),
+        Err(e) => {
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                e.to_string(),
+            );
+        }
+    };
+    if tree.total_bytes > max_bytes {
+        let details = HashMap::from([
+            ("total_bytes".to_string(), json!(tree.total_bytes)),
+            ("max_bytes".to_string(), json!(max_bytes)),
+        ]);
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ApiError::with_details(
+                ErrorCode::BadRequest,
+                format!(
+                    "Archive would contain {} bytes, more than the limit of {}",
+                    tree.total_bytes, max_bytes
+                ),
+                details,
+            )),
+        )
+            .into_response();
+    }
+
+    let filename = format!("{}.{}", archive::root_name(&path), format.extension());
+
+    // Errors after this point can only abort the response body
+    let (tx, rx) = mpsc::channel(8);
+    tokio::task::spawn_blocking(move || {
+        let out = ChannelWriter::new(tx.clone());
+        match archive::write(format, &tree.items, out) {
+            Ok(()) => {}
+            // The client went away
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                tracing::warn!("Archive of {} failed: {}", path.display(), e);
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename.replace('"', "")),
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
@@ -0,0 +1,46 @@
+//! Authentication endpoints.
+
+use axum::{Json, Router, extract::State, http::StatusCode, response::IntoResponse, routing::post};
+use winpe_agent_core::{ApiError, ErrorCode, TicketRequest, TicketResponse};
+
+use crate::auth::{AuthState, TICKET_TTL};
+use crate::terminal::SessionManager;
+
+#[derive(Clone)]
+struct TicketState {
+    auth: AuthState,
+    sessions: SessionManager,
+}
+
+/// Create auth router.
+pub fn router(auth: AuthState, session_manager: SessionManager) -> Router {
+    Router::new()
+        .route("/auth/ticket", post(issue_ticket))
+        .with_state(TicketState {
+            auth,
+            sessions: session_manager,
+        })
+}
+
+/// POST /api/v1/auth/ticket
+async fn issue_ticket(
+    State(state): State<TicketState>,
+    Json(req): Json<TicketRequest>,
+) -> impl IntoResponse {
+    if !state.sessions.session_exists(&req.session_id) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Session not found")),
+        )
+            .into_response();
+    }
+
+    (
+        StatusCode::OK,
+        Json(TicketResponse {
+            ticket: state.auth.issue_ticket(&req.session_id),
+            expires_in_sec: TICKET_TTL.as_secs(),
+        }),
+    )
+        .into_response()
+}
@@ -2,42 +2,80 @@
 
 use axum::{
     Json, Router,
-    http::StatusCode,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::{
-        IntoResponse,
+        IntoResponse, Response,
         sse::{Event, Sse},
     },
-    routing::post,
+    routing::{get, post},
 };
 use futures::stream::Stream;
+use serde::Deserialize;
 use std::convert::Infallible;
 use std::time::Instant;
+use tokio::sync::mpsc;
 use winpe_agent_core::{ApiError, ErrorCode, ExecRequest, ExecResponse};
 
 use crate::automation::executor;
+use crate::automation::registry::{CursorItem, ExecHandle, ExecRegistry, StdinError};
+use crate::config::ServerConfig;
+
+#[derive(Clone)]
+struct AutomationState {
+    /// Server-wide cap on captured bytes per output stream.
+    max_output_bytes: u64,
+    /// `exec_stream` executions, running or recently finished.
+    executions: ExecRegistry,
+}
 
 /// Create automation router.
-pub fn router() -> Router {
+pub fn router(config: &ServerConfig) -> Router {
     Router::new()
         .route("/automation/exec", post(exec_handler))
         .route("/automation/exec_stream", post(exec_stream_handler))
+        .route(
+            "/automation/exec_stream/{id}",
+            get(exec_stream_resume_handler),
+        )
+        .route("/automation/exec_stream/{id}/stdin", post(stdin_handler))
+        .with_state(AutomationState {
+            max_output_bytes: config.max_output_bytes,
+            executions: ExecRegistry::new(
+                config.max_exec_streams,
+                config.exec_replay_bytes as usize,
+            ),
+        })
 }
 
 /// POST /api/v1/automation/exec
 #[axum::debug_handler]
-async fn exec_handler(Json(req): Json<ExecRequest>) -> impl IntoResponse {
+async fn exec_handler(
+    State(state): State<AutomationState>,
+    Json(mut req): Json<ExecRequest>,
+) -> impl IntoResponse {
     let start = Instant::now();
+    let timeout_ms = *req.timeout_ms.get_or_insert(executor::DEFAULT_TIMEOUT_MS);
+    let max_output_bytes = req
+        .max_output_bytes
+        .map_or(state.max_output_bytes, |n| n.min(state.max_output_bytes));
 
-    match executor::execute_command(&req).await {
-        Ok((exit_code, stdout, stderr)) => {
+    match executor::execute_command(&req, max_output_bytes as usize).await {
+        Ok(output) => {
             let duration_ms = start.elapsed().as_millis() as u64;
             (
                 StatusCode::OK,
                 Json(ExecResponse {
-                    exit_code,
-                    stdout,
-                    stderr,
+                    exit_code: output.exit_code,
+                    stdout: output.stdout.text,
+                    stderr: output.stderr.text,
                     duration_ms,
+                    stdout_truncated: output.stdout.truncated,
+                    stderr_truncated: output.stderr.truncated,
+                    stdout_bytes: output.stdout.total_bytes,
+                    stderr_bytes: output.stderr.total_bytes,
+                    encoding: output.stdout.encoding.name(),
                 }),
             )
                 .into_response()
@@ -47,7 +85,7 @@ async fn exec_handler(Json(req): Json<ExecRequest>) -> impl IntoResponse {
                 let mut details = std::collections::HashMap::new();
                 details.insert(
                     "timeout_ms".to_string(),
-                    serde_json::Value::Number(req.timeout_ms.into()),
+                    serde_json::Value::Number(timeout_ms.into()),
                 );
                 (
                     StatusCode::REQUEST_TIMEOUT,
@@ -69,60 +107,220 @@ async fn exec_handler(Json(req): Json<ExecRequest>) -> impl IntoResponse {
                 Json(ApiError::new(ErrorCode::NotSupported, msg)),
             )
                 .into_response(),
+            executor::ExecError::InvalidRequest(msg) => (
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::new(ErrorCode::BadRequest, msg)),
+            )
+                .into_response(),
+            executor::ExecError::Busy(msg) => (
+                StatusCode::CONFLICT,
+                Json(ApiError::new(ErrorCode::Conflict, msg)),
+            )
+                .into_response(),
         },
     }
 }
 
 /// POST /api/v1/automation/exec_stream
 async fn exec_stream_handler(
-    Json(req): Json<ExecRequest>,
+    State(state): State<AutomationState>,
+    Json(mut req): Json<ExecRequest>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let start = Instant::now();
+    req.timeout_ms.get_or_insert(executor::DEFAULT_TIMEOUT_MS);
 
     let stream = async_stream::stream! {
+        // Claim a slot first, so nothing is started when the registry is full
+        let handle = match state.executions.register() {
+            Ok(handle) => handle,
+            Err(msg) => {
+                yield Ok(error_event(&msg));
+                return;
+            }
+        };
         match executor::execute_command_stream(&req).await {
-            Ok(mut rx) => {
-                while let Some(event) = rx.recv().await {
-                    match event {
-                        executor::StreamEvent::Stdout(chunk) => {
-                            let data = serde_json::json!({ "chunk": chunk });
-                            yield Ok(Event::default().event("stdout").data(data.to_string()));
-                        }
-                        executor::StreamEvent::Stderr(chunk) => {
-                            let data = serde_json::json!({ "chunk": chunk });
-                            yield Ok(Event::default().event("stderr").data(data.to_string()));
-                        }
-                        executor::StreamEvent::Exit(exit_code) => {
-                            let duration_ms = start.elapsed().as_millis() as u64;
-                            let data = serde_json::json!({
-                                "exit_code": exit_code,
-                                "duration_ms": duration_ms
-                            });
-                            yield Ok(Event::default().event("exit").data(data.to_string()));
-                            break;
-                        }
-                        executor::StreamEvent::Timeout => {
-                            let duration_ms = start.elapsed().as_millis() as u64;
-                            let data = serde_json::json!({
-                                "duration_ms": duration_ms
-                            });
-                            yield Ok(Event::default().event("timeout").data(data.to_string()));
-                            break;
-                        }
-                    }
+            Ok(exec) => {
+                // Events are logged by a separate task, so the process keeps
+                // running and its output stays replayable if this client disconnects
+                handle.set_stdin(exec.stdin);
+                let mut cursor = state
+                    .executions
+                    .follow(&handle.id, 0)
+                    .expect("execution was just registered");
+                tokio::spawn(log_events(handle, exec.events, start));
+
+                while let Some(item) = cursor.next().await {
+                    yield Ok(sse_event(item));
                 }
             }
             Err(e) => {
+                handle.discard();
                 let error_msg = match e {
                     executor::ExecError::Timeout => "Process exceeded timeout",
                     executor::ExecError::ProcessCreationFailed(ref msg) => msg.as_str(),
                     executor::ExecError::NotSupported(ref msg) => msg.as_str(),
+                    executor::ExecError::InvalidRequest(ref msg) => msg.as_str(),
+                    executor::ExecError::Busy(ref msg) => msg.as_str(),
                 };
-                let data = serde_json::json!({ "error": error_msg });
-                yield Ok(Event::default().event("error").data(data.to_string()));
+                yield Ok(error_event(error_msg));
             }
         }
     };
 
     Sse::new(stream)
 }
+
+/// An `error` event ending a stream that never started.
+fn error_event(message: &str) -> Event {
+    let data = serde_json::json!({ "error": message });
+    Event::default().event("error").data(data.to_string())
+}
+
+/// GET /api/v1/automation/exec_stream/{id}
+///
+/// Replays the events after the one named by `Last-Event-ID`, then follows
+/// the execution until it ends.
+async fn exec_stream_resume_handler(
+    State(state): State<AutomationState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let after = match headers.get("last-event-id") {
+        Some(value) => match value
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+        {
+            Some(after) => after,
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiError::new(
+                        ErrorCode::BadRequest,
+                        "Invalid Last-Event-ID",
+                    )),
+                )
+                    .into_response();
+            }
+        },
+        None => 0,
+    };
+
+    let Some(mut cursor) = state.executions.follow(&id, after) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Execution not found")),
+        )
+            .into_response();
+    };
+
+    let stream = async_stream::stream! {
+        while let Some(item) = cursor.next().await {
+            yield Ok::<_, Infallible>(sse_event(item));
+        }
+    };
+    Sse::new(stream).into_response()
+}
+
+/// Convert a logged event (or a gap in the log) into an SSE event.
+fn sse_event(item: CursorItem) -> Event {
+    match item {
+        CursorItem::Event(logged) => Event::default()
+            .id(logged.id.to_string())
+            .event(logged.event)
+            .data(logged.data),
+        CursorItem::Skipped(skipped) => {
+            let data = serde_json::json!({ "skipped": skipped });
+            Event::default().event("lagged").data(data.to_string())
+        }
+    }
+}
+
+/// Log an execution's events until it ends.
+async fn log_events(
+    handle: ExecHandle,
+    mut rx: mpsc::Receiver<executor::StreamEvent>,
+    start: Instant,
+) {
+    let data = serde_json::json!({ "exec_id": handle.id });
+    handle.push("start", data.to_string());
+
+    while let Some(event) = rx.recv().await {
+        match event {
+            executor::StreamEvent::Stdout(chunk) => {
+                let data = serde_json::json!({ "chunk": chunk });
+                handle.push("stdout", data.to_string());
+            }
+            executor::StreamEvent::Stderr(chunk) => {
+                let data = serde_json::json!({ "chunk": chunk });
+                handle.push("stderr", data.to_string());
+            }
+            executor::StreamEvent::Exit {
+                exit_code,
+                encoding,
+            } => {
+                let duration_ms = start.elapsed().as_millis() as u64;
+                let data = serde_json::json!({
+                    "exit_code": exit_code,
+                    "duration_ms": duration_ms,
+                    "encoding": encoding.name()
+                });
+                handle.push("exit", data.to_string());
+                break;
+            }
+            executor::StreamEvent::Timeout => {
+                let duration_ms = start.elapsed().as_millis() as u64;
+                let data = serde_json::json!({
+                    "duration_ms": duration_ms
+                });
+                handle.push("timeout", data.to_string());
+                break;
+            }
+            executor::StreamEvent::Cancelled => {
+                let duration_ms = start.elapsed().as_millis() as u64;
+                let data = serde_json::json!({
+                    "duration_ms": duration_ms
+                });
+                handle.push("cancelled", data.to_string());
+                break;
+            }
+        }
+    }
+    // Dropping the handle marks the execution finished
+}
+
+#[derive(Deserialize)]
+struct StdinQuery {
+    /// Close stdin after writing the body.
+    #[serde(default)]
+    close: bool,
+}
+
+/// POST /api/v1/automation/exec_stream/{id}/stdin
+async fn stdin_handler(
+    State(state): State<AutomationState>,
+    Path(id): Path<String>,
+    Query(query): Query<StdinQuery>,
+    body: Bytes,
+) -> impl IntoResponse {
+    match state
+        .executions
+        .write_stdin(&id, body.to_vec(), query.close)
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(StdinError::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Execution not found")),
+        )
+            .into_response(),
+        Err(StdinError::Closed) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::new(
+                ErrorCode::BadRequest,
+                "Stdin is not open for this execution",
+            )),
+        )
+            .into_response(),
+    }
+}
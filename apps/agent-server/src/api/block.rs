@@ -0,0 +1,360 @@
+//! Block API endpoints for imaging and restoring disks and volumes.
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use std::io;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use winpe_agent_core::{ApiError, BlockWriteResponse, ErrorCode};
+
+use super::files::{ByteRange, error, fs_error, parse_range};
+use crate::block::{self, Compression};
+use crate::stream::{ChannelReader, ChannelWriter};
+
+/// Create block device router.
+pub fn router() -> Router {
+    Router::new().route("/block/{device}", get(read).put(write))
+}
+
+#[derive(Deserialize)]
+struct ReadQuery {
+    /// `gzip` or `zstd`; uncompressed by default.
+    compress: Option<String>,
+    /// Send unreadable blocks as zeros instead of aborting.
+    #[serde(default)]
+    skip_errors: bool,
+}
+
+/// GET /api/v1/block/{device}
+///
+/// Streams raw device contents, honoring a single-range `Range` header
+/// when uncompressed.
+async fn read(
+    Path(name): Path<String>,
+    Query(query): Query<ReadQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let device = match block::resolve(&name) {
+        Ok(device) => device,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    let Some(compression) = Compression::parse(query.compress.as_deref()) else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BadRequest,
+            "compress must be gzip or zstd",
+        );
+    };
+    let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    if range.is_some() && compression != Compression::None {
+        return error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BadRequest,
+            "Range requests cannot be compressed",
+        );
+    }
+
+    let opened = {
+        let device = device.clone();
+        tokio::task::spawn_blocking(move || block::open_read(&device)).await
+    };
+    let (mut file, size) = match opened {
+        Ok(Ok(opened)) => opened,
+        Ok(Err(e)) => return fs_error(e, &device.path),
+        Err(e) => {
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                e.to_string(),
+            );
+        }
+    };
+
+    let (status, start, len) = match range.map_or(ByteRange::Full, |v| parse_range(v, size)) {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial(start, end) => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        ByteRange::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+                Json(ApiError::new(
+                    ErrorCode::BadRequest,
+                    format!("Range not satisfiable for {} bytes", size),
+                )),
+            )
+                .into_response();
+        }
+    };
+
+    // Errors after this point can only abort the response body
+    let (tx, rx) = mpsc::channel(8);
+    let path = device.path.clone();
+    tokio::task::spawn_blocking(move || {
+        let out = ChannelWriter::new(tx.clone());
+        match block::read(
+            &mut file,
+            size,
+            start,
+            len,
+            query.skip_errors,
+            compression,
+            out,
+        ) {
+            Ok(0) => {}
+            Ok(bad) => tracing::warn!("Image of {} has {} zero-filled blocks", path.display(), bad),
+            // The client went away
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                tracing::warn!("Image of {} failed: {}", path.display(), e);
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+
+    let filename = format!("{}.{}", device.name, compression.extension());
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, compression.content_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .header("X-Device-Size", size);
+    if compression == Compression::None {
+        response = response
+            .header(header::CONTENT_LENGTH, len)
+            .header(header::ACCEPT_RANGES, "bytes");
+    }
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, start + len - 1, size),
+        );
+    }
+    response
+        .body(Body::from_stream(ReceiverStream::new(rx)))
+        .unwrap_or_else(|e| {
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                e.to_string(),
+            )
+        })
+}
+
+#[derive(Deserialize)]
+struct WriteQuery {
+    /// Must be `true`: the write replaces whatever the device holds.
+    #[serde(default)]
+    destructive: bool,
+    /// Byte offset on the device to write the image at.
+    #[serde(default)]
+    offset: u64,
+    /// `gzip` or `zstd` when the body is compressed.
+    compress: Option<String>,
+}
+
+/// PUT /api/v1/block/{device}?destructive=true
+///
+/// Writes the request body to the device, decompressing it on the way.
+async fn write(Path(name): Path<String>, Query(query): Query<WriteQuery>, body: Body) -> Response {
+    if !query.destructive {
+        return error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BadRequest,
+            "Writing overwrites the device; confirm with destructive=true",
+        );
+    }
+    let device = match block::resolve(&name) {
+        Ok(device) => device,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    let Some(compression) = Compression::parse(query.compress.as_deref()) else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BadRequest,
+            "compress must be gzip or zstd",
+        );
+    };
+
+    let (tx, rx) = mpsc::channel::<io::Result<axum::body::Bytes>>(8);
+    let path = device.path.clone();
+    let offset = query.offset;
+    let writer = tokio::task::spawn_blocking(move || {
+        let (mut file, size) = block::open_write(&device)?;
+        tracing::warn!(
+            "Writing image to {} at offset {}",
+            device.path.display(),
+            offset
+        );
+        block::write(&mut file, size, offset, compression, ChannelReader::new(rx))
+    });
+
+    // Feed the body until it ends or the writer stops reading
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let failed = chunk.is_err();
+        if tx.send(chunk.map_err(io::Error::other)).await.is_err() || failed {
+            break;
+        }
+    }
+    drop(tx);
+
+    match writer.await {
+        Ok(Ok(bytes_written)) => Json(BlockWriteResponse {
+            device: path.display().to_string(),
+            offset: query.offset,
+            bytes_written,
+        })
+        .into_response(),
+        Ok(Err(e)) => write_error(e, &path),
+        Err(e) => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::Internal,
+            e.to_string(),
+        ),
+    }
+}
+
+fn write_error(e: io::Error, path: &std::path::Path) -> Response {
+    match e.kind() {
+        io::ErrorKind::FileTooLarge => error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::BadRequest,
+            e.to_string(),
+        ),
+        // Corrupt compressed data or an interrupted request body
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::Other => error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BadRequest,
+            format!("Image could not be read: {}", e),
+        ),
+        _ => fs_error(e, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    /// The `/block/{device}` URI for an image file.
+    fn uri(image: &tempfile::NamedTempFile, query: &str) -> String {
+        let path = image.path().to_string_lossy();
+        let encoded: String = path
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'_' | b'-' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect();
+        format!("/block/{}{}", encoded, query)
+    }
+
+    fn image(data: &[u8]) -> tempfile::NamedTempFile {
+        let image = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(image.path(), data).unwrap();
+        image
+    }
+
+    async fn send(request: Request<Body>) -> (StatusCode, Vec<u8>) {
+        let response = router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn put(uri: String, body: Vec<u8>) -> (StatusCode, Vec<u8>) {
+        send(Request::put(uri).body(Body::from(body)).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn write_requires_destructive() {
+        let image = image(&[1; 8192]);
+        for query in ["", "?destructive=false", "?offset=0"] {
+            let (status, _) = put(uri(&image, query), vec![2; 100]).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        }
+        assert_eq!(std::fs::read(image.path()).unwrap(), [1; 8192]);
+
+        let (status, body) = put(uri(&image, "?destructive=true&offset=10"), vec![2; 100]).await;
+        assert_eq!(status, StatusCode::OK);
+        let response: BlockWriteResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!((response.offset, response.bytes_written), (10, 100));
+        let written = std::fs::read(image.path()).unwrap();
+        assert!(written[..10].iter().all(|&b| b == 1));
+        assert!(written[10..110].iter().all(|&b| b == 2));
+        assert!(written[110..].iter().all(|&b| b == 1));
+    }
+
+    #[tokio::test]
+    async fn write_larger_than_target_is_rejected() {
+        let image = image(&[1; 4096]);
+        let (status, _) = put(uri(&image, "?destructive=true"), vec![2; 4097]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let (status, _) = put(uri(&image, "?destructive=true&offset=4000"), vec![2; 97]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn write_rejects_corrupt_compressed_body() {
+        let image = image(&[1; 4096]);
+        let (status, _) = put(
+            uri(&image, "?destructive=true&compress=zstd"),
+            b"not zstd".to_vec(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn read_range_and_compressed() {
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let image = image(&data);
+
+        let (status, body) = send(Request::get(uri(&image, "")).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, data);
+
+        let (status, body) = send(
+            Request::get(uri(&image, ""))
+                .header(header::RANGE, "bytes=4095-5000")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body, data[4095..5001]);
+
+        let (status, _) = send(
+            Request::get(uri(&image, "?compress=gzip"))
+                .header(header::RANGE, "bytes=0-10")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send(
+            Request::get(uri(&image, "?compress=zstd"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(zstd::decode_all(&body[..]).unwrap(), data);
+    }
+}
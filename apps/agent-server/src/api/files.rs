@@ -0,0 +1,534 @@
+//! Files API endpoints for uploading, downloading and hashing host files.
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::Query,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use winpe_agent_core::{
+    ApiError, ErrorCode, FileUploadResponse, HashAlgorithm, HashRequest, HashResult,
+};
+
+use crate::files;
+
+/// Create files router.
+pub fn router() -> Router {
+    Router::new()
+        .route("/files", get(download).put(upload))
+        .route("/files/hash", post(hash))
+}
+
+/// Response for a rejected request.
+pub(super) fn error(status: StatusCode, code: ErrorCode, message: impl Into<String>) -> Response {
+    (status, Json(ApiError::new(code, message))).into_response()
+}
+
+/// Response for a failed filesystem operation on `path`.
+pub(super) fn fs_error(e: std::io::Error, path: &Path) -> Response {
+    let message = format!("{}: {}", path.display(), e);
+    match e.kind() {
+        std::io::ErrorKind::NotFound => error(StatusCode::NOT_FOUND, ErrorCode::NotFound, message),
+        std::io::ErrorKind::AlreadyExists | std::io::ErrorKind::DirectoryNotEmpty => {
+            error(StatusCode::CONFLICT, ErrorCode::Conflict, message)
+        }
+        std::io::ErrorKind::PermissionDenied => {
+            error(StatusCode::FORBIDDEN, ErrorCode::Forbidden, message)
+        }
+        _ => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::Internal,
+            message,
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+    path: String,
+}
+
+/// Byte range requested by a `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum ByteRange {
+    /// No usable range; send the whole file.
+    Full,
+    /// First and last byte, inclusive.
+    Partial(u64, u64),
+    /// The range lies outside the file.
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a file of `size` bytes.
+///
+/// Only single ranges are supported; anything else is answered with the
+/// whole file, as HTTP allows.
+pub(super) fn parse_range(value: &str, size: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    let Some((first, last)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    if first.trim().is_empty() {
+        // Suffix range: the last N bytes
+        return match last.trim().parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if size == 0 => ByteRange::Unsatisfiable,
+            Ok(n) => ByteRange::Partial(size.saturating_sub(n), size - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let Ok(start) = first.trim().parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    if start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    let end = if last.trim().is_empty() {
+        size - 1
+    } else {
+        match last.trim().parse::<u64>() {
+            Ok(end) if end >= start => end.min(size - 1),
+            _ => return ByteRange::Full,
+        }
+    };
+    ByteRange::Partial(start, end)
+}
+
+/// GET /api/v1/files?path=
+///
+/// Streams a file, honoring a single-range `Range` header.
+async fn download(Query(query): Query<DownloadQuery>, headers: HeaderMap) -> Response {
+    let path = match files::resolve(&query.path) {
+        Ok(path) => path,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => return fs_error(e, &path),
+    };
+    let metadata = match file.metadata().await {
+        Ok(metadata) => metadata,
+        Err(e) => return fs_error(e, &path),
+    };
+    if metadata.is_dir() {
+        return error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BadRequest,
+            format!("{}: Path is a directory", path.display()),
+        );
+    }
+    let size = metadata.len();
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(ByteRange::Full, |v| parse_range(v, size));
+    let (status, start, len) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial(start, end) => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        ByteRange::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+                Json(ApiError::new(
+                    ErrorCode::BadRequest,
+                    format!("Range not satisfiable for {} bytes", size),
+                )),
+            )
+                .into_response();
+        }
+    };
+    if start > 0
+        && let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await
+    {
+        return fs_error(e, &path);
+    }
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_LENGTH, len)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, start + len - 1, size),
+        );
+    }
+    if let Ok(modified) = metadata.modified() {
+        let modified = chrono::DateTime::<chrono::Utc>::from(modified);
+        response = response.header(
+            header::LAST_MODIFIED,
+            modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        );
+    }
+    response
+        .body(Body::from_stream(ReaderStream::new(file.take(len))))
+        .unwrap_or_else(|e| {
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                e.to_string(),
+            )
+        })
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    path: String,
+    /// Replace an existing file instead of failing.
+    #[serde(default)]
+    overwrite: bool,
+    /// Permission bits in octal (e.g. `755`); ignored on Windows.
+    mode: Option<String>,
+    /// Modification time to give the file, in seconds since the Unix epoch.
+    mtime: Option<u64>,
+}
+
+/// PUT /api/v1/files?path=
+///
+/// Streams the body into a temporary sibling file, then renames it into
+/// place, creating missing parent directories.
+async fn upload(Query(query): Query<UploadQuery>, body: Body) -> Response {
+    let path = match files::resolve(&query.path) {
+        Ok(path) => path,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    let mode = match query.mode.as_deref().map(|m| u32::from_str_radix(m, 8)) {
+        None => None,
+        Some(Ok(mode)) if mode <= 0o7777 => Some(mode),
+        Some(_) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                "mode must be octal permission bits, e.g. 644",
+            );
+        }
+    };
+
+    let existed = match tokio::fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_dir() => {
+            return error(
+                StatusCode::CONFLICT,
+                ErrorCode::Conflict,
+                format!("{}: Path is a directory", path.display()),
+            );
+        }
+        Ok(_) if !query.overwrite => {
+            return error(
+                StatusCode::CONFLICT,
+                ErrorCode::Conflict,
+                format!("{}: File already exists", path.display()),
+            );
+        }
+        Ok(_) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return fs_error(e, &path),
+    };
+
+    if let Some(parent) = path.parent()
+        && let Err(e) = tokio::fs::create_dir_all(parent).await
+    {
+        return fs_error(e, parent);
+    }
+
+    let temp = files::temp_path(&path);
+    let size = match write_body(&temp, body, mode).await {
+        Ok(size) => size,
+        Err(response) => {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return response;
+        }
+    };
+    if let Some(mtime) = query.mtime
+        && let Err(e) = files::set_mtime(&temp, mtime)
+    {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return fs_error(e, &path);
+    }
+    if let Err(e) = tokio::fs::rename(&temp, &path).await {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return fs_error(e, &path);
+    }
+
+    let status = if existed {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    let response = FileUploadResponse {
+        path: path.display().to_string(),
+        size,
+        created: !existed,
+    };
+    (status, Json(response)).into_response()
+}
+
+/// Write a request body to `path`, returning the number of bytes written.
+async fn write_body(path: &Path, body: Body, mode: Option<u32>) -> Result<u64, Response> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| fs_error(e, path))?;
+
+    let mut size = 0u64;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            error(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                format!("Upload interrupted: {}", e),
+            )
+        })?;
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| fs_error(e, path))?;
+        size += chunk.len() as u64;
+    }
+    file.sync_all().await.map_err(|e| fs_error(e, path))?;
+
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .await
+            .map_err(|e| fs_error(e, path))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    Ok(size)
+}
+
+/// POST /api/v1/files/hash
+///
+/// Streams one JSON line per file as it is hashed. Files that cannot be
+/// read are reported in their line rather than failing the request.
+async fn hash(Json(req): Json<HashRequest>) -> Response {
+    if req.paths.is_empty() && req.pattern.is_none() {
+        return error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BadRequest,
+            "Either paths or pattern is required",
+        );
+    }
+    if req.algorithms.is_empty() {
+        return error(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BadRequest,
+            "At least one algorithm is required",
+        );
+    }
+    let matches = match req.pattern.as_deref() {
+        None => None,
+        Some(pattern) if !Path::new(pattern).is_absolute() => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                format!("Pattern must be absolute: {}", pattern),
+            );
+        }
+        Some(pattern) => match glob::glob(pattern) {
+            Ok(paths) => Some(paths),
+            Err(e) => {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    ErrorCode::BadRequest,
+                    format!("Invalid pattern: {}", e),
+                );
+            }
+        },
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Vec<u8>, std::io::Error>>(16);
+    tokio::task::spawn_blocking(move || {
+        let send = |result: HashResult| {
+            let mut line = serde_json::to_vec(&result).unwrap_or_default();
+            line.push(b'\n');
+            tx.blocking_send(Ok(line)).is_ok()
+        };
+
+        for path in &req.paths {
+            let result = match files::resolve(path) {
+                Ok(resolved) => hash_result(path.clone(), &resolved, &req.algorithms),
+                Err(e) => HashResult {
+                    path: path.clone(),
+                    size: None,
+                    hashes: Default::default(),
+                    error: Some(e),
+                },
+            };
+            if !send(result) {
+                return;
+            }
+        }
+        // Only files are hashed among pattern matches
+        for path in matches.into_iter().flatten().flatten() {
+            if path.is_file()
+                && !send(hash_result(
+                    path.display().to_string(),
+                    &path,
+                    &req.algorithms,
+                ))
+            {
+                return;
+            }
+        }
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+fn hash_result(name: String, path: &Path, algorithms: &[HashAlgorithm]) -> HashResult {
+    match files::hash::hash_file(path, algorithms) {
+        Ok((size, hashes)) => HashResult {
+            path: name,
+            size: Some(size),
+            hashes,
+            error: None,
+        },
+        Err(e) => HashResult {
+            path: name,
+            size: None,
+            hashes: Default::default(),
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range(" bytes=5-5 ", 1000), ByteRange::Partial(5, 5));
+        // The end is clamped to the last byte
+        assert_eq!(
+            parse_range("bytes=900-5000", 1000),
+            ByteRange::Partial(900, 999)
+        );
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(
+            parse_range("bytes=100-", 1000),
+            ByteRange::Partial(100, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=999-", 1000),
+            ByteRange::Partial(999, 999)
+        );
+        assert_eq!(parse_range("bytes=0-", 1), ByteRange::Partial(0, 0));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(
+            parse_range("bytes=-500", 1000),
+            ByteRange::Partial(500, 999)
+        );
+        assert_eq!(parse_range("bytes=-1", 1000), ByteRange::Partial(999, 999));
+        // A suffix longer than the file selects all of it
+        assert_eq!(parse_range("bytes=-5000", 1000), ByteRange::Partial(0, 999));
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(
+            parse_range("bytes=1000-1999", 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range("bytes=5000-6000", 1000),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn zero_size_file() {
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-0", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-1", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn unsupported_ranges_send_the_whole_file() {
+        // Multiple ranges
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=-1,0-", 1000), ByteRange::Full);
+        // Other units and malformed values
+        assert_eq!(parse_range("items=0-1", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=5", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=5-2", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=-x", 1000), ByteRange::Full);
+    }
+
+    async fn get(path: &std::path::Path, range: &str) -> Response {
+        let encoded: String = path
+            .to_string_lossy()
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'_' | b'-' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect();
+        let request = Request::get(format!("/files?path={}", encoded))
+            .header(header::RANGE, range)
+            .body(Body::empty())
+            .unwrap();
+        router().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn download_answers_ranges() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"0123456789").unwrap();
+
+        let response = get(file.path(), "bytes=-3").await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 7-9/10");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"789");
+
+        let response = get(file.path(), "bytes=10-").await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+
+        let response = get(file.path(), "bytes=0-1,4-5").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+    }
+}
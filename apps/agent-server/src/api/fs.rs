@@ -0,0 +1,274 @@
+//! Filesystem API endpoints for browsing and rearranging host files.
+
+use axum::{
+    Json, Router,
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use winpe_agent_core::{DeleteRequest, ErrorCode, HashAlgorithm, MkdirRequest, RenameRequest};
+
+use super::files::{error, fs_error};
+use crate::files::{self, browse};
+
+/// Default depth for recursive listings.
+const DEFAULT_LIST_DEPTH: u32 = 16;
+
+/// Default cap on entries returned by a listing or glob search.
+const DEFAULT_ENTRY_LIMIT: usize = 10_000;
+
+/// Create filesystem router.
+pub fn router() -> Router {
+    Router::new()
+        .route("/fs/list", get(list))
+        .route("/fs/stat", get(stat))
+        .route("/fs/glob", get(glob))
+        .route("/fs/manifest", get(manifest))
+        .route("/fs/mkdir", post(mkdir))
+        .route("/fs/rename", post(rename))
+        .route("/fs/delete", post(delete))
+}
+
+/// Run a blocking filesystem call off the async runtime.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, Response> {
+    tokio::task::spawn_blocking(f).await.map_err(|e| {
+        error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::Internal,
+            e.to_string(),
+        )
+    })
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    path: String,
+    /// Descend into subdirectories.
+    #[serde(default)]
+    recursive: bool,
+    /// Levels to descend when recursive (1 lists only direct children).
+    depth: Option<u32>,
+    /// Maximum number of entries.
+    limit: Option<usize>,
+}
+
+/// GET /api/v1/fs/list?path=
+async fn list(Query(query): Query<ListQuery>) -> Response {
+    let path = match files::resolve(&query.path) {
+        Ok(path) => path,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    let depth = if query.recursive {
+        query.depth.unwrap_or(DEFAULT_LIST_DEPTH).max(1)
+    } else {
+        1
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_ENTRY_LIMIT);
+
+    let listed = {
+        let path = path.clone();
+        blocking(move || browse::list(&path, depth, limit)).await
+    };
+    match listed {
+        Ok(Ok(listing)) => Json(listing).into_response(),
+        Ok(Err(e)) => fs_error(e, &path),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct StatQuery {
+    path: String,
+}
+
+/// GET /api/v1/fs/stat?path=
+async fn stat(Query(query): Query<StatQuery>) -> Response {
+    let path = match files::resolve(&query.path) {
+        Ok(path) => path,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    stat_response(path, StatusCode::OK).await
+}
+
+/// Respond with the metadata of `path`.
+async fn stat_response(path: PathBuf, status: StatusCode) -> Response {
+    let stated = {
+        let path = path.clone();
+        blocking(move || browse::stat(&path)).await
+    };
+    match stated {
+        Ok(Ok(entry)) => (status, Json(entry)).into_response(),
+        Ok(Err(e)) => fs_error(e, &path),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct GlobQuery {
+    /// Absolute pattern, e.g. `X:\Windows\Panther\*.log`.
+    pattern: String,
+    /// Maximum number of matches.
+    limit: Option<usize>,
+}
+
+/// GET /api/v1/fs/glob?pattern=
+async fn glob(Query(query): Query<GlobQuery>) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_ENTRY_LIMIT);
+    match blocking(move || browse::glob(&query.pattern, limit)).await {
+        Ok(Ok(matches)) => Json(matches).into_response(),
+        Ok(Err(e)) => error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct ManifestQuery {
+    path: String,
+    /// Algorithm to hash each file with; omitted means no hashes.
+    hash: Option<HashAlgorithm>,
+}
+
+/// GET /api/v1/fs/manifest?path=
+async fn manifest(Query(query): Query<ManifestQuery>) -> Response {
+    let path = match files::resolve(&query.path) {
+        Ok(path) => path,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    match tokio::fs::metadata(&path).await {
+        Ok(metadata) if !metadata.is_dir() => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                format!("{}: Not a directory", path.display()),
+            );
+        }
+        Ok(_) => {}
+        Err(e) => return fs_error(e, &path),
+    }
+
+    let walked = {
+        let path = path.clone();
+        blocking(move || browse::manifest(&path, query.hash)).await
+    };
+    match walked {
+        Ok(Ok(manifest)) => Json(manifest).into_response(),
+        Ok(Err(e)) => fs_error(e, &path),
+        Err(response) => response,
+    }
+}
+
+/// POST /api/v1/fs/mkdir
+async fn mkdir(Json(req): Json<MkdirRequest>) -> Response {
+    let path = match files::resolve(&req.path) {
+        Ok(path) => path,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    let result = if req.parents {
+        tokio::fs::create_dir_all(&path).await
+    } else {
+        tokio::fs::create_dir(&path).await
+    };
+    if let Err(e) = result {
+        return fs_error(e, &path);
+    }
+    stat_response(path, StatusCode::CREATED).await
+}
+
+/// POST /api/v1/fs/rename
+///
+/// Moves a file or directory. Files are copied when the destination is on
+/// another volume; directories cannot cross volumes.
+async fn rename(Json(req): Json<RenameRequest>) -> Response {
+    let (from, to) = match (files::resolve(&req.from), files::resolve(&req.to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => {
+            return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e);
+        }
+    };
+
+    let source = match tokio::fs::symlink_metadata(&from).await {
+        Ok(metadata) => metadata,
+        Err(e) => return fs_error(e, &from),
+    };
+    match tokio::fs::symlink_metadata(&to).await {
+        Ok(metadata) if metadata.is_dir() => {
+            return error(
+                StatusCode::CONFLICT,
+                ErrorCode::Conflict,
+                format!("{}: Path is a directory", to.display()),
+            );
+        }
+        Ok(_) if !req.overwrite => {
+            return error(
+                StatusCode::CONFLICT,
+                ErrorCode::Conflict,
+                format!("{}: File already exists", to.display()),
+            );
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return fs_error(e, &to),
+    }
+
+    match tokio::fs::rename(&from, &to).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices && source.is_file() => {
+            if let Err(response) = move_across_volumes(&from, &to).await {
+                return response;
+            }
+        }
+        Err(e) => return fs_error(e, &from),
+    }
+    stat_response(to, StatusCode::OK).await
+}
+
+/// Copy a file to another volume through a temporary file, then delete it.
+async fn move_across_volumes(from: &Path, to: &Path) -> Result<(), Response> {
+    let temp = files::temp_path(to);
+    if let Err(e) = tokio::fs::copy(from, &temp).await {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(fs_error(e, to));
+    }
+    if let Err(e) = tokio::fs::rename(&temp, to).await {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(fs_error(e, to));
+    }
+    tokio::fs::remove_file(from)
+        .await
+        .map_err(|e| fs_error(e, from))
+}
+
+/// POST /api/v1/fs/delete
+///
+/// Deleting a non-empty directory requires `recursive`. Symlinks are
+/// removed without touching their targets.
+async fn delete(Json(req): Json<DeleteRequest>) -> Response {
+    let path = match files::resolve(&req.path) {
+        Ok(path) => path,
+        Err(e) => return error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, e),
+    };
+    let metadata = match tokio::fs::symlink_metadata(&path).await {
+        Ok(metadata) => metadata,
+        Err(e) => return fs_error(e, &path),
+    };
+
+    let file_type = metadata.file_type();
+    // Directory symlinks on Windows are removed like directories
+    let dir_link = cfg!(windows) && file_type.is_symlink() && path.is_dir();
+    let result = if file_type.is_dir() && req.recursive {
+        tokio::fs::remove_dir_all(&path).await
+    } else if file_type.is_dir() || dir_link {
+        tokio::fs::remove_dir(&path).await
+    } else {
+        tokio::fs::remove_file(&path).await
+    };
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => fs_error(e, &path),
+    }
+}
@@ -19,7 +19,8 @@ async fn health_handler() -> Json<HealthResponse> {
         capabilities: Capabilities {
             conpty: conpty_available,
             automation: true,
-            terminal: conpty_available,
+            // Unix hosts always provide a pty backend
+            terminal: conpty_available || cfg!(unix),
         },
     })
 }
@@ -0,0 +1,132 @@
+//! Jobs API endpoints for background command execution.
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+};
+use serde::Deserialize;
+use winpe_agent_core::{ApiError, ErrorCode, ExecRequest, OutputStream};
+
+use crate::automation::executor::ExecError;
+use crate::automation::jobs::JobManager;
+
+/// Default and maximum size of one output page.
+const MAX_OUTPUT_PAGE: usize = 1024 * 1024;
+
+/// Create jobs router.
+pub fn router(job_manager: JobManager) -> Router {
+    Router::new()
+        .route("/jobs", post(create_job).get(list_jobs))
+        .route("/jobs/{id}", get(get_job).delete(delete_job))
+        .route("/jobs/{id}/output", get(get_output))
+        .with_state(job_manager)
+}
+
+/// POST /api/v1/jobs
+async fn create_job(
+    State(manager): State<JobManager>,
+    Json(req): Json<ExecRequest>,
+) -> impl IntoResponse {
+    match manager.create_job(req).await {
+        Ok(resp) => (StatusCode::CREATED, Json(resp)).into_response(),
+        Err(ExecError::NotSupported(msg)) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::new(ErrorCode::NotSupported, msg)),
+        )
+            .into_response(),
+        Err(ExecError::InvalidRequest(msg)) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::new(ErrorCode::BadRequest, msg)),
+        )
+            .into_response(),
+        Err(ExecError::Busy(msg)) => (
+            StatusCode::CONFLICT,
+            Json(ApiError::new(ErrorCode::Conflict, msg)),
+        )
+            .into_response(),
+        Err(ExecError::ProcessCreationFailed(msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::new(ErrorCode::Internal, msg)),
+        )
+            .into_response(),
+        // Jobs report timeouts through their state, never at creation
+        Err(ExecError::Timeout) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::new(ErrorCode::Internal, "Unexpected timeout")),
+        )
+            .into_response(),
+    }
+}
+
+/// GET /api/v1/jobs
+async fn list_jobs(State(manager): State<JobManager>) -> impl IntoResponse {
+    Json(manager.list_jobs())
+}
+
+/// GET /api/v1/jobs/{id}
+async fn get_job(State(manager): State<JobManager>, Path(id): Path<String>) -> impl IntoResponse {
+    match manager.get_job(&id) {
+        Some(info) => (StatusCode::OK, Json(info)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Job not found")),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct OutputQuery {
+    #[serde(default)]
+    offset: u64,
+    #[serde(default)]
+    stream: OutputStream,
+    /// Maximum bytes to return (capped at 1 MiB); at least one character
+    /// is returned when any output is available.
+    limit: Option<usize>,
+}
+
+/// GET /api/v1/jobs/{id}/output
+async fn get_output(
+    State(manager): State<JobManager>,
+    Path(id): Path<String>,
+    Query(query): Query<OutputQuery>,
+) -> impl IntoResponse {
+    if query.limit == Some(0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::new(
+                ErrorCode::BadRequest,
+                "limit must be at least 1",
+            )),
+        )
+            .into_response();
+    }
+    let limit = query.limit.unwrap_or(MAX_OUTPUT_PAGE).min(MAX_OUTPUT_PAGE);
+    match manager.read_output(&id, query.stream, query.offset, limit) {
+        Some(output) => (StatusCode::OK, Json(output)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Job not found")),
+        )
+            .into_response(),
+    }
+}
+
+/// DELETE /api/v1/jobs/{id}
+async fn delete_job(
+    State(manager): State<JobManager>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match manager.delete_job(&id) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, e)),
+        )
+            .into_response(),
+    }
+}
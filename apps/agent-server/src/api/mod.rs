@@ -1,16 +1,45 @@
 //! API route handlers.
 
+mod archive;
+mod auth;
 mod automation;
+mod block;
+mod files;
+mod fs;
 mod health;
+mod jobs;
+mod system;
 mod terminal;
+mod uploads;
 
+use crate::auth::{AuthState, require_auth};
+use crate::automation::jobs::JobManager;
+use crate::config::ServerConfig;
+use crate::files::uploads::UploadManager;
 use crate::terminal::SessionManager;
-use axum::Router;
+use axum::{Router, middleware};
 
 /// Create the API router with all endpoints.
-pub fn router(session_manager: SessionManager) -> Router {
+///
+/// Every route is guarded by bearer-token authentication.
+pub fn router(
+    session_manager: SessionManager,
+    job_manager: JobManager,
+    upload_manager: UploadManager,
+    auth: AuthState,
+    config: &ServerConfig,
+) -> Router {
     Router::new()
         .merge(health::router())
-        .merge(automation::router())
+        .merge(auth::router(auth.clone(), session_manager.clone()))
+        .merge(automation::router(config))
+        .merge(jobs::router(job_manager))
+        .merge(files::router())
+        .merge(uploads::router(upload_manager))
+        .merge(fs::router())
+        .merge(archive::router(config))
+        .merge(block::router())
+        .merge(system::router())
         .merge(terminal::router(session_manager))
+        .layer(middleware::from_fn_with_state(auth, require_auth))
 }
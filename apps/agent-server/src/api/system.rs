@@ -0,0 +1,45 @@
+//! System inventory endpoints for disks and volumes.
+
+use axum::{
+    Json, Router,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use winpe_agent_core::{DiskList, ErrorCode, VolumeList};
+
+use super::files::error;
+use crate::system;
+
+/// Create system inventory router.
+pub fn router() -> Router {
+    Router::new()
+        .route("/system/disks", get(disks))
+        .route("/system/volumes", get(volumes))
+}
+
+/// GET /api/v1/system/disks
+async fn disks() -> Response {
+    match tokio::task::spawn_blocking(system::disks).await {
+        Ok(Ok(disks)) => Json(DiskList { disks }).into_response(),
+        Ok(Err(e)) => inventory_error(e.to_string()),
+        Err(e) => inventory_error(e.to_string()),
+    }
+}
+
+/// GET /api/v1/system/volumes
+async fn volumes() -> Response {
+    match tokio::task::spawn_blocking(system::volumes).await {
+        Ok(Ok(volumes)) => Json(VolumeList { volumes }).into_response(),
+        Ok(Err(e)) => inventory_error(e.to_string()),
+        Err(e) => inventory_error(e.to_string()),
+    }
+}
+
+fn inventory_error(message: String) -> Response {
+    error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorCode::Internal,
+        message,
+    )
+}
@@ -1,25 +1,35 @@
-//! Terminal API endpoints for ConPTY sessions.
+//! Terminal API endpoints for pseudo terminal sessions.
 
 use axum::{
     Json, Router,
-    extract::{Path, State, WebSocketUpgrade},
-    http::StatusCode,
+    body::Body,
+    extract::{Path, Query, State, WebSocketUpgrade},
+    http::{StatusCode, header},
     response::IntoResponse,
     routing::{delete, get, post},
 };
-use winpe_agent_core::{ApiError, ErrorCode, SessionCreateRequest, SignalRequest};
+use serde::Deserialize;
+use tokio_util::io::ReaderStream;
+use winpe_agent_core::{
+    ApiError, AttachMode, ErrorCode, SessionCreateRequest, SessionExpectRequest,
+    SessionInputRequest, SessionInputResponse, SignalRequest,
+};
 
-use crate::terminal::SessionManager;
+use crate::terminal::{ExpectError, SessionManager};
 
 /// Create terminal router.
 pub fn router(session_manager: SessionManager) -> Router {
     Router::new()
         .route("/sessions", post(create_session))
         .route("/sessions", get(list_sessions))
-        .route("/sessions/:id", get(get_session))
-        .route("/sessions/:id", delete(delete_session))
-        .route("/sessions/:id/signal", post(send_signal))
-        .route("/sessions/:id/ws", get(websocket_handler))
+        .route("/sessions/{id}", get(get_session))
+        .route("/sessions/{id}", delete(delete_session))
+        .route("/sessions/{id}/signal", post(send_signal))
+        .route("/sessions/{id}/input", post(send_input))
+        .route("/sessions/{id}/expect", post(expect_output))
+        .route("/sessions/{id}/recording", get(get_recording))
+        .route("/sessions/{id}/screen", get(get_screen))
+        .route("/sessions/{id}/ws", get(websocket_handler))
         .with_state(session_manager)
 }
 
@@ -32,8 +42,11 @@ async fn create_session(
     match manager.create_session(req).await {
         Ok(resp) => (StatusCode::CREATED, Json(resp)).into_response(),
         Err(e) => {
-            // Check if error is due to ConPTY unavailability
-            if e.contains("ConPTY") || e.contains("CreatePseudoConsole") {
+            // Check if error is due to ConPTY or shell unavailability
+            if e.contains("ConPTY")
+                || e.contains("CreatePseudoConsole")
+                || e.contains("not supported")
+            {
                 (
                     StatusCode::NOT_IMPLEMENTED,
                     Json(ApiError::new(ErrorCode::NotSupported, e)),
@@ -101,10 +114,201 @@ async fn send_signal(
     }
 }
 
+/// POST /api/v1/sessions/{id}/input
+async fn send_input(
+    State(manager): State<SessionManager>,
+    Path(id): Path<String>,
+    Json(req): Json<SessionInputRequest>,
+) -> impl IntoResponse {
+    if !manager.session_exists(&id) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Session not found")),
+        )
+            .into_response();
+    }
+
+    match manager.send_input(&id, req.data.into_bytes()).await {
+        Ok(offset) => Json(SessionInputResponse { offset }).into_response(),
+        Err(e) => (
+            StatusCode::CONFLICT,
+            Json(ApiError::new(ErrorCode::Conflict, e)),
+        )
+            .into_response(),
+    }
+}
+
+/// POST /api/v1/sessions/{id}/expect
+///
+/// Waits until the pattern matches the session output. The output searched
+/// so far is returned in the error details on timeout or exit.
+async fn expect_output(
+    State(manager): State<SessionManager>,
+    Path(id): Path<String>,
+    Json(req): Json<SessionExpectRequest>,
+) -> impl IntoResponse {
+    let pattern = match regex::bytes::Regex::new(&req.pattern) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::new(
+                    ErrorCode::BadRequest,
+                    format!("Invalid pattern: {}", e),
+                )),
+            )
+                .into_response();
+        }
+    };
+
+    let timeout = std::time::Duration::from_millis(req.timeout_ms);
+    match manager.expect(&id, &pattern, req.since, timeout).await {
+        Ok(found) => Json(found).into_response(),
+        Err(ExpectError::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Session not found")),
+        )
+            .into_response(),
+        Err(ExpectError::Timeout { output, offset }) => {
+            let mut details = std::collections::HashMap::new();
+            details.insert("output".to_string(), serde_json::Value::String(output));
+            details.insert("offset".to_string(), offset.into());
+            details.insert("timeout_ms".to_string(), req.timeout_ms.into());
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                Json(ApiError::with_details(
+                    ErrorCode::Timeout,
+                    "Pattern not matched before timeout",
+                    details,
+                )),
+            )
+                .into_response()
+        }
+        Err(ExpectError::Exited {
+            output,
+            offset,
+            exit_code,
+        }) => {
+            let mut details = std::collections::HashMap::new();
+            details.insert("output".to_string(), serde_json::Value::String(output));
+            details.insert("offset".to_string(), offset.into());
+            details.insert("exit_code".to_string(), exit_code.into());
+            (
+                StatusCode::CONFLICT,
+                Json(ApiError::with_details(
+                    ErrorCode::Conflict,
+                    "Session exited before the pattern matched",
+                    details,
+                )),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// GET /api/v1/sessions/{id}/recording
+///
+/// Streams the asciicast file; available after the session has exited or
+/// been deleted.
+async fn get_recording(
+    State(manager): State<SessionManager>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Recording not found")),
+        )
+            .into_response()
+    };
+
+    let Some(path) = manager.recording_path(&id).await else {
+        return not_found();
+    };
+    match tokio::fs::File::open(&path).await {
+        Ok(file) => (
+            [
+                (header::CONTENT_TYPE, "application/x-asciicast".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}.cast\"", id),
+                ),
+            ],
+            Body::from_stream(ReaderStream::new(file)),
+        )
+            .into_response(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => not_found(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::new(
+                ErrorCode::Internal,
+                format!("Failed to open recording: {}", e),
+            )),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ScreenFormat {
+    #[default]
+    Json,
+    Text,
+}
+
+#[derive(Deserialize)]
+struct ScreenQuery {
+    /// `text` returns just the rows as `text/plain`.
+    #[serde(default)]
+    format: ScreenFormat,
+    /// Include the cursor position.
+    #[serde(default)]
+    cursor: bool,
+    /// Include styled text runs.
+    #[serde(default)]
+    attrs: bool,
+}
+
+/// GET /api/v1/sessions/{id}/screen
+async fn get_screen(
+    State(manager): State<SessionManager>,
+    Path(id): Path<String>,
+    Query(query): Query<ScreenQuery>,
+) -> impl IntoResponse {
+    let Some(snapshot) = manager
+        .screen_snapshot(&id, query.cursor, query.attrs)
+        .await
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(ErrorCode::NotFound, "Session not found")),
+        )
+            .into_response();
+    };
+
+    if query.format == ScreenFormat::Text {
+        let mut text = snapshot.lines.join("\n");
+        text.push('\n');
+        return ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response();
+    }
+    Json(snapshot).into_response()
+}
+
+#[derive(Deserialize)]
+struct AttachQuery {
+    #[serde(default)]
+    mode: AttachMode,
+    /// Replace the current controlling client instead of being rejected.
+    #[serde(default)]
+    takeover: bool,
+}
+
 /// GET /api/v1/sessions/{id}/ws
 async fn websocket_handler(
     State(manager): State<SessionManager>,
     Path(id): Path<String>,
+    Query(query): Query<AttachQuery>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
     // Check if session exists
@@ -116,6 +320,8 @@ async fn websocket_handler(
             .into_response();
     }
 
-    ws.on_upgrade(move |socket| crate::terminal::ws::handle_websocket(socket, manager, id))
-        .into_response()
+    ws.on_upgrade(move |socket| {
+        crate::terminal::ws::handle_websocket(socket, manager, id, query.mode, query.takeover)
+    })
+    .into_response()
 }
@@ -0,0 +1,150 @@
+//! Uploads API endpoints for resumable, verified file uploads.
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use winpe_agent_core::{ApiError, ErrorCode, UploadCreateRequest, UploadFinalizeRequest};
+
+use super::files::{error, fs_error};
+use crate::files::uploads::{UploadError, UploadManager};
+
+/// Create uploads router.
+pub fn router(upload_manager: UploadManager) -> Router {
+    Router::new()
+        .route("/uploads", post(create_upload).get(list_uploads))
+        .route(
+            "/uploads/{id}",
+            get(get_upload).put(put_chunk).delete(delete_upload),
+        )
+        .route("/uploads/{id}/finalize", post(finalize_upload))
+        .with_state(upload_manager)
+}
+
+fn upload_error(e: UploadError) -> Response {
+    match e {
+        UploadError::NotFound => error(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound,
+            "Upload not found",
+        ),
+        UploadError::BadRequest(msg) => error(StatusCode::BAD_REQUEST, ErrorCode::BadRequest, msg),
+        UploadError::Conflict(msg) => error(StatusCode::CONFLICT, ErrorCode::Conflict, msg),
+        UploadError::Mismatch { expected, actual } => {
+            let details = HashMap::from([
+                ("expected".to_string(), json!(expected)),
+                ("actual".to_string(), json!(actual)),
+            ]);
+            (
+                StatusCode::CONFLICT,
+                Json(ApiError::with_details(
+                    ErrorCode::Conflict,
+                    "SHA-256 mismatch; the upload must be sent again",
+                    details,
+                )),
+            )
+                .into_response()
+        }
+        UploadError::Io(e, path) => fs_error(e, &path),
+    }
+}
+
+/// POST /api/v1/uploads
+async fn create_upload(
+    State(manager): State<UploadManager>,
+    Json(req): Json<UploadCreateRequest>,
+) -> Response {
+    match manager.create(req).await {
+        Ok(info) => (StatusCode::CREATED, Json(info)).into_response(),
+        Err(e) => upload_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    /// Only uploads to this destination.
+    path: Option<String>,
+}
+
+/// GET /api/v1/uploads
+async fn list_uploads(
+    State(manager): State<UploadManager>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    Json(manager.list(query.path.as_deref())).into_response()
+}
+
+/// GET /api/v1/uploads/{id}
+async fn get_upload(State(manager): State<UploadManager>, Path(id): Path<String>) -> Response {
+    match manager.get(&id) {
+        Some(info) => Json(info).into_response(),
+        None => upload_error(UploadError::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+struct ChunkQuery {
+    offset: u64,
+}
+
+/// PUT /api/v1/uploads/{id}?offset=
+async fn put_chunk(
+    State(manager): State<UploadManager>,
+    Path(id): Path<String>,
+    Query(query): Query<ChunkQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    // Reject an oversized chunk before reading it
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let (Some(length), Some(info)) = (length, manager.get(&id))
+        && query.offset.saturating_add(length) > info.size
+    {
+        return upload_error(UploadError::BadRequest(format!(
+            "Chunk extends past the end of the file ({} bytes)",
+            info.size
+        )));
+    }
+
+    match manager.write_chunk(&id, query.offset, body).await {
+        Ok(info) => Json(info).into_response(),
+        Err(e) => upload_error(e),
+    }
+}
+
+/// POST /api/v1/uploads/{id}/finalize
+async fn finalize_upload(
+    State(manager): State<UploadManager>,
+    Path(id): Path<String>,
+    Json(req): Json<UploadFinalizeRequest>,
+) -> Response {
+    match manager.finalize(&id, &req.sha256).await {
+        Ok(response) => {
+            let status = if response.created {
+                StatusCode::CREATED
+            } else {
+                StatusCode::OK
+            };
+            (status, Json(response)).into_response()
+        }
+        Err(e) => upload_error(e),
+    }
+}
+
+/// DELETE /api/v1/uploads/{id}
+async fn delete_upload(State(manager): State<UploadManager>, Path(id): Path<String>) -> Response {
+    match manager.delete(&id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => upload_error(e),
+    }
+}
@@ -0,0 +1,303 @@
+//! Bearer-token authentication for the API.
+//!
+//! Every `/api/v1` request must carry `Authorization: Bearer <token>` with one
+//! of the configured tokens. Browsers cannot set headers on WebSocket upgrades,
+//! so `/sessions/{id}/ws` additionally accepts a short-lived, single-use
+//! `?ticket=` issued by `POST /api/v1/auth/ticket`.
+//!
+//! Authentication fails closed: with no configured token every request is
+//! rejected, and it is only turned off by the explicit `insecure` setting.
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use winpe_agent_core::{ApiError, ErrorCode};
+
+/// How long an issued WebSocket ticket stays valid.
+pub const TICKET_TTL: Duration = Duration::from_secs(30);
+
+/// An issued WebSocket ticket.
+struct Ticket {
+    session_id: String,
+    expires_at: Instant,
+}
+
+/// Shared authentication state.
+#[derive(Clone)]
+pub struct AuthState {
+    inner: Arc<AuthInner>,
+}
+
+struct AuthInner {
+    enabled: bool,
+    tokens: Vec<String>,
+    tickets: DashMap<String, Ticket>,
+}
+
+impl AuthState {
+    /// Create auth state accepting the given tokens.
+    ///
+    /// With no tokens, every request is rejected.
+    pub fn new(tokens: Vec<String>) -> Self {
+        Self::build(true, tokens)
+    }
+
+    /// Create auth state that lets every request through.
+    pub fn insecure() -> Self {
+        Self::build(false, Vec::new())
+    }
+
+    fn build(enabled: bool, tokens: Vec<String>) -> Self {
+        Self {
+            inner: Arc::new(AuthInner {
+                enabled,
+                tokens,
+                tickets: DashMap::new(),
+            }),
+        }
+    }
+
+    /// Whether authentication is enforced.
+    pub fn enabled(&self) -> bool {
+        self.inner.enabled
+    }
+
+    /// Check a bearer token against the configured tokens.
+    fn validate_token(&self, token: &str) -> bool {
+        self.inner
+            .tokens
+            .iter()
+            .any(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+    }
+
+    /// Issue a single-use ticket for attaching to a session's WebSocket.
+    pub fn issue_ticket(&self, session_id: &str) -> String {
+        let now = Instant::now();
+        self.inner.tickets.retain(|_, t| t.expires_at > now);
+
+        let ticket = random_hex();
+        self.inner.tickets.insert(
+            ticket.clone(),
+            Ticket {
+                session_id: session_id.to_string(),
+                expires_at: now + TICKET_TTL,
+            },
+        );
+        ticket
+    }
+
+    /// Consume a ticket. Succeeds only once, before expiry, for the session it was issued for.
+    fn redeem_ticket(&self, ticket: &str, session_id: &str) -> bool {
+        match self.inner.tickets.remove(ticket) {
+            Some((_, t)) => t.session_id == session_id && t.expires_at > Instant::now(),
+            None => false,
+        }
+    }
+}
+
+/// Generate a random token for a server started without one.
+pub fn generate_token() -> String {
+    random_hex()
+}
+
+/// 24 random bytes as lowercase hex.
+fn random_hex() -> String {
+    rand::random::<[u8; 24]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Middleware rejecting requests without valid credentials.
+pub async fn require_auth(State(auth): State<AuthState>, req: Request, next: Next) -> Response {
+    if !auth.enabled() {
+        return next.run(req).await;
+    }
+
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    let authorized = match bearer {
+        Some(token) => auth.validate_token(token.trim()),
+        None => match (
+            ws_session_id(req.uri().path()),
+            query_ticket(req.uri().query()),
+        ) {
+            (Some(session_id), Some(ticket)) => auth.redeem_ticket(ticket, session_id),
+            _ => false,
+        },
+    };
+
+    if authorized {
+        next.run(req).await
+    } else {
+        unauthorized()
+    }
+}
+
+/// Build the 401 response.
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(ApiError::new(
+            ErrorCode::Unauthorized,
+            "Missing or invalid credentials",
+        )),
+    )
+        .into_response()
+}
+
+/// Extract the session ID if `path` is a session WebSocket route.
+fn ws_session_id(path: &str) -> Option<&str> {
+    let path = path.strip_prefix("/api/v1").unwrap_or(path);
+    let mut segments = path.trim_start_matches('/').split('/');
+    match (
+        segments.next(),
+        segments.next(),
+        segments.next(),
+        segments.next(),
+    ) {
+        (Some("sessions"), Some(id), Some("ws"), None) if !id.is_empty() => Some(id),
+        _ => None,
+    }
+}
+
+/// Extract the `ticket` query parameter.
+fn query_ticket(query: Option<&str>) -> Option<&str> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("ticket="))
+        .filter(|t| !t.is_empty())
+}
+
+/// Compare two byte strings without short-circuiting on the first mismatch.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, middleware, routing::get};
+    use tower::ServiceExt;
+
+    fn app(auth: AuthState) -> Router {
+        Router::new()
+            .route("/api/v1/health", get(|| async { "ok" }))
+            .route("/api/v1/sessions/{id}/ws", get(|| async { "ws" }))
+            .layer(middleware::from_fn_with_state(auth, require_auth))
+    }
+
+    async fn status(auth: &AuthState, uri: &str, bearer: Option<&str>) -> StatusCode {
+        let mut req = Request::builder().uri(uri);
+        if let Some(token) = bearer {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        app(auth.clone())
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn bearer_token_is_required() {
+        let auth = AuthState::new(vec!["secret".to_string()]);
+        assert_eq!(
+            status(&auth, "/api/v1/health", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&auth, "/api/v1/health", Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&auth, "/api/v1/health", Some("secre")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&auth, "/api/v1/health", Some("secret")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn no_tokens_rejects_everything() {
+        let auth = AuthState::new(Vec::new());
+        assert!(auth.enabled());
+        assert_eq!(
+            status(&auth, "/api/v1/health", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&auth, "/api/v1/health", Some("")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn insecure_lets_everything_through() {
+        let auth = AuthState::insecure();
+        assert_eq!(status(&auth, "/api/v1/health", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn ticket_is_single_use() {
+        let auth = AuthState::new(vec!["secret".to_string()]);
+        let ticket = auth.issue_ticket("s1");
+        let uri = format!("/api/v1/sessions/s1/ws?ticket={}", ticket);
+        assert_eq!(status(&auth, &uri, None).await, StatusCode::OK);
+        assert_eq!(status(&auth, &uri, None).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn ticket_is_bound_to_its_session_and_route() {
+        let auth = AuthState::new(vec!["secret".to_string()]);
+        let ticket = auth.issue_ticket("s1");
+        let other = format!("/api/v1/sessions/s2/ws?ticket={}", ticket);
+        assert_eq!(status(&auth, &other, None).await, StatusCode::UNAUTHORIZED);
+
+        // Tickets only open WebSocket routes
+        let ticket = auth.issue_ticket("s1");
+        let health = format!("/api/v1/health?ticket={}", ticket);
+        assert_eq!(status(&auth, &health, None).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn expired_ticket_is_rejected() {
+        let auth = AuthState::new(vec!["secret".to_string()]);
+        auth.inner.tickets.insert(
+            "stale".to_string(),
+            Ticket {
+                session_id: "s1".to_string(),
+                expires_at: Instant::now() - Duration::from_millis(1),
+            },
+        );
+        assert_eq!(
+            status(&auth, "/api/v1/sessions/s1/ws?ticket=stale", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert!(auth.inner.tickets.get("stale").is_none());
+    }
+
+    #[test]
+    fn generated_tokens_are_random() {
+        let a = generate_token();
+        assert_eq!(a.len(), 48);
+        assert_ne!(a, generate_token());
+    }
+}
@@ -0,0 +1,199 @@
+//! Bounded output capture.
+//!
+//! Keeps the first and last bytes of a stream within a fixed budget and
+//! only counts what falls in between, so commands with huge output cannot
+//! exhaust server memory.
+
+use std::collections::VecDeque;
+use std::io::Read;
+
+use super::encoding::{self, EncodingChoice, OutputEncoding};
+
+/// Output captured from one pipe.
+#[derive(Debug)]
+pub struct CapturedOutput {
+    /// Retained output; a marker line replaces the dropped middle.
+    pub text: String,
+    /// Total bytes read from the pipe.
+    pub total_bytes: u64,
+    /// Whether any bytes were dropped.
+    pub truncated: bool,
+    /// Encoding used to decode the output.
+    pub encoding: OutputEncoding,
+}
+
+/// Head/tail buffer with a fixed byte budget.
+pub struct OutputCapture {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    head_limit: usize,
+    tail_limit: usize,
+    total: u64,
+}
+
+impl OutputCapture {
+    /// Create a capture retaining at most `limit` bytes, split between head and tail.
+    pub fn new(limit: usize) -> Self {
+        let head_limit = limit / 2;
+        Self {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            head_limit,
+            tail_limit: limit - head_limit,
+            total: 0,
+        }
+    }
+
+    /// Append a chunk of output.
+    pub fn push(&mut self, data: &[u8]) {
+        self.total += data.len() as u64;
+
+        let room = self.head_limit - self.head.len();
+        let (head, rest) = data.split_at(room.min(data.len()));
+        self.head.extend_from_slice(head);
+
+        // Only the last tail_limit bytes of the rest can survive
+        let rest = &rest[rest.len().saturating_sub(self.tail_limit)..];
+        self.tail.extend(rest);
+        let excess = self.tail.len().saturating_sub(self.tail_limit);
+        self.tail.drain(..excess);
+    }
+
+    /// Finish capturing and decode the retained bytes.
+    ///
+    /// With [`EncodingChoice::Auto`] the encoding is detected from the head.
+    pub fn finish(self, choice: EncodingChoice) -> CapturedOutput {
+        let retained = (self.head.len() + self.tail.len()) as u64;
+        let truncated = self.total > retained;
+        let tail: Vec<u8> = self.tail.into_iter().collect();
+
+        let sample = if self.head.is_empty() {
+            &tail
+        } else {
+            &self.head
+        };
+        let encoding = choice.resolve(sample);
+
+        let text = if truncated {
+            // The tail starts at an arbitrary offset and may begin mid-character
+            let tail_offset = self.total - tail.len() as u64;
+            format!(
+                "{}\n... [{} bytes truncated] ...\n{}",
+                encoding::decode_all(&self.head, encoding),
+                self.total - retained,
+                encoding::decode_all(encoding.resync(&tail, tail_offset), encoding)
+            )
+        } else {
+            let mut all = self.head;
+            all.extend_from_slice(&tail);
+            encoding::decode_all(&all, encoding)
+        };
+
+        CapturedOutput {
+            text,
+            total_bytes: self.total,
+            truncated,
+            encoding,
+        }
+    }
+}
+
+/// Read `pipe` to EOF, retaining at most `limit` bytes, and decode the result.
+pub fn capture(mut pipe: impl Read, limit: usize, choice: EncodingChoice) -> CapturedOutput {
+    let mut output = OutputCapture::new(limit);
+    let mut buffer = [0u8; 8192];
+
+    loop {
+        match pipe.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => output.push(&buffer[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+
+    output.finish(choice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UTF8: EncodingChoice =
+        EncodingChoice::Fixed(OutputEncoding::Standard(encoding_rs::UTF_8));
+
+    fn capture_chunks(limit: usize, chunks: &[&[u8]]) -> CapturedOutput {
+        let mut output = OutputCapture::new(limit);
+        for chunk in chunks {
+            output.push(chunk);
+        }
+        output.finish(UTF8)
+    }
+
+    #[test]
+    fn keeps_output_exactly_at_the_cap() {
+        let output = capture_chunks(10, &[b"0123456789"]);
+        assert_eq!(output.text, "0123456789");
+        assert_eq!(output.total_bytes, 10);
+        assert!(!output.truncated);
+    }
+
+    #[test]
+    fn truncates_one_byte_over_the_cap() {
+        let output = capture_chunks(10, &[b"0123456789X"]);
+        assert_eq!(output.text, "01234\n... [1 bytes truncated] ...\n6789X");
+        assert_eq!(output.total_bytes, 11);
+        assert!(output.truncated);
+    }
+
+    #[test]
+    fn small_writes_crossing_the_boundary() {
+        let data: Vec<u8> = (0..100).map(|i| b'a' + (i % 26) as u8).collect();
+        let chunks: Vec<&[u8]> = data.chunks(3).collect();
+        let output = capture_chunks(10, &chunks);
+        let expected = format!(
+            "{}\n... [90 bytes truncated] ...\n{}",
+            std::str::from_utf8(&data[..5]).unwrap(),
+            std::str::from_utf8(&data[95..]).unwrap()
+        );
+        assert_eq!(output.text, expected);
+        assert_eq!(output.total_bytes, 100);
+        assert!(output.truncated);
+    }
+
+    #[test]
+    fn single_byte_writes_match_one_large_write() {
+        let data: Vec<u8> = (0..=255u8)
+            .cycle()
+            .take(1000)
+            .map(|b| b % 94 + b'!')
+            .collect();
+        let bytes: Vec<&[u8]> = data.chunks(1).collect();
+        let one = capture_chunks(64, &[&data]);
+        let many = capture_chunks(64, &bytes);
+        assert_eq!(one.text, many.text);
+        assert_eq!(many.total_bytes, 1000);
+        assert!(many.text.contains("... [936 bytes truncated] ..."));
+    }
+
+    #[test]
+    fn tail_starting_mid_character_is_resynchronized() {
+        // "é" is two bytes; the tail cuts it in half
+        let output = capture_chunks(6, &["abcdefgé12".as_bytes()]);
+        assert_eq!(output.text, "abc\n... [5 bytes truncated] ...\n12");
+        assert_eq!(output.total_bytes, 11);
+    }
+
+    #[test]
+    fn captures_from_a_reader() {
+        let data = vec![b'x'; 20_000];
+        let output = capture(&data[..], 100, UTF8);
+        assert_eq!(output.total_bytes, 20_000);
+        assert!(output.truncated);
+        assert!(output.text.contains("... [19900 bytes truncated] ..."));
+
+        let output = capture(&b""[..], 100, UTF8);
+        assert_eq!(output.text, "");
+        assert!(!output.truncated);
+    }
+}
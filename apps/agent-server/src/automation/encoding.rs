@@ -0,0 +1,458 @@
+//! Output decoding for the `encoding` field of exec requests.
+//!
+//! Console tools in WinPE write in the OEM code page (437, 936, ...) or
+//! UTF-16LE rather than UTF-8. Decoding is streaming so multi-byte
+//! characters split across pipe reads are reassembled correctly.
+
+use encoding_rs::{CoderResult, Decoder, Encoding};
+
+/// A concrete encoding used to decode process output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// IBM PC code page 437 (not covered by `encoding_rs`).
+    Cp437,
+    /// Any encoding supported by `encoding_rs`.
+    Standard(&'static Encoding),
+}
+
+impl OutputEncoding {
+    /// Canonical lowercase name reported to clients.
+    pub fn name(&self) -> String {
+        match self {
+            OutputEncoding::Cp437 => "cp437".to_string(),
+            OutputEncoding::Standard(encoding) => encoding.name().to_ascii_lowercase(),
+        }
+    }
+
+    /// Skip bytes at the start of a fragment that begins mid-character.
+    ///
+    /// `offset` is the position of the fragment within the whole stream.
+    pub fn resync<'a>(&self, bytes: &'a [u8], offset: u64) -> &'a [u8] {
+        match self {
+            OutputEncoding::Standard(e) if *e == encoding_rs::UTF_16LE => {
+                &bytes[(offset % 2) as usize..]
+            }
+            OutputEncoding::Standard(e) if *e == encoding_rs::UTF_16BE => {
+                &bytes[(offset % 2) as usize..]
+            }
+            OutputEncoding::Standard(e) if *e == encoding_rs::UTF_8 => {
+                // Continuation bytes are 0b10xxxxxx; a sequence has at most 3
+                let skip = bytes
+                    .iter()
+                    .take(3)
+                    .take_while(|b| (**b & 0xC0) == 0x80)
+                    .count();
+                &bytes[skip..]
+            }
+            _ => bytes,
+        }
+    }
+}
+
+/// Requested decoding: a fixed encoding or detection from the output itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingChoice {
+    /// Detect from the first bytes of output.
+    Auto,
+    /// Always use this encoding.
+    Fixed(OutputEncoding),
+}
+
+impl EncodingChoice {
+    /// Parse an encoding label such as `utf-8`, `cp437`, `gbk`, `utf-16le` or `auto`.
+    ///
+    /// Windows code page numbers (`936`, `cp1252`) and WHATWG labels are accepted.
+    pub fn parse(label: &str) -> Result<Self, String> {
+        let label = label.trim().to_ascii_lowercase();
+        let encoding = match label.as_str() {
+            "auto" => return Ok(EncodingChoice::Auto),
+            "" | "utf8" => Some(OutputEncoding::Standard(encoding_rs::UTF_8)),
+            "ibm437" | "oem" => Some(OutputEncoding::Cp437),
+            "unicode" | "utf16le" => Some(OutputEncoding::Standard(encoding_rs::UTF_16LE)),
+            other => {
+                let number = other.strip_prefix("cp").unwrap_or(other);
+                match number.parse::<u32>() {
+                    Ok(code_page) => from_code_page(code_page),
+                    Err(_) => Encoding::for_label(other.as_bytes())
+                        .filter(|e| *e != encoding_rs::REPLACEMENT)
+                        .map(OutputEncoding::Standard),
+                }
+            }
+        };
+
+        encoding
+            .map(EncodingChoice::Fixed)
+            .ok_or_else(|| format!("Unsupported encoding: {}", label))
+    }
+
+    /// Resolve to a concrete encoding, sniffing `sample` if set to auto.
+    pub fn resolve(&self, sample: &[u8]) -> OutputEncoding {
+        match self {
+            EncodingChoice::Fixed(encoding) => *encoding,
+            EncodingChoice::Auto => detect(sample),
+        }
+    }
+}
+
+/// Map a Windows code page number to an encoding.
+fn from_code_page(code_page: u32) -> Option<OutputEncoding> {
+    let encoding = match code_page {
+        437 => return Some(OutputEncoding::Cp437),
+        65001 => encoding_rs::UTF_8,
+        1200 => encoding_rs::UTF_16LE,
+        1201 => encoding_rs::UTF_16BE,
+        866 => encoding_rs::IBM866,
+        874 => encoding_rs::WINDOWS_874,
+        932 => encoding_rs::SHIFT_JIS,
+        936 => encoding_rs::GBK,
+        949 => encoding_rs::EUC_KR,
+        950 => encoding_rs::BIG5,
+        20866 => encoding_rs::KOI8_R,
+        54936 => encoding_rs::GB18030,
+        1250..=1258 => Encoding::for_label(format!("windows-{}", code_page).as_bytes())?,
+        _ => return None,
+    };
+    Some(OutputEncoding::Standard(encoding))
+}
+
+/// Guess the encoding of `sample`.
+///
+/// UTF-16LE is recognized by its BOM or by NUL high bytes; valid UTF-8 is
+/// taken as UTF-8; anything else falls back to the system OEM code page.
+pub fn detect(sample: &[u8]) -> OutputEncoding {
+    if sample.starts_with(&[0xFF, 0xFE]) {
+        return OutputEncoding::Standard(encoding_rs::UTF_16LE);
+    }
+
+    let pairs = sample.len() / 2;
+    if pairs > 0 {
+        let odd_nuls = sample
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count();
+        let even_nuls = sample.iter().step_by(2).filter(|b| **b == 0).count();
+        if odd_nuls * 2 >= pairs && even_nuls * 4 < pairs {
+            return OutputEncoding::Standard(encoding_rs::UTF_16LE);
+        }
+    }
+
+    // A sequence cut off at the end of the sample is still valid UTF-8
+    match std::str::from_utf8(sample) {
+        Ok(_) => OutputEncoding::Standard(encoding_rs::UTF_8),
+        Err(e) if e.error_len().is_none() => OutputEncoding::Standard(encoding_rs::UTF_8),
+        Err(_) => oem_encoding(),
+    }
+}
+
+/// The system OEM code page (console output code page).
+#[cfg(windows)]
+fn oem_encoding() -> OutputEncoding {
+    let code_page = unsafe { windows_sys::Win32::Globalization::GetOEMCP() };
+    from_code_page(code_page).unwrap_or(OutputEncoding::Cp437)
+}
+
+/// The system OEM code page (console output code page).
+#[cfg(not(windows))]
+fn oem_encoding() -> OutputEncoding {
+    OutputEncoding::Cp437
+}
+
+/// Incremental decoder that keeps partial characters between chunks.
+pub struct StreamDecoder {
+    choice: EncodingChoice,
+    state: DecoderState,
+}
+
+enum DecoderState {
+    /// Auto detection waiting for the first non-empty chunk.
+    Pending,
+    Cp437,
+    Standard(OutputEncoding, Decoder),
+}
+
+impl StreamDecoder {
+    pub fn new(choice: EncodingChoice) -> Self {
+        let mut decoder = Self {
+            choice,
+            state: DecoderState::Pending,
+        };
+        if let EncodingChoice::Fixed(encoding) = choice {
+            decoder.start(encoding);
+        }
+        decoder
+    }
+
+    fn start(&mut self, encoding: OutputEncoding) {
+        self.state = match encoding {
+            OutputEncoding::Cp437 => DecoderState::Cp437,
+            OutputEncoding::Standard(e) => {
+                DecoderState::Standard(encoding, e.new_decoder_with_bom_removal())
+            }
+        };
+    }
+
+    /// Encoding in use; auto detection without any output so far reports UTF-8.
+    pub fn encoding(&self) -> OutputEncoding {
+        match &self.state {
+            DecoderState::Pending => self.choice.resolve(&[]),
+            DecoderState::Cp437 => OutputEncoding::Cp437,
+            DecoderState::Standard(encoding, _) => *encoding,
+        }
+    }
+
+    /// Decode a chunk; an incomplete trailing character is held for the next call.
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        self.decode_inner(bytes, false)
+    }
+
+    /// Flush any held bytes at end of stream.
+    pub fn finish(&mut self) -> String {
+        self.decode_inner(&[], true)
+    }
+
+    fn decode_inner(&mut self, bytes: &[u8], last: bool) -> String {
+        if let DecoderState::Pending = self.state {
+            if bytes.is_empty() {
+                return String::new();
+            }
+            let encoding = self.choice.resolve(bytes);
+            self.start(encoding);
+        }
+
+        match &mut self.state {
+            DecoderState::Pending => String::new(),
+            DecoderState::Cp437 => decode_cp437(bytes),
+            DecoderState::Standard(_, decoder) => {
+                let mut out = String::with_capacity(
+                    decoder
+                        .max_utf8_buffer_length(bytes.len())
+                        .unwrap_or(bytes.len() * 3 + 16),
+                );
+                let mut input = bytes;
+                loop {
+                    let (result, read, _) = decoder.decode_to_string(input, &mut out, last);
+                    input = &input[read..];
+                    match result {
+                        CoderResult::InputEmpty => break,
+                        CoderResult::OutputFull => out.reserve(input.len() * 3 + 16),
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Decode a complete byte slice in one go.
+pub fn decode_all(bytes: &[u8], encoding: OutputEncoding) -> String {
+    let mut decoder = StreamDecoder::new(EncodingChoice::Fixed(encoding));
+    let mut text = decoder.decode(bytes);
+    text.push_str(&decoder.finish());
+    text
+}
+
+fn decode_cp437(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b < 0x80 {
+                b as char
+            } else {
+                CP437_HIGH[(b - 0x80) as usize]
+            }
+        })
+        .collect()
+}
+
+/// Code page 437 characters for bytes 0x80..=0xFF.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pipe read size used by the streaming executor.
+    const READ_SIZE: usize = 4096;
+
+    fn fixed(encoding: &'static Encoding) -> EncodingChoice {
+        EncodingChoice::Fixed(OutputEncoding::Standard(encoding))
+    }
+
+    /// Decode `bytes` as if read from a pipe `chunk` bytes at a time.
+    fn decode_chunked(bytes: &[u8], chunk: usize, choice: EncodingChoice) -> String {
+        let mut decoder = StreamDecoder::new(choice);
+        let mut text: String = bytes.chunks(chunk).map(|c| decoder.decode(c)).collect();
+        text.push_str(&decoder.finish());
+        text
+    }
+
+    /// Place `text` so that its first character straddles a read boundary.
+    fn straddling(text: &str, encoding: &'static Encoding) -> (String, Vec<u8>) {
+        let (encoded, _, _) = encoding.encode(text);
+        let expected = format!("{}{}", "a".repeat(READ_SIZE - 1), text);
+        let mut bytes = vec![b'a'; READ_SIZE - 1];
+        bytes.extend_from_slice(&encoded);
+        (expected, bytes)
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn utf8_split_across_reads() {
+        let (expected, bytes) = straddling("€ and 😀", encoding_rs::UTF_8);
+        assert_eq!(
+            decode_chunked(&bytes, READ_SIZE, fixed(encoding_rs::UTF_8)),
+            expected
+        );
+        // Every possible split point of a four-byte sequence
+        let bytes = "x😀y".as_bytes();
+        for chunk in 1..bytes.len() {
+            assert_eq!(
+                decode_chunked(bytes, chunk, fixed(encoding_rs::UTF_8)),
+                "x😀y"
+            );
+        }
+    }
+
+    #[test]
+    fn gbk_split_across_reads() {
+        let (expected, bytes) = straddling("中文输出", encoding_rs::GBK);
+        assert_eq!(bytes[READ_SIZE - 1..READ_SIZE + 1], [0xd6, 0xd0]);
+        assert_eq!(
+            decode_chunked(&bytes, READ_SIZE, fixed(encoding_rs::GBK)),
+            expected
+        );
+        let choice = EncodingChoice::parse("936").unwrap();
+        assert_eq!(decode_chunked(&bytes, READ_SIZE, choice), expected);
+    }
+
+    #[test]
+    fn utf16le_split_at_odd_offsets() {
+        let text = "Volume ▲ 😀 done";
+        let bytes = utf16le(text);
+        for chunk in [1, 3, 5, READ_SIZE - 1] {
+            assert_eq!(
+                decode_chunked(&bytes, chunk, fixed(encoding_rs::UTF_16LE)),
+                text
+            );
+        }
+        // A surrogate pair split between two reads
+        let mut bytes = utf16le(&"a".repeat(READ_SIZE / 2 - 1));
+        bytes.extend(utf16le("😀"));
+        let expected = format!("{}😀", "a".repeat(READ_SIZE / 2 - 1));
+        assert_eq!(
+            decode_chunked(&bytes, READ_SIZE, fixed(encoding_rs::UTF_16LE)),
+            expected
+        );
+    }
+
+    #[test]
+    fn cp437_table() {
+        let choice = EncodingChoice::parse("cp437").unwrap();
+        assert_eq!(choice, EncodingChoice::Fixed(OutputEncoding::Cp437));
+        assert_eq!(EncodingChoice::parse("437").unwrap(), choice);
+        assert_eq!(EncodingChoice::parse("OEM").unwrap(), choice);
+
+        // A box drawn by a console tool
+        let bytes = [
+            0xc9, 0xcd, 0xcb, 0xcd, 0xbb, b'\n', 0xba, b'A', 0xb3, b'B', 0xba, b'\n', 0xc7, 0xc4,
+            0xc5, 0xc4, 0xb6, b'\n', 0xc8, 0xcd, 0xca, 0xcd, 0xbc,
+        ];
+        assert_eq!(
+            decode_all(&bytes, OutputEncoding::Cp437),
+            "╔═╦═╗\n║A│B║\n╟─┼─╢\n╚═╩═╝"
+        );
+        assert_eq!(
+            decode_all(
+                &[0x80, 0x9b, 0xb0, 0xdb, 0xe1, 0xe6, 0xf8, 0xfe, 0xff],
+                OutputEncoding::Cp437
+            ),
+            "Ç¢░█ßµ°■\u{a0}"
+        );
+        assert_eq!(
+            decode_all(b"plain ASCII", OutputEncoding::Cp437),
+            "plain ASCII"
+        );
+
+        let mut unique = CP437_HIGH.to_vec();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 128);
+    }
+
+    #[test]
+    fn auto_detects_utf16_bom() {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(utf16le("é ok"));
+        assert_eq!(
+            detect(&bytes),
+            OutputEncoding::Standard(encoding_rs::UTF_16LE)
+        );
+        // The BOM is not part of the text
+        assert_eq!(decode_chunked(&bytes, 3, EncodingChoice::Auto), "é ok");
+    }
+
+    #[test]
+    fn auto_detects_utf16_without_bom() {
+        let bytes = utf16le("Windows IP Configuration\r\n");
+        assert_eq!(
+            detect(&bytes),
+            OutputEncoding::Standard(encoding_rs::UTF_16LE)
+        );
+        let mut decoder = StreamDecoder::new(EncodingChoice::Auto);
+        assert_eq!(decoder.decode(&bytes), "Windows IP Configuration\r\n");
+        assert_eq!(decoder.encoding().name(), "utf-16le");
+    }
+
+    #[test]
+    fn auto_detects_utf8() {
+        assert_eq!(
+            detect("héllo".as_bytes()),
+            OutputEncoding::Standard(encoding_rs::UTF_8)
+        );
+        // A sequence cut off by the sample is still UTF-8
+        assert_eq!(
+            detect(&"héllo €".as_bytes()[..8]),
+            OutputEncoding::Standard(encoding_rs::UTF_8)
+        );
+        assert_eq!(detect(b""), OutputEncoding::Standard(encoding_rs::UTF_8));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn auto_falls_back_to_oem() {
+        assert_eq!(detect(&[0xc9, 0xcd, 0xbb]), OutputEncoding::Cp437);
+    }
+
+    #[test]
+    fn rejects_unknown_encodings() {
+        assert!(EncodingChoice::parse("klingon").is_err());
+        assert!(EncodingChoice::parse("12345").is_err());
+        assert_eq!(
+            EncodingChoice::parse(" AUTO ").unwrap(),
+            EncodingChoice::Auto
+        );
+    }
+
+    #[test]
+    fn resync_skips_partial_characters() {
+        let utf8 = OutputEncoding::Standard(encoding_rs::UTF_8);
+        assert_eq!(utf8.resync(&"😀x".as_bytes()[2..], 2), b"x");
+        let utf16 = OutputEncoding::Standard(encoding_rs::UTF_16LE);
+        assert_eq!(utf16.resync(&[0x00, b'x', 0x00], 7), &[b'x', 0x00]);
+        assert_eq!(utf16.resync(&[b'x', 0x00], 8), &[b'x', 0x00]);
+    }
+}
@@ -1,11 +1,22 @@
-//! Command execution using Win32 CreateProcessW.
+//! Platform-independent command execution.
 //!
 //! Supports both synchronous execution (for /automation/exec)
 //! and streaming execution (for /automation/exec_stream).
-
-use std::time::Duration;
+//!
+//! Process creation is delegated to an [`Executor`]: Win32 `CreateProcessW`
+//! with Job Objects on Windows, process groups on Unix. Output capture,
+//! timeouts and streaming are shared.
+
+use base64::Engine;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use winpe_agent_core::{ExecRequest, Shell};
+use winpe_agent_core::ExecRequest;
+
+use super::capture::{self, CapturedOutput};
+use super::encoding::{EncodingChoice, OutputEncoding, StreamDecoder};
 
 /// Errors that can occur during command execution.
 #[derive(Debug)]
@@ -15,8 +26,48 @@ pub enum ExecError {
     /// Failed to create process.
     ProcessCreationFailed(String),
     /// Feature not supported on this platform.
-    #[allow(dead_code)]
     NotSupported(String),
+    /// The request is malformed (e.g. unknown encoding).
+    InvalidRequest(String),
+    /// Too many processes of this kind are already running.
+    Busy(String),
+}
+
+/// Result of a completed command.
+#[derive(Debug)]
+pub struct ExecOutput {
+    /// Process exit code.
+    pub exit_code: i32,
+    /// Captured stdout.
+    pub stdout: CapturedOutput,
+    /// Captured stderr.
+    pub stderr: CapturedOutput,
+}
+
+/// A streaming execution in progress.
+pub struct ExecStream {
+    /// Output and completion events.
+    pub events: mpsc::Receiver<StreamEvent>,
+    /// Sender for incremental stdin when `stdin_stream` was requested.
+    /// Dropping it closes the process's stdin.
+    pub stdin: Option<mpsc::Sender<Vec<u8>>>,
+    /// Kills the process tree; the stream then ends with [`StreamEvent::Cancelled`].
+    pub cancel: CancelToken,
+}
+
+/// Requests termination of a running execution.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Ask the waiter to kill the process tree.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
 /// Events emitted during streaming execution.
@@ -26,581 +77,453 @@ pub enum StreamEvent {
     Stdout(String),
     /// Stderr data chunk.
     Stderr(String),
-    /// Process exited with code.
-    Exit(i32),
+    /// Process exited; reports the exit code and the encoding used for stdout.
+    Exit {
+        exit_code: i32,
+        encoding: OutputEncoding,
+    },
     /// Process was killed due to timeout.
     Timeout,
+    /// Process was killed through its [`CancelToken`].
+    Cancelled,
 }
 
-/// Execute a command and return captured output.
-pub async fn execute_command(req: &ExecRequest) -> Result<(i32, String, String), ExecError> {
-    #[cfg(windows)]
-    {
-        // Clone the request data for the blocking task
-        let command = req.command.clone();
-        let args = req.args.clone();
-        let shell = req.shell;
-        let cwd = req.cwd.clone();
-        let env = req.env.clone();
-        let timeout_ms = req.timeout_ms;
-
-        // Run all Windows operations in a blocking task
-        tokio::task::spawn_blocking(move || {
-            execute_command_sync(&command, &args, shell, cwd.as_deref(), &env, timeout_ms)
-        })
-        .await
-        .map_err(|e| ExecError::ProcessCreationFailed(format!("Task join error: {}", e)))?
-    }
-    #[cfg(not(windows))]
-    {
-        let _ = req;
-        Err(ExecError::NotSupported(
-            "Command execution only supported on Windows".to_string(),
-        ))
-    }
+/// A running child process and its descendants.
+///
+/// Dropping the handle kills any descendants still alive, so output pipes
+/// inherited by grandchildren are closed once the command has finished.
+pub trait ChildProcess: Send + Sync {
+    /// Return the exit code if the process has exited, without blocking.
+    fn try_wait(&self) -> io::Result<Option<i32>>;
+
+    /// Kill the process and its whole process tree.
+    fn kill_tree(&self);
 }
 
-/// Execute a command with streaming output.
-pub async fn execute_command_stream(
-    req: &ExecRequest,
-) -> Result<mpsc::Receiver<StreamEvent>, ExecError> {
+/// A newly spawned process with its pipes.
+pub struct SpawnedProcess {
+    /// Handle used to wait for or kill the process.
+    pub child: Arc<dyn ChildProcess>,
+    /// Write end of the stdin pipe, if stdin was requested.
+    pub stdin: Option<Box<dyn Write + Send>>,
+    /// Read end of the stdout pipe.
+    pub stdout: Box<dyn Read + Send>,
+    /// Read end of the stderr pipe.
+    pub stderr: Box<dyn Read + Send>,
+}
+
+/// Platform-specific process launcher.
+pub trait Executor: Send + Sync {
+    /// Spawn the process described by `req` with stdout/stderr redirected to pipes.
+    ///
+    /// Stdin is a pipe when `pipe_stdin` is set and empty otherwise.
+    fn spawn(&self, req: &ExecRequest, pipe_stdin: bool) -> Result<SpawnedProcess, ExecError>;
+}
+
+/// Get the executor for the current platform.
+pub fn platform_executor() -> &'static dyn Executor {
     #[cfg(windows)]
     {
-        execute_command_stream_windows(req).await
+        &super::win32::Win32Executor
     }
-    #[cfg(not(windows))]
+    #[cfg(unix)]
     {
-        let _ = req;
-        Err(ExecError::NotSupported(
-            "Command execution only supported on Windows".to_string(),
-        ))
+        &super::unix::UnixExecutor
     }
 }
 
-#[cfg(windows)]
-fn execute_command_sync(
-    command: &str,
-    args: &[String],
-    shell: Shell,
-    cwd: Option<&str>,
-    env: &std::collections::HashMap<String, String>,
-    timeout_ms: u64,
-) -> Result<(i32, String, String), ExecError> {
-    use std::ffi::OsStr;
-    use std::io::Read;
-    use std::os::windows::ffi::OsStrExt;
-    use std::os::windows::io::FromRawHandle;
-    use std::ptr;
-    use std::time::Instant;
-    use windows_sys::Win32::Foundation::*;
-    use windows_sys::Win32::Security::*;
-    use windows_sys::Win32::System::Pipes::*;
-    use windows_sys::Win32::System::Threading::*;
-
-    // Job Objects FFI definitions (not always available in windows-sys)
-    const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: u32 = 0x00002000;
-    const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: i32 = 9;
-
-    #[repr(C)]
-    #[derive(Clone, Copy)]
-    struct JOBOBJECT_BASIC_LIMIT_INFORMATION {
-        per_process_user_time_limit: i64,
-        per_job_user_time_limit: i64,
-        limit_flags: u32,
-        minimum_working_set_size: usize,
-        maximum_working_set_size: usize,
-        active_process_limit: u32,
-        affinity: usize,
-        priority_class: u32,
-        scheduling_class: u32,
+/// Timeout of `exec` and `exec_stream` requests that do not set one (10 minutes).
+pub const DEFAULT_TIMEOUT_MS: u64 = 600_000;
+
+/// How often to poll a running process for exit.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Stdin chunks buffered between the API and the writer thread.
+const STDIN_CHANNEL_CAPACITY: usize = 16;
+
+/// Decode the initial stdin payload of a request.
+fn request_stdin(req: &ExecRequest) -> Result<Option<Vec<u8>>, ExecError> {
+    match (&req.stdin, &req.stdin_base64) {
+        (Some(_), Some(_)) => Err(ExecError::InvalidRequest(
+            "stdin and stdin_base64 are mutually exclusive".to_string(),
+        )),
+        (Some(text), None) => Ok(Some(text.clone().into_bytes())),
+        (None, Some(encoded)) => base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map(Some)
+            .map_err(|e| ExecError::InvalidRequest(format!("Invalid stdin_base64: {}", e))),
+        (None, None) => Ok(None),
     }
+}
 
-    #[repr(C)]
-    #[derive(Clone, Copy)]
-    struct IO_COUNTERS {
-        read_operation_count: u64,
-        write_operation_count: u64,
-        other_operation_count: u64,
-        read_transfer_count: u64,
-        write_transfer_count: u64,
-        other_transfer_count: u64,
-    }
-
-    #[repr(C)]
-    struct JOBOBJECT_EXTENDED_LIMIT_INFORMATION {
-        basic_limit_information: JOBOBJECT_BASIC_LIMIT_INFORMATION,
-        io_info: IO_COUNTERS,
-        process_memory_limit: usize,
-        job_memory_limit: usize,
-        peak_process_memory_used: usize,
-        peak_job_memory_used: usize,
-    }
-
-    #[link(name = "kernel32")]
-    unsafe extern "system" {
-        fn CreateJobObjectW(
-            lpJobAttributes: *mut SECURITY_ATTRIBUTES,
-            lpName: *const u16,
-        ) -> HANDLE;
-        fn SetInformationJobObject(
-            hJob: HANDLE,
-            JobObjectInformationClass: i32,
-            lpJobObjectInformation: *const std::ffi::c_void,
-            cbJobObjectInformationLength: u32,
-        ) -> i32;
-        fn AssignProcessToJobObject(hJob: HANDLE, hProcess: HANDLE) -> i32;
-        fn TerminateJobObject(hJob: HANDLE, uExitCode: u32) -> i32;
-    }
-
-    // Build command line
-    let command_line = build_command_line(command, args, shell);
-    let mut command_line_wide: Vec<u16> = OsStr::new(&command_line)
-        .encode_wide()
-        .chain(std::iter::once(0))
-        .collect();
-
-    // Set up security attributes for inheritable handles
-    let sa = SECURITY_ATTRIBUTES {
-        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
-        lpSecurityDescriptor: ptr::null_mut(),
-        bInheritHandle: TRUE,
-    };
-
-    // Create pipes for stdout and stderr
-    let mut stdout_read: HANDLE = INVALID_HANDLE_VALUE;
-    let mut stdout_write: HANDLE = INVALID_HANDLE_VALUE;
-    let mut stderr_read: HANDLE = INVALID_HANDLE_VALUE;
-    let mut stderr_write: HANDLE = INVALID_HANDLE_VALUE;
-
-    unsafe {
-        if CreatePipe(&mut stdout_read, &mut stdout_write, &sa, 0) == 0 {
-            return Err(ExecError::ProcessCreationFailed(
-                "Failed to create stdout pipe".to_string(),
-            ));
-        }
-        SetHandleInformation(stdout_read, HANDLE_FLAG_INHERIT, 0);
-
-        if CreatePipe(&mut stderr_read, &mut stderr_write, &sa, 0) == 0 {
-            CloseHandle(stdout_read);
-            CloseHandle(stdout_write);
-            return Err(ExecError::ProcessCreationFailed(
-                "Failed to create stderr pipe".to_string(),
-            ));
-        }
-        SetHandleInformation(stderr_read, HANDLE_FLAG_INHERIT, 0);
-    }
-
-    // Set up STARTUPINFOW
-    let mut si: STARTUPINFOW = unsafe { std::mem::zeroed() };
-    si.cb = std::mem::size_of::<STARTUPINFOW>() as u32;
-    si.dwFlags = STARTF_USESTDHANDLES;
-    si.hStdOutput = stdout_write;
-    si.hStdError = stderr_write;
-    si.hStdInput = INVALID_HANDLE_VALUE;
-
-    let mut pi: PROCESS_INFORMATION = unsafe { std::mem::zeroed() };
-
-    // Working directory
-    let cwd_wide: Option<Vec<u16>> = cwd.map(|c| {
-        OsStr::new(c)
-            .encode_wide()
-            .chain(std::iter::once(0))
-            .collect()
-    });
-    let cwd_ptr = cwd_wide.as_ref().map_or(ptr::null(), |v| v.as_ptr());
-
-    // Build environment block
-    let env_block = build_environment_block(env);
-    let env_ptr = env_block.as_ptr();
-
-    // Create process with environment (CREATE_UNICODE_ENVIRONMENT for wide chars)
-    let result = unsafe {
-        CreateProcessW(
-            ptr::null(),
-            command_line_wide.as_mut_ptr(),
-            ptr::null_mut(),
-            ptr::null_mut(),
-            TRUE,
-            CREATE_NO_WINDOW | CREATE_UNICODE_ENVIRONMENT,
-            env_ptr as *const std::ffi::c_void,
-            cwd_ptr,
-            &si,
-            &mut pi,
-        )
-    };
-
-    // Close write ends of pipes (child owns them now)
-    unsafe {
-        CloseHandle(stdout_write);
-        CloseHandle(stderr_write);
-    }
-
-    if result == 0 {
-        unsafe {
-            CloseHandle(stdout_read);
-            CloseHandle(stderr_read);
-        }
-        return Err(ExecError::ProcessCreationFailed(format!(
-            "CreateProcessW failed with error {}",
-            unsafe { GetLastError() }
-        )));
-    }
-
-    unsafe { CloseHandle(pi.hThread) };
-
-    // Create Job Object for process tree termination
-    let job = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
-    if job.is_null() || job == INVALID_HANDLE_VALUE {
-        // Job creation failed, fallback to simple process termination
-        tracing::warn!("Failed to create Job Object, process tree termination disabled");
-    } else {
-        // Configure job to terminate child processes when job is closed
-        let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
-        info.basic_limit_information.limit_flags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
-
-        let set_result = unsafe {
-            SetInformationJobObject(
-                job,
-                JOB_OBJECT_EXTENDED_LIMIT_INFORMATION,
-                &info as *const _ as *const std::ffi::c_void,
-                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
-            )
-        };
-
-        if set_result == 0 {
-            tracing::warn!("Failed to configure Job Object limits");
-        }
-
-        // Assign process to job
-        let assign_result = unsafe { AssignProcessToJobObject(job, pi.hProcess) };
-        if assign_result == 0 {
-            tracing::warn!("Failed to assign process to Job Object");
-        }
-    }
-
-    // Wait for process with timeout (blocking)
-    let timeout = Duration::from_millis(timeout_ms);
-    let start = Instant::now();
-
-    loop {
-        let wait_result = unsafe { WaitForSingleObject(pi.hProcess, 100) };
-        if wait_result == WAIT_OBJECT_0 {
-            break;
+/// Write stdin on a separate thread, then close the pipe.
+///
+/// `initial` is written first, followed by chunks from `incremental` until
+/// its sender is dropped. Write errors (the child exited or closed stdin)
+/// end feeding silently.
+fn feed_stdin(
+    mut pipe: Box<dyn Write + Send>,
+    initial: Option<Vec<u8>>,
+    incremental: Option<mpsc::Receiver<Vec<u8>>>,
+) {
+    std::thread::spawn(move || {
+        if let Some(data) = initial
+            && pipe.write_all(&data).and_then(|_| pipe.flush()).is_err()
+        {
+            return;
         }
-        if start.elapsed() > timeout {
-            // Terminate job (kills entire process tree) or fallback to process
-            if !job.is_null() && job != INVALID_HANDLE_VALUE {
-                unsafe {
-                    TerminateJobObject(job, 1);
-                    CloseHandle(job);
-                }
-            } else {
-                unsafe {
-                    TerminateProcess(pi.hProcess, 1);
+        if let Some(mut rx) = incremental {
+            while let Some(chunk) = rx.blocking_recv() {
+                if pipe.write_all(&chunk).and_then(|_| pipe.flush()).is_err() {
+                    break;
                 }
             }
-            unsafe {
-                CloseHandle(pi.hProcess);
-                CloseHandle(stdout_read);
-                CloseHandle(stderr_read);
-            }
-            return Err(ExecError::Timeout);
         }
-    }
-
-    // Clean up job object
-    if !job.is_null() && job != INVALID_HANDLE_VALUE {
-        unsafe { CloseHandle(job) };
-    }
-
-    // Get exit code
-    let mut exit_code: u32 = 0;
-    unsafe {
-        GetExitCodeProcess(pi.hProcess, &mut exit_code);
-        CloseHandle(pi.hProcess);
-    }
-
-    // Read stdout
-    let mut stdout_file = unsafe { std::fs::File::from_raw_handle(stdout_read) };
-    let mut stdout_buf = Vec::new();
-    let _ = stdout_file.read_to_end(&mut stdout_buf);
-    let stdout = String::from_utf8_lossy(&stdout_buf).to_string();
-
-    // Read stderr
-    let mut stderr_file = unsafe { std::fs::File::from_raw_handle(stderr_read) };
-    let mut stderr_buf = Vec::new();
-    let _ = stderr_file.read_to_end(&mut stderr_buf);
-    let stderr = String::from_utf8_lossy(&stderr_buf).to_string();
-
-    Ok((exit_code as i32, stdout, stderr))
+    });
 }
 
-#[cfg(windows)]
-async fn execute_command_stream_windows(
+/// Execute a command and return captured output.
+///
+/// At most `max_output_bytes` are retained per stream; see [`capture`].
+/// Without `timeout_ms` the process runs until it exits.
+pub async fn execute_command(
     req: &ExecRequest,
-) -> Result<mpsc::Receiver<StreamEvent>, ExecError> {
-    use std::ffi::OsStr;
-    use std::os::windows::ffi::OsStrExt;
-    use std::ptr;
-    use windows_sys::Win32::Foundation::*;
-    use windows_sys::Win32::Security::*;
-    use windows_sys::Win32::System::Pipes::*;
-    use windows_sys::Win32::System::Threading::*;
-
-    let (tx, rx) = mpsc::channel(100);
+    max_output_bytes: usize,
+) -> Result<ExecOutput, ExecError> {
+    let req = req.clone();
 
-    // Build command line
-    let command_line = build_command_line(&req.command, &req.args, req.shell);
-    let mut command_line_wide: Vec<u16> = OsStr::new(&command_line)
-        .encode_wide()
-        .chain(std::iter::once(0))
-        .collect();
-
-    // Set up security attributes for inheritable handles
-    let sa = SECURITY_ATTRIBUTES {
-        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
-        lpSecurityDescriptor: ptr::null_mut(),
-        bInheritHandle: TRUE,
-    };
+    // Process creation and pipe reads are blocking
+    tokio::task::spawn_blocking(move || execute_command_sync(&req, max_output_bytes))
+        .await
+        .map_err(|e| ExecError::ProcessCreationFailed(format!("Task join error: {}", e)))?
+}
 
-    // Create pipes for stdout and stderr
-    let mut stdout_read: HANDLE = INVALID_HANDLE_VALUE;
-    let mut stdout_write: HANDLE = INVALID_HANDLE_VALUE;
-    let mut stderr_read: HANDLE = INVALID_HANDLE_VALUE;
-    let mut stderr_write: HANDLE = INVALID_HANDLE_VALUE;
-
-    unsafe {
-        if CreatePipe(&mut stdout_read, &mut stdout_write, &sa, 0) == 0 {
-            return Err(ExecError::ProcessCreationFailed(
-                "Failed to create stdout pipe".to_string(),
-            ));
-        }
-        SetHandleInformation(stdout_read, HANDLE_FLAG_INHERIT, 0);
-
-        if CreatePipe(&mut stderr_read, &mut stderr_write, &sa, 0) == 0 {
-            CloseHandle(stdout_read);
-            CloseHandle(stdout_write);
-            return Err(ExecError::ProcessCreationFailed(
-                "Failed to create stderr pipe".to_string(),
-            ));
-        }
-        SetHandleInformation(stderr_read, HANDLE_FLAG_INHERIT, 0);
+fn execute_command_sync(
+    req: &ExecRequest,
+    max_output_bytes: usize,
+) -> Result<ExecOutput, ExecError> {
+    let choice = EncodingChoice::parse(&req.encoding).map_err(ExecError::InvalidRequest)?;
+    let input = request_stdin(req)?;
+    let SpawnedProcess {
+        child,
+        stdin,
+        stdout,
+        stderr,
+    } = platform_executor().spawn(req, input.is_some())?;
+
+    if let Some(pipe) = stdin {
+        feed_stdin(pipe, input, None);
     }
 
-    // Set up STARTUPINFOW
-    let mut si: STARTUPINFOW = unsafe { std::mem::zeroed() };
-    si.cb = std::mem::size_of::<STARTUPINFOW>() as u32;
-    si.dwFlags = STARTF_USESTDHANDLES;
-    si.hStdOutput = stdout_write;
-    si.hStdError = stderr_write;
-    si.hStdInput = INVALID_HANDLE_VALUE;
-
-    let mut pi: PROCESS_INFORMATION = unsafe { std::mem::zeroed() };
-
-    // Working directory
-    let cwd = req.cwd.clone();
-    let cwd_wide: Option<Vec<u16>> = cwd.as_ref().map(|c| {
-        OsStr::new(c)
-            .encode_wide()
-            .chain(std::iter::once(0))
-            .collect()
-    });
-    let cwd_ptr = cwd_wide.as_ref().map_or(ptr::null(), |v| v.as_ptr());
-
-    // Build environment block
-    let env_block = build_environment_block(&req.env);
-    let env_ptr = env_block.as_ptr();
-
-    // Create process with environment
-    let result = unsafe {
-        CreateProcessW(
-            ptr::null(),
-            command_line_wide.as_mut_ptr(),
-            ptr::null_mut(),
-            ptr::null_mut(),
-            TRUE,
-            CREATE_NO_WINDOW | CREATE_UNICODE_ENVIRONMENT,
-            env_ptr as *const std::ffi::c_void,
-            cwd_ptr,
-            &si,
-            &mut pi,
-        )
+    // Drain both pipes concurrently so a full pipe never blocks the child
+    let stdout_reader =
+        std::thread::spawn(move || capture::capture(stdout, max_output_bytes, choice));
+    let stderr_reader =
+        std::thread::spawn(move || capture::capture(stderr, max_output_bytes, choice));
+
+    let outcome = wait_for_exit(
+        child.as_ref(),
+        req.timeout_ms.map(Duration::from_millis),
+        &CancelToken::default(),
+    );
+
+    // Release the handle so leftover descendants die and the pipes close
+    drop(child);
+    let empty = || capture::OutputCapture::new(0).finish(choice);
+    let stdout = stdout_reader.join().unwrap_or_else(|_| empty());
+    let stderr = stderr_reader.join().unwrap_or_else(|_| empty());
+
+    let WaitOutcome::Exited(exit_code) = outcome else {
+        return Err(ExecError::Timeout);
     };
 
-    // Close write ends of pipes
-    unsafe {
-        CloseHandle(stdout_write);
-        CloseHandle(stderr_write);
-    }
-
-    if result == 0 {
-        unsafe {
-            CloseHandle(stdout_read);
-            CloseHandle(stderr_read);
-        }
-        return Err(ExecError::ProcessCreationFailed(format!(
-            "CreateProcessW failed with error {}",
-            unsafe { GetLastError() }
-        )));
-    }
+    Ok(ExecOutput {
+        exit_code,
+        stdout,
+        stderr,
+    })
+}
 
-    unsafe { CloseHandle(pi.hThread) };
+/// Execute a command with streaming output.
+///
+/// Without `timeout_ms` the process runs until it exits or is cancelled.
+pub async fn execute_command_stream(req: &ExecRequest) -> Result<ExecStream, ExecError> {
+    let (tx, rx) = mpsc::channel(100);
 
-    // Store handle values as usize for Send safety
-    let process_handle = pi.hProcess as usize;
-    let stdout_read_val = stdout_read as usize;
-    let stderr_read_val = stderr_read as usize;
-    let timeout_ms = req.timeout_ms;
+    let choice = EncodingChoice::parse(&req.encoding).map_err(ExecError::InvalidRequest)?;
+    let input = request_stdin(req)?;
+    let SpawnedProcess {
+        child,
+        stdin,
+        stdout,
+        stderr,
+    } = platform_executor().spawn(req, input.is_some() || req.stdin_stream)?;
+
+    let mut stdin_tx = None;
+    if let Some(pipe) = stdin {
+        let incremental = req.stdin_stream.then(|| {
+            let (tx, rx) = mpsc::channel(STDIN_CHANNEL_CAPACITY);
+            stdin_tx = Some(tx);
+            rx
+        });
+        feed_stdin(pipe, input, incremental);
+    }
+    let timeout = req.timeout_ms.map(Duration::from_millis);
+    let cancel = CancelToken::default();
 
-    // Spawn task to read and stream output using std::thread
+    // Spawn threads to read and stream output
     let tx_stdout = tx.clone();
-    std::thread::spawn(move || {
-        stream_pipe(stdout_read_val as HANDLE, tx_stdout, true);
-    });
+    let stdout_reader = std::thread::spawn(move || stream_pipe(stdout, tx_stdout, true, choice));
 
     let tx_stderr = tx.clone();
-    std::thread::spawn(move || {
-        stream_pipe(stderr_read_val as HANDLE, tx_stderr, false);
-    });
+    let stderr_reader = std::thread::spawn(move || stream_pipe(stderr, tx_stderr, false, choice));
 
     // Spawn a thread to wait for process and send exit event
+    let waiter_cancel = cancel.clone();
     std::thread::spawn(move || {
-        let timeout = Duration::from_millis(timeout_ms);
-        let start = std::time::Instant::now();
-        let handle = process_handle as HANDLE;
-        let mut timed_out = false;
-
-        loop {
-            let result = unsafe { WaitForSingleObject(handle, 100) };
-            if result == WAIT_OBJECT_0 {
-                break;
-            }
-            if start.elapsed() > timeout {
-                unsafe {
-                    TerminateProcess(handle, 1);
-                }
-                timed_out = true;
-                break;
-            }
-            std::thread::sleep(Duration::from_millis(50));
-        }
+        let outcome = wait_for_exit(child.as_ref(), timeout, &waiter_cancel);
+
+        // Deliver all output before the final event
+        drop(child);
+        let encoding = stdout_reader.join().unwrap_or_else(|_| choice.resolve(&[]));
+        let _ = stderr_reader.join();
+
+        // Send appropriate event based on how the process ended
+        let event = match outcome {
+            WaitOutcome::Exited(exit_code) => StreamEvent::Exit {
+                exit_code,
+                encoding,
+            },
+            WaitOutcome::TimedOut => StreamEvent::Timeout,
+            WaitOutcome::Cancelled => StreamEvent::Cancelled,
+        };
+        let _ = tx.blocking_send(event);
+    });
 
-        unsafe {
-            CloseHandle(handle);
-        }
+    Ok(ExecStream {
+        events: rx,
+        stdin: stdin_tx,
+        cancel,
+    })
+}
 
-        // Send appropriate event based on whether we timed out
-        if timed_out {
-            let _ = tx.blocking_send(StreamEvent::Timeout);
-        } else {
-            let mut exit_code: u32 = 0;
-            unsafe {
-                GetExitCodeProcess(handle, &mut exit_code);
+/// How a waited-for process ended.
+enum WaitOutcome {
+    Exited(i32),
+    TimedOut,
+    Cancelled,
+}
+
+/// Poll until the process exits, the timeout (if any) elapses or `cancel` is triggered.
+///
+/// On timeout or cancellation the process tree is killed before returning.
+fn wait_for_exit(
+    child: &dyn ChildProcess,
+    timeout: Option<Duration>,
+    cancel: &CancelToken,
+) -> WaitOutcome {
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(code)) => return WaitOutcome::Exited(code),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!("Failed to query process status: {}", e);
+                return WaitOutcome::Exited(-1);
             }
-            let _ = tx.blocking_send(StreamEvent::Exit(exit_code as i32));
         }
-    });
-
-    Ok(rx)
+        let outcome = if cancel.is_cancelled() {
+            WaitOutcome::Cancelled
+        } else if timeout.is_some_and(|timeout| start.elapsed() > timeout) {
+            WaitOutcome::TimedOut
+        } else {
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+        child.kill_tree();
+        return outcome;
+    }
 }
 
-#[cfg(windows)]
+/// Forward a pipe as decoded chunks; returns the encoding that was used.
 fn stream_pipe(
-    handle: windows_sys::Win32::Foundation::HANDLE,
+    mut pipe: Box<dyn Read + Send>,
     tx: mpsc::Sender<StreamEvent>,
     is_stdout: bool,
-) {
-    use std::io::Read;
-    use std::os::windows::io::FromRawHandle;
-
-    let mut file = unsafe { std::fs::File::from_raw_handle(handle) };
+    choice: EncodingChoice,
+) -> OutputEncoding {
     let mut buffer = [0u8; 4096];
+    let mut decoder = StreamDecoder::new(choice);
+    let event = |chunk: String| {
+        if is_stdout {
+            StreamEvent::Stdout(chunk)
+        } else {
+            StreamEvent::Stderr(chunk)
+        }
+    };
 
     loop {
-        match file.read(&mut buffer) {
+        match pipe.read(&mut buffer) {
             Ok(0) => break,
             Ok(n) => {
-                let chunk = String::from_utf8_lossy(&buffer[..n]).to_string();
-                let event = if is_stdout {
-                    StreamEvent::Stdout(chunk)
-                } else {
-                    StreamEvent::Stderr(chunk)
-                };
-                if tx.blocking_send(event).is_err() {
-                    break;
+                // A character split across reads is held back until complete
+                let chunk = decoder.decode(&buffer[..n]);
+                if !chunk.is_empty() && tx.blocking_send(event(chunk)).is_err() {
+                    return decoder.encoding();
                 }
             }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
             Err(_) => break,
         }
     }
-}
 
-/// Build command line string from request.
-fn build_command_line(command: &str, args: &[String], shell: Shell) -> String {
-    match shell {
-        Shell::Cmd => {
-            let mut cmd = format!("cmd.exe /c {}", command);
-            for arg in args {
-                cmd.push(' ');
-                if arg.contains(' ') {
-                    cmd.push('"');
-                    cmd.push_str(arg);
-                    cmd.push('"');
-                } else {
-                    cmd.push_str(arg);
-                }
-            }
-            cmd
-        }
-        Shell::Powershell => {
-            let mut cmd = String::from("powershell.exe -NoLogo -NoProfile -Command ");
-            cmd.push_str(command);
-            for arg in args {
-                cmd.push(' ');
-                if arg.contains(' ') {
-                    cmd.push('"');
-                    cmd.push_str(arg);
-                    cmd.push('"');
-                } else {
-                    cmd.push_str(arg);
-                }
-            }
-            cmd
-        }
+    let rest = decoder.finish();
+    if !rest.is_empty() {
+        let _ = tx.blocking_send(event(rest));
     }
+    decoder.encoding()
 }
 
-/// Build environment block for CreateProcessW.
-/// The block is a null-terminated sequence of null-terminated "KEY=VALUE" strings.
-#[cfg(windows)]
-fn build_environment_block(extra_env: &std::collections::HashMap<String, String>) -> Vec<u16> {
-    use std::ffi::OsStr;
-    use std::os::windows::ffi::OsStrExt;
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use winpe_agent_core::Shell;
+
+    fn sh(script: &str) -> ExecRequest {
+        serde_json::from_value(serde_json::json!({ "shell": "sh", "command": script })).unwrap()
+    }
+
+    async fn run(req: &ExecRequest) -> ExecOutput {
+        execute_command(req, 1024 * 1024).await.unwrap()
+    }
+
+    fn alive(pid: u32) -> bool {
+        std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .map(|stat| {
+                !stat
+                    .rsplit(')')
+                    .next()
+                    .unwrap_or("")
+                    .trim_start()
+                    .starts_with('Z')
+            })
+            .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn exit_code_is_propagated() {
+        assert_eq!(run(&sh("exit 7")).await.exit_code, 7);
+        assert_eq!(run(&sh("true")).await.exit_code, 0);
+        assert_eq!(run(&sh("kill -9 $$")).await.exit_code, 128 + 9);
+    }
 
-    let mut env_strings: Vec<String> = Vec::new();
+    #[tokio::test]
+    async fn separates_stdout_and_stderr() {
+        let output = run(&sh("echo out; echo err >&2")).await;
+        assert_eq!(output.stdout.text, "out\n");
+        assert_eq!(output.stderr.text, "err\n");
+    }
 
-    // Inherit current environment
-    for (key, value) in std::env::vars() {
-        env_strings.push(format!("{}={}", key, value));
+    #[tokio::test]
+    async fn direct_passes_arguments_unmodified() {
+        let mut req = sh("printf");
+        req.shell = Shell::Direct;
+        req.args = vec!["[%s]".to_string(), "a b".to_string(), "$HOME".to_string()];
+        assert_eq!(run(&req).await.stdout.text, "[a b][$HOME]");
     }
 
-    // Add/override with request environment
-    for (key, value) in extra_env {
-        // Remove any existing entry with this key
-        env_strings.retain(|s| !s.starts_with(&format!("{}=", key)));
-        env_strings.push(format!("{}={}", key, value));
+    #[tokio::test]
+    async fn sets_env_and_cwd() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let mut req = sh("echo \"$GREETING\"; pwd -P");
+        req.env
+            .insert("GREETING".to_string(), "hello world".to_string());
+        req.cwd = Some(dir.to_string_lossy().into_owned());
+        let output = run(&req).await;
+        assert_eq!(
+            output.stdout.text,
+            format!("hello world\n{}\n", dir.display())
+        );
     }
 
-    // Sort for consistency (optional but nice)
-    env_strings.sort();
+    #[tokio::test]
+    async fn feeds_stdin() {
+        let mut req = sh("cat");
+        req.stdin = Some("line one\nline two\n".to_string());
+        assert_eq!(run(&req).await.stdout.text, "line one\nline two\n");
+
+        let mut req = sh("od -An -tx1");
+        req.stdin_base64 = Some("AP8=".to_string());
+        assert_eq!(run(&req).await.stdout.text.trim(), "00 ff");
 
-    // Build the block: each string null-terminated, then double null at end
-    let mut block: Vec<u16> = Vec::new();
-    for s in env_strings {
-        block.extend(OsStr::new(&s).encode_wide());
-        block.push(0); // null terminator for this string
+        // Without stdin the child reads EOF instead of hanging
+        assert_eq!(run(&sh("cat; echo done")).await.stdout.text, "done\n");
     }
-    block.push(0); // final null terminator
 
-    block
+    #[tokio::test]
+    async fn rejects_both_stdin_forms() {
+        let mut req = sh("cat");
+        req.stdin = Some("a".to_string());
+        req.stdin_base64 = Some("YQ==".to_string());
+        assert!(matches!(
+            execute_command(&req, 1024).await,
+            Err(ExecError::InvalidRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_the_whole_tree() {
+        let mut req = sh("sleep 1000 & echo $!; wait");
+        req.timeout_ms = Some(300);
+        let mut stream = execute_command_stream(&req).await.unwrap();
+        let Some(StreamEvent::Stdout(pid)) = stream.events.recv().await else {
+            panic!("expected the background pid");
+        };
+        let grandchild: u32 = pid.trim().parse().unwrap();
+        assert!(matches!(
+            stream.events.recv().await,
+            Some(StreamEvent::Timeout)
+        ));
+        // SIGKILL lands asynchronously
+        let mut gone = false;
+        for _ in 0..100 {
+            if !alive(grandchild) {
+                gone = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(gone);
+    }
+
+    #[tokio::test]
+    async fn timeout_returns_despite_inherited_pipes() {
+        // The grandchild holds stdout open; the timeout must still close it
+        let mut req = sh("sleep 1000 & sleep 1000");
+        req.timeout_ms = Some(300);
+        let start = Instant::now();
+        assert!(matches!(
+            execute_command(&req, 1024).await,
+            Err(ExecError::Timeout)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn streams_stdin_and_cancels() {
+        let mut req = sh("read line; echo \"got $line\"; sleep 1000");
+        req.stdin_stream = true;
+        let mut stream = execute_command_stream(&req).await.unwrap();
+        let stdin = stream.stdin.take().unwrap();
+        stdin.send(b"ping\n".to_vec()).await.unwrap();
+        let Some(StreamEvent::Stdout(line)) = stream.events.recv().await else {
+            panic!("expected output");
+        };
+        assert_eq!(line, "got ping\n");
+        stream.cancel.cancel();
+        assert!(matches!(
+            stream.events.recv().await,
+            Some(StreamEvent::Cancelled)
+        ));
+    }
 }
@@ -0,0 +1,527 @@
+//! Background jobs.
+//!
+//! A job is an `ExecRequest` running detached from any HTTP request. Output
+//! is kept in bounded per-stream logs addressed by absolute offsets, so
+//! clients can poll incrementally and reconnect after a dropped connection.
+//! Finished jobs are kept for a retention period, then removed.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use ulid::Ulid;
+use winpe_agent_core::{
+    ExecRequest, JobCreateResponse, JobInfo, JobOutputResponse, JobState, OutputStream,
+};
+
+use super::executor::{self, CancelToken, ExecError, StreamEvent};
+
+/// How often finished jobs are checked for expiry.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Decoded output of one stream, keeping only the most recent `limit` bytes.
+struct OutputLog {
+    /// Absolute offset of the first retained byte.
+    base: u64,
+    data: String,
+    limit: usize,
+}
+
+impl OutputLog {
+    fn new(limit: usize) -> Self {
+        Self {
+            base: 0,
+            data: String::new(),
+            limit,
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.base + self.data.len() as u64
+    }
+
+    fn push(&mut self, chunk: &str) {
+        self.data.push_str(chunk);
+        if self.data.len() > self.limit {
+            // Drop from the front, on a character boundary
+            let mut cut = self.data.len() - self.limit;
+            while !self.data.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.data.drain(..cut);
+            self.base += cut as u64;
+        }
+    }
+
+    /// Read up to `max` bytes from absolute `offset`.
+    ///
+    /// Returns the actual start offset, the text and whether `offset` had been discarded.
+    /// A read never ends mid-character, but returns at least one whole
+    /// character when `max` is smaller than it, so polling always advances.
+    fn read(&self, offset: u64, max: usize) -> (u64, &str, bool) {
+        let truncated = offset < self.base;
+        let start = offset.clamp(self.base, self.total());
+        let mut from = (start - self.base) as usize;
+        while !self.data.is_char_boundary(from) {
+            from += 1;
+        }
+        let mut to = (from + max).min(self.data.len());
+        while !self.data.is_char_boundary(to) {
+            to -= 1;
+        }
+        if to == from && max > 0 && from < self.data.len() {
+            to += 1;
+            while !self.data.is_char_boundary(to) {
+                to += 1;
+            }
+        }
+        (self.base + from as u64, &self.data[from..to], truncated)
+    }
+}
+
+struct JobStatus {
+    state: JobState,
+    exit_code: Option<i32>,
+    finished_at: Option<DateTime<Utc>>,
+    /// Monotonic completion time used for retention.
+    finished: Option<Instant>,
+    encoding: Option<String>,
+    stdout: OutputLog,
+    stderr: OutputLog,
+}
+
+struct Job {
+    id: String,
+    request: ExecRequest,
+    created_at: DateTime<Utc>,
+    cancel: CancelToken,
+    status: Mutex<JobStatus>,
+}
+
+impl Job {
+    fn info(&self) -> JobInfo {
+        let status = self.status.lock().unwrap();
+        JobInfo {
+            id: self.id.clone(),
+            state: status.state,
+            shell: self.request.shell,
+            command: self.request.command.clone(),
+            args: self.request.args.clone(),
+            exit_code: status.exit_code,
+            created_at: self.created_at.to_rfc3339(),
+            finished_at: status.finished_at.map(|t| t.to_rfc3339()),
+            stdout_bytes: status.stdout.total(),
+            stderr_bytes: status.stderr.total(),
+            encoding: status.encoding.clone(),
+        }
+    }
+
+    fn finish(&self, state: JobState, exit_code: Option<i32>, encoding: Option<String>) {
+        let mut status = self.status.lock().unwrap();
+        status.state = state;
+        status.exit_code = exit_code;
+        status.finished_at = Some(Utc::now());
+        status.finished = Some(Instant::now());
+        if encoding.is_some() {
+            status.encoding = encoding;
+        }
+    }
+}
+
+/// Thread-safe job manager using DashMap.
+#[derive(Clone)]
+pub struct JobManager {
+    jobs: Arc<DashMap<String, Arc<Job>>>,
+    /// Serializes the limit check with the insertion of a new job.
+    admission: Arc<tokio::sync::Mutex<()>>,
+    /// Running and retained jobs kept at most.
+    max_jobs: usize,
+    /// Retention of finished jobs.
+    retention: Duration,
+    /// Bytes of recent output kept per stream.
+    max_output_bytes: usize,
+    /// Timeout for jobs that do not set `timeout_ms`; `None` lets them run
+    /// until they exit.
+    default_timeout_ms: Option<u64>,
+}
+
+impl JobManager {
+    /// Create a new job manager.
+    pub fn new(
+        max_jobs: usize,
+        retention: Duration,
+        max_output_bytes: usize,
+        default_timeout_ms: Option<u64>,
+    ) -> Self {
+        Self {
+            jobs: Arc::new(DashMap::new()),
+            admission: Arc::new(tokio::sync::Mutex::new(())),
+            max_jobs,
+            retention,
+            max_output_bytes,
+            default_timeout_ms,
+        }
+    }
+
+    /// Start a background task to remove expired jobs.
+    /// Should be called once when the server starts.
+    pub fn start_cleanup_task(&self) {
+        let jobs = self.jobs.clone();
+        let retention = self.retention;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                jobs.retain(|id, job| {
+                    let status = job.status.lock().unwrap();
+                    let expired = status.finished.is_some_and(|t| t.elapsed() > retention);
+                    if expired {
+                        tracing::info!("Removing expired job {}", id);
+                    }
+                    !expired
+                });
+            }
+        });
+    }
+
+    /// Start a job in the background.
+    ///
+    /// Jobs are meant for long-running work, so they do not get the
+    /// 10-minute default timeout of `exec`. When the manager is full, the
+    /// job that finished first is removed; if every job is still running,
+    /// the new one is refused.
+    pub async fn create_job(&self, mut req: ExecRequest) -> Result<JobCreateResponse, ExecError> {
+        if req.stdin_stream {
+            return Err(ExecError::InvalidRequest(
+                "stdin_stream is not supported for jobs".to_string(),
+            ));
+        }
+        if req.timeout_ms.is_none() {
+            req.timeout_ms = self.default_timeout_ms;
+        }
+
+        let _admission = self.admission.lock().await;
+        while self.jobs.len() >= self.max_jobs {
+            if !self.evict_finished() {
+                return Err(ExecError::Busy(format!(
+                    "Too many running jobs (limit {})",
+                    self.max_jobs
+                )));
+            }
+        }
+
+        let exec = executor::execute_command_stream(&req).await?;
+        let id = Ulid::new().to_string();
+        let created_at = Utc::now();
+
+        let job = Arc::new(Job {
+            id: id.clone(),
+            request: req,
+            created_at,
+            cancel: exec.cancel,
+            status: Mutex::new(JobStatus {
+                state: JobState::Running,
+                exit_code: None,
+                finished_at: None,
+                finished: None,
+                encoding: None,
+                stdout: OutputLog::new(self.max_output_bytes),
+                stderr: OutputLog::new(self.max_output_bytes),
+            }),
+        });
+        self.jobs.insert(id.clone(), job.clone());
+
+        // Collect events until the process ends
+        let mut events = exec.events;
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                match event {
+                    StreamEvent::Stdout(chunk) => job.status.lock().unwrap().stdout.push(&chunk),
+                    StreamEvent::Stderr(chunk) => job.status.lock().unwrap().stderr.push(&chunk),
+                    StreamEvent::Exit {
+                        exit_code,
+                        encoding,
+                    } => {
+                        job.finish(JobState::Exited, Some(exit_code), Some(encoding.name()));
+                        break;
+                    }
+                    StreamEvent::Timeout => {
+                        job.finish(JobState::TimedOut, None, None);
+                        break;
+                    }
+                    StreamEvent::Cancelled => {
+                        job.finish(JobState::Cancelled, None, None);
+                        break;
+                    }
+                }
+            }
+            tracing::info!("Job {} finished", job.id);
+        });
+
+        tracing::info!("Started job {}", id);
+        Ok(JobCreateResponse {
+            id,
+            created_at: created_at.to_rfc3339(),
+        })
+    }
+
+    /// Remove the job that finished first; false if none has.
+    fn evict_finished(&self) -> bool {
+        let oldest = self
+            .jobs
+            .iter()
+            .filter_map(|entry| {
+                let finished = entry.status.lock().unwrap().finished?;
+                Some((finished, entry.key().clone()))
+            })
+            .min();
+        match oldest {
+            Some((_, id)) => {
+                tracing::info!("Evicting finished job {}", id);
+                self.jobs.remove(&id).is_some()
+            }
+            None => false,
+        }
+    }
+
+    /// List all jobs, oldest first.
+    pub fn list_jobs(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self.jobs.iter().map(|entry| entry.info()).collect();
+        jobs.sort_by(|a, b| a.id.cmp(&b.id));
+        jobs
+    }
+
+    /// Get job info by ID.
+    pub fn get_job(&self, id: &str) -> Option<JobInfo> {
+        self.jobs.get(id).map(|job| job.info())
+    }
+
+    /// Read up to `max` bytes of a job's output starting at `offset`.
+    pub fn read_output(
+        &self,
+        id: &str,
+        stream: OutputStream,
+        offset: u64,
+        max: usize,
+    ) -> Option<JobOutputResponse> {
+        let job = self.jobs.get(id)?;
+        let status = job.status.lock().unwrap();
+        let log = match stream {
+            OutputStream::Stdout => &status.stdout,
+            OutputStream::Stderr => &status.stderr,
+        };
+
+        let (start, data, truncated) = log.read(offset, max);
+        let next_offset = start + data.len() as u64;
+        Some(JobOutputResponse {
+            stream,
+            offset: start,
+            next_offset,
+            data: data.to_string(),
+            truncated,
+            complete: status.state != JobState::Running && next_offset == log.total(),
+        })
+    }
+
+    /// Cancel a running job, or remove a finished one.
+    pub fn delete_job(&self, id: &str) -> Result<(), String> {
+        let job = self
+            .jobs
+            .get(id)
+            .map(|entry| entry.value().clone())
+            .ok_or_else(|| format!("Job {} not found", id))?;
+
+        if job.status.lock().unwrap().state == JobState::Running {
+            // The job stays listed as cancelled until it expires
+            job.cancel.cancel();
+            tracing::info!("Cancelling job {}", id);
+        } else {
+            self.jobs.remove(id);
+            tracing::info!("Removed job {}", id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(default_timeout_ms: Option<u64>) -> JobManager {
+        JobManager::new(
+            64,
+            Duration::from_secs(3600),
+            1024 * 1024,
+            default_timeout_ms,
+        )
+    }
+
+    #[test]
+    fn output_log_keeps_recent_bytes() {
+        let mut log = OutputLog::new(8);
+        log.push("0123456789");
+        assert_eq!(log.total(), 10);
+        assert_eq!(log.read(0, 100), (2, "23456789", true));
+        assert_eq!(log.read(5, 2), (5, "56", false));
+        assert_eq!(log.read(10, 100), (10, "", false));
+        assert_eq!(log.read(99, 100), (10, "", false));
+    }
+
+    #[test]
+    fn output_log_cuts_on_character_boundaries() {
+        let mut log = OutputLog::new(4);
+        log.push("é€");
+        // One byte over the limit, but "é" can only be dropped whole
+        assert_eq!(log.read(0, 100), (2, "€", true));
+        // A read never ends mid-character, but always makes progress
+        assert_eq!(log.read(2, 2), (2, "€", false));
+        assert_eq!(log.read(2, 1), (2, "€", false));
+        log.push("aé");
+        assert_eq!(log.read(5, 2), (5, "a", false));
+        assert_eq!(log.read(6, 1), (6, "é", false));
+        assert_eq!(log.read(6, 0), (6, "", false));
+    }
+
+    #[cfg(unix)]
+    async fn create(manager: &JobManager, script: &str) -> String {
+        let req: ExecRequest =
+            serde_json::from_value(serde_json::json!({ "shell": "sh", "command": script }))
+                .unwrap();
+        manager.create_job(req).await.unwrap().id
+    }
+
+    #[cfg(unix)]
+    async fn wait_until(
+        manager: &JobManager,
+        id: &str,
+        done: impl Fn(&JobInfo) -> bool,
+    ) -> JobInfo {
+        for _ in 0..200 {
+            let info = manager.get_job(id).unwrap();
+            if done(&info) {
+                return info;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("job {} did not reach the expected state", id);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn jobs_have_no_default_timeout() {
+        let manager = manager(None);
+        let id = create(&manager, "true").await;
+        assert_eq!(manager.jobs.get(&id).unwrap().request.timeout_ms, None);
+
+        let manager = self::manager(Some(200));
+        let id = create(&manager, "sleep 1000").await;
+        let info = wait_until(&manager, &id, |i| i.state != JobState::Running).await;
+        assert_eq!(info.state, JobState::TimedOut);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn polls_output_by_offset() {
+        let manager = manager(None);
+        let id = create(
+            &manager,
+            "for i in 1 2 3; do echo line $i; sleep 0.1; done; echo oops >&2",
+        )
+        .await;
+
+        // Small reads from the last next_offset reassemble the stream
+        let mut stdout = String::new();
+        let mut offset = 0;
+        loop {
+            let out = manager
+                .read_output(&id, OutputStream::Stdout, offset, 4)
+                .unwrap();
+            assert_eq!(out.offset, offset);
+            assert!(!out.truncated);
+            stdout.push_str(&out.data);
+            offset = out.next_offset;
+            if out.complete {
+                break;
+            }
+            if out.data.is_empty() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+        assert_eq!(stdout, "line 1\nline 2\nline 3\n");
+
+        let info = manager.get_job(&id).unwrap();
+        assert_eq!(info.state, JobState::Exited);
+        assert_eq!(info.exit_code, Some(0));
+        assert_eq!(info.stdout_bytes, 21);
+        let stderr = manager
+            .read_output(&id, OutputStream::Stderr, 0, 1024)
+            .unwrap();
+        assert_eq!(stderr.data, "oops\n");
+        assert!(stderr.complete);
+        // Reading past the end returns nothing
+        let out = manager
+            .read_output(&id, OutputStream::Stdout, 1000, 1024)
+            .unwrap();
+        assert_eq!((out.offset, out.data.as_str()), (21, ""));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn delete_cancels_then_removes() {
+        let manager = manager(None);
+        let id = create(&manager, "echo started; sleep 1000").await;
+        wait_until(&manager, &id, |i| i.stdout_bytes > 0).await;
+
+        manager.delete_job(&id).unwrap();
+        let info = wait_until(&manager, &id, |i| i.state != JobState::Running).await;
+        assert_eq!(info.state, JobState::Cancelled);
+        assert_eq!(info.exit_code, None);
+        assert!(info.finished_at.is_some());
+        // Output stays readable until the job is removed
+        let out = manager
+            .read_output(&id, OutputStream::Stdout, 0, 1024)
+            .unwrap();
+        assert_eq!(out.data, "started\n");
+        assert!(out.complete);
+
+        manager.delete_job(&id).unwrap();
+        assert!(manager.get_job(&id).is_none());
+        assert!(manager.delete_job(&id).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn limits_running_and_retained_jobs() {
+        let manager = JobManager::new(2, Duration::from_secs(3600), 1024, None);
+        let first = create(&manager, "sleep 1000").await;
+        let second = create(&manager, "sleep 1000").await;
+        let req: ExecRequest =
+            serde_json::from_value(serde_json::json!({ "shell": "sh", "command": "true" }))
+                .unwrap();
+        assert!(matches!(
+            manager.create_job(req.clone()).await,
+            Err(ExecError::Busy(_))
+        ));
+
+        // A finished job makes room, and is the one removed
+        manager.delete_job(&second).unwrap();
+        wait_until(&manager, &second, |i| i.state != JobState::Running).await;
+        let third = manager.create_job(req).await.unwrap().id;
+        assert!(manager.get_job(&second).is_none());
+        assert!(manager.get_job(&first).is_some());
+        assert!(manager.get_job(&third).is_some());
+        manager.delete_job(&first).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_stdin_stream() {
+        let req: ExecRequest = serde_json::from_value(
+            serde_json::json!({ "shell": "direct", "command": "x", "stdin_stream": true }),
+        )
+        .unwrap();
+        assert!(matches!(
+            manager(None).create_job(req).await,
+            Err(ExecError::InvalidRequest(_))
+        ));
+    }
+}
@@ -1,3 +1,12 @@
 //! Automation module for command execution.
 
+pub mod capture;
+pub mod encoding;
 pub mod executor;
+pub mod jobs;
+pub(crate) mod quoting;
+pub mod registry;
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+pub(crate) mod win32;
@@ -0,0 +1,413 @@
+//! Command line quoting for each launch mode.
+//!
+//! Pure string logic shared by the platform executors:
+//! - Windows argv quoting, the inverse of `CommandLineToArgvW`
+//! - `cmd.exe` metacharacter escaping
+//! - PowerShell and POSIX shell literals
+
+// The Windows helpers are only called by the Win32 executor
+#![cfg_attr(not(windows), allow(dead_code))]
+
+/// Characters `cmd.exe` interprets on its command line.
+const CMD_METACHARS: &[char] = &['(', ')', '%', '!', '^', '"', '<', '>', '&', '|'];
+
+/// Quote an argument so `CommandLineToArgvW` (and the MSVC CRT) parse it back unchanged.
+///
+/// Backslashes are literal unless they precede a quote; those are doubled,
+/// and embedded quotes are escaped with a backslash.
+pub fn windows_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                push_backslashes(&mut quoted, backslashes * 2 + 1);
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                push_backslashes(&mut quoted, backslashes);
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    // Backslashes before the closing quote must not escape it
+    push_backslashes(&mut quoted, backslashes * 2);
+    quoted.push('"');
+    quoted
+}
+
+fn push_backslashes(s: &mut String, count: usize) {
+    s.extend(std::iter::repeat_n('\\', count));
+}
+
+/// Reject text that cannot be carried on a Windows command line.
+///
+/// The command line is passed NUL-terminated, so a NUL would silently cut it short.
+pub fn check_nul(s: &str) -> Result<(), String> {
+    if s.contains('\0') {
+        return Err("Command lines cannot contain NUL characters".to_string());
+    }
+    Ok(())
+}
+
+/// Join a program and its arguments into a Windows command line.
+pub fn windows_command_line(program: &str, args: &[String]) -> Result<String, String> {
+    check_nul(program)?;
+    let mut line = windows_arg(program);
+    for arg in args {
+        check_nul(arg)?;
+        line.push(' ');
+        line.push_str(&windows_arg(arg));
+    }
+    Ok(line)
+}
+
+/// Escape `cmd.exe` metacharacters with `^` so they reach the program literally.
+///
+/// Apply to text that is already argv-quoted. `%` escaped as `^%` is not
+/// expanded on a `cmd /c` command line, since `%VAR^%` names no variable.
+pub fn cmd_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() * 2);
+    for c in s.chars() {
+        if CMD_METACHARS.contains(&c) {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Build the `cmd.exe /c` command line running `command` with escaped `args`.
+///
+/// `command` is a cmd snippet and passed through verbatim; each argument is
+/// argv-quoted and then metacharacter-escaped. cmd cannot carry line breaks
+/// inside an argument, so those are rejected, as is NUL anywhere.
+pub fn cmd_command_line(command: &str, args: &[String]) -> Result<String, String> {
+    check_nul(command)?;
+    let mut line = format!("cmd.exe /d /s /c \"{}", command);
+    for arg in args {
+        check_nul(arg)?;
+        if arg.contains(['\r', '\n']) {
+            return Err("Arguments passed through cmd cannot contain line breaks".to_string());
+        }
+        line.push(' ');
+        line.push_str(&cmd_escape(&windows_arg(arg)));
+    }
+    // With /s, cmd strips exactly the first and last quote and runs the rest as-is
+    line.push('"');
+    Ok(line)
+}
+
+/// Quote an argument as a PowerShell single-quoted string literal.
+pub fn powershell_literal(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('\'');
+    for c in arg.chars() {
+        // PowerShell also treats typographic single quotes as quote characters
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Quote an argument for POSIX shells.
+#[cfg(unix)]
+pub fn posix_arg(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Build a shell script from a command snippet and arguments quoted with `quote`.
+pub fn script(command: &str, args: &[String], quote: fn(&str) -> String) -> String {
+    let mut script = command.to_string();
+    for arg in args {
+        script.push(' ');
+        script.push_str(&quote(arg));
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arguments every quoting mode must carry through unchanged.
+    const ARGS: &[&str] = &[
+        "",
+        "plain",
+        "two words",
+        "tab\there",
+        "  leading and trailing  ",
+        "say \"hi\"",
+        "\"",
+        "\"\"",
+        "trailing\\",
+        "trailing\\\\",
+        "C:\\Program Files\\",
+        "back\\\"quote",
+        "back\\\\\"quote",
+        "\\\\server\\share\\",
+        "a & b",
+        "a|b",
+        "100%",
+        "%PATH%",
+        "%%",
+        "^caret^",
+        "bang!",
+        "<in> >out",
+        "(paren)",
+        "'single' `tick` $HOME",
+        "ünïcödé",
+        "中文 路径",
+        "emoji 😀",
+        "‘typographic’",
+    ];
+
+    fn args() -> Vec<String> {
+        ARGS.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// Split a command line the way `CommandLineToArgvW` and the MSVC CRT do.
+    fn command_line_to_argv(line: &str) -> Vec<String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut argv = Vec::new();
+        let mut i = 0;
+
+        // The program name ends at the first whitespace outside quotes; no escapes
+        let mut program = String::new();
+        let mut quoted = false;
+        while i < chars.len() && (quoted || !matches!(chars[i], ' ' | '\t')) {
+            match chars[i] {
+                '"' => quoted = !quoted,
+                c => program.push(c),
+            }
+            i += 1;
+        }
+        argv.push(program);
+
+        loop {
+            while i < chars.len() && matches!(chars[i], ' ' | '\t') {
+                i += 1;
+            }
+            if i == chars.len() {
+                return argv;
+            }
+            let mut arg = String::new();
+            let mut quoted = false;
+            while i < chars.len() && (quoted || !matches!(chars[i], ' ' | '\t')) {
+                match chars[i] {
+                    '\\' => {
+                        let run = chars[i..].iter().take_while(|&&c| c == '\\').count();
+                        i += run;
+                        if chars.get(i) == Some(&'"') {
+                            arg.extend(std::iter::repeat_n('\\', run / 2));
+                            if run % 2 == 1 {
+                                arg.push('"');
+                                i += 1;
+                            }
+                        } else {
+                            arg.extend(std::iter::repeat_n('\\', run));
+                        }
+                        continue;
+                    }
+                    // Inside quotes, a doubled quote is a literal quote
+                    '"' if quoted && chars.get(i + 1) == Some(&'"') => {
+                        arg.push('"');
+                        i += 1;
+                    }
+                    '"' => quoted = !quoted,
+                    c => arg.push(c),
+                }
+                i += 1;
+            }
+            argv.push(arg);
+        }
+    }
+
+    /// Process a `cmd /d /s /c "..."` line the way cmd.exe does before
+    /// starting the program: expand `%VAR%`, then remove `^` escapes.
+    fn cmd_to_argv(line: &str) -> Vec<String> {
+        let inner = line
+            .strip_prefix("cmd.exe /d /s /c \"")
+            .and_then(|rest| rest.strip_suffix('"'))
+            .unwrap();
+        let env = |name: &str| name.eq_ignore_ascii_case("PATH").then_some("EXPANDED");
+
+        let mut expanded = String::new();
+        let mut rest = inner;
+        while let Some(start) = rest.find('%') {
+            expanded.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            match after
+                .find('%')
+                .and_then(|end| Some((end, env(&after[..end])?)))
+            {
+                Some((end, value)) => {
+                    expanded.push_str(value);
+                    rest = &after[end + 1..];
+                }
+                // Undefined variables are left as typed
+                None => {
+                    expanded.push('%');
+                    rest = after;
+                }
+            }
+        }
+        expanded.push_str(rest);
+
+        let mut unescaped = String::new();
+        let mut quoted = false;
+        let mut chars = expanded.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '^' if !quoted => unescaped.extend(chars.next()),
+                '"' => {
+                    quoted = !quoted;
+                    unescaped.push(c);
+                }
+                _ => unescaped.push(c),
+            }
+        }
+        command_line_to_argv(&unescaped)
+    }
+
+    /// Read back a PowerShell single-quoted string literal.
+    fn parse_powershell_literal(literal: &str) -> String {
+        let is_quote =
+            |c: char| matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}');
+        let chars: Vec<char> = literal.chars().collect();
+        assert!(is_quote(chars[0]) && is_quote(chars[chars.len() - 1]));
+        let body = &chars[1..chars.len() - 1];
+        let mut value = String::new();
+        let mut i = 0;
+        while i < body.len() {
+            if is_quote(body[i]) {
+                // A lone quote would end the literal early
+                assert!(i + 1 < body.len() && is_quote(body[i + 1]), "{}", literal);
+                i += 1;
+            }
+            value.push(body[i]);
+            i += 1;
+        }
+        value
+    }
+
+    #[test]
+    fn windows_arg_round_trips() {
+        for arg in ARGS {
+            let line = format!("prog.exe {}", windows_arg(arg));
+            assert_eq!(command_line_to_argv(&line), ["prog.exe", arg], "{}", line);
+        }
+    }
+
+    #[test]
+    fn windows_arg_leaves_simple_args_alone() {
+        assert_eq!(windows_arg("plain"), "plain");
+        assert_eq!(windows_arg(r"C:\dir\file"), r"C:\dir\file");
+        assert_eq!(windows_arg(""), "\"\"");
+        assert_eq!(windows_arg(r"dir\"), r"dir\");
+        assert_eq!(windows_arg(r"a dir\"), r#""a dir\\""#);
+        assert_eq!(windows_arg(r#"a"b"#), r#""a\"b""#);
+    }
+
+    #[test]
+    fn windows_command_line_round_trips() {
+        let line = windows_command_line(r"C:\Program Files\tool.exe", &args()).unwrap();
+        let mut expected = vec![r"C:\Program Files\tool.exe".to_string()];
+        expected.extend(args());
+        assert_eq!(command_line_to_argv(&line), expected);
+    }
+
+    #[test]
+    fn cmd_command_line_round_trips() {
+        let line = cmd_command_line("prog.exe", &args()).unwrap();
+        let mut expected = vec!["prog.exe".to_string()];
+        expected.extend(args());
+        assert_eq!(cmd_to_argv(&line), expected, "{}", line);
+    }
+
+    #[test]
+    fn cmd_command_passes_through() {
+        let line = cmd_command_line("echo %PATH% & dir", &["x y".to_string()]).unwrap();
+        assert_eq!(line, r#"cmd.exe /d /s /c "echo %PATH% & dir ^"x y^"""#);
+    }
+
+    #[test]
+    fn cmd_escape_covers_metacharacters() {
+        assert_eq!(cmd_escape("&|%^!<>()\""), "^&^|^%^^^!^<^>^(^)^\"");
+        assert_eq!(cmd_escape("plain text"), "plain text");
+    }
+
+    #[test]
+    fn powershell_literal_round_trips() {
+        for arg in ARGS {
+            let literal = powershell_literal(arg);
+            assert_eq!(parse_powershell_literal(&literal), *arg);
+            // The script travels to powershell.exe as a single argument
+            let line = format!("powershell.exe -Command {}", windows_arg(&literal));
+            assert_eq!(command_line_to_argv(&line)[2], literal);
+        }
+        assert_eq!(powershell_literal("it's"), "'it''s'");
+        assert_eq!(powershell_literal("$env:PATH"), "'$env:PATH'");
+    }
+
+    #[test]
+    fn rejects_nul() {
+        let nul = vec!["a\0b".to_string()];
+        assert!(windows_command_line("prog.exe", &nul).is_err());
+        assert!(windows_command_line("prog\0.exe", &[]).is_err());
+        assert!(cmd_command_line("prog.exe", &nul).is_err());
+        assert!(cmd_command_line("echo\0", &[]).is_err());
+        assert!(check_nul("fine").is_ok());
+    }
+
+    #[test]
+    fn cmd_rejects_line_breaks() {
+        for arg in ["a\nb", "a\rb", "\r\n"] {
+            assert!(cmd_command_line("prog.exe", &[arg.to_string()]).is_err());
+        }
+        // Other modes carry them inside quotes
+        let line = windows_command_line("prog.exe", &["a\nb".to_string()]).unwrap();
+        assert_eq!(command_line_to_argv(&line), ["prog.exe", "a\nb"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn posix_arg_round_trips() {
+        let mut args = args();
+        args.push("new\nline".to_string());
+        args.push("it's".to_string());
+        args.push("-n".to_string());
+        let script = script("printf '%s\\0'", &args, posix_arg);
+        let output = std::process::Command::new("/bin/sh")
+            .args(["-c", &script])
+            .output()
+            .unwrap();
+        let printed: Vec<&[u8]> = output.stdout.split(|&b| b == 0).collect();
+        let expected: Vec<&[u8]> = args
+            .iter()
+            .map(|a| a.as_bytes())
+            .chain([&b""[..]])
+            .collect();
+        assert_eq!(printed, expected);
+        assert_eq!(posix_arg("plain-arg_1.txt"), "plain-arg_1.txt");
+        assert_eq!(posix_arg(""), "''");
+    }
+}
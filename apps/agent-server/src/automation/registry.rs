@@ -0,0 +1,397 @@
+//! Registry of streaming executions.
+//!
+//! `exec_stream` registers each execution under the ID announced in its
+//! `start` event, so follow-up requests (such as stdin writes) can address it.
+//!
+//! Every execution keeps a bounded log of its recent SSE events numbered
+//! from 1. Clients read the log through an [`EventCursor`], so a client that
+//! lost its connection can resume after the last event ID it received.
+//! Finished executions stay replayable for [`FINISHED_RETENTION`], or until
+//! the registry needs room for a new execution.
+
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, mpsc};
+use ulid::Ulid;
+
+/// How long a finished execution's events remain available for replay.
+const FINISHED_RETENTION: Duration = Duration::from_secs(300);
+
+/// Errors when writing to an execution's stdin.
+#[derive(Debug)]
+pub enum StdinError {
+    /// No running execution with this ID.
+    NotFound,
+    /// Stdin was not opened for streaming, was closed, or the process stopped reading.
+    Closed,
+}
+
+/// A logged SSE event.
+#[derive(Clone)]
+pub struct LoggedEvent {
+    /// Sequence number, starting at 1.
+    pub id: u64,
+    /// SSE event name.
+    pub event: &'static str,
+    /// JSON payload.
+    pub data: String,
+}
+
+/// Recent events of one execution, keeping at most `limit` payload bytes.
+struct EventLog {
+    events: VecDeque<LoggedEvent>,
+    bytes: usize,
+    limit: usize,
+    next_id: u64,
+    /// When the execution finished, once it has.
+    finished: Option<Instant>,
+}
+
+impl EventLog {
+    /// ID of the oldest retained event.
+    fn first_id(&self) -> u64 {
+        self.events.front().map_or(self.next_id, |e| e.id)
+    }
+
+    fn push(&mut self, event: &'static str, data: String) {
+        self.bytes += data.len();
+        self.events.push_back(LoggedEvent {
+            id: self.next_id,
+            event,
+            data,
+        });
+        self.next_id += 1;
+        // Always keep the newest event, however large
+        while self.bytes > self.limit && self.events.len() > 1 {
+            if let Some(old) = self.events.pop_front() {
+                self.bytes -= old.data.len();
+            }
+        }
+    }
+}
+
+struct ExecEntry {
+    stdin: Mutex<Option<mpsc::Sender<Vec<u8>>>>,
+    log: Mutex<EventLog>,
+    /// Wakes cursors waiting for new events.
+    changed: Notify,
+}
+
+/// Shared map of streaming executions.
+#[derive(Clone)]
+pub struct ExecRegistry {
+    executions: Arc<DashMap<String, Arc<ExecEntry>>>,
+    /// Serializes registrations so the limit holds under concurrent requests.
+    admission: Arc<Mutex<()>>,
+    /// Running and retained executions kept at most.
+    max_executions: usize,
+    /// Bytes of recent event data kept per execution.
+    buffer_bytes: usize,
+}
+
+impl ExecRegistry {
+    pub fn new(max_executions: usize, buffer_bytes: usize) -> Self {
+        Self {
+            executions: Arc::new(DashMap::new()),
+            admission: Arc::new(Mutex::new(())),
+            max_executions,
+            buffer_bytes,
+        }
+    }
+
+    /// Register an execution, before its process is started.
+    ///
+    /// When the registry is full, the execution that finished first is
+    /// evicted; if every execution is still running, registration fails.
+    /// The execution is marked finished when the returned handle is dropped,
+    /// and removed after [`FINISHED_RETENTION`].
+    pub fn register(&self) -> Result<ExecHandle, String> {
+        let _admission = self.admission.lock().unwrap();
+        while self.executions.len() >= self.max_executions {
+            if !self.evict_finished() {
+                return Err(format!(
+                    "Too many running executions (limit {})",
+                    self.max_executions
+                ));
+            }
+        }
+
+        let id = Ulid::new().to_string();
+        let entry = Arc::new(ExecEntry {
+            stdin: Mutex::new(None),
+            log: Mutex::new(EventLog {
+                events: VecDeque::new(),
+                bytes: 0,
+                limit: self.buffer_bytes,
+                next_id: 1,
+                finished: None,
+            }),
+            changed: Notify::new(),
+        });
+        self.executions.insert(id.clone(), entry.clone());
+        Ok(ExecHandle {
+            registry: self.clone(),
+            entry,
+            id,
+        })
+    }
+
+    /// Remove the execution that finished first; false if none has.
+    fn evict_finished(&self) -> bool {
+        let oldest = self
+            .executions
+            .iter()
+            .filter_map(|e| Some((e.log.lock().unwrap().finished?, e.key().clone())))
+            .min();
+        match oldest {
+            Some((_, id)) => {
+                tracing::debug!("Evicting finished execution {}", id);
+                self.executions.remove(&id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Follow an execution's events with IDs greater than `after`.
+    pub fn follow(&self, id: &str, after: u64) -> Option<EventCursor> {
+        let entry = self.executions.get(id)?.value().clone();
+        Some(EventCursor {
+            entry,
+            next_id: after + 1,
+        })
+    }
+
+    /// Write `data` to the execution's stdin, then close it if `close` is set.
+    pub async fn write_stdin(
+        &self,
+        id: &str,
+        data: Vec<u8>,
+        close: bool,
+    ) -> Result<(), StdinError> {
+        let entry = self
+            .executions
+            .get(id)
+            .map(|e| e.value().clone())
+            .ok_or(StdinError::NotFound)?;
+
+        let sender = entry.stdin.lock().unwrap().clone();
+        let sender = sender.ok_or(StdinError::Closed)?;
+        if !data.is_empty() {
+            sender.send(data).await.map_err(|_| StdinError::Closed)?;
+        }
+
+        if close {
+            // Dropping the last sender lets the writer thread close the pipe
+            entry.stdin.lock().unwrap().take();
+        }
+        Ok(())
+    }
+}
+
+/// Producer side of a registered execution.
+pub struct ExecHandle {
+    registry: ExecRegistry,
+    entry: Arc<ExecEntry>,
+    /// Execution ID.
+    pub id: String,
+}
+
+impl ExecHandle {
+    /// Attach the sender feeding the process's stdin.
+    pub fn set_stdin(&self, stdin: Option<mpsc::Sender<Vec<u8>>>) {
+        *self.entry.stdin.lock().unwrap() = stdin;
+    }
+
+    /// Remove an execution whose process could not be started.
+    pub fn discard(self) {
+        self.registry.executions.remove(&self.id);
+    }
+
+    /// Append an event and wake its followers.
+    pub fn push(&self, event: &'static str, data: String) {
+        self.entry.log.lock().unwrap().push(event, data);
+        self.entry.changed.notify_waiters();
+    }
+}
+
+impl Drop for ExecHandle {
+    fn drop(&mut self) {
+        self.entry.stdin.lock().unwrap().take();
+        self.entry.log.lock().unwrap().finished = Some(Instant::now());
+        self.entry.changed.notify_waiters();
+
+        let executions = self.registry.executions.clone();
+        let id = self.id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(FINISHED_RETENTION).await;
+            executions.remove(&id);
+        });
+    }
+}
+
+/// An item read from an [`EventCursor`].
+pub enum CursorItem {
+    /// The next event.
+    Event(LoggedEvent),
+    /// This many events were discarded from the log before they could be read.
+    Skipped(u64),
+}
+
+/// Reads an execution's events in order, waiting for new ones.
+pub struct EventCursor {
+    entry: Arc<ExecEntry>,
+    next_id: u64,
+}
+
+impl EventCursor {
+    /// Return the next item, or `None` once the execution finished and all events were read.
+    pub async fn next(&mut self) -> Option<CursorItem> {
+        loop {
+            // Register for wakeups before checking, so no push is missed
+            let changed = self.entry.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            {
+                let log = self.entry.log.lock().unwrap();
+                let first_id = log.first_id();
+                if self.next_id < first_id {
+                    let skipped = first_id - self.next_id;
+                    self.next_id = first_id;
+                    return Some(CursorItem::Skipped(skipped));
+                }
+                if let Some(event) = log.events.get((self.next_id - first_id) as usize) {
+                    self.next_id += 1;
+                    return Some(CursorItem::Event(event.clone()));
+                }
+                if log.finished.is_some() {
+                    return None;
+                }
+            }
+
+            changed.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(cursor: &mut EventCursor) -> Vec<String> {
+        let mut items = Vec::new();
+        while let Some(item) = cursor.next().await {
+            items.push(match item {
+                CursorItem::Event(e) => format!("{}:{}:{}", e.id, e.event, e.data),
+                CursorItem::Skipped(n) => format!("skipped {}", n),
+            });
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn replays_after_an_event_id() {
+        let registry = ExecRegistry::new(4, 1024);
+        let handle = registry.register().unwrap();
+        let id = handle.id.clone();
+        handle.push("stdout", "a".to_string());
+        handle.push("stdout", "b".to_string());
+        handle.push("exit", "0".to_string());
+        drop(handle);
+
+        let mut cursor = registry.follow(&id, 1).unwrap();
+        assert_eq!(collect(&mut cursor).await, ["2:stdout:b", "3:exit:0"]);
+        assert!(registry.follow("unknown", 0).is_none());
+    }
+
+    #[tokio::test]
+    async fn follows_live_events() {
+        let registry = ExecRegistry::new(4, 1024);
+        let handle = registry.register().unwrap();
+        let mut cursor = registry.follow(&handle.id, 0).unwrap();
+        let reader = tokio::spawn(async move { collect(&mut cursor).await });
+        tokio::task::yield_now().await;
+        handle.push("stdout", "late".to_string());
+        drop(handle);
+        assert_eq!(reader.await.unwrap(), ["1:stdout:late"]);
+    }
+
+    #[tokio::test]
+    async fn replay_buffer_is_bounded() {
+        let registry = ExecRegistry::new(4, 10);
+        let handle = registry.register().unwrap();
+        for i in 0..5 {
+            handle.push("stdout", format!("chunk{}", i));
+        }
+        let id = handle.id.clone();
+        drop(handle);
+
+        // Only the last 10 bytes (two 6-byte events do not fit) are kept
+        let mut cursor = registry.follow(&id, 0).unwrap();
+        assert_eq!(collect(&mut cursor).await, ["skipped 4", "5:stdout:chunk4"]);
+    }
+
+    #[tokio::test]
+    async fn evicts_oldest_finished_first() {
+        let registry = ExecRegistry::new(3, 1024);
+        let first = registry.register().unwrap();
+        let second = registry.register().unwrap();
+        let running = registry.register().unwrap();
+        let (first_id, second_id) = (first.id.clone(), second.id.clone());
+        // The later registration finishes first
+        drop(second);
+        drop(first);
+
+        let fourth = registry.register().unwrap();
+        assert!(registry.follow(&second_id, 0).is_none());
+        assert!(registry.follow(&first_id, 0).is_some());
+
+        let _fifth = registry.register().unwrap();
+        assert!(registry.follow(&first_id, 0).is_none());
+        assert!(registry.follow(&running.id, 0).is_some());
+        assert!(registry.follow(&fourth.id, 0).is_some());
+    }
+
+    #[tokio::test]
+    async fn refuses_when_all_are_running() {
+        let registry = ExecRegistry::new(2, 1024);
+        let _a = registry.register().unwrap();
+        let b = registry.register().unwrap();
+        assert!(registry.register().is_err());
+
+        // A discarded execution frees its slot at once
+        b.discard();
+        assert!(registry.register().is_ok());
+    }
+
+    #[tokio::test]
+    async fn writes_and_closes_stdin() {
+        let registry = ExecRegistry::new(4, 1024);
+        let handle = registry.register().unwrap();
+        assert!(matches!(
+            registry.write_stdin(&handle.id, b"x".to_vec(), false).await,
+            Err(StdinError::Closed)
+        ));
+
+        let (tx, mut rx) = mpsc::channel(4);
+        handle.set_stdin(Some(tx));
+        registry
+            .write_stdin(&handle.id, b"hello".to_vec(), true)
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().await.unwrap(), b"hello");
+        // Closing dropped the only sender
+        assert!(rx.recv().await.is_none());
+        assert!(matches!(
+            registry.write_stdin(&handle.id, Vec::new(), false).await,
+            Err(StdinError::Closed)
+        ));
+        assert!(matches!(
+            registry.write_stdin("unknown", Vec::new(), false).await,
+            Err(StdinError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+//! Unix executor using process groups.
+//!
+//! Each command runs as the leader of a new process group, so a timeout
+//! kills the whole tree with a single `kill(-pgid, SIGKILL)`.
+
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use winpe_agent_core::{ExecRequest, Shell};
+
+use super::executor::{ChildProcess, ExecError, Executor, SpawnedProcess};
+use super::quoting;
+
+/// Executor backed by `std::process::Command`.
+pub struct UnixExecutor;
+
+/// A process leading its own process group.
+///
+/// The leader is only reaped on drop, after the group has been killed:
+/// while it is a zombie its pid, and so the group id, cannot be reused,
+/// so `kill_tree` never signals an unrelated group.
+struct UnixChild {
+    child: Child,
+    pgid: libc::pid_t,
+}
+
+impl ChildProcess for UnixChild {
+    fn try_wait(&self) -> std::io::Result<Option<i32>> {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // SAFETY: `info` is a valid out pointer; WNOWAIT leaves the child waitable.
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                self.pgid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        if result == -1 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(e);
+        }
+        // SAFETY: waitid filled in a SIGCHLD siginfo, or left it zeroed.
+        let (pid, status) = unsafe { (info.si_pid(), info.si_status()) };
+        if pid == 0 {
+            return Ok(None);
+        }
+        // Report signal deaths the way shells do
+        if info.si_code == libc::CLD_EXITED {
+            Ok(Some(status))
+        } else {
+            Ok(Some(128 + status))
+        }
+    }
+
+    fn kill_tree(&self) {
+        unsafe {
+            libc::kill(-self.pgid, libc::SIGKILL);
+        }
+    }
+}
+
+impl Drop for UnixChild {
+    fn drop(&mut self) {
+        // Kill background descendants, then reap the leader
+        self.kill_tree();
+        let _ = self.child.wait();
+    }
+}
+
+impl Executor for UnixExecutor {
+    fn spawn(&self, req: &ExecRequest, pipe_stdin: bool) -> Result<SpawnedProcess, ExecError> {
+        let posix_script = || quoting::script(&req.command, &req.args, quoting::posix_arg);
+        let (program, args) = match req.shell {
+            Shell::Sh => ("/bin/sh", vec!["-c".to_string(), posix_script()]),
+            Shell::Bash => ("bash", vec!["-c".to_string(), posix_script()]),
+            Shell::Powershell => (
+                "pwsh",
+                vec![
+                    "-NoLogo".to_string(),
+                    "-NoProfile".to_string(),
+                    "-Command".to_string(),
+                    quoting::script(&req.command, &req.args, quoting::powershell_literal),
+                ],
+            ),
+            // No shell: argv is passed through unmodified
+            Shell::Direct => (req.command.as_str(), req.args.clone()),
+            Shell::Cmd => {
+                return Err(ExecError::NotSupported(
+                    "Shell Cmd is not supported on this platform".to_string(),
+                ));
+            }
+        };
+
+        let mut command = Command::new(program);
+        command
+            .args(&args)
+            .envs(&req.env)
+            .stdin(if pipe_stdin {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+        if let Some(cwd) = &req.cwd {
+            command.current_dir(cwd);
+        }
+
+        let mut child = command.spawn().map_err(|e| {
+            ExecError::ProcessCreationFailed(format!("Failed to spawn {}: {}", program, e))
+        })?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .map(|pipe| Box::new(pipe) as Box<dyn std::io::Write + Send>);
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let pgid = child.id() as libc::pid_t;
+
+        Ok(SpawnedProcess {
+            child: Arc::new(UnixChild { child, pgid }),
+            stdin,
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::time::Duration;
+
+    fn spawn(script: &str) -> SpawnedProcess {
+        let req: ExecRequest =
+            serde_json::from_value(serde_json::json!({ "shell": "sh", "command": script }))
+                .unwrap();
+        UnixExecutor.spawn(&req, false).unwrap()
+    }
+
+    fn first_line(process: &mut SpawnedProcess) -> String {
+        let mut line = String::new();
+        BufReader::new(&mut process.stdout)
+            .read_line(&mut line)
+            .unwrap();
+        line.trim().to_string()
+    }
+
+    /// Whether `pid` is gone within a few seconds; SIGKILL lands asynchronously.
+    fn exits(pid: u32) -> bool {
+        (0..100).any(|_| {
+            std::thread::sleep(Duration::from_millis(50));
+            // A killed orphan may linger as a zombie until init reaps it
+            !std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .map(|stat| {
+                    !stat
+                        .rsplit(')')
+                        .next()
+                        .unwrap_or("")
+                        .trim_start()
+                        .starts_with('Z')
+                })
+                .unwrap_or(false)
+        })
+    }
+
+    fn wait(child: &dyn ChildProcess) -> i32 {
+        loop {
+            if let Some(code) = child.try_wait().unwrap() {
+                return code;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn kill_tree_kills_grandchildren() {
+        let mut process = spawn("sleep 1000 & echo $!; wait");
+        let grandchild: u32 = first_line(&mut process).parse().unwrap();
+        process.child.kill_tree();
+        assert_eq!(wait(process.child.as_ref()), 128 + libc::SIGKILL);
+        assert!(exits(grandchild));
+    }
+
+    #[test]
+    fn drop_kills_background_descendants() {
+        let mut process = spawn("sleep 1000 & echo $!");
+        let grandchild: u32 = first_line(&mut process).parse().unwrap();
+        assert_eq!(wait(process.child.as_ref()), 0);
+        drop(process);
+        assert!(exits(grandchild));
+    }
+
+    /// Whether `pid` is an unreaped zombie.
+    fn is_zombie(pid: u32) -> bool {
+        std::fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| {
+            stat.rsplit(')')
+                .next()
+                .unwrap_or("")
+                .trim_start()
+                .starts_with('Z')
+        })
+    }
+
+    #[test]
+    fn leader_stays_unreaped_until_drop() {
+        let mut process = spawn("echo $$; exit 7");
+        let leader: u32 = first_line(&mut process).parse().unwrap();
+        assert_eq!(wait(process.child.as_ref()), 7);
+        // The exit code can be asked for again, and the pid is still held
+        assert_eq!(process.child.try_wait().unwrap(), Some(7));
+        assert!(is_zombie(leader));
+        // Signalling the group of a zombie leader reaches no other process
+        process.child.kill_tree();
+        drop(process);
+        assert!(!is_zombie(leader));
+    }
+
+    #[test]
+    fn cmd_is_not_supported() {
+        let req: ExecRequest =
+            serde_json::from_value(serde_json::json!({ "shell": "cmd", "command": "dir" }))
+                .unwrap();
+        assert!(matches!(
+            UnixExecutor.spawn(&req, false),
+            Err(ExecError::NotSupported(_))
+        ));
+    }
+}
@@ -0,0 +1,428 @@
+//! Windows executor using Win32 `CreateProcessW` and Job Objects.
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::FromRawHandle;
+use std::ptr;
+use std::sync::Arc;
+use windows_sys::Win32::Foundation::*;
+use windows_sys::Win32::Security::*;
+use windows_sys::Win32::System::Pipes::*;
+use windows_sys::Win32::System::Threading::*;
+use winpe_agent_core::{ExecRequest, Shell};
+
+use super::executor::{ChildProcess, ExecError, Executor, SpawnedProcess};
+use super::quoting;
+
+// Job Objects FFI definitions (not always available in windows-sys)
+const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: u32 = 0x00002000;
+const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: i32 = 9;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct JOBOBJECT_BASIC_LIMIT_INFORMATION {
+    per_process_user_time_limit: i64,
+    per_job_user_time_limit: i64,
+    limit_flags: u32,
+    minimum_working_set_size: usize,
+    maximum_working_set_size: usize,
+    active_process_limit: u32,
+    affinity: usize,
+    priority_class: u32,
+    scheduling_class: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IO_COUNTERS {
+    read_operation_count: u64,
+    write_operation_count: u64,
+    other_operation_count: u64,
+    read_transfer_count: u64,
+    write_transfer_count: u64,
+    other_transfer_count: u64,
+}
+
+#[repr(C)]
+struct JOBOBJECT_EXTENDED_LIMIT_INFORMATION {
+    basic_limit_information: JOBOBJECT_BASIC_LIMIT_INFORMATION,
+    io_info: IO_COUNTERS,
+    process_memory_limit: usize,
+    job_memory_limit: usize,
+    peak_process_memory_used: usize,
+    peak_job_memory_used: usize,
+}
+
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn CreateJobObjectW(lpJobAttributes: *mut SECURITY_ATTRIBUTES, lpName: *const u16) -> HANDLE;
+    fn SetInformationJobObject(
+        hJob: HANDLE,
+        JobObjectInformationClass: i32,
+        lpJobObjectInformation: *const std::ffi::c_void,
+        cbJobObjectInformationLength: u32,
+    ) -> i32;
+    fn AssignProcessToJobObject(hJob: HANDLE, hProcess: HANDLE) -> i32;
+    fn TerminateJobObject(hJob: HANDLE, uExitCode: u32) -> i32;
+}
+
+/// Executor backed by `CreateProcessW`.
+pub struct Win32Executor;
+
+/// A process running inside a kill-on-close Job Object.
+///
+/// Handles are stored as usize for Send safety; `job` is 0 if no job could be created.
+struct Win32Child {
+    process: usize,
+    job: usize,
+}
+
+impl ChildProcess for Win32Child {
+    fn try_wait(&self) -> std::io::Result<Option<i32>> {
+        let handle = self.process as HANDLE;
+        if unsafe { WaitForSingleObject(handle, 0) } != WAIT_OBJECT_0 {
+            return Ok(None);
+        }
+        let mut exit_code: u32 = 0;
+        if unsafe { GetExitCodeProcess(handle, &mut exit_code) } == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Some(exit_code as i32))
+    }
+
+    fn kill_tree(&self) {
+        // Terminate job (kills entire process tree) or fallback to process
+        unsafe {
+            if self.job != 0 {
+                TerminateJobObject(self.job as HANDLE, 1);
+            } else {
+                TerminateProcess(self.process as HANDLE, 1);
+            }
+        }
+    }
+}
+
+impl Drop for Win32Child {
+    fn drop(&mut self) {
+        unsafe {
+            // Closing a kill-on-close job terminates any remaining descendants
+            if self.job != 0 {
+                CloseHandle(self.job as HANDLE);
+            }
+            CloseHandle(self.process as HANDLE);
+        }
+    }
+}
+
+/// A process attribute list limiting inheritance to a set of handles.
+///
+/// `CreateProcessW` otherwise hands the child every inheritable handle in
+/// the server, including the pipe ends of commands spawned at the same
+/// time, whose readers would then not see EOF until this child exits.
+struct InheritList {
+    /// Storage for the opaque list, aligned for the pointers it holds.
+    buffer: Vec<usize>,
+    /// Referenced by the list rather than copied into it.
+    handles: Vec<HANDLE>,
+}
+
+impl InheritList {
+    fn new(handles: Vec<HANDLE>) -> Result<Self, String> {
+        let mut size: usize = 0;
+        unsafe {
+            InitializeProcThreadAttributeList(ptr::null_mut(), 1, 0, &mut size);
+        }
+        let mut buffer = vec![0usize; size.div_ceil(std::mem::size_of::<usize>())];
+        let attributes = buffer.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST;
+        if unsafe { InitializeProcThreadAttributeList(attributes, 1, 0, &mut size) } == 0 {
+            return Err(format!(
+                "InitializeProcThreadAttributeList failed with error {}",
+                unsafe { GetLastError() }
+            ));
+        }
+        // From here on the list is deleted on drop
+        let mut list = Self { buffer, handles };
+        let result = unsafe {
+            UpdateProcThreadAttribute(
+                list.attributes(),
+                0,
+                PROC_THREAD_ATTRIBUTE_HANDLE_LIST as usize,
+                list.handles.as_ptr() as *const std::ffi::c_void,
+                list.handles.len() * std::mem::size_of::<HANDLE>(),
+                ptr::null_mut(),
+                ptr::null(),
+            )
+        };
+        if result == 0 {
+            return Err(format!(
+                "UpdateProcThreadAttribute failed with error {}",
+                unsafe { GetLastError() }
+            ));
+        }
+        Ok(list)
+    }
+
+    fn attributes(&mut self) -> LPPROC_THREAD_ATTRIBUTE_LIST {
+        self.buffer.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST
+    }
+}
+
+impl Drop for InheritList {
+    fn drop(&mut self) {
+        unsafe { DeleteProcThreadAttributeList(self.attributes()) };
+    }
+}
+
+impl Executor for Win32Executor {
+    fn spawn(&self, req: &ExecRequest, pipe_stdin: bool) -> Result<SpawnedProcess, ExecError> {
+        // Build command line
+        let command_line = build_command_line(&req.command, &req.args, req.shell)?;
+        let mut command_line_wide: Vec<u16> = OsStr::new(&command_line)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
+
+        // Set up security attributes for inheritable handles
+        let sa = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: ptr::null_mut(),
+            bInheritHandle: TRUE,
+        };
+
+        // Create pipes for stdout and stderr
+        let mut stdout_read: HANDLE = INVALID_HANDLE_VALUE;
+        let mut stdout_write: HANDLE = INVALID_HANDLE_VALUE;
+        let mut stderr_read: HANDLE = INVALID_HANDLE_VALUE;
+        let mut stderr_write: HANDLE = INVALID_HANDLE_VALUE;
+
+        unsafe {
+            if CreatePipe(&mut stdout_read, &mut stdout_write, &sa, 0) == 0 {
+                return Err(ExecError::ProcessCreationFailed(
+                    "Failed to create stdout pipe".to_string(),
+                ));
+            }
+            SetHandleInformation(stdout_read, HANDLE_FLAG_INHERIT, 0);
+
+            if CreatePipe(&mut stderr_read, &mut stderr_write, &sa, 0) == 0 {
+                CloseHandle(stdout_read);
+                CloseHandle(stdout_write);
+                return Err(ExecError::ProcessCreationFailed(
+                    "Failed to create stderr pipe".to_string(),
+                ));
+            }
+            SetHandleInformation(stderr_read, HANDLE_FLAG_INHERIT, 0);
+        }
+
+        // Optional stdin pipe; the child inherits the read end
+        let mut stdin_read: HANDLE = INVALID_HANDLE_VALUE;
+        let mut stdin_write: HANDLE = INVALID_HANDLE_VALUE;
+        if pipe_stdin {
+            unsafe {
+                if CreatePipe(&mut stdin_read, &mut stdin_write, &sa, 0) == 0 {
+                    CloseHandle(stdout_read);
+                    CloseHandle(stdout_write);
+                    CloseHandle(stderr_read);
+                    CloseHandle(stderr_write);
+                    return Err(ExecError::ProcessCreationFailed(
+                        "Failed to create stdin pipe".to_string(),
+                    ));
+                }
+                SetHandleInformation(stdin_write, HANDLE_FLAG_INHERIT, 0);
+            }
+        }
+
+        // Set up STARTUPINFOEXW
+        let mut si: STARTUPINFOEXW = unsafe { std::mem::zeroed() };
+        si.StartupInfo.cb = std::mem::size_of::<STARTUPINFOEXW>() as u32;
+        si.StartupInfo.dwFlags = STARTF_USESTDHANDLES;
+        si.StartupInfo.hStdOutput = stdout_write;
+        si.StartupInfo.hStdError = stderr_write;
+        si.StartupInfo.hStdInput = stdin_read;
+        let mut inherited = vec![stdout_write, stderr_write];
+        if pipe_stdin {
+            inherited.push(stdin_read);
+        }
+
+        let mut pi: PROCESS_INFORMATION = unsafe { std::mem::zeroed() };
+
+        // Working directory
+        let cwd_wide: Option<Vec<u16>> = req.cwd.as_ref().map(|c| {
+            OsStr::new(c)
+                .encode_wide()
+                .chain(std::iter::once(0))
+                .collect()
+        });
+        let cwd_ptr = cwd_wide.as_ref().map_or(ptr::null(), |v| v.as_ptr());
+
+        // Build environment block
+        let env_block = build_environment_block(&req.env);
+        let env_ptr = env_block.as_ptr();
+
+        // Create the process suspended so it cannot spawn children before joining the job
+        let created = InheritList::new(inherited).and_then(|mut list| {
+            si.lpAttributeList = list.attributes();
+            let result = unsafe {
+                CreateProcessW(
+                    ptr::null(),
+                    command_line_wide.as_mut_ptr(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    TRUE,
+                    EXTENDED_STARTUPINFO_PRESENT
+                        | CREATE_NO_WINDOW
+                        | CREATE_UNICODE_ENVIRONMENT
+                        | CREATE_SUSPENDED,
+                    env_ptr as *const std::ffi::c_void,
+                    cwd_ptr,
+                    &si.StartupInfo,
+                    &mut pi,
+                )
+            };
+            if result == 0 {
+                return Err(format!("CreateProcessW failed with error {}", unsafe {
+                    GetLastError()
+                }));
+            }
+            Ok(())
+        });
+
+        // Close the child's ends of the pipes (child owns them now)
+        unsafe {
+            CloseHandle(stdout_write);
+            CloseHandle(stderr_write);
+            if pipe_stdin {
+                CloseHandle(stdin_read);
+            }
+        }
+
+        if let Err(e) = created {
+            unsafe {
+                CloseHandle(stdout_read);
+                CloseHandle(stderr_read);
+                if pipe_stdin {
+                    CloseHandle(stdin_write);
+                }
+            }
+            return Err(ExecError::ProcessCreationFailed(e));
+        }
+
+        let job = create_kill_on_close_job(pi.hProcess);
+
+        unsafe {
+            ResumeThread(pi.hThread);
+            CloseHandle(pi.hThread);
+        }
+
+        let stdin = pipe_stdin.then(|| {
+            Box::new(unsafe { std::fs::File::from_raw_handle(stdin_write) })
+                as Box<dyn std::io::Write + Send>
+        });
+        let stdout = unsafe { std::fs::File::from_raw_handle(stdout_read) };
+        let stderr = unsafe { std::fs::File::from_raw_handle(stderr_read) };
+
+        Ok(SpawnedProcess {
+            child: Arc::new(Win32Child {
+                process: pi.hProcess as usize,
+                job,
+            }),
+            stdin,
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+        })
+    }
+}
+
+/// Create a Job Object for process tree termination and assign `process` to it.
+///
+/// Returns the job handle as usize, or 0 if the job could not be set up.
+fn create_kill_on_close_job(process: HANDLE) -> usize {
+    let job = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+    if job.is_null() || job == INVALID_HANDLE_VALUE {
+        // Job creation failed, fallback to simple process termination
+        tracing::warn!("Failed to create Job Object, process tree termination disabled");
+        return 0;
+    }
+
+    // Configure job to terminate child processes when job is closed
+    let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+    info.basic_limit_information.limit_flags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+
+    let set_result = unsafe {
+        SetInformationJobObject(
+            job,
+            JOB_OBJECT_EXTENDED_LIMIT_INFORMATION,
+            &info as *const _ as *const std::ffi::c_void,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        )
+    };
+
+    if set_result == 0 {
+        tracing::warn!("Failed to configure Job Object limits");
+    }
+
+    // Assign process to job
+    let assign_result = unsafe { AssignProcessToJobObject(job, process) };
+    if assign_result == 0 {
+        tracing::warn!("Failed to assign process to Job Object");
+        unsafe { CloseHandle(job) };
+        return 0;
+    }
+
+    job as usize
+}
+
+/// Build command line string from request.
+fn build_command_line(command: &str, args: &[String], shell: Shell) -> Result<String, ExecError> {
+    match shell {
+        Shell::Cmd => quoting::cmd_command_line(command, args).map_err(ExecError::InvalidRequest),
+        Shell::Powershell => {
+            // PowerShell joins its arguments into one script; pass it as a single argv entry
+            let script = quoting::script(command, args, quoting::powershell_literal);
+            quoting::check_nul(&script).map_err(ExecError::InvalidRequest)?;
+            Ok(format!(
+                "powershell.exe -NoLogo -NoProfile -Command {}",
+                quoting::windows_arg(&script)
+            ))
+        }
+        Shell::Direct => {
+            quoting::windows_command_line(command, args).map_err(ExecError::InvalidRequest)
+        }
+        Shell::Sh | Shell::Bash => Err(ExecError::NotSupported(format!(
+            "Shell {:?} is not supported on Windows",
+            shell
+        ))),
+    }
+}
+
+/// Build environment block for CreateProcessW.
+/// The block is a null-terminated sequence of null-terminated "KEY=VALUE" strings.
+pub(crate) fn build_environment_block(
+    extra_env: &std::collections::HashMap<String, String>,
+) -> Vec<u16> {
+    let mut env_strings: Vec<String> = Vec::new();
+
+    // Inherit current environment
+    for (key, value) in std::env::vars() {
+        env_strings.push(format!("{}={}", key, value));
+    }
+
+    // Add/override with request environment
+    for (key, value) in extra_env {
+        // Remove any existing entry with this key
+        env_strings.retain(|s| !s.starts_with(&format!("{}=", key)));
+        env_strings.push(format!("{}={}", key, value));
+    }
+
+    // Sort for consistency (optional but nice)
+    env_strings.sort();
+
+    // Build the block: each string null-terminated, then double null at end
+    let mut block: Vec<u16> = Vec::new();
+    for s in env_strings {
+        block.extend(OsStr::new(&s).encode_wide());
+        block.push(0); // null terminator for this string
+    }
+    block.push(0); // final null terminator
+
+    block
+}
@@ -0,0 +1,562 @@
+//! Raw access to disks, volumes and disk image files.
+//!
+//! Devices are opened through their raw paths (`\\.\PhysicalDriveN` and
+//! `\\.\C:` on Windows, `/dev/*` elsewhere). Raw Windows devices only
+//! accept transfers in whole sectors, so every read and write starts on an
+//! [`ALIGN`] boundary and covers whole blocks or runs to the end of the
+//! device; partial blocks at either end of a write are read back and merged.
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(unix)]
+use unix as sys;
+#[cfg(windows)]
+use win32 as sys;
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// Transfer alignment; a multiple of every common sector size.
+pub const ALIGN: u64 = 4096;
+
+/// Bytes read or written per device call.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// zstd level used for downloads; favors speed over ratio.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Stream compression of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(value: Option<&str>) -> Option<Self> {
+        match value {
+            None | Some("none") => Some(Self::None),
+            Some("gzip" | "gz") => Some(Self::Gzip),
+            Some("zstd" | "zst") => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// File name suffix of an image in this compression.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::None => "img",
+            Self::Gzip => "img.gz",
+            Self::Zstd => "img.zst",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::None => "application/octet-stream",
+            Self::Gzip => "application/gzip",
+            Self::Zstd => "application/zstd",
+        }
+    }
+}
+
+/// A device named in a request.
+#[derive(Clone)]
+pub struct Device {
+    /// Path to open.
+    pub path: PathBuf,
+    /// Name to use for downloaded images.
+    pub name: String,
+    /// Whether this is a volume, which must be locked before writing.
+    volume: bool,
+}
+
+/// Resolve a device name or an absolute path to a device or image file.
+///
+/// Bare names are looked up the platform's way: `disk0`,
+/// `PhysicalDrive0` and `C:` on Windows, `sda` or `loop0` under `/dev`
+/// elsewhere.
+pub fn resolve(name: &str) -> Result<Device, String> {
+    if name.is_empty() {
+        return Err("Missing device".to_string());
+    }
+    let path = PathBuf::from(name);
+    if path.is_absolute() || name.starts_with(r"\\") {
+        let file_name = path
+            .file_stem()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| name.to_string());
+        return Ok(Device {
+            volume: sys::is_volume_path(name),
+            name: sanitize(&file_name),
+            path,
+        });
+    }
+    if name.contains(['/', '\\']) || name == ".." {
+        return Err(format!("Invalid device name: {}", name));
+    }
+    let (path, volume) = sys::device_path(name)?;
+    Ok(Device {
+        path,
+        name: sanitize(name),
+        volume,
+    })
+}
+
+/// Keep only characters that are safe in a download file name.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect();
+    if name.is_empty() {
+        "disk".to_string()
+    } else {
+        name
+    }
+}
+
+/// Open a device for reading, returning it with its size in bytes.
+pub fn open_read(device: &Device) -> io::Result<(File, u64)> {
+    let mut file = File::open(&device.path)?;
+    let size = sys::device_size(&mut file)?;
+    Ok((file, size))
+}
+
+/// Open a device for writing, returning it with its size in bytes.
+///
+/// Volumes are locked and dismounted for as long as the file is open.
+pub fn open_write(device: &Device) -> io::Result<(File, u64)> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&device.path)?;
+    if device.volume {
+        sys::lock_volume(&file)?;
+    }
+    let size = sys::device_size(&mut file)?;
+    Ok((file, size))
+}
+
+/// Copy `len` bytes from `start` to `out`, compressing them as asked.
+///
+/// With `skip_errors`, blocks that cannot be read are sent as zeros, as
+/// `dd conv=noerror,sync` does; the number of such blocks is returned.
+pub fn read(
+    file: &mut File,
+    size: u64,
+    start: u64,
+    len: u64,
+    skip_errors: bool,
+    compression: Compression,
+    out: impl Write,
+) -> io::Result<u64> {
+    match compression {
+        Compression::None => {
+            let mut out = out;
+            let bad = copy_out(file, size, start, len, skip_errors, &mut out)?;
+            out.flush()?;
+            Ok(bad)
+        }
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(out, flate2::Compression::fast());
+            let bad = copy_out(file, size, start, len, skip_errors, &mut encoder)?;
+            encoder.finish()?.flush()?;
+            Ok(bad)
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(out, ZSTD_LEVEL)?;
+            let bad = copy_out(file, size, start, len, skip_errors, &mut encoder)?;
+            encoder.finish()?.flush()?;
+            Ok(bad)
+        }
+    }
+}
+
+fn copy_out(
+    file: &mut File,
+    size: u64,
+    start: u64,
+    len: u64,
+    skip_errors: bool,
+    out: &mut impl Write,
+) -> io::Result<u64> {
+    let end = start + len;
+    let mut pos = start - start % ALIGN;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut bad = 0;
+    while pos < end {
+        let n = (size - pos).min(CHUNK_SIZE as u64) as usize;
+        let block = &mut buf[..n];
+        match read_at(file, pos, block) {
+            Ok(()) => {}
+            Err(_) if skip_errors => bad += read_blocks(file, pos, block),
+            Err(e) => return Err(e),
+        }
+        let from = start.saturating_sub(pos) as usize;
+        let to = (end - pos).min(n as u64) as usize;
+        out.write_all(&block[from..to])?;
+        pos += n as u64;
+    }
+    Ok(bad)
+}
+
+/// Read `buf` one aligned block at a time, zeroing blocks that fail.
+fn read_blocks(file: &mut File, pos: u64, buf: &mut [u8]) -> u64 {
+    let mut bad = 0;
+    for (index, block) in buf.chunks_mut(ALIGN as usize).enumerate() {
+        let offset = pos + index as u64 * ALIGN;
+        if read_at(file, offset, block).is_err() {
+            block.fill(0);
+            bad += 1;
+        }
+    }
+    if bad > 0 {
+        tracing::warn!("Zero-filled {} unreadable blocks at offset {}", bad, pos);
+    }
+    bad
+}
+
+/// Write the image read from `input` at `offset`, returning its length.
+///
+/// Fails with `FileTooLarge` if the image runs past the end of the
+/// device; whatever fitted has been written by then.
+pub fn write(
+    file: &mut File,
+    size: u64,
+    offset: u64,
+    compression: Compression,
+    input: impl Read,
+) -> io::Result<u64> {
+    match compression {
+        Compression::None => copy_in(file, size, offset, input),
+        Compression::Gzip => copy_in(file, size, offset, flate2::read::MultiGzDecoder::new(input)),
+        Compression::Zstd => copy_in(file, size, offset, zstd::Decoder::new(input)?),
+    }
+}
+
+fn copy_in(file: &mut File, size: u64, offset: u64, mut input: impl Read) -> io::Result<u64> {
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            format!("Image extends past the end of the device ({} bytes)", size),
+        )
+    };
+    if offset > size {
+        return Err(too_large());
+    }
+
+    // `buf` holds data for the device from `pos`, which stays aligned
+    let mut pos = offset - offset % ALIGN;
+    let mut buf = Vec::with_capacity(CHUNK_SIZE + ALIGN as usize);
+    if pos < offset {
+        buf = read_block(file, size, pos)?;
+        buf.truncate((offset - pos) as usize);
+    }
+
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    let mut written = 0u64;
+    loop {
+        let n = match input.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if pos + (buf.len() + n) as u64 > size {
+            return Err(too_large());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        written += n as u64;
+
+        if buf.len() >= CHUNK_SIZE {
+            let whole = buf.len() - buf.len() % ALIGN as usize;
+            write_at(file, pos, &buf[..whole])?;
+            buf.drain(..whole);
+            pos += whole as u64;
+        }
+    }
+
+    // Complete a partial last block with what the device already holds
+    let partial = buf.len() % ALIGN as usize;
+    let block_start = pos + (buf.len() - partial) as u64;
+    if partial != 0 && block_start + (partial as u64) < size {
+        let existing = read_block(file, size, block_start)?;
+        buf.extend_from_slice(&existing[partial..]);
+    }
+    if !buf.is_empty() {
+        write_at(file, pos, &buf)?;
+    }
+    file.sync_all()?;
+    Ok(written)
+}
+
+/// Read `len` bytes at any `offset`, through aligned reads.
+pub fn read_range(file: &mut File, size: u64, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let end = offset.saturating_add(len as u64);
+    if end > size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    let start = offset - offset % ALIGN;
+    let aligned_end = end.div_ceil(ALIGN).saturating_mul(ALIGN).min(size);
+    let mut buf = vec![0u8; (aligned_end - start) as usize];
+    read_at(file, start, &mut buf)?;
+    let from = (offset - start) as usize;
+    buf.truncate(from + len);
+    buf.drain(..from);
+    Ok(buf)
+}
+
+/// Read the aligned block at `pos`, cut short at the end of the device.
+fn read_block(file: &mut File, size: u64, pos: u64) -> io::Result<Vec<u8>> {
+    let mut block = vec![0u8; (size - pos).min(ALIGN) as usize];
+    read_at(file, pos, &mut block)?;
+    Ok(block)
+}
+
+fn read_at(file: &mut File, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(pos))?;
+    file.read_exact(buf)
+}
+
+fn write_at(file: &mut File, pos: u64, buf: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(pos))?;
+    file.write_all(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that differ at every position within a block.
+    fn pattern(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+    }
+
+    /// An image file holding `data`, opened for reading and writing.
+    fn image(data: &[u8]) -> (tempfile::NamedTempFile, File) {
+        let image = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(image.path(), data).unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(image.path())
+            .unwrap();
+        (image, file)
+    }
+
+    fn read_all(
+        file: &mut File,
+        size: u64,
+        start: u64,
+        len: u64,
+        compression: Compression,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        read(file, size, start, len, false, compression, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn read_range_at_unaligned_offsets() {
+        // Not a whole number of blocks, so the last block is partial
+        let data = pattern(3 * ALIGN as usize + 100, 0);
+        let size = data.len() as u64;
+        let (_image, mut file) = image(&data);
+
+        for (offset, len) in [
+            (0, 1),
+            (1, 10),
+            (4095, 2),
+            (4000, 5000),
+            (12_290, 94),
+            (0, data.len()),
+        ] {
+            let bytes = read_range(&mut file, size, offset, len).unwrap();
+            assert_eq!(
+                bytes,
+                data[offset as usize..offset as usize + len],
+                "{} +{}",
+                offset,
+                len
+            );
+        }
+        assert!(read_range(&mut file, size, size, 0).unwrap().is_empty());
+        let past_end = read_range(&mut file, size, size - 10, 11).unwrap_err();
+        assert_eq!(past_end.kind(), io::ErrorKind::UnexpectedEof);
+        let overflow = read_range(&mut file, size, u64::MAX, 2).unwrap_err();
+        assert_eq!(overflow.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn read_unaligned_spans_and_partial_last_block() {
+        // Larger than one chunk, so several device reads are needed
+        let data = pattern(CHUNK_SIZE + 5000, 1);
+        let size = data.len() as u64;
+        let (_image, mut file) = image(&data);
+
+        assert_eq!(read_all(&mut file, size, 0, size, Compression::None), data);
+        for (start, len) in [
+            (100, 5000),
+            (4097, CHUNK_SIZE as u64),
+            (size - 7, 7),
+            (size, 0),
+        ] {
+            let bytes = read_all(&mut file, size, start, len, Compression::None);
+            assert_eq!(
+                bytes,
+                data[start as usize..(start + len) as usize],
+                "{} +{}",
+                start,
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn write_at_unaligned_offset_keeps_neighbours() {
+        let data = pattern(4 * ALIGN as usize, 2);
+        let size = data.len() as u64;
+        let (image, mut file) = image(&data);
+
+        let patch = pattern(ALIGN as usize + 30, 0x5a);
+        let offset = ALIGN - 10;
+        let written = write(&mut file, size, offset, Compression::None, &patch[..]).unwrap();
+        assert_eq!(written, patch.len() as u64);
+
+        let mut expected = data.clone();
+        expected[offset as usize..offset as usize + patch.len()].copy_from_slice(&patch);
+        assert_eq!(std::fs::read(image.path()).unwrap(), expected);
+    }
+
+    #[test]
+    fn write_partial_last_block() {
+        let data = pattern(2 * ALIGN as usize + 123, 3);
+        let size = data.len() as u64;
+        let (image, mut file) = image(&data);
+
+        // Ends exactly at the end of the device, inside its last block
+        write(
+            &mut file,
+            size,
+            size - 50,
+            Compression::None,
+            &[0xee; 50][..],
+        )
+        .unwrap();
+        // Ends before the end of the device, inside the same block
+        write(
+            &mut file,
+            size,
+            2 * ALIGN + 1,
+            Compression::None,
+            &[0xdd; 3][..],
+        )
+        .unwrap();
+
+        let mut expected = data.clone();
+        let end = expected.len();
+        expected[end - 50..].fill(0xee);
+        expected[2 * ALIGN as usize + 1..2 * ALIGN as usize + 4].fill(0xdd);
+        let image = std::fs::read(image.path()).unwrap();
+        assert_eq!(image.len(), data.len());
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn write_larger_than_a_chunk() {
+        let data = vec![0u8; 2 * CHUNK_SIZE + 3 * ALIGN as usize];
+        let size = data.len() as u64;
+        let (image, mut file) = image(&data);
+
+        let patch = pattern(CHUNK_SIZE + 777, 4);
+        write(&mut file, size, 333, Compression::None, &patch[..]).unwrap();
+        let image = std::fs::read(image.path()).unwrap();
+        assert!(image[..333].iter().all(|&b| b == 0));
+        assert_eq!(image[333..333 + patch.len()], patch);
+        assert!(image[333 + patch.len()..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn image_larger_than_target_is_rejected() {
+        let data = pattern(2 * ALIGN as usize, 5);
+        let size = data.len() as u64;
+        let (image, mut file) = image(&data);
+
+        let too_large = write(
+            &mut file,
+            size,
+            0,
+            Compression::None,
+            &pattern(data.len() + 1, 6)[..],
+        );
+        assert_eq!(too_large.unwrap_err().kind(), io::ErrorKind::FileTooLarge);
+        let past_end = write(
+            &mut file,
+            size,
+            100,
+            Compression::None,
+            &pattern(data.len() - 99, 6)[..],
+        );
+        assert_eq!(past_end.unwrap_err().kind(), io::ErrorKind::FileTooLarge);
+        let bad_offset = write(&mut file, size, size + 1, Compression::None, &[][..]);
+        assert_eq!(bad_offset.unwrap_err().kind(), io::ErrorKind::FileTooLarge);
+
+        // An image that fits exactly is fine
+        let exact = pattern(data.len() - 100, 7);
+        write(&mut file, size, 100, Compression::None, &exact[..]).unwrap();
+        assert_eq!(std::fs::read(image.path()).unwrap()[100..], exact);
+    }
+
+    #[test]
+    fn compressed_round_trips() {
+        let data = pattern(CHUNK_SIZE + 4321, 8);
+        let size = data.len() as u64;
+        let (_source, mut source) = image(&data);
+
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let compressed = read_all(&mut source, size, 0, size, compression);
+            assert!(compressed.len() < data.len());
+            let (target, mut file) = image(&vec![0u8; data.len()]);
+            let written = write(&mut file, size, 0, compression, &compressed[..]).unwrap();
+            assert_eq!(written, size);
+            assert_eq!(
+                std::fs::read(target.path()).unwrap(),
+                data,
+                "{:?}",
+                compression
+            );
+
+            // A range of the device compresses on its own
+            let part = read_all(&mut source, size, 10, 5000, compression);
+            let (target, mut file) = image(&vec![0u8; 6000]);
+            write(&mut file, 6000, 1, compression, &part[..]).unwrap();
+            assert_eq!(
+                std::fs::read(target.path()).unwrap()[1..5001],
+                data[10..5010]
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_compressed_input_fails() {
+        let (_image, mut file) = image(&[0u8; 8192]);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            assert!(write(&mut file, 8192, 0, compression, &b"not compressed"[..]).is_err());
+        }
+    }
+
+    #[test]
+    fn compression_names() {
+        assert_eq!(Compression::parse(None), Some(Compression::None));
+        assert_eq!(Compression::parse(Some("gz")), Some(Compression::Gzip));
+        assert_eq!(Compression::parse(Some("zst")), Some(Compression::Zstd));
+        assert_eq!(Compression::parse(Some("xz")), None);
+        assert_eq!(Compression::Zstd.extension(), "img.zst");
+    }
+}
@@ -0,0 +1,29 @@
+//! Unix block device backend.
+//!
+//! Devices are the nodes under `/dev`; loop devices and plain image files
+//! behave the same, which makes this backend convenient for testing.
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Path of a bare device name such as `sda` or `loop0`.
+pub fn device_path(name: &str) -> Result<(PathBuf, bool), String> {
+    Ok((PathBuf::from("/dev").join(name), false))
+}
+
+/// Volumes need no locking here.
+pub fn is_volume_path(_path: &str) -> bool {
+    false
+}
+
+/// Size of a block device or file; seeking to the end works for both.
+pub fn device_size(file: &mut File) -> io::Result<u64> {
+    let size = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(size)
+}
+
+pub fn lock_volume(_file: &File) -> io::Result<()> {
+    Ok(())
+}
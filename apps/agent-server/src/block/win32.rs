@@ -0,0 +1,104 @@
+//! Windows block device backend.
+//!
+//! Disks are `\\.\PhysicalDriveN` and volumes `\\.\C:`. Raw device
+//! handles do not report a file size, so it is queried with
+//! `IOCTL_DISK_GET_LENGTH_INFO`.
+
+use std::fs::File;
+use std::io;
+use std::os::windows::io::AsRawHandle;
+use std::path::PathBuf;
+use std::ptr;
+use windows_sys::Win32::System::IO::DeviceIoControl;
+use windows_sys::Win32::System::Ioctl::{
+    FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME, GET_LENGTH_INFORMATION, IOCTL_DISK_GET_LENGTH_INFO,
+};
+
+/// Path of a bare device name: `disk0` or `PhysicalDrive0` for a disk,
+/// `C` or `C:` for a volume.
+pub fn device_path(name: &str) -> Result<(PathBuf, bool), String> {
+    let lower = name.to_ascii_lowercase();
+    let number = lower
+        .strip_prefix("physicaldrive")
+        .or_else(|| lower.strip_prefix("disk"));
+    if let Some(number) = number
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+    {
+        return Ok((
+            PathBuf::from(format!(r"\\.\PhysicalDrive{}", number)),
+            false,
+        ));
+    }
+
+    let letter = name.strip_suffix(':').unwrap_or(name);
+    if letter.len() == 1 && letter.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Ok((
+            PathBuf::from(format!(r"\\.\{}:", letter.to_ascii_uppercase())),
+            true,
+        ));
+    }
+    Err(format!("Unknown device: {}", name))
+}
+
+/// Whether an absolute path names a volume (`\\.\C:` or `\\?\Volume{...}`).
+pub fn is_volume_path(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    if lower.starts_with(r"\\?\volume{") {
+        return true;
+    }
+    lower
+        .strip_prefix(r"\\.\")
+        .is_some_and(|rest| rest.len() == 2 && rest.ends_with(':'))
+}
+
+/// Size of a disk, volume or image file.
+pub fn device_size(file: &mut File) -> io::Result<u64> {
+    let mut info = GET_LENGTH_INFORMATION::default();
+    let mut returned = 0u32;
+    // SAFETY: the handle is open and `info` outlives the call.
+    let ok = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            IOCTL_DISK_GET_LENGTH_INFO,
+            ptr::null(),
+            0,
+            &mut info as *mut GET_LENGTH_INFORMATION as *mut _,
+            std::mem::size_of::<GET_LENGTH_INFORMATION>() as u32,
+            &mut returned,
+            ptr::null_mut(),
+        )
+    };
+    if ok != 0 {
+        return Ok(info.Length as u64);
+    }
+    // Image files are not devices
+    Ok(file.metadata()?.len())
+}
+
+/// Lock and dismount a volume so its sectors can be written.
+///
+/// Both last until the handle is closed. Fails if files on the volume
+/// are open.
+pub fn lock_volume(file: &File) -> io::Result<()> {
+    for code in [FSCTL_LOCK_VOLUME, FSCTL_DISMOUNT_VOLUME] {
+        let mut returned = 0u32;
+        // SAFETY: the handle is open; neither control code takes buffers.
+        let ok = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle(),
+                code,
+                ptr::null(),
+                0,
+                ptr::null_mut(),
+                0,
+                &mut returned,
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
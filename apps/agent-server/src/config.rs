@@ -0,0 +1,172 @@
+//! Server configuration.
+//!
+//! Settings are read from a JSON file (`winpe-agent.json` in the working
+//! directory, or the path in `WINPE_AGENT_CONFIG`) and then overridden by
+//! environment variables.
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Default configuration file name, resolved against the working directory.
+const DEFAULT_CONFIG_FILE: &str = "winpe-agent.json";
+
+/// Default cap on captured bytes per output stream (16 MiB).
+const DEFAULT_MAX_OUTPUT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Default time an idle resumable upload is kept (24 hours).
+const DEFAULT_UPLOAD_RETENTION_SECS: u64 = 24 * 3600;
+
+/// Default cap on file data in one directory archive (4 GiB).
+const DEFAULT_MAX_ARCHIVE_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Default bytes of recent events kept per `exec_stream` execution (1 MiB).
+const DEFAULT_EXEC_REPLAY_BYTES: u64 = 1024 * 1024;
+
+/// Default limit on running and retained `exec_stream` executions.
+const DEFAULT_MAX_EXEC_STREAMS: usize = 64;
+
+/// Default limit on running and retained jobs.
+const DEFAULT_MAX_JOBS: usize = 64;
+
+/// Default retention of finished jobs (1 hour).
+const DEFAULT_JOB_RETENTION_SECS: u64 = 3600;
+
+/// Server-wide configuration.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Bearer tokens accepted by the API. When empty, a random token is
+    /// generated at startup and logged.
+    pub tokens: Vec<String>,
+    /// Turn authentication off entirely.
+    pub insecure: bool,
+    /// Upper bound on captured bytes per output stream for `/automation/exec`.
+    /// Requests may ask for less, never more.
+    pub max_output_bytes: u64,
+    /// Bytes of recent events kept per `exec_stream` execution for replay.
+    pub exec_replay_bytes: u64,
+    /// Running and recently finished `exec_stream` executions kept at most;
+    /// the oldest finished ones are evicted to make room.
+    pub max_exec_streams: usize,
+    /// Running and finished jobs kept at most; the oldest finished ones are
+    /// removed to make room, and new jobs are refused while all are running.
+    pub max_jobs: usize,
+    /// Seconds a finished job (and its output) is kept before removal.
+    pub job_retention_secs: u64,
+    /// Timeout in seconds for jobs that do not set `timeout_ms`; 0 lets
+    /// them run until they exit.
+    pub job_timeout_secs: u64,
+    /// Seconds a resumable upload may sit idle before it is discarded.
+    pub upload_retention_secs: u64,
+    /// Upper bound on bytes of file data in one `/archive` download.
+    /// Requests may ask for less, never more.
+    pub max_archive_bytes: u64,
+    /// Directory where terminal session recordings are written.
+    pub recording_dir: PathBuf,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            tokens: Vec::new(),
+            insecure: false,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+            exec_replay_bytes: DEFAULT_EXEC_REPLAY_BYTES,
+            max_exec_streams: DEFAULT_MAX_EXEC_STREAMS,
+            max_jobs: DEFAULT_MAX_JOBS,
+            job_retention_secs: DEFAULT_JOB_RETENTION_SECS,
+            job_timeout_secs: 0,
+            upload_retention_secs: DEFAULT_UPLOAD_RETENTION_SECS,
+            max_archive_bytes: DEFAULT_MAX_ARCHIVE_BYTES,
+            recording_dir: std::env::temp_dir().join("winpe-agent-recordings"),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load configuration from the config file and environment.
+    ///
+    /// A missing default config file is not an error; an explicitly
+    /// configured file that cannot be read or parsed is.
+    pub fn load() -> Result<Self, String> {
+        let (path, explicit) = match std::env::var_os("WINPE_AGENT_CONFIG") {
+            Some(p) => (PathBuf::from(p), true),
+            None => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+        };
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?,
+            Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to read config file {}: {}",
+                    path.display(),
+                    e
+                ));
+            }
+        };
+
+        config.apply_env();
+        Ok(config)
+    }
+
+    /// Apply environment variable overrides.
+    fn apply_env(&mut self) {
+        // WINPE_AGENT_TOKENS: comma-separated list of additional tokens
+        if let Ok(tokens) = std::env::var("WINPE_AGENT_TOKENS") {
+            self.tokens.extend(
+                tokens
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from),
+            );
+        }
+
+        // WINPE_AGENT_INSECURE=1: accept requests without credentials
+        if let Ok(value) = std::env::var("WINPE_AGENT_INSECURE") {
+            self.insecure = matches!(value.trim(), "1" | "true" | "yes");
+        }
+
+        if let Some(bytes) = env_parse("WINPE_AGENT_MAX_OUTPUT_BYTES") {
+            self.max_output_bytes = bytes;
+        }
+        if let Some(bytes) = env_parse("WINPE_AGENT_EXEC_REPLAY_BYTES") {
+            self.exec_replay_bytes = bytes;
+        }
+        if let Some(count) = env_parse("WINPE_AGENT_MAX_EXEC_STREAMS") {
+            self.max_exec_streams = count;
+        }
+        if let Some(count) = env_parse("WINPE_AGENT_MAX_JOBS") {
+            self.max_jobs = count;
+        }
+        if let Some(secs) = env_parse("WINPE_AGENT_JOB_RETENTION_SECS") {
+            self.job_retention_secs = secs;
+        }
+        if let Some(secs) = env_parse("WINPE_AGENT_JOB_TIMEOUT_SECS") {
+            self.job_timeout_secs = secs;
+        }
+        if let Some(secs) = env_parse("WINPE_AGENT_UPLOAD_RETENTION_SECS") {
+            self.upload_retention_secs = secs;
+        }
+        if let Some(bytes) = env_parse("WINPE_AGENT_MAX_ARCHIVE_BYTES") {
+            self.max_archive_bytes = bytes;
+        }
+        if let Some(dir) = std::env::var_os("WINPE_AGENT_RECORDING_DIR") {
+            self.recording_dir = PathBuf::from(dir);
+        }
+    }
+}
+
+/// Parse an environment variable, ignoring it (with a warning) if malformed.
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    match value.trim().parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            tracing::warn!("Ignoring invalid {}={:?}", name, value);
+            None
+        }
+    }
+}
@@ -0,0 +1,406 @@
+//! Zip and tar.gz archives of directory trees, written as they are read.
+//!
+//! The tree is walked first so the total size is known before any output
+//! is produced; the archive is then written through a [`ChannelWriter`]
+//! straight into the response body, never touching the disk.
+
+use chrono::{Datelike, Timelike};
+use glob::{MatchOptions, Pattern};
+use std::fs::Metadata;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::stream::ChannelWriter;
+
+/// Archive container format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Zip,
+    TarGz,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "zip" => Some(Self::Zip),
+            "tar.gz" | "tgz" => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// Include and exclude globs, matched against paths relative to the root.
+///
+/// A pattern containing `/` matches the whole relative path; any other
+/// pattern matches the file name alone. Backslashes count as `/`.
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    /// Parse comma-separated pattern lists.
+    pub fn parse(include: Option<&str>, exclude: Option<&str>) -> Result<Self, String> {
+        Ok(Self {
+            include: parse_patterns(include)?,
+            exclude: parse_patterns(exclude)?,
+        })
+    }
+
+    /// Whether a file should be archived.
+    fn includes_file(&self, relative: &str) -> bool {
+        (self.include.is_empty() || matches_any(&self.include, relative))
+            && !matches_any(&self.exclude, relative)
+    }
+
+    /// Whether a directory should be descended into.
+    fn includes_dir(&self, relative: &str) -> bool {
+        !matches_any(&self.exclude, relative)
+    }
+}
+
+fn parse_patterns(list: Option<&str>) -> Result<Vec<Pattern>, String> {
+    list.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            Pattern::new(&p.replace('\\', "/"))
+                .map_err(|e| format!("Invalid pattern '{}': {}", p, e))
+        })
+        .collect()
+}
+
+fn matches_any(patterns: &[Pattern], relative: &str) -> bool {
+    let options = MatchOptions {
+        case_sensitive: !cfg!(windows),
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    let name = relative.rsplit('/').next().unwrap_or(relative);
+    patterns.iter().any(|pattern| {
+        let target = if pattern.as_str().contains('/') {
+            relative
+        } else {
+            name
+        };
+        pattern.matches_with(target, options)
+    })
+}
+
+/// A file or directory to archive.
+pub struct Item {
+    path: PathBuf,
+    /// Path inside the archive, `/`-separated.
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+    mode: u32,
+}
+
+/// Everything under a root directory that passes a filter.
+pub struct Tree {
+    pub items: Vec<Item>,
+    /// Total size of the files, in bytes.
+    pub total_bytes: u64,
+}
+
+/// Walk `root`, naming entries `<root name>/<relative path>`.
+///
+/// Directory entries are only recorded when no include patterns are given,
+/// since otherwise they would mostly be empty. Symlinks and other special
+/// files are skipped, as are subdirectories that cannot be read.
+pub fn collect(root: &Path, filter: &Filter) -> io::Result<Tree> {
+    let prefix = root_name(root);
+    let mut tree = Tree {
+        items: Vec::new(),
+        total_bytes: 0,
+    };
+    // Fail on the root itself, unlike on its subdirectories
+    let children = std::fs::read_dir(root)?;
+    walk(children, &prefix, "", filter, &mut tree);
+    Ok(tree)
+}
+
+fn walk(children: std::fs::ReadDir, prefix: &str, parent: &str, filter: &Filter, tree: &mut Tree) {
+    let mut children: Vec<_> = children
+        .flatten()
+        .filter_map(|child| {
+            let metadata = std::fs::symlink_metadata(child.path()).ok()?;
+            Some((child.path(), metadata))
+        })
+        .collect();
+    children.sort_by(|a, b| a.0.file_name().cmp(&b.0.file_name()));
+
+    for (path, metadata) in children {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let relative = if parent.is_empty() {
+            name.into_owned()
+        } else {
+            format!("{}/{}", parent, name)
+        };
+
+        if metadata.is_dir() {
+            if !filter.includes_dir(&relative) {
+                continue;
+            }
+            if filter.include.is_empty() {
+                tree.items
+                    .push(item(path.clone(), prefix, &relative, &metadata));
+            }
+            if let Ok(grandchildren) = std::fs::read_dir(&path) {
+                walk(grandchildren, prefix, &relative, filter, tree);
+            }
+        } else if metadata.is_file() && filter.includes_file(&relative) {
+            tree.total_bytes += metadata.len();
+            tree.items.push(item(path, prefix, &relative, &metadata));
+        }
+    }
+}
+
+fn item(path: PathBuf, prefix: &str, relative: &str, metadata: &Metadata) -> Item {
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o7777
+    };
+    #[cfg(not(unix))]
+    let mode = if metadata.is_dir() { 0o755 } else { 0o644 };
+
+    Item {
+        path,
+        name: format!("{}/{}", prefix, relative),
+        is_dir: metadata.is_dir(),
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        modified: metadata.modified().ok(),
+        mode,
+    }
+}
+
+/// Name of the top-level archive directory for `root`.
+///
+/// Roots such as `C:\` have no file name and use their drive letters.
+pub fn root_name(root: &Path) -> String {
+    match root.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => {
+            let name: String = root
+                .to_string_lossy()
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .collect();
+            if name.is_empty() {
+                "root".to_string()
+            } else {
+                name
+            }
+        }
+    }
+}
+
+/// Write the archive of `items` to `out`.
+///
+/// Files that cannot be opened (e.g. locked by another process) are
+/// skipped. Files that shrink while being read are padded with zeros so
+/// the recorded size stays valid.
+pub fn write(format: Format, items: &[Item], out: ChannelWriter) -> io::Result<()> {
+    let out = match format {
+        Format::Zip => write_zip(items, out)?,
+        Format::TarGz => write_tar_gz(items, out)?,
+    };
+    out.close()
+}
+
+fn write_tar_gz(items: &[Item], out: ChannelWriter) -> io::Result<ChannelWriter> {
+    let encoder = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+
+    for item in items {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(item.mode);
+        header.set_mtime(
+            item.modified
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs()),
+        );
+        if item.is_dir {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, format!("{}/", item.name), io::empty())?;
+            continue;
+        }
+
+        let Some(file) = open(item) else {
+            continue;
+        };
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(item.size);
+        let data = file.take(item.size).chain(io::repeat(0)).take(item.size);
+        builder.append_data(&mut header, &item.name, data)?;
+    }
+
+    builder.into_inner()?.finish()
+}
+
+fn write_zip(items: &[Item], out: ChannelWriter) -> io::Result<ChannelWriter> {
+    let mut zip = zip::ZipWriter::new_stream(out);
+
+    for item in items {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .last_modified_time(zip_time(item.modified))
+            .unix_permissions(item.mode)
+            .large_file(item.size >= u32::MAX as u64);
+        if item.is_dir {
+            // `add_directory` omits the data descriptor a streamed entry
+            // announces; an empty entry named with a slash is equivalent
+            zip.start_file(
+                format!("{}/", item.name),
+                options.compression_method(zip::CompressionMethod::Stored),
+            )?;
+            continue;
+        }
+
+        let Some(file) = open(item) else {
+            continue;
+        };
+        zip.start_file(&item.name, options)?;
+        io::copy(&mut file.take(item.size), &mut zip)?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+fn open(item: &Item) -> Option<std::fs::File> {
+    match std::fs::File::open(&item.path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            tracing::warn!("Skipping {} in archive: {}", item.path.display(), e);
+            None
+        }
+    }
+}
+
+/// Zip timestamp (local time, 2-second resolution) for a modification time.
+fn zip_time(modified: Option<SystemTime>) -> zip::DateTime {
+    modified
+        .and_then(|t| {
+            let t = chrono::DateTime::<chrono::Local>::from(t);
+            zip::DateTime::from_date_and_time(
+                u16::try_from(t.year()).ok()?,
+                t.month() as u8,
+                t.day() as u8,
+                t.hour() as u8,
+                t.minute() as u8,
+                t.second() as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(include: &str, exclude: &str) -> Filter {
+        Filter::parse(Some(include), Some(exclude)).unwrap()
+    }
+
+    #[test]
+    fn empty_filter_includes_everything() {
+        let filter = Filter::parse(None, None).unwrap();
+        assert!(filter.includes_file("a.txt"));
+        assert!(filter.includes_file("deep/er/b.log"));
+        assert!(filter.includes_dir("deep"));
+        // Blank entries in a list are ignored
+        let filter = rules(" , ", ",");
+        assert!(filter.includes_file("a.txt"));
+    }
+
+    #[test]
+    fn patterns_without_slash_match_the_file_name() {
+        let filter = rules("*.log, *.txt", "");
+        assert!(filter.includes_file("a.txt"));
+        assert!(filter.includes_file("logs/2024/app.log"));
+        assert!(!filter.includes_file("logs/app.log.1"));
+        assert!(!filter.includes_file("txt/readme.md"));
+    }
+
+    #[test]
+    fn patterns_with_slash_match_the_relative_path() {
+        let filter = rules("logs/*.log", "");
+        assert!(filter.includes_file("logs/app.log"));
+        assert!(!filter.includes_file("app.log"));
+        // `*` does not cross directories, `**` does
+        assert!(!filter.includes_file("logs/old/app.log"));
+        let filter = rules("logs/**/*.log", "");
+        assert!(filter.includes_file("logs/old/app.log"));
+        assert!(filter.includes_file("logs/app.log"));
+        // Backslashes in patterns are separators
+        let filter = rules(r"logs\*.log", "");
+        assert!(filter.includes_file("logs/app.log"));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = rules("*.log", "debug.log, tmp");
+        assert!(filter.includes_file("app.log"));
+        assert!(!filter.includes_file("sub/debug.log"));
+        assert!(!filter.includes_dir("tmp"));
+        assert!(!filter.includes_dir("a/tmp"));
+        // Include patterns do not prune directories
+        assert!(filter.includes_dir("src"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        let err = Filter::parse(Some("ok, [a-"), None).err().unwrap();
+        assert!(err.contains("[a-"), "{}", err);
+        assert!(Filter::parse(None, Some("***")).is_err());
+    }
+
+    #[test]
+    fn collect_applies_the_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("logs/old")).unwrap();
+        std::fs::create_dir_all(root.join("tmp")).unwrap();
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        std::fs::write(root.join("logs/app.log"), "app").unwrap();
+        std::fs::write(root.join("logs/old/app.log"), "old").unwrap();
+        std::fs::write(root.join("tmp/x.log"), "x").unwrap();
+
+        let names = |tree: &Tree| -> Vec<String> {
+            tree.items.iter().map(|item| item.name.clone()).collect()
+        };
+
+        let tree = collect(&root, &rules("", "tmp, old")).unwrap();
+        assert_eq!(
+            names(&tree),
+            ["root/a.txt", "root/logs", "root/logs/app.log"]
+        );
+        assert_eq!(tree.total_bytes, 4);
+
+        // With include patterns, only matching files are listed
+        let tree = collect(&root, &rules("*.log", "tmp")).unwrap();
+        assert_eq!(names(&tree), ["root/logs/app.log", "root/logs/old/app.log"]);
+        assert_eq!(tree.total_bytes, 6);
+    }
+}
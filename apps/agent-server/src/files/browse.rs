@@ -0,0 +1,396 @@
+//! Directory listing, metadata, glob search and manifests.
+//!
+//! These walk the filesystem synchronously and are meant to run on a
+//! blocking thread.
+
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use winpe_agent_core::{
+    DirListing, DirManifest, FileEntry, FileKind, GlobResponse, HashAlgorithm, ManifestEntry,
+};
+
+/// Describe the entry at `path` without following a final symlink.
+pub fn stat(path: &Path) -> io::Result<FileEntry> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    Ok(entry(path, &metadata, None))
+}
+
+/// List `dir` down to `max_depth` levels, returning at most `limit` entries.
+///
+/// Symlinked directories are reported but not descended into, and
+/// subdirectories that cannot be read are skipped.
+pub fn list(dir: &Path, max_depth: u32, limit: usize) -> io::Result<DirListing> {
+    let mut listing = DirListing {
+        path: dir.display().to_string(),
+        entries: Vec::new(),
+        truncated: false,
+    };
+    // Fail on the listed directory itself, unlike on its subdirectories
+    let children = read_sorted(dir)?;
+    walk(children, 1, max_depth, limit, &mut listing);
+    Ok(listing)
+}
+
+fn walk(
+    children: Vec<(PathBuf, Metadata)>,
+    depth: u32,
+    max_depth: u32,
+    limit: usize,
+    listing: &mut DirListing,
+) {
+    for (path, metadata) in children {
+        if listing.entries.len() >= limit {
+            listing.truncated = true;
+            return;
+        }
+        listing.entries.push(entry(&path, &metadata, Some(depth)));
+        if metadata.is_dir()
+            && depth < max_depth
+            && let Ok(grandchildren) = read_sorted(&path)
+        {
+            walk(grandchildren, depth + 1, max_depth, limit, listing);
+        }
+    }
+}
+
+/// Read a directory's entries sorted by name.
+fn read_sorted(dir: &Path) -> io::Result<Vec<(PathBuf, Metadata)>> {
+    let mut children = Vec::new();
+    for child in std::fs::read_dir(dir)? {
+        let child = child?;
+        // Entries can vanish while listing
+        if let Ok(metadata) = std::fs::symlink_metadata(child.path()) {
+            children.push((child.path(), metadata));
+        }
+    }
+    children.sort_by(|a, b| a.0.file_name().cmp(&b.0.file_name()));
+    Ok(children)
+}
+
+/// Find paths matching an absolute glob `pattern`, returning at most `limit`.
+///
+/// Supports `*`, `?`, `[...]` and `**` for any number of directories.
+/// Paths that cannot be read while matching are skipped.
+pub fn glob(pattern: &str, limit: usize) -> Result<GlobResponse, String> {
+    if !Path::new(pattern).is_absolute() {
+        return Err(format!("Pattern must be absolute: {}", pattern));
+    }
+    let paths = ::glob::glob(pattern).map_err(|e| format!("Invalid pattern: {}", e))?;
+
+    let mut response = GlobResponse {
+        entries: Vec::new(),
+        truncated: false,
+    };
+    for path in paths.flatten() {
+        if response.entries.len() >= limit {
+            response.truncated = true;
+            break;
+        }
+        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+            response.entries.push(entry(&path, &metadata, None));
+        }
+    }
+    Ok(response)
+}
+
+/// Describe every file and directory under `root`, relative to it.
+///
+/// With `hash`, each file is also hashed; files that cannot be read are
+/// listed without one. Symlinks are skipped, as are subdirectories that
+/// cannot be read.
+pub fn manifest(root: &Path, hash: Option<HashAlgorithm>) -> io::Result<DirManifest> {
+    let mut manifest = DirManifest {
+        path: root.display().to_string(),
+        dirs: Vec::new(),
+        files: Vec::new(),
+    };
+    // Fail on the root itself, unlike on its subdirectories
+    let children = read_sorted(root)?;
+    walk_manifest(children, "", hash, &mut manifest);
+    Ok(manifest)
+}
+
+fn walk_manifest(
+    children: Vec<(PathBuf, Metadata)>,
+    parent: &str,
+    hash: Option<HashAlgorithm>,
+    manifest: &mut DirManifest,
+) {
+    for (path, metadata) in children {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let relative = if parent.is_empty() {
+            name.into_owned()
+        } else {
+            format!("{}/{}", parent, name)
+        };
+
+        if metadata.is_dir() {
+            manifest.dirs.push(relative.clone());
+            if let Ok(grandchildren) = read_sorted(&path) {
+                walk_manifest(grandchildren, &relative, hash, manifest);
+            }
+        } else if metadata.is_file() {
+            let hash = hash.and_then(|algorithm| {
+                let (_, mut hashes) = super::hash::hash_file(&path, &[algorithm]).ok()?;
+                hashes.remove(algorithm.as_str())
+            });
+            manifest.files.push(ManifestEntry {
+                path: relative,
+                size: metadata.len(),
+                mtime: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+                hash,
+            });
+        }
+    }
+}
+
+fn entry(path: &Path, metadata: &Metadata, depth: Option<u32>) -> FileEntry {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_dir() {
+        FileKind::Dir
+    } else if file_type.is_file() {
+        FileKind::File
+    } else {
+        FileKind::Other
+    };
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        // Roots such as `C:\` have no file name
+        .unwrap_or_else(|| path.display().to_string());
+
+    let mut entry = FileEntry {
+        hidden: name.starts_with('.'),
+        name,
+        path: path.display().to_string(),
+        kind,
+        size: if kind == FileKind::Dir {
+            0
+        } else {
+            metadata.len()
+        },
+        modified: timestamp(metadata.modified()),
+        created: timestamp(metadata.created()),
+        accessed: timestamp(metadata.accessed()),
+        readonly: metadata.permissions().readonly(),
+        system: false,
+        attributes: None,
+        mode: None,
+        depth,
+    };
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        use windows_sys::Win32::Storage::FileSystem::{
+            FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_SYSTEM,
+        };
+        let attributes = metadata.file_attributes();
+        entry.hidden = attributes & FILE_ATTRIBUTE_HIDDEN != 0;
+        entry.system = attributes & FILE_ATTRIBUTE_SYSTEM != 0;
+        entry.attributes = Some(attributes);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        entry.mode = Some(metadata.permissions().mode() & 0o7777);
+    }
+
+    entry
+}
+
+fn timestamp(time: io::Result<SystemTime>) -> Option<String> {
+    time.ok()
+        .map(|t| chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tree with nested directories and files of known sizes.
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("b/c")).unwrap();
+        std::fs::create_dir(dir.path().join("empty")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"a").unwrap();
+        std::fs::write(dir.path().join("b/one.log"), b"one").unwrap();
+        std::fs::write(dir.path().join("b/c/two.log"), b"hello").unwrap();
+        dir
+    }
+
+    /// Names and depths of the listed entries, in order.
+    fn names(listing: &DirListing) -> Vec<(&str, Option<u32>)> {
+        listing
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.depth))
+            .collect()
+    }
+
+    /// A glob pattern for `suffix` under `dir`.
+    fn pattern(dir: &Path, suffix: &str) -> String {
+        let root = ::glob::Pattern::escape(&dir.display().to_string());
+        format!("{}{}{}", root, std::path::MAIN_SEPARATOR, suffix)
+    }
+
+    /// Sorted names of the glob matches.
+    fn matched(response: &GlobResponse) -> Vec<&str> {
+        let mut names: Vec<_> = response.entries.iter().map(|e| e.name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn list_descends_to_max_depth() {
+        let dir = tree();
+        let listing = list(dir.path(), 1, 100).unwrap();
+        assert_eq!(
+            names(&listing),
+            [("a.txt", Some(1)), ("b", Some(1)), ("empty", Some(1))]
+        );
+        assert!(!listing.truncated);
+        assert_eq!(listing.entries[0].kind, FileKind::File);
+        assert_eq!(listing.entries[0].size, 1);
+        assert_eq!(listing.entries[1].kind, FileKind::Dir);
+
+        // Children follow their parent directory
+        let listing = list(dir.path(), 3, 100).unwrap();
+        assert_eq!(
+            names(&listing),
+            [
+                ("a.txt", Some(1)),
+                ("b", Some(1)),
+                ("c", Some(2)),
+                ("two.log", Some(3)),
+                ("one.log", Some(2)),
+                ("empty", Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_truncates_at_limit() {
+        let dir = tree();
+        let listing = list(dir.path(), 3, 3).unwrap();
+        assert_eq!(
+            names(&listing),
+            [("a.txt", Some(1)), ("b", Some(1)), ("c", Some(2))]
+        );
+        assert!(listing.truncated);
+
+        // Exactly reaching the limit is not truncation
+        let listing = list(dir.path(), 1, 3).unwrap();
+        assert_eq!(listing.entries.len(), 3);
+        assert!(!listing.truncated);
+
+        let listing = list(dir.path(), 1, 0).unwrap();
+        assert!(listing.entries.is_empty());
+        assert!(listing.truncated);
+    }
+
+    #[test]
+    fn list_of_missing_dir_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(list(&dir.path().join("missing"), 1, 100).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn list_does_not_follow_symlinked_dirs() {
+        let dir = tree();
+        std::os::unix::fs::symlink(dir.path().join("b"), dir.path().join("link")).unwrap();
+        let listing = list(dir.path(), 3, 100).unwrap();
+        let link = listing.entries.iter().find(|e| e.name == "link").unwrap();
+        assert_eq!(link.kind, FileKind::Symlink);
+        assert_eq!(listing.entries.len(), 7);
+    }
+
+    #[test]
+    fn glob_matches_names_and_nested_dirs() {
+        let dir = tree();
+        let response = glob(&pattern(dir.path(), "*.txt"), 100).unwrap();
+        assert_eq!(matched(&response), ["a.txt"]);
+        assert!(!response.truncated);
+
+        // `*` stays in one directory, `**` crosses any number
+        let response = glob(&pattern(dir.path(), "*.log"), 100).unwrap();
+        assert!(response.entries.is_empty());
+        let response = glob(&pattern(dir.path(), "**/*.log"), 100).unwrap();
+        assert_eq!(matched(&response), ["one.log", "two.log"]);
+
+        let response = glob(&pattern(dir.path(), "?"), 100).unwrap();
+        assert_eq!(matched(&response), ["b"]);
+        assert_eq!(response.entries[0].kind, FileKind::Dir);
+        assert_eq!(response.entries[0].depth, None);
+    }
+
+    #[test]
+    fn glob_truncates_at_limit() {
+        let dir = tree();
+        let response = glob(&pattern(dir.path(), "**/*"), 2).unwrap();
+        assert_eq!(response.entries.len(), 2);
+        assert!(response.truncated);
+
+        // Six entries in the tree: a limit of six is not truncated
+        let response = glob(&pattern(dir.path(), "**/*"), 6).unwrap();
+        assert_eq!(response.entries.len(), 6);
+        assert!(!response.truncated);
+    }
+
+    #[test]
+    fn glob_rejects_relative_and_invalid_patterns() {
+        let err = glob("*.txt", 100).unwrap_err();
+        assert!(err.starts_with("Pattern must be absolute"), "{}", err);
+
+        let dir = tree();
+        let err = glob(&pattern(dir.path(), "[a-"), 100).unwrap_err();
+        assert!(err.starts_with("Invalid pattern"), "{}", err);
+    }
+
+    #[test]
+    fn manifest_lists_parents_first_with_relative_paths() {
+        let dir = tree();
+        let manifest = manifest(dir.path(), Some(HashAlgorithm::Sha256)).unwrap();
+        assert_eq!(manifest.dirs, ["b", "b/c", "empty"]);
+        let files: Vec<_> = manifest
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), f.size))
+            .collect();
+        assert_eq!(files, [("a.txt", 1), ("b/c/two.log", 5), ("b/one.log", 3)]);
+        assert_eq!(
+            manifest.files[1].hash.as_deref(),
+            Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")
+        );
+        assert!(manifest.files.iter().all(|f| f.mtime.is_some()));
+
+        let plain = super::manifest(dir.path(), None).unwrap();
+        assert!(plain.files.iter().all(|f| f.hash.is_none()));
+    }
+
+    #[test]
+    fn manifest_of_missing_root_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(manifest(&dir.path().join("missing"), None).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn manifest_skips_symlinks() {
+        let dir = tree();
+        std::os::unix::fs::symlink(dir.path().join("b"), dir.path().join("link_dir")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("a.txt"), dir.path().join("link_file")).unwrap();
+        let manifest = manifest(dir.path(), None).unwrap();
+        assert_eq!(manifest.dirs, ["b", "b/c", "empty"]);
+        assert_eq!(manifest.files.len(), 3);
+    }
+}
@@ -0,0 +1,170 @@
+//! File hashing with several algorithms in one pass.
+
+use sha2::Digest;
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::Path;
+use winpe_agent_core::HashAlgorithm;
+
+/// Size of the buffer files are read through.
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+enum State {
+    Sha256(sha2::Sha256),
+    Sha1(sha1::Sha1),
+    Md5(md5::Md5),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl State {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Sha1 => Self::Sha1(sha1::Sha1::new()),
+            HashAlgorithm::Md5 => Self::Md5(md5::Md5::new()),
+            HashAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Sha1(h) => h.update(data),
+            Self::Md5(h) => h.update(data),
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Self::Sha256(h) => format!("{:x}", h.finalize()),
+            Self::Sha1(h) => format!("{:x}", h.finalize()),
+            Self::Md5(h) => format!("{:x}", h.finalize()),
+            Self::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Hash a file with each of `algorithms`, reading it once.
+///
+/// Returns the number of bytes read and the hex digest per algorithm name.
+pub fn hash_file(
+    path: &Path,
+    algorithms: &[HashAlgorithm],
+) -> io::Result<(u64, BTreeMap<String, String>)> {
+    let mut file = std::fs::File::open(path)?;
+    if file.metadata()?.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            "Path is a directory",
+        ));
+    }
+
+    let mut states: Vec<(HashAlgorithm, State)> =
+        algorithms.iter().map(|&a| (a, State::new(a))).collect();
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    let mut size = 0u64;
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for (_, state) in &mut states {
+            state.update(&buf[..n]);
+        }
+        size += n as u64;
+    }
+
+    let hashes = states
+        .into_iter()
+        .map(|(algorithm, state)| (algorithm.as_str().to_string(), state.finish()))
+        .collect();
+    Ok((size, hashes))
+}
+
+/// Hex SHA-256 of a file's contents.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let (_, mut hashes) = hash_file(path, &[HashAlgorithm::Sha256])?;
+    Ok(hashes
+        .remove(HashAlgorithm::Sha256.as_str())
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(data: &[u8]) -> tempfile::NamedTempFile {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), data).unwrap();
+        file
+    }
+
+    #[test]
+    fn known_digests() {
+        let file = file(b"abc");
+        let all = [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Sha1,
+            HashAlgorithm::Md5,
+            HashAlgorithm::Blake3,
+        ];
+        let (size, hashes) = hash_file(file.path(), &all).unwrap();
+        assert_eq!(size, 3);
+        assert_eq!(hashes.len(), 4);
+        assert_eq!(
+            hashes["sha256"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hashes["sha1"], "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hashes["md5"], "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hashes["blake3"],
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn empty_file_and_no_algorithms() {
+        let file = file(b"");
+        assert_eq!(
+            sha256_file(file.path()).unwrap(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let (size, hashes) = hash_file(file.path(), &[]).unwrap();
+        assert_eq!(size, 0);
+        assert!(hashes.is_empty());
+    }
+
+    #[test]
+    fn files_larger_than_the_buffer() {
+        let data: Vec<u8> = (0..READ_BUFFER_SIZE * 2 + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let file = file(&data);
+        let (size, hashes) = hash_file(file.path(), &[HashAlgorithm::Sha256]).unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(
+            hashes["sha256"],
+            format!("{:x}", sha2::Sha256::digest(&data))
+        );
+    }
+
+    #[test]
+    fn directories_and_missing_files_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = hash_file(dir.path(), &[HashAlgorithm::Sha256]).unwrap_err();
+        // Windows refuses to open a directory as a file in the first place
+        #[cfg(unix)]
+        assert_eq!(err.kind(), io::ErrorKind::IsADirectory);
+        #[cfg(not(unix))]
+        let _ = err;
+
+        let err = sha256_file(&dir.path().join("missing")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,44 @@
+//! Host filesystem access for the file transfer and filesystem APIs.
+//!
+//! Paths are absolute paths on the host. The agent runs with full
+//! privileges, so no location is off limits; the checks here only reject
+//! paths that cannot be interpreted unambiguously.
+
+pub mod archive;
+pub mod browse;
+pub mod hash;
+pub mod uploads;
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use ulid::Ulid;
+
+/// Parse a path from a request, requiring it to be absolute.
+pub fn resolve(path: &str) -> Result<PathBuf, String> {
+    if path.is_empty() {
+        return Err("Missing path".to_string());
+    }
+    let path = PathBuf::from(path);
+    if !path.is_absolute() {
+        return Err(format!("Path must be absolute: {}", path.display()));
+    }
+    Ok(path)
+}
+
+/// Hidden sibling of `dest` to write into before renaming it into place.
+///
+/// Readers never see a partially written file, and a failed transfer
+/// leaves the destination untouched.
+pub fn temp_path(dest: &Path) -> PathBuf {
+    let name = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    dest.with_file_name(format!(".{}.{}.part", name, Ulid::new()))
+}
+
+/// Set the modification time of `path` to `mtime` seconds since the Unix epoch.
+pub fn set_mtime(path: &Path, mtime: u64) -> std::io::Result<()> {
+    let file = std::fs::OpenOptions::new().write(true).open(path)?;
+    file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime))
+}
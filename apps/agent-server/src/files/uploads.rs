@@ -0,0 +1,788 @@
+//! Resumable uploads.
+//!
+//! An upload is written in chunks, at any offset, into a temporary sibling
+//! of its destination. Received ranges are tracked so a client can resume
+//! after a dropped connection. Finalizing checks the SHA-256 of the whole
+//! file and renames it into place. Uploads without activity for the
+//! retention period are discarded.
+
+use axum::body::Body;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures::StreamExt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use ulid::Ulid;
+use winpe_agent_core::{FileUploadResponse, ReceivedRange, UploadCreateRequest, UploadInfo};
+
+/// How often idle uploads are checked for expiry.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Why an upload operation failed.
+pub enum UploadError {
+    /// No upload with this ID.
+    NotFound,
+    /// The request is invalid.
+    BadRequest(String),
+    /// The upload or its destination is not in a state that allows this.
+    Conflict(String),
+    /// Finalize found different content than expected.
+    Mismatch { expected: String, actual: String },
+    /// A filesystem operation on the path failed.
+    Io(io::Error, PathBuf),
+}
+
+struct UploadState {
+    received: Vec<ReceivedRange>,
+    updated_at: DateTime<Utc>,
+    /// Monotonic time of the last activity, used for expiry.
+    touched: Instant,
+    /// Chunk writes in progress.
+    writers: usize,
+    finalizing: bool,
+    /// Set once the upload is deleted or discarded; the last writer then
+    /// removes the temporary file.
+    deleted: bool,
+}
+
+struct Upload {
+    id: String,
+    dest: PathBuf,
+    temp: PathBuf,
+    size: u64,
+    overwrite: bool,
+    /// Permission bits applied on finalize; Windows has none to set.
+    #[cfg(unix)]
+    mode: Option<u32>,
+    mtime: Option<u64>,
+    created_at: DateTime<Utc>,
+    state: Mutex<UploadState>,
+}
+
+impl Upload {
+    fn info(&self) -> UploadInfo {
+        let state = self.state.lock().unwrap();
+        UploadInfo {
+            id: self.id.clone(),
+            path: self.dest.display().to_string(),
+            size: self.size,
+            overwrite: self.overwrite,
+            received: state.received.clone(),
+            received_bytes: state.received.iter().map(|r| r.end - r.start).sum(),
+            created_at: self.created_at.to_rfc3339(),
+            updated_at: state.updated_at.to_rfc3339(),
+        }
+    }
+
+    /// Record `start..end` as received.
+    fn receive(&self, start: u64, end: u64) {
+        let mut state = self.state.lock().unwrap();
+        add_range(&mut state.received, start, end);
+        state.updated_at = Utc::now();
+        state.touched = Instant::now();
+    }
+}
+
+/// Insert a range, keeping the list sorted with touching ranges merged.
+fn add_range(ranges: &mut Vec<ReceivedRange>, start: u64, end: u64) {
+    if start >= end {
+        return;
+    }
+    ranges.push(ReceivedRange { start, end });
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ReceivedRange> = Vec::with_capacity(ranges.len());
+    for range in ranges.drain(..) {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    *ranges = merged;
+}
+
+/// Marks a chunk write in progress for as long as it lives.
+struct WriterGuard(Arc<Upload>);
+
+impl Drop for WriterGuard {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.writers -= 1;
+        if state.deleted && state.writers == 0 {
+            drop(state);
+            remove_temp(&self.0.temp);
+        }
+    }
+}
+
+/// Remove a temporary file, logging rather than returning failures.
+fn remove_temp(temp: &std::path::Path) {
+    match std::fs::remove_file(temp) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("Failed to remove {}: {}", temp.display(), e),
+    }
+}
+
+/// Thread-safe upload manager using DashMap.
+#[derive(Clone)]
+pub struct UploadManager {
+    uploads: Arc<DashMap<String, Arc<Upload>>>,
+    /// How long an upload may sit idle before it is discarded.
+    retention: Duration,
+}
+
+impl UploadManager {
+    /// Create a new upload manager.
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            uploads: Arc::new(DashMap::new()),
+            retention,
+        }
+    }
+
+    /// Start a background task to discard idle uploads.
+    /// Should be called once when the server starts.
+    pub fn start_cleanup_task(&self) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                manager.discard_idle().await;
+            }
+        });
+    }
+
+    /// Discard uploads idle for longer than the retention period.
+    async fn discard_idle(&self) {
+        let mut expired = Vec::new();
+        self.uploads.retain(|id, upload| {
+            let state = upload.state.lock().unwrap();
+            let idle =
+                state.writers == 0 && !state.finalizing && state.touched.elapsed() > self.retention;
+            if idle {
+                tracing::info!("Discarding idle upload {}", id);
+                expired.push(upload.clone());
+            }
+            !idle
+        });
+        for upload in expired {
+            // A writer that looked the upload up just before is refused
+            upload.state.lock().unwrap().deleted = true;
+            let temp = upload.temp.clone();
+            let _ = tokio::task::spawn_blocking(move || remove_temp(&temp)).await;
+        }
+    }
+
+    /// Start an upload, reserving a temporary file next to the destination.
+    pub async fn create(&self, req: UploadCreateRequest) -> Result<UploadInfo, UploadError> {
+        let dest = super::resolve(&req.path).map_err(UploadError::BadRequest)?;
+        // Ignored on Windows, but still validated there
+        #[cfg_attr(not(unix), allow(unused_variables))]
+        let mode = match req.mode.as_deref().map(|m| u32::from_str_radix(m, 8)) {
+            None => None,
+            Some(Ok(mode)) if mode <= 0o7777 => Some(mode),
+            Some(_) => {
+                return Err(UploadError::BadRequest(
+                    "mode must be octal permission bits, e.g. 644".to_string(),
+                ));
+            }
+        };
+        check_destination(&dest, req.overwrite).await?;
+
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| UploadError::Io(e, parent.to_path_buf()))?;
+        }
+        let temp = super::temp_path(&dest);
+        let file = tokio::fs::File::create(&temp)
+            .await
+            .map_err(|e| UploadError::Io(e, temp.clone()))?;
+        if let Err(e) = file.set_len(req.size).await {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(UploadError::Io(e, dest));
+        }
+
+        let now = Utc::now();
+        let upload = Arc::new(Upload {
+            id: Ulid::new().to_string(),
+            dest,
+            temp,
+            size: req.size,
+            overwrite: req.overwrite,
+            #[cfg(unix)]
+            mode,
+            mtime: req.mtime,
+            created_at: now,
+            state: Mutex::new(UploadState {
+                received: Vec::new(),
+                updated_at: now,
+                touched: Instant::now(),
+                writers: 0,
+                finalizing: false,
+                deleted: false,
+            }),
+        });
+        tracing::info!(
+            "Created upload {} for {} ({} bytes)",
+            upload.id,
+            upload.dest.display(),
+            upload.size
+        );
+        let info = upload.info();
+        self.uploads.insert(upload.id.clone(), upload);
+        Ok(info)
+    }
+
+    /// List uploads, oldest first, optionally only those for `path`.
+    pub fn list(&self, path: Option<&str>) -> Vec<UploadInfo> {
+        let path = path.map(PathBuf::from);
+        let mut uploads: Vec<UploadInfo> = self
+            .uploads
+            .iter()
+            .filter(|entry| path.as_ref().is_none_or(|p| *p == entry.dest))
+            .map(|entry| entry.info())
+            .collect();
+        uploads.sort_by(|a, b| a.id.cmp(&b.id));
+        uploads
+    }
+
+    /// Get upload info by ID.
+    pub fn get(&self, id: &str) -> Option<UploadInfo> {
+        self.uploads.get(id).map(|upload| upload.info())
+    }
+
+    /// Write a chunk at `offset`.
+    ///
+    /// Whatever arrived before an interrupted body is kept, so the client
+    /// only needs to resend the rest.
+    pub async fn write_chunk(
+        &self,
+        id: &str,
+        offset: u64,
+        body: Body,
+    ) -> Result<UploadInfo, UploadError> {
+        let upload = self
+            .uploads
+            .get(id)
+            .map(|entry| entry.value().clone())
+            .ok_or(UploadError::NotFound)?;
+        if offset > upload.size {
+            return Err(UploadError::BadRequest(format!(
+                "Offset {} is past the end of the file ({} bytes)",
+                offset, upload.size
+            )));
+        }
+        // Declared before the file, so the file is closed when the guard drops
+        let _guard = {
+            let mut state = upload.state.lock().unwrap();
+            if state.deleted {
+                return Err(UploadError::NotFound);
+            }
+            if state.finalizing {
+                return Err(UploadError::Conflict(
+                    "Upload is being finalized".to_string(),
+                ));
+            }
+            state.writers += 1;
+            state.touched = Instant::now();
+            WriterGuard(upload.clone())
+        };
+
+        let io_error = |e| UploadError::Io(e, upload.dest.clone());
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&upload.temp)
+            .await
+            .map_err(io_error)?;
+        file.seek(io::SeekFrom::Start(offset))
+            .await
+            .map_err(io_error)?;
+
+        let mut written = 0u64;
+        let mut stream = body.into_data_stream();
+        let result = loop {
+            let chunk = match stream.next().await {
+                None => break Ok(()),
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    break Err(UploadError::BadRequest(format!(
+                        "Upload interrupted: {}",
+                        e
+                    )));
+                }
+            };
+            if offset + written + chunk.len() as u64 > upload.size {
+                break Err(UploadError::BadRequest(format!(
+                    "Chunk extends past the end of the file ({} bytes)",
+                    upload.size
+                )));
+            }
+            if let Err(e) = file.write_all(&chunk).await {
+                break Err(io_error(e));
+            }
+            written += chunk.len() as u64;
+            if upload.state.lock().unwrap().deleted {
+                break Err(UploadError::NotFound);
+            }
+        };
+
+        // Keep what reached the file, even if the chunk was cut short
+        if let Err(e) = file.flush().await {
+            return Err(io_error(e));
+        }
+        upload.receive(offset, offset + written);
+        result.map(|()| upload.info())
+    }
+
+    /// Verify a complete upload and move it to its destination.
+    ///
+    /// On a checksum mismatch the received ranges are cleared, so the
+    /// client resends the whole file.
+    pub async fn finalize(
+        &self,
+        id: &str,
+        sha256: &str,
+    ) -> Result<FileUploadResponse, UploadError> {
+        let upload = self
+            .uploads
+            .get(id)
+            .map(|entry| entry.value().clone())
+            .ok_or(UploadError::NotFound)?;
+        {
+            let mut state = upload.state.lock().unwrap();
+            if state.deleted {
+                return Err(UploadError::NotFound);
+            }
+            let received: u64 = state.received.iter().map(|r| r.end - r.start).sum();
+            if received < upload.size {
+                return Err(UploadError::Conflict(format!(
+                    "Upload is incomplete: {} of {} bytes received",
+                    received, upload.size
+                )));
+            }
+            if state.writers > 0 || state.finalizing {
+                return Err(UploadError::Conflict(
+                    "Upload is still being written".to_string(),
+                ));
+            }
+            state.finalizing = true;
+        }
+
+        let result = self.finish(&upload, sha256).await;
+        let mut state = upload.state.lock().unwrap();
+        state.finalizing = false;
+        state.touched = Instant::now();
+        match result {
+            Ok(response) => {
+                drop(state);
+                self.uploads.remove(id);
+                tracing::info!("Finalized upload {} to {}", id, upload.dest.display());
+                Ok(response)
+            }
+            Err(e) => {
+                if matches!(e, UploadError::Mismatch { .. }) {
+                    state.received.clear();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn finish(
+        &self,
+        upload: &Upload,
+        sha256: &str,
+    ) -> Result<FileUploadResponse, UploadError> {
+        let temp = upload.temp.clone();
+        let actual = tokio::task::spawn_blocking(move || super::hash::sha256_file(&temp))
+            .await
+            .map_err(|e| UploadError::Io(io::Error::other(e), upload.dest.clone()))?
+            .map_err(|e| UploadError::Io(e, upload.dest.clone()))?;
+        let expected = sha256.trim().to_ascii_lowercase();
+        if actual != expected {
+            return Err(UploadError::Mismatch { expected, actual });
+        }
+
+        let existed = check_destination(&upload.dest, upload.overwrite).await?;
+        let io_error = |e| UploadError::Io(e, upload.dest.clone());
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&upload.temp)
+            .await
+            .map_err(io_error)?;
+        file.sync_all().await.map_err(io_error)?;
+        drop(file);
+        if let Some(mtime) = upload.mtime {
+            super::set_mtime(&upload.temp, mtime).map_err(io_error)?;
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = upload.mode {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&upload.temp, std::fs::Permissions::from_mode(mode))
+                .await
+                .map_err(io_error)?;
+        }
+        tokio::fs::rename(&upload.temp, &upload.dest)
+            .await
+            .map_err(io_error)?;
+
+        Ok(FileUploadResponse {
+            path: upload.dest.display().to_string(),
+            size: upload.size,
+            created: !existed,
+        })
+    }
+
+    /// Abandon an upload and delete its temporary file.
+    ///
+    /// Writes in progress stop at their next chunk; the file is removed
+    /// once the last of them has closed it, as Windows refuses to delete
+    /// an open file.
+    pub async fn delete(&self, id: &str) -> Result<(), UploadError> {
+        let upload = self
+            .uploads
+            .get(id)
+            .map(|entry| entry.value().clone())
+            .ok_or(UploadError::NotFound)?;
+        let writing = {
+            let mut state = upload.state.lock().unwrap();
+            if state.deleted {
+                return Err(UploadError::NotFound);
+            }
+            if state.finalizing {
+                return Err(UploadError::Conflict(
+                    "Upload is being finalized".to_string(),
+                ));
+            }
+            state.deleted = true;
+            state.writers > 0
+        };
+        self.uploads.remove(id);
+        tracing::info!("Deleted upload {}", id);
+        if writing {
+            return Ok(());
+        }
+        match tokio::fs::remove_file(&upload.temp).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(UploadError::Io(e, upload.temp.clone())),
+        }
+    }
+}
+
+/// Check that `dest` may be written, returning whether it exists.
+async fn check_destination(dest: &std::path::Path, overwrite: bool) -> Result<bool, UploadError> {
+    match tokio::fs::metadata(dest).await {
+        Ok(metadata) if metadata.is_dir() => Err(UploadError::Conflict(format!(
+            "{}: Path is a directory",
+            dest.display()
+        ))),
+        Ok(_) if !overwrite => Err(UploadError::Conflict(format!(
+            "{}: File already exists",
+            dest.display()
+        ))),
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(UploadError::Io(e, dest.to_path_buf())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use sha2::Digest;
+
+    fn range(start: u64, end: u64) -> ReceivedRange {
+        ReceivedRange { start, end }
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        format!("{:x}", sha2::Sha256::digest(data))
+    }
+
+    async fn create(
+        manager: &UploadManager,
+        dir: &tempfile::TempDir,
+        size: u64,
+    ) -> (UploadInfo, PathBuf) {
+        let dest = dir.path().join("file.bin");
+        let info = manager
+            .create(UploadCreateRequest {
+                path: dest.display().to_string(),
+                size,
+                overwrite: false,
+                mode: None,
+                mtime: None,
+            })
+            .await
+            .ok()
+            .unwrap();
+        (info, dest)
+    }
+
+    async fn write(manager: &UploadManager, id: &str, offset: u64, data: &[u8]) -> UploadInfo {
+        manager
+            .write_chunk(id, offset, Body::from(data.to_vec()))
+            .await
+            .ok()
+            .unwrap()
+    }
+
+    /// Files in `dir`, which holds the destination and temporary files.
+    fn entries(dir: &tempfile::TempDir) -> usize {
+        std::fs::read_dir(dir.path()).unwrap().count()
+    }
+
+    #[test]
+    fn add_range_merges_overlapping_and_touching() {
+        let mut ranges = Vec::new();
+        add_range(&mut ranges, 10, 20);
+        add_range(&mut ranges, 30, 40);
+        assert_eq!(ranges, [range(10, 20), range(30, 40)]);
+        // Touching the end of one range
+        add_range(&mut ranges, 20, 25);
+        assert_eq!(ranges, [range(10, 25), range(30, 40)]);
+        // Overlapping both neighbours
+        add_range(&mut ranges, 22, 35);
+        assert_eq!(ranges, [range(10, 40)]);
+        // Contained, before, and empty
+        add_range(&mut ranges, 12, 18);
+        add_range(&mut ranges, 0, 5);
+        add_range(&mut ranges, 7, 7);
+        assert_eq!(ranges, [range(0, 5), range(10, 40)]);
+        add_range(&mut ranges, 5, 10);
+        assert_eq!(ranges, [range(0, 40)]);
+    }
+
+    #[tokio::test]
+    async fn out_of_order_chunks_finalize() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_secs(3600));
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let (info, dest) = create(&manager, &dir, data.len() as u64).await;
+
+        write(&manager, &info.id, 6000, &data[6000..]).await;
+        let progress = write(&manager, &info.id, 0, &data[..3000]).await;
+        assert_eq!(progress.received, [range(0, 3000), range(6000, 10_000)]);
+        assert_eq!(progress.received_bytes, 7000);
+        // A resent, overlapping chunk is harmless
+        write(&manager, &info.id, 2000, &data[2000..4000]).await;
+        let progress = write(&manager, &info.id, 4000, &data[4000..6000]).await;
+        assert_eq!(progress.received, [range(0, 10_000)]);
+
+        let response = manager
+            .finalize(&info.id, &sha256(&data))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(response.size, 10_000);
+        assert!(response.created);
+        assert_eq!(std::fs::read(&dest).unwrap(), data);
+        assert!(manager.get(&info.id).is_none());
+        assert_eq!(entries(&dir), 1);
+    }
+
+    #[tokio::test]
+    async fn sha256_mismatch_clears_received_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_secs(3600));
+        let (info, dest) = create(&manager, &dir, 5).await;
+        write(&manager, &info.id, 0, b"hello").await;
+
+        let result = manager.finalize(&info.id, &sha256(b"world")).await;
+        let Err(UploadError::Mismatch { expected, actual }) = result else {
+            panic!("expected a checksum mismatch");
+        };
+        assert_eq!(expected, sha256(b"world"));
+        assert_eq!(actual, sha256(b"hello"));
+        assert!(!dest.exists());
+        // The client must resend everything
+        assert!(manager.get(&info.id).unwrap().received.is_empty());
+
+        write(&manager, &info.id, 0, b"hello").await;
+        // Case and whitespace in the expected hash do not matter
+        let expected = format!(" {} ", sha256(b"hello").to_uppercase());
+        assert!(manager.finalize(&info.id, &expected).await.is_ok());
+        assert_eq!(std::fs::read(&dest).unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn size_is_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_secs(3600));
+        let (info, dest) = create(&manager, &dir, 8).await;
+
+        let past_end = manager.write_chunk(&info.id, 9, Body::from("x")).await;
+        assert!(matches!(past_end, Err(UploadError::BadRequest(_))));
+        let too_long = manager.write_chunk(&info.id, 4, Body::from("abcdef")).await;
+        assert!(matches!(too_long, Err(UploadError::BadRequest(_))));
+
+        write(&manager, &info.id, 0, b"1234").await;
+        let incomplete = manager.finalize(&info.id, &sha256(b"1234")).await;
+        assert!(matches!(incomplete, Err(UploadError::Conflict(_))));
+        write(&manager, &info.id, 4, b"5678").await;
+        assert!(
+            manager
+                .finalize(&info.id, &sha256(b"12345678"))
+                .await
+                .is_ok()
+        );
+        assert_eq!(std::fs::metadata(&dest).unwrap().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn interrupted_chunk_keeps_what_arrived() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_secs(3600));
+        let (info, _) = create(&manager, &dir, 10).await;
+
+        let body = Body::from_stream(futures::stream::iter([
+            Ok(Bytes::from_static(b"abcd")),
+            Err(io::Error::from(io::ErrorKind::ConnectionReset)),
+        ]));
+        let result = manager.write_chunk(&info.id, 0, body).await;
+        assert!(matches!(result, Err(UploadError::BadRequest(_))));
+        assert_eq!(manager.get(&info.id).unwrap().received, [range(0, 4)]);
+    }
+
+    #[tokio::test]
+    async fn existing_destination_needs_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_secs(3600));
+        let dest = dir.path().join("file.bin");
+        std::fs::write(&dest, b"old").unwrap();
+        let mut req = UploadCreateRequest {
+            path: dest.display().to_string(),
+            size: 3,
+            overwrite: false,
+            mode: None,
+            mtime: None,
+        };
+        assert!(matches!(
+            manager.create(req.clone()).await,
+            Err(UploadError::Conflict(_))
+        ));
+        req.overwrite = true;
+        let info = manager.create(req).await.ok().unwrap();
+        write(&manager, &info.id, 0, b"new").await;
+        let response = manager
+            .finalize(&info.id, &sha256(b"new"))
+            .await
+            .ok()
+            .unwrap();
+        assert!(!response.created);
+        assert_eq!(std::fs::read(&dest).unwrap(), b"new");
+    }
+
+    #[tokio::test]
+    async fn idle_uploads_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_millis(50));
+        let (idle, _) = create(&manager, &dir, 4).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (fresh, _) = create(&manager, &dir, 4).await;
+        assert_eq!(entries(&dir), 2);
+
+        manager.discard_idle().await;
+        assert!(manager.get(&idle.id).is_none());
+        assert!(manager.get(&fresh.id).is_some());
+        // The idle upload's temporary file is gone
+        assert_eq!(entries(&dir), 1);
+        assert!(matches!(
+            manager.write_chunk(&idle.id, 0, Body::from("x")).await,
+            Err(UploadError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_temporary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_secs(3600));
+        let (info, _) = create(&manager, &dir, 4).await;
+        assert_eq!(entries(&dir), 1);
+        assert!(manager.delete(&info.id).await.is_ok());
+        assert_eq!(entries(&dir), 0);
+        assert!(matches!(
+            manager.delete(&info.id).await,
+            Err(UploadError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn delete_during_write_removes_file_after_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_secs(3600));
+        let (info, _) = create(&manager, &dir, 8).await;
+
+        let (tx, rx) = tokio::sync::mpsc::channel::<io::Result<Bytes>>(1);
+        let writer = {
+            let manager = manager.clone();
+            let id = info.id.clone();
+            let body = Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx));
+            tokio::spawn(async move { manager.write_chunk(&id, 0, body).await })
+        };
+        tx.send(Ok(Bytes::from_static(b"ab"))).await.unwrap();
+        while manager
+            .uploads
+            .get(&info.id)
+            .unwrap()
+            .state
+            .lock()
+            .unwrap()
+            .writers
+            == 0
+        {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert!(manager.delete(&info.id).await.is_ok());
+        assert!(manager.get(&info.id).is_none());
+        // The writer still holds the file open
+        assert_eq!(entries(&dir), 1);
+        // Its next chunk ends the write, and the file goes with it
+        let _ = tx.send(Ok(Bytes::from_static(b"cd"))).await;
+        drop(tx);
+        assert!(matches!(writer.await.unwrap(), Err(UploadError::NotFound)));
+        assert_eq!(entries(&dir), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn applies_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(Duration::from_secs(3600));
+        let dest = dir.path().join("script.sh");
+        let req = |mode: &str| UploadCreateRequest {
+            path: dest.display().to_string(),
+            size: 2,
+            overwrite: false,
+            mode: Some(mode.to_string()),
+            mtime: Some(1_000_000_000),
+        };
+        assert!(matches!(
+            manager.create(req("999")).await,
+            Err(UploadError::BadRequest(_))
+        ));
+        let info = manager.create(req("750")).await.ok().unwrap();
+        write(&manager, &info.id, 0, b"#!").await;
+        assert!(manager.finalize(&info.id, &sha256(b"#!")).await.is_ok());
+        let metadata = std::fs::metadata(&dest).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o750);
+        let mtime = metadata.modified().unwrap();
+        assert_eq!(
+            mtime
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            1_000_000_000
+        );
+    }
+}
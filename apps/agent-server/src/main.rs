@@ -2,11 +2,23 @@
 //!
 //! Provides:
 //! - Automation API: Execute single commands
+//! - Jobs API: Background commands with output polling
 //! - Terminal API: ConPTY-backed interactive sessions
+//! - Files API: Upload and download host files, with resumable uploads
+//! - Filesystem API: List, inspect, move and delete host files
+//! - Archive API: Download directory trees as zip or tar.gz
+//! - Block API: Image and restore raw disks and volumes
+//! - System API: Disk and volume inventory
 //! - Static UI: xterm.js web interface
 
 mod api;
+mod auth;
 mod automation;
+mod block;
+mod config;
+mod files;
+mod stream;
+mod system;
 mod terminal;
 
 use axum::Router;
@@ -28,15 +40,63 @@ async fn main() {
 
     tracing::info!("Starting winpe-agent-server v{}", winpe_agent_core::VERSION);
 
+    // Load configuration
+    let config = match config::ServerConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let auth = if config.insecure {
+        tracing::warn!("Authentication is DISABLED (insecure mode); anyone can use the API");
+        auth::AuthState::insecure()
+    } else if config.tokens.is_empty() {
+        // Fail closed: never serve a SYSTEM shell without credentials
+        let token = auth::generate_token();
+        tracing::warn!(
+            "No API tokens configured (WINPE_AGENT_TOKENS or winpe-agent.json); generated token for this run: {}",
+            token
+        );
+        auth::AuthState::new(vec![token])
+    } else {
+        auth::AuthState::new(config.tokens.clone())
+    };
+
     // Initialize session manager
-    let session_manager = terminal::SessionManager::new();
+    let session_manager = terminal::SessionManager::new(config.recording_dir.clone());
 
     // Start background task to clean up idle sessions
     session_manager.start_cleanup_task();
 
+    // Initialize job manager and expire finished jobs in the background
+    let job_manager = automation::jobs::JobManager::new(
+        config.max_jobs,
+        std::time::Duration::from_secs(config.job_retention_secs),
+        config.max_output_bytes as usize,
+        (config.job_timeout_secs > 0).then(|| config.job_timeout_secs * 1000),
+    );
+    job_manager.start_cleanup_task();
+
+    // Initialize upload manager and discard idle uploads in the background
+    let upload_manager = files::uploads::UploadManager::new(std::time::Duration::from_secs(
+        config.upload_retention_secs,
+    ));
+    upload_manager.start_cleanup_task();
+
     // Build the router
     let app = Router::new()
-        .nest("/api/v1", api::router(session_manager.clone()))
+        .nest(
+            "/api/v1",
+            api::router(
+                session_manager.clone(),
+                job_manager,
+                upload_manager,
+                auth,
+                &config,
+            ),
+        )
         .nest_service(
             "/ui",
             ServeDir::new("ui").append_index_html_on_directories(true),
@@ -0,0 +1,275 @@
+//! Disk and volume inventory.
+//!
+//! The platform backends enumerate disks and volumes; partition tables
+//! and filesystem signatures are read from raw sectors by shared code,
+//! so a locked or unformatted volume is still described as far as its
+//! sectors allow.
+
+mod partition;
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(unix)]
+use unix as sys;
+#[cfg(windows)]
+use win32 as sys;
+
+use std::fs::File;
+use std::io;
+use winpe_agent_core::{DiskInfo, PartitionStyle, VolumeInfo};
+
+use crate::block;
+
+/// Bytes read from the start of a partition to recognize its filesystem.
+const PROBE_BYTES: usize = 4096;
+
+/// List the host's disks with their partition tables.
+pub fn disks() -> io::Result<Vec<DiskInfo>> {
+    Ok(scan()?.into_iter().map(|disk| disk.info).collect())
+}
+
+/// List the host's volumes.
+pub fn volumes() -> io::Result<Vec<VolumeInfo>> {
+    sys::volumes(&scan()?)
+}
+
+/// A disk found by a backend, before its partition table is read.
+struct Found {
+    number: u32,
+    /// Name for [`block::resolve`].
+    device: String,
+    /// Size in bytes if the backend knows it without opening the disk.
+    size: u64,
+    sector_size: u32,
+    model: Option<String>,
+}
+
+/// A disk with its partitions read and probed.
+struct Scanned {
+    info: DiskInfo,
+    /// Probe of each partition in `info.partitions`, if it could be read.
+    probes: Vec<Option<Probe>>,
+    /// Probe of the whole disk when it has no partition table.
+    whole: Option<Probe>,
+}
+
+fn scan() -> io::Result<Vec<Scanned>> {
+    Ok(sys::find_disks()?.into_iter().map(scan_disk).collect())
+}
+
+/// Read a disk's partition table and probe its partitions.
+///
+/// Failures are reported in the disk's `error` rather than dropping it.
+fn scan_disk(found: Found) -> Scanned {
+    let mut info = DiskInfo {
+        number: found.number,
+        device: found.device,
+        size: found.size,
+        sector_size: found.sector_size,
+        model: found.model,
+        partition_style: None,
+        disk_id: None,
+        partitions: Vec::new(),
+        error: None,
+    };
+    let opened = block::resolve(&info.device)
+        .map_err(io::Error::other)
+        .and_then(|device| block::open_read(&device));
+    let (mut file, size) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            info.error = Some(e.to_string());
+            return Scanned {
+                info,
+                probes: Vec::new(),
+                whole: None,
+            };
+        }
+    };
+    info.size = size;
+
+    match partition::read(&mut file, size, info.sector_size) {
+        Ok(table) => {
+            info.partition_style = Some(table.style);
+            info.disk_id = table.disk_id;
+            info.partitions = table.partitions;
+        }
+        Err(e) => info.error = Some(format!("Partition table: {}", e)),
+    }
+    let probes = info
+        .partitions
+        .iter()
+        .map(|p| probe(&mut file, size, p.offset, p.size).ok())
+        .collect();
+    let whole = if info.partition_style == Some(PartitionStyle::Raw) {
+        probe(&mut file, size, 0, size).ok()
+    } else {
+        None
+    };
+    Scanned {
+        info,
+        probes,
+        whole,
+    }
+}
+
+/// Filesystem recognized from a volume's first sectors.
+#[derive(Debug, Clone, Default)]
+struct Probe {
+    filesystem: Option<String>,
+    label: Option<String>,
+    bitlocker: bool,
+}
+
+/// Recognize the filesystem of the `len`-byte volume at `offset`.
+fn probe(file: &mut File, size: u64, offset: u64, len: u64) -> io::Result<Probe> {
+    let n = len.min(PROBE_BYTES as u64) as usize;
+    let mut head = block::read_range(file, size, offset, n)?;
+    head.resize(PROBE_BYTES, 0);
+    Ok(recognize(&head))
+}
+
+/// Recognize a filesystem from its first [`PROBE_BYTES`] bytes.
+fn recognize(head: &[u8]) -> Probe {
+    let fs = |name: &str, label: Option<String>| Probe {
+        filesystem: Some(name.to_string()),
+        label,
+        bitlocker: false,
+    };
+    match &head[3..11] {
+        b"-FVE-FS-" => {
+            return Probe {
+                bitlocker: true,
+                ..Probe::default()
+            };
+        }
+        b"NTFS    " => return fs("NTFS", None),
+        b"EXFAT   " => return fs("exFAT", None),
+        b"ReFS\0\0\0\0" => return fs("ReFS", None),
+        _ => {}
+    }
+    if head[510..512] == [0x55, 0xaa] {
+        if &head[82..87] == b"FAT32" {
+            return fs("FAT32", fat_label(&head[71..82]));
+        }
+        if &head[54..58] == b"FAT1" {
+            return fs("FAT", fat_label(&head[43..54]));
+        }
+    }
+
+    // ext2/3/4 superblock at byte 1024
+    let sb = &head[1024..2048];
+    if sb[56..58] == [0x53, 0xef] {
+        let compat = u32::from_le_bytes(sb[92..96].try_into().unwrap());
+        let incompat = u32::from_le_bytes(sb[96..100].try_into().unwrap());
+        let name = if incompat & 0x40 != 0 {
+            "ext4"
+        } else if compat & 0x4 != 0 {
+            "ext3"
+        } else {
+            "ext2"
+        };
+        return fs(name, text_label(&sb[120..136]));
+    }
+    if &head[4086..4096] == b"SWAPSPACE2" {
+        return fs("swap", None);
+    }
+    Probe::default()
+}
+
+fn fat_label(raw: &[u8]) -> Option<String> {
+    text_label(raw).filter(|label| label != "NO NAME")
+}
+
+fn text_label(raw: &[u8]) -> Option<String> {
+    let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    let label = String::from_utf8_lossy(&raw[..end]).trim().to_string();
+    Some(label).filter(|l| !l.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A [`PROBE_BYTES`] head with `oem` at the OEM ID and a boot signature.
+    fn boot_sector(oem: &[u8; 8]) -> Vec<u8> {
+        let mut head = vec![0; PROBE_BYTES];
+        head[0..3].copy_from_slice(&[0xeb, 0x52, 0x90]);
+        head[3..11].copy_from_slice(oem);
+        head[510..512].copy_from_slice(&[0x55, 0xaa]);
+        head
+    }
+
+    fn recognized(head: &[u8]) -> (Option<String>, Option<String>, bool) {
+        let probe = recognize(head);
+        (probe.filesystem, probe.label, probe.bitlocker)
+    }
+
+    #[test]
+    fn recognizes_ntfs_and_exfat() {
+        let ntfs = boot_sector(b"NTFS    ");
+        assert_eq!(recognized(&ntfs), (Some("NTFS".into()), None, false));
+        let exfat = boot_sector(b"EXFAT   ");
+        assert_eq!(recognized(&exfat), (Some("exFAT".into()), None, false));
+    }
+
+    #[test]
+    fn recognizes_fat32_with_label() {
+        let mut fat32 = boot_sector(b"MSDOS5.0");
+        fat32[71..82].copy_from_slice(b"WINPE      ");
+        fat32[82..90].copy_from_slice(b"FAT32   ");
+        assert_eq!(
+            recognized(&fat32),
+            (Some("FAT32".into()), Some("WINPE".into()), false)
+        );
+        fat32[71..82].copy_from_slice(b"NO NAME    ");
+        assert_eq!(recognized(&fat32), (Some("FAT32".into()), None, false));
+
+        // Without the boot signature the type string is not trusted
+        fat32[510..512].fill(0);
+        assert_eq!(recognized(&fat32), (None, None, false));
+    }
+
+    #[test]
+    fn recognizes_fat16_with_label() {
+        let mut fat = boot_sector(b"MSDOS5.0");
+        fat[43..54].copy_from_slice(b"SYSTEM     ");
+        fat[54..62].copy_from_slice(b"FAT16   ");
+        assert_eq!(
+            recognized(&fat),
+            (Some("FAT".into()), Some("SYSTEM".into()), false)
+        );
+    }
+
+    #[test]
+    fn recognizes_bitlocker() {
+        // The encrypted volume's real filesystem is not visible
+        let bitlocker = boot_sector(b"-FVE-FS-");
+        assert_eq!(recognized(&bitlocker), (None, None, true));
+    }
+
+    #[test]
+    fn recognizes_ext_and_swap() {
+        let mut ext = vec![0; PROBE_BYTES];
+        ext[1024 + 56..1024 + 58].copy_from_slice(&[0x53, 0xef]);
+        ext[1024 + 120..1024 + 125].copy_from_slice(b"root\0");
+        assert_eq!(
+            recognized(&ext),
+            (Some("ext2".into()), Some("root".into()), false)
+        );
+        ext[1024 + 96] = 0x40;
+        assert_eq!(recognized(&ext).0.as_deref(), Some("ext4"));
+
+        let mut swap = vec![0; PROBE_BYTES];
+        swap[4086..].copy_from_slice(b"SWAPSPACE2");
+        assert_eq!(recognized(&swap).0.as_deref(), Some("swap"));
+    }
+
+    #[test]
+    fn unknown_contents() {
+        assert_eq!(recognized(&vec![0; PROBE_BYTES]), (None, None, false));
+        assert_eq!(recognized(&boot_sector(b"MSWIN4.1")), (None, None, false));
+    }
+}
@@ -0,0 +1,587 @@
+//! MBR and GPT partition table parsing.
+//!
+//! Tables are read straight from the disk's sectors, the same way on
+//! every platform, so Windows disks and Linux loop devices over image
+//! files go through identical code.
+
+use std::fs::File;
+use std::io;
+use winpe_agent_core::{PartitionInfo, PartitionStyle};
+
+use crate::block;
+
+/// Partition type byte of an MBR extended partition container.
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// MBR type byte of a GPT protective partition.
+const GPT_PROTECTIVE: u8 = 0xee;
+
+/// Limit on the size of the GPT partition entry array.
+const MAX_GPT_ENTRIES_BYTES: u64 = 1024 * 1024;
+
+/// Limit on logical partitions, in case an EBR chain loops.
+const MAX_LOGICAL: u32 = 128;
+
+/// A parsed partition table.
+pub struct Table {
+    pub style: PartitionStyle,
+    pub disk_id: Option<String>,
+    pub partitions: Vec<PartitionInfo>,
+}
+
+/// Read the partition table of a disk of `size` bytes.
+///
+/// `sector_size` is the disk's logical sector size; a GPT header is also
+/// looked for at the other common size, as image files do not report one.
+pub fn read(file: &mut File, size: u64, sector_size: u32) -> io::Result<Table> {
+    let raw = Table {
+        style: PartitionStyle::Raw,
+        disk_id: None,
+        partitions: Vec::new(),
+    };
+    if size < 512 {
+        return Ok(raw);
+    }
+    let mbr = block::read_range(file, size, 0, 512)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        return Ok(raw);
+    }
+
+    let entries: Vec<MbrEntry> = (0..4).filter_map(|i| MbrEntry::parse(&mbr, i)).collect();
+    if entries.iter().any(|e| e.kind == GPT_PROTECTIVE) {
+        let mut sizes = vec![sector_size as u64];
+        for other in [512, 4096] {
+            if !sizes.contains(&other) {
+                sizes.push(other);
+            }
+        }
+        for sector in sizes {
+            if let Some(table) = read_gpt(file, size, sector)? {
+                return Ok(table);
+            }
+        }
+    }
+
+    // A FAT or NTFS boot sector also ends in 55 AA; it has no valid entries
+    if entries.is_empty() || looks_like_boot_sector(&mbr) {
+        return Ok(raw);
+    }
+    read_mbr(file, size, sector_size as u64, &mbr, &entries)
+}
+
+/// A used primary or logical MBR entry.
+struct MbrEntry {
+    number: u32,
+    active: bool,
+    kind: u8,
+    /// First sector, relative to the table holding the entry.
+    start: u64,
+    sectors: u64,
+}
+
+impl MbrEntry {
+    fn parse(sector: &[u8], index: usize) -> Option<Self> {
+        let e = &sector[446 + index * 16..462 + index * 16];
+        let kind = e[4];
+        let start = u32::from_le_bytes(e[8..12].try_into().unwrap()) as u64;
+        let sectors = u32::from_le_bytes(e[12..16].try_into().unwrap()) as u64;
+        if kind == 0 || sectors == 0 {
+            return None;
+        }
+        Some(Self {
+            number: index as u32 + 1,
+            active: e[0] == 0x80,
+            kind,
+            start,
+            sectors,
+        })
+    }
+}
+
+fn looks_like_boot_sector(sector: &[u8]) -> bool {
+    matches!(&sector[3..11], b"NTFS    " | b"EXFAT   " | b"-FVE-FS-")
+        || &sector[82..87] == b"FAT32"
+        || &sector[54..58] == b"FAT1"
+}
+
+fn read_mbr(
+    file: &mut File,
+    size: u64,
+    sector: u64,
+    mbr: &[u8],
+    entries: &[MbrEntry],
+) -> io::Result<Table> {
+    let mut partitions = Vec::new();
+    let mut logical = Vec::new();
+    for entry in entries {
+        partitions.push(mbr_partition(entry, 0, sector));
+        if EXTENDED_TYPES.contains(&entry.kind) && logical.is_empty() {
+            logical = read_logical(file, size, sector, entry.start);
+        }
+    }
+    partitions.extend(logical);
+
+    let signature = u32::from_le_bytes(mbr[440..444].try_into().unwrap());
+    Ok(Table {
+        style: PartitionStyle::Mbr,
+        disk_id: Some(format!("{:08x}", signature)),
+        partitions,
+    })
+}
+
+/// Follow the chain of extended boot records starting at sector `base`.
+///
+/// Stops quietly at the first record that cannot be read, keeping the
+/// logical partitions found so far.
+fn read_logical(file: &mut File, size: u64, sector: u64, base: u64) -> Vec<PartitionInfo> {
+    let mut partitions = Vec::new();
+    let mut ebr = base;
+    for number in 5..5 + MAX_LOGICAL {
+        let Ok(record) = block::read_range(file, size, ebr * sector, 512) else {
+            break;
+        };
+        if record[510..512] != [0x55, 0xaa] {
+            break;
+        }
+        if let Some(mut entry) = MbrEntry::parse(&record, 0) {
+            entry.number = number;
+            partitions.push(mbr_partition(&entry, ebr, sector));
+        }
+        // The link to the next record is relative to the extended partition
+        match MbrEntry::parse(&record, 1) {
+            Some(next) if base + next.start > ebr => ebr = base + next.start,
+            _ => break,
+        }
+    }
+    partitions
+}
+
+fn mbr_partition(entry: &MbrEntry, table: u64, sector: u64) -> PartitionInfo {
+    PartitionInfo {
+        number: entry.number,
+        offset: (table + entry.start) * sector,
+        size: entry.sectors * sector,
+        type_id: format!("0x{:02x}", entry.kind),
+        type_name: mbr_type_name(entry.kind).map(str::to_string),
+        guid: None,
+        name: None,
+        attributes: None,
+        active: entry.active,
+    }
+}
+
+/// Parse the GPT whose header is at LBA 1 for `sector`-byte sectors.
+///
+/// Returns `None` if there is no header there.
+fn read_gpt(file: &mut File, size: u64, sector: u64) -> io::Result<Option<Table>> {
+    if size < sector * 2 {
+        return Ok(None);
+    }
+    let header = block::read_range(file, size, sector, 92)?;
+    if &header[0..8] != b"EFI PART" {
+        return Ok(None);
+    }
+    let entries_lba = u64_at(&header, 72);
+    let count = u32_at(&header, 80) as u64;
+    let entry_size = u32_at(&header, 84) as u64;
+    if entry_size < 128 || count * entry_size > MAX_GPT_ENTRIES_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid GPT partition entry array",
+        ));
+    }
+    let array = block::read_range(
+        file,
+        size,
+        entries_lba.saturating_mul(sector),
+        (count * entry_size) as usize,
+    )?;
+
+    let mut partitions = Vec::new();
+    for (index, entry) in array.chunks_exact(entry_size as usize).enumerate() {
+        let kind = &entry[0..16];
+        if kind.iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first = u64_at(entry, 32);
+        let last = u64_at(entry, 40);
+        let type_id = guid(kind);
+        let name: Vec<u16> = entry[56..128]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        partitions.push(PartitionInfo {
+            number: index as u32 + 1,
+            offset: first.saturating_mul(sector),
+            size: (last + 1).saturating_sub(first).saturating_mul(sector),
+            type_name: gpt_type_name(&type_id).map(str::to_string),
+            type_id,
+            guid: Some(guid(&entry[16..32])),
+            name: Some(String::from_utf16_lossy(&name)).filter(|n| !n.is_empty()),
+            attributes: Some(u64_at(entry, 48)),
+            active: false,
+        });
+    }
+    Ok(Some(Table {
+        style: PartitionStyle::Gpt,
+        disk_id: Some(guid(&header[56..72])),
+        partitions,
+    }))
+}
+
+/// Format a mixed-endian on-disk GUID.
+fn guid(b: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        u32_at(b, 0),
+        u16::from_le_bytes([b[4], b[5]]),
+        u16::from_le_bytes([b[6], b[7]]),
+        b[8],
+        b[9],
+        b[10],
+        b[11],
+        b[12],
+        b[13],
+        b[14],
+        b[15]
+    )
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+fn gpt_type_name(type_id: &str) -> Option<&'static str> {
+    Some(match type_id {
+        "c12a7328-f81f-11d2-ba4b-00a0c93ec93b" => "EFI System",
+        "e3c9e316-0b5c-4db8-817d-f92df00215ae" => "Microsoft Reserved",
+        "ebd0a0a2-b9e5-4433-87c0-68b6b72699c7" => "Basic Data",
+        "de94bba4-06d1-4d40-a16a-bfd50179d6ac" => "Windows Recovery",
+        "5808c8aa-7e8f-42e0-85d2-e1e90434cfb3" => "LDM Metadata",
+        "af9b60a0-1431-4f62-bc68-3311714a69ad" => "LDM Data",
+        "e75caf8f-f680-4cee-afa3-b001e56efc2d" => "Storage Spaces",
+        "21686148-6449-6e6f-744e-656564454649" => "BIOS Boot",
+        "0fc63daf-8483-4772-8e79-3d69d8477de4" => "Linux Filesystem",
+        "0657fd6d-a4ab-43c4-84e5-0933c84b4f4f" => "Linux Swap",
+        "e6d6d379-f507-44c2-a23c-238f2a3df928" => "Linux LVM",
+        "a19d880f-05fc-4d3b-a006-743f0f84911e" => "Linux RAID",
+        _ => return None,
+    })
+}
+
+fn mbr_type_name(kind: u8) -> Option<&'static str> {
+    Some(match kind {
+        0x01 => "FAT12",
+        0x04 | 0x06 | 0x0e => "FAT16",
+        0x05 | 0x0f | 0x85 => "Extended",
+        0x07 => "NTFS/exFAT",
+        0x0b | 0x0c => "FAT32",
+        0x17 => "Hidden NTFS",
+        0x1b | 0x1c => "Hidden FAT32",
+        0x27 => "Windows Recovery",
+        0x42 => "LDM",
+        0x82 => "Linux Swap",
+        0x83 => "Linux",
+        0x8e => "Linux LVM",
+        0xee => "GPT Protective",
+        0xef => "EFI System",
+        0xfd => "Linux RAID",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom, Write};
+
+    const EFI_SYSTEM: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+    const BASIC_DATA: &str = "ebd0a0a2-b9e5-4433-87c0-68b6b72699c7";
+
+    /// A sparse disk image of `size` bytes with `writes` applied.
+    fn disk(size: u64, writes: &[(u64, Vec<u8>)]) -> File {
+        let mut file = tempfile::tempfile().unwrap();
+        file.set_len(size).unwrap();
+        for (offset, data) in writes {
+            file.seek(SeekFrom::Start(*offset)).unwrap();
+            file.write_all(data).unwrap();
+        }
+        file
+    }
+
+    /// An empty MBR or EBR sector with its boot signature.
+    fn boot_record() -> Vec<u8> {
+        let mut sector = vec![0; 512];
+        sector[510..512].copy_from_slice(&[0x55, 0xaa]);
+        sector
+    }
+
+    fn set_entry(
+        sector: &mut [u8],
+        index: usize,
+        active: bool,
+        kind: u8,
+        start: u32,
+        sectors: u32,
+    ) {
+        let e = &mut sector[446 + index * 16..462 + index * 16];
+        e[0] = if active { 0x80 } else { 0 };
+        e[4] = kind;
+        e[8..12].copy_from_slice(&start.to_le_bytes());
+        e[12..16].copy_from_slice(&sectors.to_le_bytes());
+    }
+
+    /// The on-disk bytes of a GUID, the inverse of [`guid`].
+    fn guid_bytes(s: &str) -> [u8; 16] {
+        let hex: Vec<u8> = s
+            .replace('-', "")
+            .as_bytes()
+            .chunks(2)
+            .map(|c| u8::from_str_radix(std::str::from_utf8(c).unwrap(), 16).unwrap())
+            .collect();
+        let mut b = [0; 16];
+        b.copy_from_slice(&hex);
+        b[0..4].reverse();
+        b[4..6].reverse();
+        b[6..8].reverse();
+        b
+    }
+
+    fn gpt_entry(
+        kind: &str,
+        unique: &str,
+        first: u64,
+        last: u64,
+        attributes: u64,
+        name: &str,
+    ) -> Vec<u8> {
+        let mut entry = vec![0; 128];
+        entry[0..16].copy_from_slice(&guid_bytes(kind));
+        entry[16..32].copy_from_slice(&guid_bytes(unique));
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+        entry[48..56].copy_from_slice(&attributes.to_le_bytes());
+        for (i, c) in name.encode_utf16().enumerate() {
+            entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entry
+    }
+
+    /// A protective MBR, a GPT header and `count` entries at LBA 2.
+    fn gpt_disk(sector: u64, size: u64, count: u32, entries: &[Vec<u8>]) -> File {
+        let mut mbr = boot_record();
+        set_entry(&mut mbr, 0, false, GPT_PROTECTIVE, 1, u32::MAX);
+        let mut header = vec![0; 92];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[56..72].copy_from_slice(&guid_bytes("01234567-89ab-cdef-0123-456789abcdef"));
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&count.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        disk(
+            size,
+            &[(0, mbr), (sector, header), (2 * sector, entries.concat())],
+        )
+    }
+
+    #[test]
+    fn gpt_at_512_and_4096_byte_sectors() {
+        for sector in [512u64, 4096] {
+            let entries = [
+                gpt_entry(
+                    EFI_SYSTEM,
+                    "11111111-2222-3333-4444-555555555555",
+                    256,
+                    511,
+                    0,
+                    "EFI system partition",
+                ),
+                vec![0; 128],
+                gpt_entry(
+                    BASIC_DATA,
+                    "66666666-7777-8888-9999-aaaaaaaaaaaa",
+                    512,
+                    1023,
+                    1 << 63,
+                    "",
+                ),
+            ];
+            // Image files report 512-byte sectors, so 4096 is found by trying it
+            for reported in [512, sector as u32] {
+                let mut file = gpt_disk(sector, 1024 * sector, 128, &entries);
+                let table = read(&mut file, 1024 * sector, reported).unwrap();
+                assert_eq!(table.style, PartitionStyle::Gpt);
+                assert_eq!(
+                    table.disk_id.as_deref(),
+                    Some("01234567-89ab-cdef-0123-456789abcdef")
+                );
+
+                let [efi, data] = &table.partitions[..] else {
+                    panic!("{} partitions", table.partitions.len());
+                };
+                assert_eq!(
+                    (efi.number, efi.offset, efi.size),
+                    (1, 256 * sector, 256 * sector)
+                );
+                assert_eq!(efi.type_id, EFI_SYSTEM);
+                assert_eq!(efi.type_name.as_deref(), Some("EFI System"));
+                assert_eq!(
+                    efi.guid.as_deref(),
+                    Some("11111111-2222-3333-4444-555555555555")
+                );
+                assert_eq!(efi.name.as_deref(), Some("EFI system partition"));
+                assert_eq!(
+                    (data.number, data.offset, data.size),
+                    (3, 512 * sector, 512 * sector)
+                );
+                assert_eq!(data.type_name.as_deref(), Some("Basic Data"));
+                assert_eq!(data.name, None);
+                assert_eq!(data.attributes, Some(1 << 63));
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_gpt_partition_array() {
+        // The header promises 128 entries but the image ends after 8
+        let mut file = gpt_disk(512, 2 * 512 + 8 * 128, 128, &[]);
+        let e = read(&mut file, 2 * 512 + 8 * 128, 512).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        // An entry array too large to be real is refused before reading it
+        let mut file = gpt_disk(512, 1024 * 1024, 1 << 20, &[]);
+        let e = read(&mut file, 1024 * 1024, 512).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn mbr_with_extended_partition() {
+        let mut mbr = boot_record();
+        mbr[440..444].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        set_entry(&mut mbr, 0, true, 0x07, 2048, 4096);
+        set_entry(&mut mbr, 1, false, 0x0f, 8192, 16384);
+        // Logical starts are relative to their EBR, links to the extended partition
+        let mut first = boot_record();
+        set_entry(&mut first, 0, false, 0x83, 63, 1000);
+        set_entry(&mut first, 1, false, 0x05, 2048, 2000);
+        let mut second = boot_record();
+        set_entry(&mut second, 0, false, 0x07, 63, 500);
+        let mut file = disk(
+            24576 * 512,
+            &[(0, mbr), (8192 * 512, first), (10240 * 512, second)],
+        );
+
+        let table = read(&mut file, 24576 * 512, 512).unwrap();
+        assert_eq!(table.style, PartitionStyle::Mbr);
+        assert_eq!(table.disk_id.as_deref(), Some("12345678"));
+        let found: Vec<_> = table
+            .partitions
+            .iter()
+            .map(|p| {
+                (
+                    p.number,
+                    p.type_id.as_str(),
+                    p.offset / 512,
+                    p.size / 512,
+                    p.active,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (1, "0x07", 2048, 4096, true),
+                (2, "0x0f", 8192, 16384, false),
+                (5, "0x83", 8192 + 63, 1000, false),
+                (6, "0x07", 10240 + 63, 500, false),
+            ]
+        );
+        assert_eq!(table.partitions[2].type_name.as_deref(), Some("Linux"));
+    }
+
+    #[test]
+    fn looping_ebr_chain_terminates() {
+        let mut mbr = boot_record();
+        set_entry(&mut mbr, 0, false, 0x05, 100, 10000);
+
+        // An EBR linking to itself
+        let mut own = boot_record();
+        set_entry(&mut own, 0, false, 0x07, 1, 10);
+        set_entry(&mut own, 1, false, 0x05, 0, 10);
+        let mut file = disk(1024 * 1024 * 8, &[(0, mbr.clone()), (100 * 512, own)]);
+        let table = read(&mut file, 1024 * 1024 * 8, 512).unwrap();
+        assert_eq!(table.partitions.len(), 2);
+
+        // A second EBR linking back to the first
+        let mut first = boot_record();
+        set_entry(&mut first, 0, false, 0x07, 1, 10);
+        set_entry(&mut first, 1, false, 0x05, 20, 10);
+        let mut back = boot_record();
+        set_entry(&mut back, 0, false, 0x07, 1, 5);
+        set_entry(&mut back, 1, false, 0x05, 0, 10);
+        let mut file = disk(
+            1024 * 1024 * 8,
+            &[(0, mbr.clone()), (100 * 512, first), (120 * 512, back)],
+        );
+        let numbers: Vec<u32> = read(&mut file, 1024 * 1024 * 8, 512)
+            .unwrap()
+            .partitions
+            .iter()
+            .map(|p| p.number)
+            .collect();
+        assert_eq!(numbers, [1, 5, 6]);
+
+        // A chain that keeps going stops at the logical partition limit
+        let mut writes = vec![(0, mbr)];
+        for i in 0..200u32 {
+            let mut ebr = boot_record();
+            set_entry(&mut ebr, 0, false, 0x07, 1, 1);
+            set_entry(&mut ebr, 1, false, 0x05, (i + 1) * 2, 2);
+            writes.push(((100 + i as u64 * 2) * 512, ebr));
+        }
+        let mut file = disk(1024 * 1024 * 8, &writes);
+        let table = read(&mut file, 1024 * 1024 * 8, 512).unwrap();
+        assert_eq!(table.partitions.len(), 1 + MAX_LOGICAL as usize);
+    }
+
+    #[test]
+    fn ebr_past_end_of_disk_keeps_earlier_partitions() {
+        let mut mbr = boot_record();
+        set_entry(&mut mbr, 0, false, 0x05, 4, 100);
+        let mut ebr = boot_record();
+        set_entry(&mut ebr, 0, false, 0x83, 1, 2);
+        set_entry(&mut ebr, 1, false, 0x05, 1000, 10);
+        let mut file = disk(16 * 512, &[(0, mbr), (4 * 512, ebr)]);
+        let table = read(&mut file, 16 * 512, 512).unwrap();
+        assert_eq!(table.partitions.len(), 2);
+    }
+
+    #[test]
+    fn unpartitioned_disks_are_raw() {
+        // No boot signature
+        let mut file = disk(4096, &[]);
+        assert_eq!(
+            read(&mut file, 4096, 512).unwrap().style,
+            PartitionStyle::Raw
+        );
+        // A volume boot sector ends in 55 AA without being a partition table
+        let mut ntfs = boot_record();
+        ntfs[3..11].copy_from_slice(b"NTFS    ");
+        set_entry(&mut ntfs, 0, false, 0x07, 1, 1);
+        let mut file = disk(4096, &[(0, ntfs)]);
+        assert_eq!(
+            read(&mut file, 4096, 512).unwrap().style,
+            PartitionStyle::Raw
+        );
+        // Too small to hold a sector
+        let mut file = disk(100, &[]);
+        assert_eq!(
+            read(&mut file, 100, 512).unwrap().style,
+            PartitionStyle::Raw
+        );
+    }
+}
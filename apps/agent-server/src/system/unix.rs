@@ -0,0 +1,235 @@
+//! Linux inventory backend.
+//!
+//! Disks are the entries of `/sys/block`, partitions their numbered
+//! children, and mount points come from `/proc/self/mounts`. Loop devices
+//! over image files show up like any other disk.
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use winpe_agent_core::{BitLockerStatus, VolumeInfo};
+
+use super::{Found, Probe, Scanned};
+
+const SYS_BLOCK: &str = "/sys/block";
+
+/// sysfs reports sizes and offsets in 512-byte units.
+const SYSFS_SECTOR: u64 = 512;
+
+/// MBR type IDs of extended partition containers.
+const EXTENDED_TYPES: [&str; 3] = ["0x05", "0x0f", "0x85"];
+
+/// Disks in `/sys/block`, numbered in name order.
+///
+/// Empty devices (unattached loop devices, empty card readers) and RAM
+/// disks are left out.
+pub(super) fn find_disks() -> io::Result<Vec<Found>> {
+    let mut names: Vec<String> = fs::read_dir(SYS_BLOCK)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with("ram") && !name.starts_with("zram"))
+        .collect();
+    names.sort();
+
+    let mut disks = Vec::new();
+    for name in names {
+        let dir = Path::new(SYS_BLOCK).join(&name);
+        let size = read_number(&dir.join("size")).unwrap_or(0) * SYSFS_SECTOR;
+        if size == 0 {
+            continue;
+        }
+        disks.push(Found {
+            number: disks.len() as u32,
+            sector_size: read_number(&dir.join("queue/logical_block_size"))
+                .map_or(512, |n| n as u32),
+            model: fs::read_to_string(dir.join("device/model"))
+                .ok()
+                .map(|m| m.trim().to_string())
+                .filter(|m| !m.is_empty()),
+            device: name,
+            size,
+        });
+    }
+    Ok(disks)
+}
+
+/// Partitions of each disk, and unpartitioned disks.
+///
+/// A partition the kernel has no device for (a loop device attached
+/// without partition scanning, say) is listed with the disk's path; its
+/// `offset` locates it on the disk.
+pub(super) fn volumes(disks: &[Scanned]) -> io::Result<Vec<VolumeInfo>> {
+    let mounts = read_mounts();
+    let mut volumes = Vec::new();
+    for disk in disks {
+        let disk_path = PathBuf::from("/dev").join(&disk.info.device);
+        let kernel = kernel_partitions(&disk.info.device);
+        if disk.info.partitions.is_empty() && kernel.is_empty() {
+            let mut volume = volume(&disk_path, disk.info.size, &mounts);
+            volume.disk = Some(disk.info.number);
+            if let Some(probe) = &disk.whole {
+                apply_probe(&mut volume, probe);
+            }
+            // Skip disks that could not be read unless something is mounted
+            if disk.whole.is_some() || !volume.mount_points.is_empty() {
+                volumes.push(volume);
+            }
+            continue;
+        }
+
+        for (index, part) in disk.info.partitions.iter().enumerate() {
+            // Extended partitions only contain other partitions
+            if EXTENDED_TYPES.contains(&part.type_id.as_str()) {
+                continue;
+            }
+            let path = kernel
+                .iter()
+                .find(|k| k.number == part.number)
+                .map_or(&disk_path, |k| &k.path);
+            let mut volume = volume(path, part.size, &mounts);
+            volume.disk = Some(disk.info.number);
+            volume.partition = Some(part.number);
+            volume.offset = Some(part.offset);
+            if let Some(Some(probe)) = disk.probes.get(index) {
+                apply_probe(&mut volume, probe);
+            }
+            volumes.push(volume);
+        }
+
+        // The table could not be read; the kernel's partitions are all we know
+        if disk.info.partitions.is_empty() {
+            for part in kernel {
+                let mut volume = volume(&part.path, part.size, &mounts);
+                volume.disk = Some(disk.info.number);
+                volume.partition = Some(part.number);
+                volume.offset = Some(part.offset);
+                volumes.push(volume);
+            }
+        }
+    }
+    Ok(volumes)
+}
+
+fn apply_probe(volume: &mut VolumeInfo, probe: &Probe) {
+    volume.filesystem = probe.filesystem.clone().or(volume.filesystem.take());
+    volume.label = probe.label.clone();
+    // Whether the volume is unlocked cannot be told from here
+    volume.bitlocker = Some(if probe.bitlocker {
+        BitLockerStatus::Encrypted
+    } else {
+        BitLockerStatus::Off
+    });
+}
+
+/// A volume at `path`, with what its mounts tell about it.
+fn volume(path: &Path, size: u64, mounts: &[Mount]) -> VolumeInfo {
+    let mounted: Vec<&Mount> = mounts.iter().filter(|m| m.source == path).collect();
+    VolumeInfo {
+        path: path.display().to_string(),
+        letter: None,
+        mount_points: mounted.iter().map(|m| m.target.clone()).collect(),
+        filesystem: mounted.first().map(|m| m.fstype.clone()),
+        label: None,
+        size,
+        free: mounted.first().and_then(|m| free_space(&m.target)),
+        disk: None,
+        partition: None,
+        offset: None,
+        bitlocker: None,
+    }
+}
+
+struct KernelPartition {
+    path: PathBuf,
+    number: u32,
+    offset: u64,
+    size: u64,
+}
+
+/// Partition devices under `/sys/block/{disk}`, in number order.
+fn kernel_partitions(disk: &str) -> Vec<KernelPartition> {
+    let Ok(entries) = fs::read_dir(Path::new(SYS_BLOCK).join(disk)) else {
+        return Vec::new();
+    };
+    let mut parts: Vec<KernelPartition> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let dir = entry.path();
+            Some(KernelPartition {
+                number: read_number(&dir.join("partition"))? as u32,
+                offset: read_number(&dir.join("start"))? * SYSFS_SECTOR,
+                size: read_number(&dir.join("size"))? * SYSFS_SECTOR,
+                path: PathBuf::from("/dev").join(entry.file_name()),
+            })
+        })
+        .collect();
+    parts.sort_by_key(|p| p.number);
+    parts
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+struct Mount {
+    /// Device path, with symlinks such as `/dev/mapper/*` resolved.
+    source: PathBuf,
+    target: String,
+    fstype: String,
+}
+
+fn read_mounts() -> Vec<Mount> {
+    let Ok(text) = fs::read_to_string("/proc/self/mounts") else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let source = unescape(fields.next()?);
+            let target = unescape(fields.next()?);
+            let fstype = fields.next()?.to_string();
+            if !source.starts_with("/dev/") {
+                return None;
+            }
+            let source = fs::canonicalize(&source).unwrap_or_else(|_| PathBuf::from(source));
+            Some(Mount {
+                source,
+                target,
+                fstype,
+            })
+        })
+        .collect()
+}
+
+/// Decode the octal escapes (`\040` for space) used in mount tables.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|digits| bytes[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        if let Some(digits) = octal {
+            out.push(digits.iter().fold(0u8, |n, d| (n << 3) | (d - b'0')));
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Free bytes available on the filesystem mounted at `target`.
+fn free_space(target: &str) -> Option<u64> {
+    let path = CString::new(Path::new(target).as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out pointer.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
@@ -0,0 +1,252 @@
+//! Windows inventory backend.
+//!
+//! Disks are probed as `\\.\PhysicalDriveN`; volumes are enumerated with
+//! `FindFirstVolumeW` and tied to their partition through the disk
+//! extents the volume manager reports.
+
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::AsRawHandle;
+use std::ptr;
+use windows_sys::Win32::Foundation::{ERROR_MORE_DATA, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::Storage::FileSystem::{
+    FindFirstVolumeW, FindNextVolumeW, FindVolumeClose, GetDiskFreeSpaceExW, GetVolumeInformationW,
+    GetVolumePathNamesForVolumeNameW, IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS,
+};
+use windows_sys::Win32::System::IO::DeviceIoControl;
+use windows_sys::Win32::System::Ioctl::{
+    DISK_GEOMETRY_EX, IOCTL_DISK_GET_DRIVE_GEOMETRY_EX, VOLUME_DISK_EXTENTS,
+};
+use winpe_agent_core::{BitLockerStatus, VolumeInfo};
+
+use super::{Found, Scanned};
+
+/// Disk numbers probed; Windows numbers disks densely from 0.
+const MAX_DISKS: u32 = 64;
+
+/// Buffer length for volume names, labels and filesystem names.
+const NAME_LEN: usize = 261;
+
+/// Physical disks that exist.
+///
+/// A disk that exists but cannot be opened is still listed, so that the
+/// error shows up in its entry.
+pub(super) fn find_disks() -> io::Result<Vec<Found>> {
+    let mut disks = Vec::new();
+    for number in 0..MAX_DISKS {
+        let sector_size = match File::open(format!(r"\\.\PhysicalDrive{}", number)) {
+            Ok(file) => sector_size(&file).unwrap_or(512),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(_) => 512,
+        };
+        disks.push(Found {
+            number,
+            device: format!("disk{}", number),
+            size: 0,
+            sector_size,
+            model: None,
+        });
+    }
+    Ok(disks)
+}
+
+fn sector_size(file: &File) -> io::Result<u32> {
+    let mut geometry = DISK_GEOMETRY_EX::default();
+    let mut returned = 0u32;
+    // SAFETY: the handle is open and `geometry` outlives the call.
+    let ok = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+            ptr::null(),
+            0,
+            &mut geometry as *mut DISK_GEOMETRY_EX as *mut _,
+            std::mem::size_of::<DISK_GEOMETRY_EX>() as u32,
+            &mut returned,
+            ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(geometry.Geometry.BytesPerSector)
+}
+
+/// Every volume the volume manager knows, including unmounted ones.
+pub(super) fn volumes(disks: &[Scanned]) -> io::Result<Vec<VolumeInfo>> {
+    let mut name = [0u16; NAME_LEN];
+    // SAFETY: `name` is writable for the length passed.
+    let find = unsafe { FindFirstVolumeW(name.as_mut_ptr(), name.len() as u32) };
+    if find == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    let mut volumes = Vec::new();
+    loop {
+        volumes.push(volume(&from_wide(&name), disks));
+        // SAFETY: `find` is an open search handle; `name` as above.
+        if unsafe { FindNextVolumeW(find, name.as_mut_ptr(), name.len() as u32) } == 0 {
+            break;
+        }
+    }
+    // SAFETY: `find` is an open search handle, closed once.
+    unsafe { FindVolumeClose(find) };
+    Ok(volumes)
+}
+
+/// Describe the volume `name` (`\\?\Volume{GUID}\`).
+fn volume(name: &str, disks: &[Scanned]) -> VolumeInfo {
+    let wide = to_wide(name);
+    let mount_points = mount_points(&wide);
+    let letter = mount_points
+        .iter()
+        .find(|p| p.len() == 3 && p.ends_with(":\\"))
+        .map(|p| p[..2].to_string());
+
+    let mut label = [0u16; NAME_LEN];
+    let mut fs_name = [0u16; NAME_LEN];
+    // SAFETY: both buffers are writable for the lengths passed; the
+    // optional out pointers are null.
+    let mounted = unsafe {
+        GetVolumeInformationW(
+            wide.as_ptr(),
+            label.as_mut_ptr(),
+            label.len() as u32,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            fs_name.as_mut_ptr(),
+            fs_name.len() as u32,
+        )
+    } != 0;
+
+    let mut free = 0u64;
+    let mut total = 0u64;
+    // SAFETY: the out pointers are valid; the third is optional.
+    let sized =
+        unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut free, &mut total, ptr::null_mut()) } != 0;
+
+    let mut info = VolumeInfo {
+        path: name.to_string(),
+        letter,
+        mount_points,
+        filesystem: Some(from_wide(&fs_name)).filter(|_| mounted),
+        label: Some(from_wide(&label)).filter(|l| mounted && !l.is_empty()),
+        size: if sized { total } else { 0 },
+        free: sized.then_some(free),
+        disk: None,
+        partition: None,
+        offset: None,
+        bitlocker: None,
+    };
+
+    let Some((disk_number, offset, length)) = disk_extent(name) else {
+        return info;
+    };
+    info.disk = Some(disk_number);
+    info.offset = Some(offset);
+    if !sized {
+        info.size = length;
+    }
+    let Some(disk) = disks.iter().find(|d| d.info.number == disk_number) else {
+        return info;
+    };
+    let probe = match disk.info.partitions.iter().position(|p| p.offset == offset) {
+        Some(index) => {
+            info.partition = Some(disk.info.partitions[index].number);
+            disk.probes.get(index).and_then(Option::as_ref)
+        }
+        // A volume filling an unpartitioned disk
+        None => disk.whole.as_ref().filter(|_| offset == 0),
+    };
+    if let Some(probe) = probe {
+        // Raw sectors keep the BitLocker header even when unlocked
+        info.bitlocker = Some(match (probe.bitlocker, mounted) {
+            (false, _) => BitLockerStatus::Off,
+            (true, true) => BitLockerStatus::Unlocked,
+            (true, false) => BitLockerStatus::Locked,
+        });
+        if info.filesystem.is_none() {
+            info.filesystem = probe.filesystem.clone();
+            info.label = probe.label.clone();
+        }
+    }
+    info
+}
+
+/// Drive letter roots and folders the volume is mounted at.
+fn mount_points(volume: &[u16]) -> Vec<String> {
+    let mut buf = vec![0u16; NAME_LEN];
+    loop {
+        let mut needed = 0u32;
+        // SAFETY: `buf` is writable for the length passed.
+        let ok = unsafe {
+            GetVolumePathNamesForVolumeNameW(
+                volume.as_ptr(),
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                &mut needed,
+            )
+        };
+        if ok != 0 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(ERROR_MORE_DATA as i32) || needed as usize <= buf.len() {
+            return Vec::new();
+        }
+        buf = vec![0u16; needed as usize];
+    }
+    // A list of NUL-terminated strings, ended by an empty one
+    buf.split(|&c| c == 0)
+        .take_while(|s| !s.is_empty())
+        .map(String::from_utf16_lossy)
+        .collect()
+}
+
+/// Disk number, byte offset and length of a volume on a single disk.
+///
+/// Spanned and striped volumes have several extents and are not tied to
+/// a partition.
+fn disk_extent(name: &str) -> Option<(u32, u64, u64)> {
+    // Without the trailing backslash the path names the volume device
+    let file = OpenOptions::new()
+        .access_mode(0)
+        .open(name.trim_end_matches('\\'))
+        .ok()?;
+    let mut extents = VOLUME_DISK_EXTENTS::default();
+    let mut returned = 0u32;
+    // SAFETY: the handle is open and `extents` outlives the call.
+    let ok = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS,
+            ptr::null(),
+            0,
+            &mut extents as *mut VOLUME_DISK_EXTENTS as *mut _,
+            std::mem::size_of::<VOLUME_DISK_EXTENTS>() as u32,
+            &mut returned,
+            ptr::null_mut(),
+        )
+    };
+    if ok == 0 || extents.NumberOfDiskExtents != 1 {
+        return None;
+    }
+    let extent = extents.Extents[0];
+    Some((
+        extent.DiskNumber,
+        extent.StartingOffset as u64,
+        extent.ExtentLength as u64,
+    ))
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+fn from_wide(buf: &[u16]) -> String {
+    let end = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..end])
+}
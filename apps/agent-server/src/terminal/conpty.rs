@@ -1,22 +1,201 @@
-//! ConPTY (Windows Pseudo Console) bindings.
+//! ConPTY (Windows Pseudo Console) backend.
 //!
 //! Provides safe wrappers around Win32 ConPTY APIs.
 
-#[cfg(windows)]
+use std::os::windows::io::FromRawHandle;
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[cfg(windows)]
 use windows_sys::Win32::Foundation::{CloseHandle, FALSE, GetLastError, HANDLE};
-#[cfg(windows)]
 use windows_sys::Win32::System::Console::HPCON;
-#[cfg(windows)]
 use windows_sys::Win32::System::Threading::*;
+use winpe_agent_core::Shell;
+
+use super::pty::{PtyProcess, PtySpawnSpec, SpawnedPty};
+use crate::automation::quoting;
+use crate::automation::win32::build_environment_block;
+
+/// A process attached to a ConPTY.
+///
+/// Handles are stored as usize for Send safety and converted back when needed.
+/// The pseudo console is closed (and `hpc` zeroed) once the process exits.
+pub struct ConPtyProcess {
+    hpc: AtomicUsize,
+    process: usize,
+    pid: u32,
+}
+
+impl PtyProcess for ConPtyProcess {
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), String> {
+        use windows_sys::Win32::Foundation::S_OK;
+        use windows_sys::Win32::System::Console::{COORD, ResizePseudoConsole};
+        let hpc = self.hpc.load(Ordering::Acquire);
+        if hpc == 0 {
+            return Err("Pseudo console is closed".to_string());
+        }
+        unsafe {
+            let size = COORD {
+                X: cols as i16,
+                Y: rows as i16,
+            };
+            let result = ResizePseudoConsole(hpc as HPCON, size);
+            if result != S_OK {
+                return Err(format!("ResizePseudoConsole failed: 0x{:08X}", result));
+            }
+            Ok(())
+        }
+    }
+
+    fn terminate(&self) {
+        unsafe {
+            TerminateProcess(self.process as HANDLE, 1);
+        }
+    }
+
+    fn wait(&self) -> i32 {
+        let mut exit_code: u32 = 0;
+        unsafe {
+            WaitForSingleObject(self.process as HANDLE, INFINITE);
+            if GetExitCodeProcess(self.process as HANDLE, &mut exit_code) == 0 {
+                tracing::warn!("GetExitCodeProcess failed: {}", GetLastError());
+            }
+        }
+        // The output pipe only reaches EOF once the pseudo console is closed
+        self.close_pseudo_console();
+        exit_code as i32
+    }
+}
+
+impl ConPtyProcess {
+    fn close_pseudo_console(&self) {
+        use windows_sys::Win32::System::Console::ClosePseudoConsole;
+        let hpc = self.hpc.swap(0, Ordering::AcqRel);
+        if hpc != 0 {
+            unsafe {
+                ClosePseudoConsole(hpc as HPCON);
+            }
+        }
+    }
+}
+
+impl Drop for ConPtyProcess {
+    fn drop(&mut self) {
+        self.close_pseudo_console();
+        unsafe {
+            CloseHandle(self.process as HANDLE);
+        }
+    }
+}
+
+/// Create a ConPTY and spawn the requested shell on it.
+pub fn spawn(spec: &PtySpawnSpec) -> Result<SpawnedPty, String> {
+    use windows_sys::Win32::Foundation::{INVALID_HANDLE_VALUE, S_OK};
+    use windows_sys::Win32::System::Console::{COORD, ClosePseudoConsole, CreatePseudoConsole};
+    use windows_sys::Win32::System::Pipes::CreatePipe;
+
+    // Build command line
+    let command_line = match (spec.program, spec.shell) {
+        (Some(program), _) => quoting::windows_command_line(program, spec.args)?,
+        (None, Shell::Cmd) => "cmd.exe".to_string(),
+        (None, Shell::Powershell) => "powershell.exe -NoLogo -NoProfile".to_string(),
+        (None, Shell::Sh | Shell::Bash) => {
+            return Err(format!(
+                "Shell {:?} is not supported on Windows",
+                spec.shell
+            ));
+        }
+        (None, Shell::Direct) => {
+            return Err("Shell Direct requires a program for sessions".to_string());
+        }
+    };
+    let env_block = build_environment_block(spec.env);
+
+    // Create pipes for ConPTY I/O
+    let (hpc, input_write, output_read): (HPCON, HANDLE, HANDLE) = unsafe {
+        let mut pty_input_read: HANDLE = INVALID_HANDLE_VALUE;
+        let mut pty_input_write: HANDLE = INVALID_HANDLE_VALUE;
+        let mut pty_output_read: HANDLE = INVALID_HANDLE_VALUE;
+        let mut pty_output_write: HANDLE = INVALID_HANDLE_VALUE;
+
+        if CreatePipe(
+            &mut pty_input_read,
+            &mut pty_input_write,
+            std::ptr::null_mut(),
+            0,
+        ) == 0
+        {
+            return Err("Failed to create input pipe".to_string());
+        }
+
+        if CreatePipe(
+            &mut pty_output_read,
+            &mut pty_output_write,
+            std::ptr::null_mut(),
+            0,
+        ) == 0
+        {
+            CloseHandle(pty_input_read);
+            CloseHandle(pty_input_write);
+            return Err("Failed to create output pipe".to_string());
+        }
+
+        let size = COORD {
+            X: spec.cols as i16,
+            Y: spec.rows as i16,
+        };
+
+        let mut hpc: HPCON = 0;
+        let result = CreatePseudoConsole(size, pty_input_read, pty_output_write, 0, &mut hpc);
+
+        if result != S_OK {
+            CloseHandle(pty_input_read);
+            CloseHandle(pty_input_write);
+            CloseHandle(pty_output_read);
+            CloseHandle(pty_output_write);
+            return Err(format!("CreatePseudoConsole failed: 0x{:08X}", result));
+        }
+
+        // Close the handles that the ConPTY now owns
+        CloseHandle(pty_input_read);
+        CloseHandle(pty_output_write);
+
+        (hpc, pty_input_write, pty_output_read)
+    };
+
+    // Spawn process attached to ConPTY
+    let (process, pid) = match spawn_process(hpc, &command_line, &env_block, spec.cwd) {
+        Ok(p) => p,
+        Err(e) => unsafe {
+            ClosePseudoConsole(hpc);
+            CloseHandle(input_write);
+            CloseHandle(output_read);
+            return Err(e);
+        },
+    };
+
+    let reader = unsafe { std::fs::File::from_raw_handle(output_read) };
+    let writer = unsafe { std::fs::File::from_raw_handle(input_write) };
+
+    Ok(SpawnedPty {
+        process: Box::new(ConPtyProcess {
+            hpc: AtomicUsize::new(hpc as usize),
+            process: process as usize,
+            pid,
+        }),
+        reader: Box::new(reader),
+        writer: Box::new(writer),
+    })
+}
 
 /// Spawn a process attached to a pseudo console.
-#[cfg(windows)]
-pub fn spawn_process(
+fn spawn_process(
     hpc: HPCON,
     command_line: &str,
+    env_block: &[u16],
     cwd: Option<&str>,
 ) -> Result<(HANDLE, u32), String> {
     use std::ffi::OsStr;
@@ -85,8 +264,8 @@ pub fn spawn_process(
             ptr::null_mut(),
             ptr::null_mut(),
             FALSE,
-            EXTENDED_STARTUPINFO_PRESENT,
-            ptr::null(),
+            EXTENDED_STARTUPINFO_PRESENT | CREATE_UNICODE_ENVIRONMENT,
+            env_block.as_ptr() as *const std::ffi::c_void,
             cwd_ptr,
             &si.StartupInfo,
             &mut pi,
@@ -102,12 +281,3 @@ pub fn spawn_process(
         Ok((pi.hProcess, pi.dwProcessId))
     }
 }
-
-#[cfg(not(windows))]
-pub fn spawn_process(
-    _hpc: (),
-    _command_line: &str,
-    _cwd: Option<&str>,
-) -> Result<((), u32), String> {
-    Err("spawn_process is only available on Windows".to_string())
-}
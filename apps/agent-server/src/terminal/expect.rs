@@ -0,0 +1,257 @@
+//! Waiting for patterns in session output.
+//!
+//! Output positions are byte offsets into everything the session has
+//! written. A search can start at an earlier offset, read back from the
+//! scrollback, so output that arrived before the request is not missed.
+
+use regex::bytes::Regex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{RwLock, broadcast};
+use winpe_agent_core::SessionExpectResponse;
+
+use super::session::Session;
+
+/// Most output kept while searching; older bytes are dropped.
+const MAX_SEARCH_BYTES: usize = 1024 * 1024;
+
+/// Why a search ended without a match.
+pub enum ExpectError {
+    /// No session with this ID.
+    NotFound,
+    /// The timeout elapsed.
+    Timeout { output: String, offset: u64 },
+    /// The process exited.
+    Exited {
+        output: String,
+        offset: u64,
+        exit_code: i32,
+    },
+}
+
+/// Output searched so far, starting at `start`.
+struct SearchBuffer {
+    start: u64,
+    data: Vec<u8>,
+}
+
+impl SearchBuffer {
+    fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+
+    fn append(&mut self, offset: u64, bytes: &[u8]) {
+        if offset > self.end() {
+            // Output was discarded before it could be read
+            self.start = offset;
+            self.data.clear();
+        }
+        self.data.extend_from_slice(bytes);
+        if self.data.len() > MAX_SEARCH_BYTES {
+            let excess = self.data.len() - MAX_SEARCH_BYTES;
+            self.data.drain(..excess);
+            self.start += excess as u64;
+        }
+    }
+
+    fn find(&self, pattern: &Regex) -> Option<SessionExpectResponse> {
+        let caps = pattern.captures(&self.data)?;
+        let whole = caps.get(0)?;
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        Some(SessionExpectResponse {
+            start: self.start,
+            before: text(&self.data[..whole.start()]),
+            matched: text(whole.as_bytes()),
+            groups: caps
+                .iter()
+                .skip(1)
+                .map(|group| group.map(|m| text(m.as_bytes())))
+                .collect(),
+            named: pattern
+                .capture_names()
+                .flatten()
+                .filter_map(|name| Some((name.to_string(), text(caps.name(name)?.as_bytes()))))
+                .collect::<HashMap<_, _>>(),
+            offset: self.start + whole.end() as u64,
+        })
+    }
+
+    fn output(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+}
+
+/// Read buffered output from `offset` and subscribe to what follows.
+///
+/// Both happen under the scrollback lock, so nothing is missed or repeated.
+fn resync(session: &Session, offset: u64) -> (u64, Vec<u8>, broadcast::Receiver<Vec<u8>>) {
+    let scrollback = session.scrollback.lock().unwrap();
+    let (start, bytes) = scrollback.since(offset);
+    (start, bytes, session.output_tx.subscribe())
+}
+
+/// Wait until `pattern` matches the session's output from `since` on.
+///
+/// Without `since`, only output written after the call is searched.
+pub async fn expect(
+    session: Arc<RwLock<Session>>,
+    pattern: &Regex,
+    since: Option<u64>,
+    timeout: Duration,
+) -> Result<SessionExpectResponse, ExpectError> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let (mut buffer, mut output_rx, mut exited) = {
+        let session = session.read().await;
+        let since = since.unwrap_or_else(|| session.scrollback.lock().unwrap().end());
+        let (start, bytes, output_rx) = resync(&session, since);
+        let mut buffer = SearchBuffer {
+            start,
+            data: Vec::new(),
+        };
+        buffer.append(start, &bytes);
+        (buffer, output_rx, session.exited.clone())
+    };
+
+    loop {
+        if let Some(found) = buffer.find(pattern) {
+            return Ok(found);
+        }
+
+        tokio::select! {
+            received = output_rx.recv() => match received {
+                Ok(data) => {
+                    let end = buffer.end();
+                    buffer.append(end, &data);
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    // Catch up from the scrollback and start a fresh subscription
+                    let session = session.read().await;
+                    let (start, bytes, rx) = resync(&session, buffer.end());
+                    buffer.append(start, &bytes);
+                    output_rx = rx;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(ExpectError::Exited {
+                        output: buffer.output(),
+                        offset: buffer.end(),
+                        exit_code: -1,
+                    });
+                }
+            },
+            Ok(exit_code) = async {
+                exited.wait_for(Option::is_some).await.map(|code| code.unwrap_or(-1))
+            } => {
+                // All output was broadcast before the exit; search what is still queued
+                while let Ok(data) = output_rx.try_recv() {
+                    let end = buffer.end();
+                    buffer.append(end, &data);
+                }
+                return match buffer.find(pattern) {
+                    Some(found) => Ok(found),
+                    None => Err(ExpectError::Exited {
+                        output: buffer.output(),
+                        offset: buffer.end(),
+                        exit_code,
+                    }),
+                };
+            }
+            _ = tokio::time::sleep_until(deadline) => {
+                return Err(ExpectError::Timeout {
+                    output: buffer.output(),
+                    offset: buffer.end(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(start: u64, data: &[u8]) -> SearchBuffer {
+        let mut buffer = SearchBuffer {
+            start,
+            data: Vec::new(),
+        };
+        buffer.append(start, data);
+        buffer
+    }
+
+    #[test]
+    fn match_offsets_are_absolute() {
+        let (first, second) = (
+            &b"C:\\> dir\r\n"[..],
+            &b" Volume in drive C is OS\r\nC:\\> "[..],
+        );
+        let mut buffer = buffer(100, first);
+        let end = buffer.end();
+        buffer.append(end, second);
+        assert_eq!(buffer.end(), 100 + (first.len() + second.len()) as u64);
+
+        let pattern = Regex::new(r"drive (\w) is").unwrap();
+        let found = buffer.find(&pattern).unwrap();
+        assert_eq!(found.start, 100);
+        assert_eq!(found.before, "C:\\> dir\r\n Volume in ");
+        assert_eq!(found.matched, "drive C is");
+        assert_eq!(found.groups, [Some("C".to_string())]);
+        assert!(found.named.is_empty());
+        // Just past the match, so a follow-up search does not see it again
+        assert_eq!(found.offset, 100 + (found.before.len() + 10) as u64);
+        assert!(buffer.find(&Regex::new("missing").unwrap()).is_none());
+    }
+
+    #[test]
+    fn named_and_unmatched_groups() {
+        let buffer = buffer(0, b"exit code 5\n");
+        let pattern = Regex::new(r"(?<word>exit) (signal)?\s*code (?<code>\d+)").unwrap();
+        let found = buffer.find(&pattern).unwrap();
+        assert_eq!(
+            found.groups,
+            [Some("exit".to_string()), None, Some("5".to_string())]
+        );
+        assert_eq!(found.named.len(), 2);
+        assert_eq!(found.named["word"], "exit");
+        assert_eq!(found.named["code"], "5");
+    }
+
+    #[test]
+    fn gap_after_lag_starts_over() {
+        let mut buffer = buffer(0, b"before ");
+        // Output between 7 and 50 was discarded before it could be read
+        buffer.append(50, b"after");
+        assert_eq!((buffer.start, buffer.end()), (50, 55));
+        assert_eq!(buffer.output(), "after");
+        let found = buffer.find(&Regex::new("after").unwrap()).unwrap();
+        assert_eq!(
+            (found.start, found.before.as_str(), found.offset),
+            (50, "", 55)
+        );
+        // A contiguous append keeps what is there
+        buffer.append(55, b"!");
+        assert_eq!(buffer.output(), "after!");
+    }
+
+    #[test]
+    fn keeps_the_most_recent_mebibyte() {
+        let mut buffer = buffer(10, &vec![b'a'; MAX_SEARCH_BYTES - 3]);
+        let end = buffer.end();
+        buffer.append(end, b"0123456789");
+        assert_eq!(buffer.data.len(), MAX_SEARCH_BYTES);
+        assert_eq!(buffer.start, 10 + 7);
+        assert_eq!(buffer.end(), 10 + MAX_SEARCH_BYTES as u64 + 7);
+
+        let found = buffer.find(&Regex::new("345").unwrap()).unwrap();
+        assert_eq!(found.offset, buffer.end() - 4);
+        assert_eq!(found.before.len(), MAX_SEARCH_BYTES - 7);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let buffer = buffer(0, b"\xff\xfeprompt> ");
+        let found = buffer.find(&Regex::new("prompt> ").unwrap()).unwrap();
+        assert_eq!(found.before, "\u{fffd}\u{fffd}");
+        assert_eq!(found.offset, 10);
+    }
+}
@@ -1,7 +1,19 @@
-//! Terminal module for ConPTY-backed interactive sessions.
+//! Terminal module for pseudo terminal backed interactive sessions.
+//!
+//! Sessions run on Windows ConPTY or, for development and testing, on a Unix pty.
 
+#[cfg(windows)]
 mod conpty;
+mod expect;
+mod pty;
+mod recording;
+mod screen;
+mod scrollback;
 mod session;
+mod title;
+#[cfg(unix)]
+mod unix_pty;
 pub mod ws;
 
+pub use expect::ExpectError;
 pub use session::SessionManager;
@@ -0,0 +1,77 @@
+//! Platform-independent pseudo terminal abstraction.
+//!
+//! Sessions talk to a [`PtyProcess`] plus a pair of blocking byte streams,
+//! regardless of whether the backend is Windows ConPTY or a Unix pty.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use winpe_agent_core::Shell;
+
+/// Parameters for spawning a process on a pseudo terminal.
+pub struct PtySpawnSpec<'a> {
+    /// Shell to run when no `program` is given.
+    pub shell: Shell,
+    /// Program to run instead of the shell.
+    pub program: Option<&'a str>,
+    /// Arguments for `program`.
+    pub args: &'a [String],
+    /// Variables added to the inherited environment.
+    pub env: &'a HashMap<String, String>,
+    /// Working directory.
+    pub cwd: Option<&'a str>,
+    /// Initial terminal columns.
+    pub cols: u16,
+    /// Initial terminal rows.
+    pub rows: u16,
+}
+
+/// A process attached to a pseudo terminal.
+pub trait PtyProcess: Send + Sync {
+    /// Process ID of the child.
+    fn pid(&self) -> u32;
+
+    /// Resize the pseudo terminal.
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), String>;
+
+    /// Forcibly terminate the child process.
+    fn terminate(&self);
+
+    /// Block until the child exits and return its exit code.
+    ///
+    /// Afterwards the output stream reaches EOF once drained.
+    fn wait(&self) -> i32;
+}
+
+/// A freshly spawned pseudo terminal process and its I/O streams.
+pub struct SpawnedPty {
+    /// Handle used to control the process.
+    pub process: Box<dyn PtyProcess>,
+    /// Terminal output (blocking).
+    pub reader: Box<dyn Read + Send>,
+    /// Terminal input (blocking).
+    pub writer: Box<dyn Write + Send>,
+}
+
+/// Spawn a shell on a new pseudo terminal using the platform backend.
+pub fn spawn(spec: &PtySpawnSpec) -> Result<SpawnedPty, String> {
+    #[cfg(windows)]
+    {
+        super::conpty::spawn(spec)
+    }
+    #[cfg(unix)]
+    {
+        super::unix_pty::spawn(spec)
+    }
+}
+
+/// Bytes to write after startup to switch the shell to UTF-8, if any.
+pub fn utf8_init_sequence(shell: Shell) -> Option<&'static [u8]> {
+    match shell {
+        Shell::Cmd => Some(b"chcp 65001\r\n"),
+        Shell::Powershell => Some(
+            b"[Console]::InputEncoding=[Text.UTF8Encoding]::UTF8;[Console]::OutputEncoding=[Text.UTF8Encoding]::UTF8\r\n",
+        ),
+        // Unix shells follow the locale and need no initialization
+        Shell::Sh | Shell::Bash | Shell::Direct => None,
+    }
+}
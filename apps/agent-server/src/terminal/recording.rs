@@ -0,0 +1,204 @@
+//! Asciicast v2 recording of terminal sessions.
+//!
+//! A recording is a JSON header line followed by one JSON array per event:
+//! `[seconds, "o", text]` for output, `"i"` for input and `"r"` for resizes
+//! (`"COLSxROWS"`). Event data is text, so a UTF-8 sequence split across two
+//! reads is carried over to the next event instead of being mangled.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Writes a session's events to an asciicast v2 file.
+pub struct Recorder {
+    file: BufWriter<File>,
+    started: Instant,
+    record_input: bool,
+    /// Incomplete UTF-8 sequence at the end of the last output chunk.
+    output_pending: Vec<u8>,
+    /// Incomplete UTF-8 sequence at the end of the last input chunk.
+    input_pending: Vec<u8>,
+    /// Set after a write error; the recording stops there.
+    failed: bool,
+}
+
+impl Recorder {
+    /// Create the file at `path` and write the header.
+    pub fn create(
+        path: &Path,
+        cols: u16,
+        rows: u16,
+        command: &str,
+        record_input: bool,
+    ) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": chrono::Utc::now().timestamp(),
+            "command": command,
+        });
+        serde_json::to_writer(&mut file, &header)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+
+        Ok(Self {
+            file,
+            started: Instant::now(),
+            record_input,
+            output_pending: Vec::new(),
+            input_pending: Vec::new(),
+            failed: false,
+        })
+    }
+
+    /// Record terminal output.
+    pub fn output(&mut self, data: &[u8]) {
+        let text = decode_utf8(&mut self.output_pending, data);
+        self.event("o", &text);
+    }
+
+    /// Record input sent to the terminal, if input recording is enabled.
+    pub fn input(&mut self, data: &[u8]) {
+        if self.record_input {
+            let text = decode_utf8(&mut self.input_pending, data);
+            self.event("i", &text);
+        }
+    }
+
+    /// Record a terminal resize.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.event("r", &format!("{}x{}", cols, rows));
+    }
+
+    /// Write buffered events to the file.
+    pub fn flush(&mut self) {
+        if !self.failed
+            && let Err(e) = self.file.flush()
+        {
+            self.fail(e);
+        }
+    }
+
+    fn event(&mut self, code: &str, data: &str) {
+        if self.failed || data.is_empty() {
+            return;
+        }
+        // Microsecond precision keeps the timestamps short
+        let time = self.started.elapsed().as_micros() as f64 / 1_000_000.0;
+        let result = serde_json::to_writer(&mut self.file, &(time, code, data))
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.file.write_all(b"\n"));
+        if let Err(e) = result {
+            self.fail(e);
+        }
+    }
+
+    fn fail(&mut self, e: std::io::Error) {
+        tracing::warn!("Session recording stopped: {}", e);
+        self.failed = true;
+    }
+}
+
+/// Decode `data` after the bytes left over from the previous call.
+///
+/// Invalid sequences become U+FFFD; an incomplete sequence at the end is
+/// kept in `pending` for the next call.
+fn decode_utf8(pending: &mut Vec<u8>, data: &[u8]) -> String {
+    pending.extend_from_slice(data);
+    let mut text = String::new();
+    let mut rest: &[u8] = pending;
+    while !rest.is_empty() {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                rest = &[];
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(&String::from_utf8_lossy(valid));
+                match e.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        rest = &after[len..];
+                    }
+                    None => {
+                        rest = after;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    *pending = rest.to_vec();
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multibyte_sequences_split_across_chunks() {
+        let text = "a é € 𝄞 中文";
+        let bytes = text.as_bytes();
+        for at in 0..=bytes.len() {
+            let mut pending = Vec::new();
+            let first = decode_utf8(&mut pending, &bytes[..at]);
+            let second = decode_utf8(&mut pending, &bytes[at..]);
+            assert_eq!(first + &second, text, "split at {}", at);
+            assert!(pending.is_empty());
+        }
+
+        // A four-byte character arriving one byte at a time
+        let mut pending = Vec::new();
+        let parts: Vec<String> = "𝄞"
+            .as_bytes()
+            .iter()
+            .map(|&b| decode_utf8(&mut pending, &[b]))
+            .collect();
+        assert_eq!(parts, ["", "", "", "𝄞"]);
+    }
+
+    #[test]
+    fn invalid_bytes_become_replacement_characters() {
+        let mut pending = Vec::new();
+        assert_eq!(decode_utf8(&mut pending, b"a\xffb\xc3"), "a\u{fffd}b");
+        assert_eq!(pending, b"\xc3");
+        // The held-back lead byte is not continued, so it is invalid too
+        assert_eq!(decode_utf8(&mut pending, b"x"), "\u{fffd}x");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn recording_joins_split_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.cast");
+        let mut recorder = Recorder::create(&path, 80, 24, "cmd.exe", false).unwrap();
+        let bytes = "€uro".as_bytes();
+        recorder.output(&bytes[..2]);
+        recorder.output(&bytes[2..]);
+        recorder.input(b"ignored");
+        recorder.resize(100, 30);
+        recorder.flush();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 80);
+        let events: Vec<(&str, &str)> = lines[1..]
+            .iter()
+            .map(|e| (e[1].as_str().unwrap(), e[2].as_str().unwrap()))
+            .collect();
+        // Nothing is written until the character is complete
+        assert_eq!(events, [("o", "€uro"), ("r", "100x30")]);
+    }
+}
@@ -0,0 +1,138 @@
+//! Virtual screen of a terminal session.
+//!
+//! Output is fed through a VT100/xterm parser as it is read, so the visible
+//! grid can be inspected by automation without a client attached.
+
+use winpe_agent_core::{ScreenCursor, ScreenSnapshot, ScreenSpan};
+
+/// Parsed screen state, kept at the session's terminal size.
+pub struct Screen {
+    parser: vt100::Parser,
+}
+
+impl Screen {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Self {
+            parser: vt100::Parser::new(rows, cols, 0),
+        }
+    }
+
+    /// Apply terminal output.
+    pub fn process(&mut self, bytes: &[u8]) {
+        self.parser.process(bytes);
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.parser.set_size(rows, cols);
+    }
+
+    /// Capture the visible grid, optionally with the cursor and styled text runs.
+    pub fn snapshot(&self, cursor: bool, attrs: bool) -> ScreenSnapshot {
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+        ScreenSnapshot {
+            cols,
+            rows,
+            lines: screen.rows(0, cols).collect(),
+            alternate_screen: screen.alternate_screen(),
+            cursor: cursor.then(|| {
+                let (row, col) = screen.cursor_position();
+                ScreenCursor {
+                    row,
+                    col,
+                    visible: !screen.hide_cursor(),
+                }
+            }),
+            attrs: attrs.then(|| styled_spans(screen)),
+        }
+    }
+}
+
+/// Attributes of a cell, compared to decide where a span ends.
+#[derive(PartialEq)]
+struct Style {
+    fg: Option<String>,
+    bg: Option<String>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+}
+
+impl Style {
+    fn of(cell: &vt100::Cell) -> Self {
+        Self {
+            fg: color(cell.fgcolor()),
+            bg: color(cell.bgcolor()),
+            bold: cell.bold(),
+            italic: cell.italic(),
+            underline: cell.underline(),
+            inverse: cell.inverse(),
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        self.fg.is_none()
+            && self.bg.is_none()
+            && !(self.bold || self.italic || self.underline || self.inverse)
+    }
+}
+
+fn color(color: vt100::Color) -> Option<String> {
+    match color {
+        vt100::Color::Default => None,
+        vt100::Color::Idx(i) => Some(i.to_string()),
+        vt100::Color::Rgb(r, g, b) => Some(format!("#{:02x}{:02x}{:02x}", r, g, b)),
+    }
+}
+
+/// Group runs of cells with the same non-default style into spans.
+fn styled_spans(screen: &vt100::Screen) -> Vec<ScreenSpan> {
+    let (rows, cols) = screen.size();
+    let mut spans = Vec::new();
+    for row in 0..rows {
+        let mut current: Option<(Style, ScreenSpan)> = None;
+        for col in 0..cols {
+            let Some(cell) = screen.cell(row, col) else {
+                break;
+            };
+            if cell.is_wide_continuation() {
+                continue;
+            }
+            let style = Style::of(cell);
+            let text = if cell.has_contents() {
+                cell.contents()
+            } else {
+                " ".to_string()
+            };
+
+            if let Some((current_style, span)) = &mut current
+                && *current_style == style
+            {
+                span.text.push_str(&text);
+                continue;
+            }
+            if let Some((_, span)) = current.take() {
+                spans.push(span);
+            }
+            if !style.is_default() {
+                let span = ScreenSpan {
+                    row,
+                    col,
+                    text,
+                    fg: style.fg.clone(),
+                    bg: style.bg.clone(),
+                    bold: style.bold,
+                    italic: style.italic,
+                    underline: style.underline,
+                    inverse: style.inverse,
+                };
+                current = Some((style, span));
+            }
+        }
+        if let Some((_, span)) = current {
+            spans.push(span);
+        }
+    }
+    spans
+}
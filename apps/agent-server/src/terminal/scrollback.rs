@@ -0,0 +1,134 @@
+//! Bounded buffer of recent terminal output.
+//!
+//! Replayed to a client when it attaches, so a reconnecting terminal shows
+//! what was on screen instead of starting blank.
+
+use std::collections::VecDeque;
+
+/// Ring buffer keeping the most recent `limit` bytes of output.
+pub struct Scrollback {
+    data: VecDeque<u8>,
+    limit: usize,
+    /// Whether older output has been discarded.
+    wrapped: bool,
+    /// Total bytes of output pushed, i.e. the offset of the next byte.
+    end: u64,
+}
+
+impl Scrollback {
+    /// Create a buffer holding up to `limit` bytes; `0` disables it.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            data: VecDeque::new(),
+            limit,
+            wrapped: false,
+            end: 0,
+        }
+    }
+
+    /// Append output, discarding the oldest bytes beyond the limit.
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.end += chunk.len() as u64;
+        if self.limit == 0 {
+            return;
+        }
+        let overflow = (self.data.len() + chunk.len()).saturating_sub(self.limit);
+        if overflow > 0 {
+            self.wrapped = true;
+        }
+        if chunk.len() >= self.limit {
+            self.data.clear();
+            self.data.extend(&chunk[chunk.len() - self.limit..]);
+        } else {
+            self.data.drain(..overflow);
+            self.data.extend(chunk);
+        }
+    }
+
+    /// Copy of the buffered output, starting at a line boundary once the buffer has wrapped.
+    ///
+    /// Dropping the partial first line avoids replaying half an escape
+    /// sequence or a split UTF-8 character.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let (front, back) = self.data.as_slices();
+        let mut bytes = Vec::with_capacity(self.data.len());
+        bytes.extend_from_slice(front);
+        bytes.extend_from_slice(back);
+        if self.wrapped
+            && let Some(newline) = bytes.iter().position(|&b| b == b'\n')
+        {
+            bytes.drain(..=newline);
+        }
+        bytes
+    }
+
+    /// Output offset just past the last byte pushed.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Buffered output from `offset` on, with the offset it actually starts at.
+    ///
+    /// Starts later than `offset` if that part was already discarded.
+    pub fn since(&self, offset: u64) -> (u64, Vec<u8>) {
+        let start = self.end - self.data.len() as u64;
+        let from = offset.clamp(start, self.end);
+        let bytes = self
+            .data
+            .range((from - start) as usize..)
+            .copied()
+            .collect();
+        (from, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since_before_inside_and_after_the_window() {
+        let mut scrollback = Scrollback::new(8);
+        scrollback.push(b"0123");
+        scrollback.push(b"456789ab");
+        // Offsets 4..12 are retained
+        assert_eq!(scrollback.end(), 12);
+        assert_eq!(scrollback.since(0), (4, b"456789ab".to_vec()));
+        assert_eq!(scrollback.since(4), (4, b"456789ab".to_vec()));
+        assert_eq!(scrollback.since(9), (9, b"9ab".to_vec()));
+        assert_eq!(scrollback.since(12), (12, Vec::new()));
+        assert_eq!(scrollback.since(100), (12, Vec::new()));
+    }
+
+    #[test]
+    fn trims_across_pushes() {
+        let mut scrollback = Scrollback::new(5);
+        for chunk in [&b"ab"[..], b"cd", b"ef", b"g"] {
+            scrollback.push(chunk);
+        }
+        assert_eq!(scrollback.since(0), (2, b"cdefg".to_vec()));
+        // A chunk longer than the limit keeps its own tail
+        scrollback.push(b"0123456789");
+        assert_eq!(scrollback.end(), 17);
+        assert_eq!(scrollback.since(0), (12, b"56789".to_vec()));
+    }
+
+    #[test]
+    fn disabled_buffer_still_counts_output() {
+        let mut scrollback = Scrollback::new(0);
+        scrollback.push(b"hello");
+        assert_eq!(scrollback.end(), 5);
+        assert_eq!(scrollback.since(0), (5, Vec::new()));
+        assert!(scrollback.snapshot().is_empty());
+    }
+
+    #[test]
+    fn snapshot_drops_partial_first_line_once_wrapped() {
+        let mut scrollback = Scrollback::new(10);
+        scrollback.push(b"one\ntwo");
+        assert_eq!(scrollback.snapshot(), b"one\ntwo");
+        scrollback.push(b"\nthree");
+        // "one\ntwo\nthree" lost its first 3 bytes: "\ntwo\nthree"
+        assert_eq!(scrollback.snapshot(), b"two\nthree");
+    }
+}
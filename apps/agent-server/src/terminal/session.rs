@@ -1,96 +1,50 @@
-//! Session management for ConPTY terminal sessions.
+//! Session management for pseudo terminal sessions.
 
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use ulid::Ulid;
 use winpe_agent_core::{
-    SessionCreateRequest, SessionCreateResponse, SessionInfo, SessionState, Shell, Signal,
+    AttachMode, AttachedClient, ScreenSnapshot, SessionCreateRequest, SessionCreateResponse,
+    SessionExpectResponse, SessionInfo, SessionState, Shell, Signal, WsServerMessage,
 };
 
-#[cfg(windows)]
-use super::conpty::spawn_process;
+use super::expect::{self, ExpectError};
+use super::pty::{self, PtyProcess, PtySpawnSpec};
+use super::recording::Recorder;
+use super::screen::Screen;
+use super::scrollback::Scrollback;
+use super::title::TitleParser;
 
-#[cfg(windows)]
-use windows_sys::Win32::Foundation::HANDLE;
+/// Upper bound for a session's requested scrollback.
+const MAX_SCROLLBACK_BYTES: u64 = 16 * 1024 * 1024;
 
-/// A Send-safe wrapper for Windows handles.
-/// We store as usize for Send safety then convert back when needed.
-#[cfg(windows)]
-#[derive(Debug, Clone, Copy)]
-pub struct SendHandle(pub usize);
+/// How long to wait for trailing output after the process exits.
+const OUTPUT_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
-#[cfg(windows)]
-impl SendHandle {
-    pub fn from_handle(h: HANDLE) -> Self {
-        Self(h as usize)
-    }
-
-    pub fn as_handle(&self) -> HANDLE {
-        self.0 as HANDLE
-    }
-}
-
-#[cfg(windows)]
-unsafe impl Send for SendHandle {}
-#[cfg(windows)]
-unsafe impl Sync for SendHandle {}
-
-/// Wrapper for ConPTY that is Send + Sync.
-/// Stores handle as usize for Send safety.
-#[cfg(windows)]
-pub struct SendablePty {
-    handle: usize,
-}
-
-#[cfg(windows)]
-impl SendablePty {
-    pub fn new(handle: windows_sys::Win32::System::Console::HPCON) -> Self {
-        Self {
-            handle: handle as usize,
-        }
-    }
-
-    pub fn resize(&self, cols: u16, rows: u16) -> Result<(), String> {
-        use windows_sys::Win32::Foundation::S_OK;
-        use windows_sys::Win32::System::Console::{COORD, HPCON, ResizePseudoConsole};
-        unsafe {
-            let size = COORD {
-                X: cols as i16,
-                Y: rows as i16,
-            };
-            let result = ResizePseudoConsole(self.handle as HPCON, size);
-            if result != S_OK {
-                return Err(format!("ResizePseudoConsole failed: 0x{:08X}", result));
-            }
-            Ok(())
-        }
-    }
-}
-
-#[cfg(windows)]
-unsafe impl Send for SendablePty {}
-#[cfg(windows)]
-unsafe impl Sync for SendablePty {}
-
-#[cfg(windows)]
-impl Drop for SendablePty {
-    fn drop(&mut self) {
-        use windows_sys::Win32::System::Console::{ClosePseudoConsole, HPCON};
-        unsafe {
-            ClosePseudoConsole(self.handle as HPCON);
-        }
-    }
+/// A WebSocket client attached to a session.
+pub struct Client {
+    pub id: String,
+    pub mode: AttachMode,
+    pub attached_at: DateTime<Utc>,
+    /// Fired when another client takes over control.
+    kick: oneshot::Sender<()>,
 }
 
 /// Session state stored in the manager.
 pub struct Session {
     pub id: String,
     pub shell: Shell,
+    /// Program run instead of the shell, if any.
+    pub program: Option<String>,
     pub pid: u32,
     pub state: SessionState,
-    pub attached: bool,
+    pub exit_code: Option<i32>,
+    pub exited_at: Option<DateTime<Utc>>,
+    /// Attached WebSocket clients; at most one has [`AttachMode::Control`].
+    pub clients: Vec<Client>,
     pub cols: u16,
     pub rows: u16,
     pub created_at: DateTime<Utc>,
@@ -98,34 +52,123 @@ pub struct Session {
     /// Idle timeout in seconds (from creation request).
     pub idle_timeout_sec: u64,
 
-    #[cfg(windows)]
-    pub process_handle: SendHandle,
-    #[cfg(windows)]
-    pub pty: Arc<SendablePty>,
+    /// The pseudo terminal process backing this session.
+    pub pty: Arc<dyn PtyProcess>,
 
     /// Channel to send input to the session.
     pub input_tx: mpsc::Sender<Vec<u8>>,
     /// Broadcast channel for output - allows multiple subscribers for reconnection.
     pub output_tx: broadcast::Sender<Vec<u8>>,
+    /// Recent output, replayed on attach. Updated together with `output_tx`.
+    pub scrollback: Arc<Mutex<Scrollback>>,
+    /// Current screen contents, parsed from the output.
+    pub screen: Arc<Mutex<Screen>>,
+    /// Asciicast recording, if requested at creation.
+    pub recording: Option<Arc<Mutex<Recorder>>>,
+    /// Set to the exit code once the process has exited and its output was broadcast.
+    pub exited: watch::Receiver<Option<i32>>,
+    /// Control messages for every attached client (resizes, title changes).
+    pub events_tx: broadcast::Sender<WsServerMessage>,
+    /// Last window title set by the terminal, sent to clients on attach.
+    pub title: Arc<Mutex<Option<String>>>,
 }
 
-// Ensure Session is Send + Sync
-#[cfg(windows)]
-unsafe impl Send for Session {}
-#[cfg(windows)]
-unsafe impl Sync for Session {}
+impl Session {
+    /// Attach a client, returning its ID and a receiver fired if it loses control.
+    ///
+    /// A second controlling client is rejected unless `takeover` is set, in
+    /// which case the current controller is kicked.
+    pub fn attach(
+        &mut self,
+        mode: AttachMode,
+        takeover: bool,
+    ) -> Result<(String, oneshot::Receiver<()>), String> {
+        let id = Ulid::new().to_string();
+        if mode == AttachMode::Control
+            && let Some(index) = self
+                .clients
+                .iter()
+                .position(|c| c.mode == AttachMode::Control)
+        {
+            if !takeover {
+                return Err("Session already has a controlling client".to_string());
+            }
+            let previous = self.clients.remove(index);
+            tracing::info!(
+                "Client {} took over control of session {} from {}",
+                id,
+                self.id,
+                previous.id
+            );
+            let _ = previous.kick.send(());
+        }
+
+        let (kick, kicked) = oneshot::channel();
+        self.clients.push(Client {
+            id: id.clone(),
+            mode,
+            attached_at: Utc::now(),
+            kick,
+        });
+        Ok((id, kicked))
+    }
+
+    /// Remove a client; does nothing if it was already kicked.
+    pub fn detach(&mut self, client_id: &str) {
+        self.clients.retain(|c| c.id != client_id);
+    }
+
+    /// Public view of this session.
+    pub fn info(&self) -> SessionInfo {
+        SessionInfo {
+            id: self.id.clone(),
+            shell: self.shell,
+            program: self.program.clone(),
+            pid: self.pid,
+            state: self.state,
+            exit_code: self.exit_code,
+            exited_at: self.exited_at.map(|t| t.to_rfc3339()),
+            recording: self.recording.is_some(),
+            clients: self
+                .clients
+                .iter()
+                .map(|c| AttachedClient {
+                    id: c.id.clone(),
+                    mode: c.mode,
+                    attached_at: c.attached_at.to_rfc3339(),
+                })
+                .collect(),
+            cols: self.cols,
+            rows: self.rows,
+            created_at: self.created_at.to_rfc3339(),
+            last_activity_at: self.last_activity.to_rfc3339(),
+        }
+    }
+
+    /// Subscribe to output, returning the scrollback to replay first.
+    ///
+    /// Both are taken under the scrollback lock, so no output is lost or
+    /// repeated between the replay and the subscription.
+    pub fn subscribe_output(&self) -> (Vec<u8>, broadcast::Receiver<Vec<u8>>) {
+        let scrollback = self.scrollback.lock().unwrap();
+        (scrollback.snapshot(), self.output_tx.subscribe())
+    }
+}
 
 /// Thread-safe session manager using DashMap.
 #[derive(Clone)]
 pub struct SessionManager {
     sessions: Arc<DashMap<String, Arc<tokio::sync::RwLock<Session>>>>,
+    /// Directory holding session recordings, named `{id}.cast`.
+    recording_dir: Arc<PathBuf>,
 }
 
 impl SessionManager {
-    /// Create a new session manager.
-    pub fn new() -> Self {
+    /// Create a new session manager storing recordings in `recording_dir`.
+    pub fn new(recording_dir: PathBuf) -> Self {
         Self {
             sessions: Arc::new(DashMap::new()),
+            recording_dir: Arc::new(recording_dir),
         }
     }
 
@@ -156,7 +199,7 @@ impl SessionManager {
             };
 
             // Only clean up detached sessions that have exceeded idle timeout
-            if !session.attached {
+            if session.clients.is_empty() {
                 let idle_duration = now
                     .signed_duration_since(session.last_activity)
                     .num_seconds();
@@ -175,17 +218,8 @@ impl SessionManager {
         // Remove and terminate idle sessions
         for id in to_remove {
             if let Some((_, session)) = sessions.remove(&id) {
-                #[cfg(windows)]
-                {
-                    use windows_sys::Win32::Foundation::CloseHandle;
-                    use windows_sys::Win32::System::Threading::TerminateProcess;
-
-                    if let Ok(session) = session.try_write() {
-                        unsafe {
-                            TerminateProcess(session.process_handle.as_handle(), 1);
-                            CloseHandle(session.process_handle.as_handle());
-                        }
-                    }
+                if let Ok(session) = session.try_read() {
+                    session.pty.terminate();
                 }
                 tracing::info!("Terminated idle session {}", id);
             }
@@ -193,156 +227,169 @@ impl SessionManager {
     }
 
     /// Create a new terminal session.
-    #[cfg(windows)]
     pub async fn create_session(
         &self,
         req: SessionCreateRequest,
     ) -> Result<SessionCreateResponse, String> {
-        use std::io::Write;
-        use std::os::windows::io::FromRawHandle;
-        use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE, S_OK};
-        use windows_sys::Win32::System::Console::{COORD, CreatePseudoConsole, HPCON};
-        use windows_sys::Win32::System::Pipes::CreatePipe;
+        use std::io::{Read, Write};
 
         let id = Ulid::new().to_string();
         let now = Utc::now();
 
-        // Create pipes for ConPTY I/O
-        let (pty_handle, input_write, output_read): (HPCON, HANDLE, HANDLE) = unsafe {
-            let mut pty_input_read: HANDLE = INVALID_HANDLE_VALUE;
-            let mut pty_input_write: HANDLE = INVALID_HANDLE_VALUE;
-            let mut pty_output_read: HANDLE = INVALID_HANDLE_VALUE;
-            let mut pty_output_write: HANDLE = INVALID_HANDLE_VALUE;
-
-            if CreatePipe(
-                &mut pty_input_read,
-                &mut pty_input_write,
-                std::ptr::null_mut(),
-                0,
-            ) == 0
-            {
-                return Err("Failed to create input pipe".to_string());
-            }
-
-            if CreatePipe(
-                &mut pty_output_read,
-                &mut pty_output_write,
-                std::ptr::null_mut(),
-                0,
-            ) == 0
-            {
-                CloseHandle(pty_input_read);
-                CloseHandle(pty_input_write);
-                return Err("Failed to create output pipe".to_string());
-            }
-
-            let size = COORD {
-                X: req.cols as i16,
-                Y: req.rows as i16,
+        // Spawn the shell on a new pseudo terminal
+        let spawned = pty::spawn(&PtySpawnSpec {
+            shell: req.shell,
+            program: req.program.as_deref(),
+            args: &req.args,
+            env: &req.env,
+            cwd: req.cwd.as_deref(),
+            cols: req.cols,
+            rows: req.rows,
+        })?;
+        let pty: Arc<dyn PtyProcess> = Arc::from(spawned.process);
+        let mut reader = spawned.reader;
+        let mut writer = spawned.writer;
+
+        // Start the recording before any output is read
+        let recording = if req.record {
+            let path = self.recording_dir.join(format!("{}.cast", id));
+            let command = match &req.program {
+                Some(program) => program.clone(),
+                None => serde_json::to_value(req.shell)
+                    .ok()
+                    .and_then(|v| v.as_str().map(String::from))
+                    .unwrap_or_default(),
             };
-
-            let mut hpc: HPCON = 0;
-            let result = CreatePseudoConsole(size, pty_input_read, pty_output_write, 0, &mut hpc);
-
-            if result != S_OK {
-                CloseHandle(pty_input_read);
-                CloseHandle(pty_input_write);
-                CloseHandle(pty_output_read);
-                CloseHandle(pty_output_write);
-                return Err(format!("CreatePseudoConsole failed: 0x{:08X}", result));
+            match Recorder::create(&path, req.cols, req.rows, &command, req.record_input) {
+                Ok(recorder) => Some(Arc::new(Mutex::new(recorder))),
+                Err(e) => {
+                    pty.terminate();
+                    return Err(format!("Failed to create recording: {}", e));
+                }
             }
-
-            // Close the handles that the ConPTY now owns
-            CloseHandle(pty_input_read);
-            CloseHandle(pty_output_write);
-
-            (hpc, pty_input_write, pty_output_read)
+        } else {
+            None
         };
 
-        let pty = Arc::new(SendablePty::new(pty_handle));
-
-        // Build command line
-        let command_line = match req.shell {
-            Shell::Cmd => "cmd.exe".to_string(),
-            Shell::Powershell => "powershell.exe -NoLogo -NoProfile".to_string(),
-        };
-
-        // Spawn process attached to ConPTY
-        let (process_handle_raw, pid) =
-            spawn_process(pty_handle, &command_line, req.cwd.as_deref())?;
-        // Immediately wrap in SendHandle to satisfy Send requirement
-        let process_handle = SendHandle::from_handle(process_handle_raw);
-
         // Create channels for I/O
         let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(100);
         // Use broadcast for output to support reconnection
         let (output_tx, _) = broadcast::channel::<Vec<u8>>(256);
-
-        // Wrap handles for Send (convert to usize)
-        let input_handle = SendHandle::from_handle(input_write);
-        let output_handle = SendHandle::from_handle(output_read);
-
-        // Spawn input writer task using std::thread (not tokio) for raw handles
+        let (events_tx, _) = broadcast::channel::<WsServerMessage>(16);
+        let title = Arc::new(Mutex::new(None));
+        let screen = Arc::new(Mutex::new(Screen::new(req.cols, req.rows)));
+        let scrollback = Arc::new(Mutex::new(Scrollback::new(
+            req.scrollback_bytes.min(MAX_SCROLLBACK_BYTES) as usize,
+        )));
+
+        // Spawn input writer task using std::thread (not tokio) for blocking I/O
+        let input_recording = recording.clone();
         std::thread::spawn(move || {
-            let mut file = unsafe { std::fs::File::from_raw_handle(input_handle.as_handle()) };
             while let Some(data) = input_rx.blocking_recv() {
-                if file.write_all(&data).is_err() {
+                if let Some(recording) = &input_recording {
+                    recording.lock().unwrap().input(&data);
+                }
+                if writer.write_all(&data).is_err() {
                     break;
                 }
-                let _ = file.flush();
+                let _ = writer.flush();
             }
         });
 
         // Spawn output reader task using std::thread
         let output_tx_clone = output_tx.clone();
+        let scrollback_clone = scrollback.clone();
+        // Dropped when the reader finishes, telling the exit watcher all output was sent
+        let (reader_done_tx, reader_done_rx) = std::sync::mpsc::channel::<()>();
+        let events_tx_clone = events_tx.clone();
+        let title_clone = title.clone();
+        let output_recording = recording.clone();
+        let screen_clone = screen.clone();
         std::thread::spawn(move || {
-            use std::io::Read;
-            let mut file = unsafe { std::fs::File::from_raw_handle(output_handle.as_handle()) };
+            let _reader_done = reader_done_tx;
+            let mut title_parser = TitleParser::new();
             let mut buffer = [0u8; 4096];
             loop {
-                match file.read(&mut buffer) {
+                match reader.read(&mut buffer) {
                     Ok(0) => break,
                     Ok(n) => {
+                        let mut scrollback = scrollback_clone.lock().unwrap();
+                        scrollback.push(&buffer[..n]);
                         // Ignore send errors - no subscribers is OK
                         let _ = output_tx_clone.send(buffer[..n].to_vec());
+                        drop(scrollback);
+
+                        screen_clone.lock().unwrap().process(&buffer[..n]);
+                        if let Some(recording) = &output_recording {
+                            recording.lock().unwrap().output(&buffer[..n]);
+                        }
+
+                        if let Some(title) = title_parser.feed(&buffer[..n]) {
+                            *title_clone.lock().unwrap() = Some(title.clone());
+                            let _ = events_tx_clone.send(WsServerMessage::Title { title });
+                        }
                     }
                     Err(_) => break,
                 }
             }
         });
 
-        // Send UTF-8 initialization if requested
-        if req.init.force_utf8 {
-            let init_cmd = match req.shell {
-                Shell::Cmd => "chcp 65001\r\n".as_bytes().to_vec(),
-                Shell::Powershell => {
-                    "[Console]::InputEncoding=[Text.UTF8Encoding]::UTF8;[Console]::OutputEncoding=[Text.UTF8Encoding]::UTF8\r\n"
-                        .as_bytes()
-                        .to_vec()
-                }
-            };
-            let _ = input_tx.send(init_cmd).await;
+        // Send UTF-8 initialization if requested; programs get no shell input
+        if req.init.force_utf8
+            && req.program.is_none()
+            && let Some(init_cmd) = pty::utf8_init_sequence(req.shell)
+        {
+            let _ = input_tx.send(init_cmd.to_vec()).await;
         }
 
+        let (exit_tx, exited) = watch::channel(None);
+        let waiter_pty = pty.clone();
+
         let session = Session {
             id: id.clone(),
             shell: req.shell,
-            pid,
+            program: req.program.clone(),
+            pid: pty.pid(),
             state: SessionState::Running,
-            attached: false,
+            exit_code: None,
+            exited_at: None,
+            clients: Vec::new(),
             cols: req.cols,
             rows: req.rows,
             created_at: now,
             last_activity: now,
             idle_timeout_sec: req.idle_timeout_sec,
-            process_handle,
             pty,
             input_tx,
             output_tx,
+            scrollback,
+            screen,
+            recording,
+            exited,
+            events_tx,
+            title,
         };
 
-        self.sessions
-            .insert(id.clone(), Arc::new(tokio::sync::RwLock::new(session)));
+        let session = Arc::new(tokio::sync::RwLock::new(session));
+        self.sessions.insert(id.clone(), session.clone());
+
+        // Watch the process and record its exit
+        std::thread::spawn(move || {
+            let exit_code = waiter_pty.wait();
+            // Broadcast trailing output before reporting the exit
+            let _ = reader_done_rx.recv_timeout(OUTPUT_DRAIN_TIMEOUT);
+            {
+                let mut session = session.blocking_write();
+                session.state = SessionState::Exited;
+                session.exit_code = Some(exit_code);
+                session.exited_at = Some(Utc::now());
+                if let Some(recording) = &session.recording {
+                    recording.lock().unwrap().flush();
+                }
+                tracing::info!("Session {} exited with code {}", session.id, exit_code);
+            }
+            let _ = exit_tx.send(Some(exit_code));
+        });
 
         Ok(SessionCreateResponse {
             id: id.clone(),
@@ -351,30 +398,12 @@ impl SessionManager {
         })
     }
 
-    #[cfg(not(windows))]
-    pub async fn create_session(
-        &self,
-        _req: SessionCreateRequest,
-    ) -> Result<SessionCreateResponse, String> {
-        Err("ConPTY is only available on Windows".to_string())
-    }
-
     /// List all sessions.
     pub fn list_sessions(&self) -> Vec<SessionInfo> {
         let mut result = Vec::new();
         for entry in self.sessions.iter() {
             if let Ok(session) = entry.value().try_read() {
-                result.push(SessionInfo {
-                    id: session.id.clone(),
-                    shell: session.shell,
-                    pid: session.pid,
-                    state: session.state,
-                    attached: session.attached,
-                    cols: session.cols,
-                    rows: session.rows,
-                    created_at: session.created_at.to_rfc3339(),
-                    last_activity_at: session.last_activity.to_rfc3339(),
-                });
+                result.push(session.info());
             }
         }
         result
@@ -382,19 +411,9 @@ impl SessionManager {
 
     /// Get session info by ID.
     pub fn get_session(&self, id: &str) -> Option<SessionInfo> {
-        self.sessions.get(id).and_then(|entry| {
-            entry.try_read().ok().map(|session| SessionInfo {
-                id: session.id.clone(),
-                shell: session.shell,
-                pid: session.pid,
-                state: session.state,
-                attached: session.attached,
-                cols: session.cols,
-                rows: session.rows,
-                created_at: session.created_at.to_rfc3339(),
-                last_activity_at: session.last_activity.to_rfc3339(),
-            })
-        })
+        self.sessions
+            .get(id)
+            .and_then(|entry| entry.try_read().ok().map(|session| session.info()))
     }
 
     /// Check if a session exists.
@@ -407,37 +426,89 @@ impl SessionManager {
         self.sessions.get(id).map(|entry| entry.value().clone())
     }
 
-    /// Terminate a session.
-    #[cfg(windows)]
-    pub async fn terminate_session(&self, id: &str) -> Result<(), String> {
-        use windows_sys::Win32::Foundation::CloseHandle;
-        use windows_sys::Win32::System::Threading::TerminateProcess;
+    /// Path of a session's recording file, flushing it first if the session is live.
+    ///
+    /// Recordings outlive their sessions, so the file may exist for an ID no
+    /// longer in the manager. Returns `None` for IDs that are not ULIDs.
+    pub async fn recording_path(&self, id: &str) -> Option<PathBuf> {
+        let id = Ulid::from_string(id).ok()?.to_string();
+        let session = self.sessions.get(&id).map(|entry| entry.value().clone());
+        if let Some(session) = session
+            && let Some(recording) = &session.read().await.recording
+        {
+            recording.lock().unwrap().flush();
+        }
+        Some(self.recording_dir.join(format!("{}.cast", id)))
+    }
 
+    /// Type input into a session, returning the output offset at that point.
+    pub async fn send_input(&self, id: &str, data: Vec<u8>) -> Result<u64, String> {
         let session = self
             .sessions
-            .remove(id)
-            .map(|(_, v)| v)
+            .get(id)
+            .map(|entry| entry.value().clone())
             .ok_or_else(|| "Session not found".to_string())?;
 
-        let session = session.write().await;
-        unsafe {
-            TerminateProcess(session.process_handle.as_handle(), 1);
-            CloseHandle(session.process_handle.as_handle());
-        }
+        let (input_tx, offset) = {
+            let mut session = session.write().await;
+            if session.state == SessionState::Exited {
+                return Err("Session has exited".to_string());
+            }
+            session.last_activity = Utc::now();
+            let offset = session.scrollback.lock().unwrap().end();
+            (session.input_tx.clone(), offset)
+        };
+        input_tx
+            .send(data)
+            .await
+            .map_err(|_| "Session has exited".to_string())?;
+        Ok(offset)
+    }
 
-        Ok(())
+    /// Wait for `pattern` in a session's output; see [`expect::expect`].
+    pub async fn expect(
+        &self,
+        id: &str,
+        pattern: &regex::bytes::Regex,
+        since: Option<u64>,
+        timeout: std::time::Duration,
+    ) -> Result<SessionExpectResponse, ExpectError> {
+        let session = self
+            .sessions
+            .get(id)
+            .map(|entry| entry.value().clone())
+            .ok_or(ExpectError::NotFound)?;
+        expect::expect(session, pattern, since, timeout).await
     }
 
-    #[cfg(not(windows))]
+    /// Snapshot of a session's current screen.
+    pub async fn screen_snapshot(
+        &self,
+        id: &str,
+        cursor: bool,
+        attrs: bool,
+    ) -> Option<ScreenSnapshot> {
+        let session = self.sessions.get(id).map(|entry| entry.value().clone())?;
+        let session = session.read().await;
+        let screen = session.screen.lock().unwrap();
+        Some(screen.snapshot(cursor, attrs))
+    }
+
+    /// Terminate a session.
     pub async fn terminate_session(&self, id: &str) -> Result<(), String> {
-        self.sessions
+        let session = self
+            .sessions
             .remove(id)
+            .map(|(_, v)| v)
             .ok_or_else(|| "Session not found".to_string())?;
+
+        let session = session.read().await;
+        session.pty.terminate();
+
         Ok(())
     }
 
     /// Send a signal to a session.
-    #[cfg(windows)]
     pub async fn send_signal(&self, id: &str, signal: Signal) -> Result<(), String> {
         let session = self
             .sessions
@@ -456,26 +527,14 @@ impl SessionManager {
                 let _ = session.input_tx.send(vec![0x03]).await;
             }
             Signal::Terminate => {
-                use windows_sys::Win32::System::Threading::TerminateProcess;
-                unsafe {
-                    TerminateProcess(session.process_handle.as_handle(), 1);
-                }
+                session.pty.terminate();
             }
         }
 
         Ok(())
     }
 
-    #[cfg(not(windows))]
-    pub async fn send_signal(&self, id: &str, _signal: Signal) -> Result<(), String> {
-        if !self.sessions.contains_key(id) {
-            return Err("Session not found".to_string());
-        }
-        Err("Signals not supported on this platform".to_string())
-    }
-
     /// Resize a session's terminal.
-    #[cfg(windows)]
     pub async fn resize_session(&self, id: &str, cols: u16, rows: u16) -> Result<(), String> {
         let session = self
             .sessions
@@ -486,21 +545,14 @@ impl SessionManager {
         session.pty.resize(cols, rows)?;
         session.cols = cols;
         session.rows = rows;
-
-        Ok(())
-    }
-
-    #[cfg(not(windows))]
-    pub async fn resize_session(&self, id: &str, _cols: u16, _rows: u16) -> Result<(), String> {
-        if !self.sessions.contains_key(id) {
-            return Err("Session not found".to_string());
+        session.screen.lock().unwrap().resize(cols, rows);
+        if let Some(recording) = &session.recording {
+            recording.lock().unwrap().resize(cols, rows);
         }
-        Err("Resize not supported on this platform".to_string())
-    }
-}
+        let _ = session
+            .events_tx
+            .send(WsServerMessage::Resized { cols, rows });
 
-impl Default for SessionManager {
-    fn default() -> Self {
-        Self::new()
+        Ok(())
     }
 }
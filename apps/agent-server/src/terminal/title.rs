@@ -0,0 +1,160 @@
+//! Window title tracking.
+//!
+//! Scans terminal output for the OSC 0 / OSC 2 "set window title" sequences
+//! (`ESC ] 0 ; title BEL`, terminated by BEL or `ESC \`), which both ConPTY
+//! and Unix shells emit, across chunk boundaries.
+
+/// Longest OSC payload collected; longer sequences are ignored.
+const MAX_OSC_LEN: usize = 4096;
+
+enum State {
+    Ground,
+    Escape,
+    Osc,
+    /// `ESC` seen inside an OSC, possibly starting the `ESC \` terminator.
+    OscEscape,
+}
+
+/// Incremental scanner for window title changes.
+pub struct TitleParser {
+    state: State,
+    osc: Vec<u8>,
+}
+
+impl TitleParser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            osc: Vec::new(),
+        }
+    }
+
+    /// Feed output bytes; returns the last title set within them, if any.
+    pub fn feed(&mut self, bytes: &[u8]) -> Option<String> {
+        let mut title = None;
+        for &b in bytes {
+            self.state = match self.state {
+                State::Ground if b == 0x1b => State::Escape,
+                State::Ground => State::Ground,
+                State::Escape if b == b']' => {
+                    self.osc.clear();
+                    State::Osc
+                }
+                State::Escape if b == 0x1b => State::Escape,
+                State::Escape => State::Ground,
+                State::Osc if b == 0x07 => {
+                    title = self.finish().or(title);
+                    State::Ground
+                }
+                State::Osc if b == 0x1b => State::OscEscape,
+                State::Osc => {
+                    if self.osc.len() < MAX_OSC_LEN {
+                        self.osc.push(b);
+                    }
+                    State::Osc
+                }
+                State::OscEscape if b == b'\\' => {
+                    title = self.finish().or(title);
+                    State::Ground
+                }
+                // Any other escape aborts the OSC and may start a new sequence
+                State::OscEscape if b == b']' => {
+                    self.osc.clear();
+                    State::Osc
+                }
+                State::OscEscape => State::Ground,
+            };
+        }
+        title
+    }
+
+    /// Extract the title from a completed OSC payload.
+    fn finish(&mut self) -> Option<String> {
+        if self.osc.len() >= MAX_OSC_LEN {
+            return None;
+        }
+        let payload = String::from_utf8_lossy(&self.osc);
+        let (command, text) = payload.split_once(';')?;
+        matches!(command, "0" | "2").then(|| text.to_string())
+    }
+}
+
+impl Default for TitleParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn title(bytes: &[u8]) -> Option<String> {
+        TitleParser::new().feed(bytes)
+    }
+
+    #[test]
+    fn osc_0_and_2_with_either_terminator() {
+        assert_eq!(
+            title(b"\x1b]0;Administrator: cmd\x07"),
+            Some("Administrator: cmd".into())
+        );
+        assert_eq!(title(b"\x1b]2;vim\x1b\\"), Some("vim".into()));
+        assert_eq!(title(b"text\x1b]0;\x07more"), Some(String::new()));
+        // Semicolons in the title are kept
+        assert_eq!(title(b"\x1b]2;a;b\x07"), Some("a;b".into()));
+    }
+
+    #[test]
+    fn other_sequences_are_not_titles() {
+        // OSC 1 sets the icon name, OSC 8 is a hyperlink
+        assert_eq!(title(b"\x1b]1;icon\x07"), None);
+        assert_eq!(title(b"\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\"), None);
+        assert_eq!(title(b"\x1b[2J\x1b[0;31mred\x07"), None);
+        assert_eq!(title(b"no escapes ; 0 ] at all\x07"), None);
+    }
+
+    #[test]
+    fn last_title_in_a_chunk_wins() {
+        let bytes = b"\x1b]0;first\x07\x1b]2;second\x1b\\\x1b]1;icon\x07";
+        assert_eq!(title(bytes), Some("second".into()));
+    }
+
+    #[test]
+    fn sequences_split_across_reads() {
+        let bytes = "\x1b]0;Ünïcode title\x1b\\".as_bytes();
+        // Every split point, including inside the ESC \ terminator and a UTF-8 character
+        for at in 0..bytes.len() {
+            let mut parser = TitleParser::new();
+            assert_eq!(parser.feed(&bytes[..at]), None, "split at {}", at);
+            assert_eq!(
+                parser.feed(&bytes[at..]),
+                Some("Ünïcode title".into()),
+                "split at {}",
+                at
+            );
+        }
+        // One byte at a time
+        let mut parser = TitleParser::new();
+        let found: Vec<_> = b"\x1b]2;x\x07"
+            .iter()
+            .filter_map(|&b| parser.feed(&[b]))
+            .collect();
+        assert_eq!(found, ["x"]);
+    }
+
+    #[test]
+    fn interrupted_and_oversized_sequences_are_dropped() {
+        // A new OSC restarts collection; another escape aborts it
+        assert_eq!(title(b"\x1b]0;lost\x1b]0;kept\x07"), Some("kept".into()));
+        assert_eq!(title(b"\x1b]0;lost\x1b[m\x07"), None);
+
+        let mut long = b"\x1b]0;".to_vec();
+        long.extend(std::iter::repeat_n(b'x', MAX_OSC_LEN));
+        long.push(0x07);
+        let mut parser = TitleParser::new();
+        assert_eq!(parser.feed(&long), None);
+        // The parser recovers for the next sequence
+        assert_eq!(parser.feed(b"\x1b]0;ok\x07"), Some("ok".into()));
+    }
+}
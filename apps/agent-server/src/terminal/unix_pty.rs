@@ -0,0 +1,392 @@
+//! Unix pseudo terminal backend.
+//!
+//! Uses `openpty(3)` and runs the shell as a session leader with the pty
+//! slave as its controlling terminal, so job control, `SIGWINCH` and Ctrl+C
+//! behave as in a regular terminal emulator.
+
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use winpe_agent_core::Shell;
+
+use super::pty::{PtyProcess, PtySpawnSpec, SpawnedPty};
+
+/// A process attached to a Unix pty.
+pub struct UnixPtyProcess {
+    master: OwnedFd,
+    child: Mutex<ChildState>,
+    pid: u32,
+}
+
+struct ChildState {
+    child: Child,
+    /// Once reaped, the pid (and the group and session named after it)
+    /// may belong to an unrelated process, so nothing may be signalled.
+    reaped: bool,
+}
+
+impl PtyProcess for UnixPtyProcess {
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), String> {
+        let size = winsize(cols, rows);
+        // Changing the window size makes the kernel deliver SIGWINCH to the foreground group
+        if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+            return Err(format!(
+                "TIOCSWINSZ failed: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+
+    fn terminate(&self) {
+        // Holding the lock keeps `wait` from reaping the child mid-kill
+        let state = self.child.lock().unwrap();
+        if state.reaped {
+            return;
+        }
+        let sid = self.pid as libc::pid_t;
+        unsafe {
+            // The shell leads its own process group; jobs started by an interactive
+            // shell get their own groups, so also kill the terminal's foreground group
+            let foreground = libc::tcgetpgrp(self.master.as_raw_fd());
+            if foreground > 0 && foreground != sid {
+                libc::kill(-foreground, libc::SIGKILL);
+            }
+            libc::kill(-sid, libc::SIGKILL);
+        }
+        kill_session_members(sid);
+    }
+
+    fn wait(&self) -> i32 {
+        // Wait without reaping: the zombie keeps the pid from being reused
+        wait_exited(self.pid);
+        // Background jobs still hold the pty slave open; end them so the reader sees EOF
+        self.terminate();
+        let mut state = self.child.lock().unwrap();
+        let status = state.child.wait();
+        state.reaped = true;
+        match status {
+            // Report signal deaths the way shells do
+            Ok(s) => s.code().unwrap_or_else(|| 128 + s.signal().unwrap_or(0)),
+            Err(e) => {
+                tracing::warn!("Failed to wait for pty process: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+impl Drop for UnixPtyProcess {
+    fn drop(&mut self) {
+        // Reap the child so it does not linger as a zombie
+        self.terminate();
+        if let Ok(state) = self.child.get_mut()
+            && !state.reaped
+        {
+            let _ = state.child.wait();
+            state.reaped = true;
+        }
+    }
+}
+
+/// Block until `pid` has exited, leaving it unreaped.
+fn wait_exited(pid: u32) {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // SAFETY: `info` is a valid out pointer; WNOWAIT leaves the child waitable.
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if result == 0 || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted
+        {
+            return;
+        }
+    }
+}
+
+/// Create a pty and spawn the requested shell on it.
+pub fn spawn(spec: &PtySpawnSpec) -> Result<SpawnedPty, String> {
+    let (program, args): (&str, Vec<String>) = match (spec.program, spec.shell) {
+        (Some(program), _) => (program, spec.args.to_vec()),
+        (None, Shell::Sh) => ("/bin/sh", Vec::new()),
+        (None, Shell::Bash) => ("bash", Vec::new()),
+        (None, Shell::Powershell) => (
+            "pwsh",
+            vec!["-NoLogo".to_string(), "-NoProfile".to_string()],
+        ),
+        (None, Shell::Cmd) => {
+            return Err("Shell Cmd is not supported on this platform".to_string());
+        }
+        (None, Shell::Direct) => {
+            return Err("Shell Direct requires a program for sessions".to_string());
+        }
+    };
+
+    let (master, slave) = open_pty(spec.cols, spec.rows)?;
+
+    let dup = |fd: &OwnedFd| {
+        fd.try_clone()
+            .map_err(|e| format!("Failed to duplicate pty descriptor: {}", e))
+    };
+
+    let mut command = Command::new(program);
+    command
+        .args(&args)
+        .env("TERM", "xterm-256color")
+        .envs(spec.env)
+        .stdin(Stdio::from(dup(&slave)?))
+        .stdout(Stdio::from(dup(&slave)?))
+        .stderr(Stdio::from(slave));
+    if let Some(cwd) = spec.cwd {
+        command.current_dir(cwd);
+    }
+
+    // Become a session leader and adopt the pty slave (now stdin) as controlling terminal
+    unsafe {
+        command.pre_exec(|| {
+            // Dispositions of ignored signals survive exec; an interactive shell needs the defaults
+            for signal in [
+                libc::SIGINT,
+                libc::SIGQUIT,
+                libc::SIGTSTP,
+                libc::SIGTTIN,
+                libc::SIGTTOU,
+                libc::SIGHUP,
+                libc::SIGTERM,
+                libc::SIGCHLD,
+            ] {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let child = command
+        .spawn()
+        .map_err(|e| format!("Failed to spawn {}: {}", program, e))?;
+    // Drop our copies of the slave so the reader sees EOF when the child exits
+    drop(command);
+
+    let reader = File::from(dup(&master)?);
+    let writer = File::from(dup(&master)?);
+    let pid = child.id();
+
+    Ok(SpawnedPty {
+        process: Box::new(UnixPtyProcess {
+            master,
+            child: Mutex::new(ChildState {
+                child,
+                reaped: false,
+            }),
+            pid,
+        }),
+        reader: Box::new(reader),
+        writer: Box::new(writer),
+    })
+}
+
+/// Open a pty pair with the given initial size.
+fn open_pty(cols: u16, rows: u16) -> Result<(OwnedFd, OwnedFd), String> {
+    let mut master: libc::c_int = -1;
+    let mut slave: libc::c_int = -1;
+    let size = winsize(cols, rows);
+
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &size,
+        )
+    };
+    if result == -1 {
+        return Err(format!(
+            "openpty failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+    // openpty does not set close-on-exec; keep stray descriptors out of the child
+    unsafe {
+        libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(slave.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+
+    Ok((master, slave))
+}
+
+/// Kill background jobs left in the session after the shell's own groups are gone.
+#[cfg(target_os = "linux")]
+fn kill_session_members(sid: libc::pid_t) {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<libc::pid_t>().ok())
+        else {
+            continue;
+        };
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+            continue;
+        };
+        // Fields after the parenthesized command name: state ppid pgrp session ...
+        let session = stat
+            .rsplit_once(')')
+            .and_then(|(_, rest)| rest.split_whitespace().nth(3))
+            .and_then(|s| s.parse::<libc::pid_t>().ok());
+        if session == Some(sid) {
+            unsafe {
+                libc::kill(pid, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kill_session_members(_sid: libc::pid_t) {}
+
+fn winsize(cols: u16, rows: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn run(script: &str, cols: u16, rows: u16) -> (SpawnedPty, mpsc::Receiver<String>) {
+        let args = vec!["-c".to_string(), script.to_string()];
+        let env = HashMap::new();
+        let mut pty = spawn(&PtySpawnSpec {
+            shell: Shell::Sh,
+            program: Some("/bin/sh"),
+            args: &args,
+            env: &env,
+            cwd: None,
+            cols,
+            rows,
+        })
+        .unwrap();
+        // Forward output line by line until the slave side is closed
+        let mut reader = std::mem::replace(&mut pty.reader, Box::new(std::io::empty()));
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut pending = Vec::new();
+            let mut buf = [0u8; 1024];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                pending.extend_from_slice(&buf[..n]);
+                while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=end).collect();
+                    let _ = tx.send(String::from_utf8_lossy(&line).trim().to_string());
+                }
+            }
+        });
+        (pty, rx)
+    }
+
+    fn next_line(rx: &mpsc::Receiver<String>) -> String {
+        rx.recv_timeout(Duration::from_secs(10)).unwrap()
+    }
+
+    fn alive(pid: u32) -> bool {
+        // A killed orphan may linger as a zombie until init reaps it
+        std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .map(|stat| {
+                !stat
+                    .rsplit(')')
+                    .next()
+                    .unwrap_or("")
+                    .trim_start()
+                    .starts_with('Z')
+            })
+            .unwrap_or(false)
+    }
+
+    /// Whether `pid` is gone within a few seconds; SIGKILL lands asynchronously.
+    fn exits(pid: u32) -> bool {
+        (0..100).any(|_| {
+            std::thread::sleep(Duration::from_millis(50));
+            !alive(pid)
+        })
+    }
+
+    #[test]
+    fn echoes_output_and_returns_exit_code() {
+        let (pty, rx) = run("echo hello; exit 3", 80, 24);
+        assert_eq!(next_line(&rx), "hello");
+        assert_eq!(pty.process.wait(), 3);
+    }
+
+    #[test]
+    fn echoes_input() {
+        let (mut pty, rx) = run("read line; echo \"got $line\"", 80, 24);
+        pty.writer.write_all(b"ping\n").unwrap();
+        // The terminal echoes the input before the shell answers
+        assert_eq!(next_line(&rx), "ping");
+        assert_eq!(next_line(&rx), "got ping");
+        assert_eq!(pty.process.wait(), 0);
+    }
+
+    #[test]
+    fn resize_is_seen_by_the_child() {
+        let (mut pty, rx) = run("stty size; read line; stty size", 80, 24);
+        assert_eq!(next_line(&rx), "24 80");
+        pty.process.resize(100, 40).unwrap();
+        pty.writer.write_all(b"\n").unwrap();
+        assert_eq!(next_line(&rx), "");
+        assert_eq!(next_line(&rx), "40 100");
+        assert_eq!(pty.process.wait(), 0);
+    }
+
+    #[test]
+    fn terminate_kills_the_process_tree() {
+        let (pty, rx) = run("sleep 1000 & echo $!; wait", 80, 24);
+        let background: u32 = next_line(&rx).parse().unwrap();
+        assert!(alive(background));
+        pty.process.terminate();
+        assert_eq!(pty.process.wait(), 128 + libc::SIGKILL);
+        assert!(exits(background));
+    }
+
+    #[test]
+    fn wait_ends_background_jobs() {
+        let (pty, rx) = run("sleep 1000 & echo $!", 80, 24);
+        let background: u32 = next_line(&rx).parse().unwrap();
+        assert_eq!(pty.process.wait(), 0);
+        assert!(exits(background));
+        // Once reaped, the pid is no longer signalled
+        pty.process.terminate();
+        drop(pty);
+    }
+}
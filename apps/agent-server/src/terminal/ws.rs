@@ -1,106 +1,184 @@
 //! WebSocket handler for terminal sessions.
 //!
+//! A session has at most one controlling client and any number of
+//! read-only viewers; input and control messages from viewers are ignored.
+//!
+//! Everything sent to a client goes through a single outbound queue drained
+//! by a writer task: terminal output, [`WsServerMessage`] control messages
+//! and the final close frame.
+//!
 //! WebSocket close codes used:
 //! - 1000: Normal closure
-//! - 1008: Policy violation (e.g., session already attached)
+//! - 1008: Policy violation (e.g., session already controlled, or control taken over)
 //! - 1011: Unexpected condition (e.g., session not found)
 
 use axum::extract::ws::{CloseFrame, Message, WebSocket};
 use futures::{SinkExt, StreamExt};
-use winpe_agent_core::WsControlMessage;
+use tokio::sync::{broadcast, mpsc};
+use winpe_agent_core::{AttachMode, WsControlMessage, WsServerMessage};
 
 use super::SessionManager;
 
+/// Messages buffered for a client before output forwarding waits on it.
+const OUTBOUND_CAPACITY: usize = 64;
+
+/// Encode a control message as a text frame.
+fn server_message(msg: &WsServerMessage) -> Message {
+    let text = serde_json::to_string(msg).expect("server message serializes");
+    Message::Text(text.into())
+}
+
+fn close_message(code: u16, reason: &str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    }))
+}
+
 /// Handle a WebSocket connection for a terminal session.
-pub async fn handle_websocket(socket: WebSocket, manager: SessionManager, session_id: String) {
+///
+/// With `takeover`, a controlling client replaces the current controller,
+/// which is disconnected.
+pub async fn handle_websocket(
+    socket: WebSocket,
+    manager: SessionManager,
+    session_id: String,
+    mode: AttachMode,
+    takeover: bool,
+) {
     let session = match manager.get_session_for_ws(&session_id) {
         Some(s) => s,
         None => {
             tracing::error!("Session {} not found", session_id);
             // Send close with 1011 (unexpected condition)
             let (mut sender, _) = socket.split();
-            let _ = sender
-                .send(Message::Close(Some(CloseFrame {
-                    code: 1011,
-                    reason: "Session not found".into(),
-                })))
-                .await;
+            let _ = sender.send(close_message(1011, "Session not found")).await;
             return;
         }
     };
 
-    // Check if session is already attached - reject second connection
-    {
-        let session_guard = session.read().await;
-        if session_guard.attached {
-            tracing::warn!(
-                "Session {} already has a client attached, rejecting",
-                session_id
-            );
+    // Register the client; a second controller is rejected unless taking over
+    let attached = {
+        let mut session_guard = session.write().await;
+        session_guard.last_activity = chrono::Utc::now();
+        session_guard.attach(mode, takeover)
+    };
+    let (client_id, mut kicked) = match attached {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::warn!("Rejecting client for session {}: {}", session_id, e);
             // Send close with 1008 (policy violation)
             let (mut sender, _) = socket.split();
-            let _ = sender
-                .send(Message::Close(Some(CloseFrame {
-                    code: 1008,
-                    reason: "Session already attached".into(),
-                })))
-                .await;
+            let _ = sender.send(close_message(1008, &e)).await;
             return;
         }
-    }
-
-    // Mark session as attached
-    {
-        let mut session_guard = session.write().await;
-        session_guard.attached = true;
-        session_guard.last_activity = chrono::Utc::now();
-    }
+    };
+    let is_controller = mode == AttachMode::Control;
 
     // Subscribe to the output broadcast channel - allows reconnection
-    let mut output_rx = {
+    let (scrollback, mut output_rx, mut events_rx, mut exited, title, input_tx) = {
         let session_guard = session.read().await;
-        session_guard.output_tx.subscribe()
+        let (scrollback, output_rx) = session_guard.subscribe_output();
+        (
+            scrollback,
+            output_rx,
+            session_guard.events_tx.subscribe(),
+            session_guard.exited.clone(),
+            session_guard.title.lock().unwrap().clone(),
+            session_guard.input_tx.clone(),
+        )
     };
 
     let (mut ws_sender, mut ws_receiver) = socket.split();
 
-    // Get input sender
-    let input_tx = {
-        let session_guard = session.read().await;
-        session_guard.input_tx.clone()
-    };
-
-    let manager_clone = manager.clone();
-    let session_id_clone = session_id.clone();
-    let session_clone = session.clone();
+    // Single writer owning the socket's send half; stops after a close frame
+    let (outbound_tx, mut outbound_rx) = mpsc::channel::<Message>(OUTBOUND_CAPACITY);
+    let mut writer_task = tokio::spawn(async move {
+        while let Some(msg) = outbound_rx.recv().await {
+            let is_close = matches!(msg, Message::Close(_));
+            if ws_sender.send(msg).await.is_err() || is_close {
+                break;
+            }
+        }
+    });
 
-    // Spawn task to forward output to WebSocket
+    // Forward session output and events to the outbound queue
+    let outbound = outbound_tx.clone();
     let output_task = tokio::spawn(async move {
-        loop {
-            match output_rx.recv().await {
-                Ok(data) => {
-                    if ws_sender.send(Message::Binary(data.into())).await.is_err() {
-                        break;
+        // Replay recent output so the terminal is not blank after reattaching
+        if !scrollback.is_empty()
+            && outbound
+                .send(Message::Binary(scrollback.into()))
+                .await
+                .is_err()
+        {
+            return;
+        }
+        if let Some(title) = title {
+            let _ = outbound
+                .send(server_message(&WsServerMessage::Title { title }))
+                .await;
+        }
+
+        let close = loop {
+            let msg = tokio::select! {
+                received = output_rx.recv() => match received {
+                    Ok(data) => Message::Binary(data.into()),
+                    Err(broadcast::error::RecvError::Closed) => {
+                        break close_message(1000, "Session ended");
+                    }
+                    Err(broadcast::error::RecvError::Lagged(dropped_chunks)) => {
+                        // This client fell behind; tell it output is missing
+                        server_message(&WsServerMessage::Lagged { dropped_chunks })
+                    }
+                },
+                Ok(event) = events_rx.recv() => server_message(&event),
+                Ok(code) = async {
+                    // Copy the code out so no watch guard is held across awaits
+                    exited.wait_for(Option::is_some).await.map(|code| code.unwrap_or(-1))
+                } => {
+                    // All output was broadcast before the exit; forward what is still queued
+                    while let Ok(data) = output_rx.try_recv() {
+                        if outbound.send(Message::Binary(data.into())).await.is_err() {
+                            return;
+                        }
                     }
+                    let _ = outbound
+                        .send(server_message(&WsServerMessage::Exit { code }))
+                        .await;
+                    break close_message(1000, "Session ended");
                 }
-                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
-                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
-                    // Subscriber lagged behind, continue receiving
-                    continue;
+                Ok(()) = &mut kicked => {
+                    break close_message(1008, "Control taken over by another client");
                 }
+            };
+            if outbound.send(msg).await.is_err() {
+                return;
             }
-        }
-        // Send normal close when output ends
-        let _ = ws_sender
-            .send(Message::Close(Some(CloseFrame {
-                code: 1000,
-                reason: "Session ended".into(),
-            })))
-            .await;
+        };
+        let _ = outbound.send(close).await;
     });
 
-    // Handle incoming WebSocket messages
-    while let Some(msg) = ws_receiver.next().await {
+    let manager_clone = manager.clone();
+    let session_id_clone = session_id.clone();
+    let session_clone = session.clone();
+    let reply = |msg: WsServerMessage| {
+        let outbound = outbound_tx.clone();
+        async move {
+            let _ = outbound.send(server_message(&msg)).await;
+        }
+    };
+
+    // Handle incoming WebSocket messages until the client leaves or the connection closes
+    loop {
+        let msg = tokio::select! {
+            msg = ws_receiver.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = &mut writer_task => break,
+        };
+
         // Update last activity
         {
             let mut session_guard = session_clone.write().await;
@@ -108,21 +186,38 @@ pub async fn handle_websocket(socket: WebSocket, manager: SessionManager, sessio
         }
 
         match msg {
+            Ok(Message::Binary(_)) if !is_controller => {
+                // Viewers are read-only
+            }
             Ok(Message::Binary(data)) => {
-                // Raw terminal input
-                if input_tx.send(data.to_vec()).await.is_err() {
+                // Raw terminal input; stop if the writer thread is gone
+                let sent = input_tx.send(data.to_vec()).await;
+                if sent.is_err() {
                     break;
                 }
             }
             Ok(Message::Text(text)) => {
                 // JSON control message
                 match serde_json::from_str::<WsControlMessage>(&text) {
+                    Ok(WsControlMessage::Resize { .. } | WsControlMessage::Signal { .. })
+                        if !is_controller =>
+                    {
+                        reply(WsServerMessage::Error {
+                            message: "Viewers cannot control the session".to_string(),
+                        })
+                        .await;
+                    }
                     Ok(WsControlMessage::Resize { cols, rows }) => {
+                        // Success is acknowledged to every client with `resized`
                         if let Err(e) = manager_clone
                             .resize_session(&session_id_clone, cols, rows)
                             .await
                         {
                             tracing::warn!("Failed to resize session: {}", e);
+                            reply(WsServerMessage::Error {
+                                message: format!("Resize failed: {}", e),
+                            })
+                            .await;
                         }
                     }
                     Ok(WsControlMessage::Signal { name }) => {
@@ -132,17 +227,24 @@ pub async fn handle_websocket(socket: WebSocket, manager: SessionManager, sessio
                             "terminate" => Some(winpe_agent_core::Signal::Terminate),
                             _ => None,
                         };
-                        if let Some(sig) = signal
-                            && let Err(e) = manager_clone.send_signal(&session_id_clone, sig).await
-                        {
+                        let result = match signal {
+                            Some(sig) => manager_clone.send_signal(&session_id_clone, sig).await,
+                            None => Err(format!("Unknown signal: {}", name)),
+                        };
+                        if let Err(e) = result {
                             tracing::warn!("Failed to send signal: {}", e);
+                            reply(WsServerMessage::Error { message: e }).await;
                         }
                     }
-                    Ok(WsControlMessage::Ping { t: _ }) => {
-                        // TODO: Implement pong response (requires refactoring to share ws_sender)
+                    Ok(WsControlMessage::Ping { t }) => {
+                        reply(WsServerMessage::Pong { t }).await;
                     }
                     Err(e) => {
                         tracing::warn!("Failed to parse control message: {}", e);
+                        reply(WsServerMessage::Error {
+                            message: format!("Invalid control message: {}", e),
+                        })
+                        .await;
                     }
                 }
             }
@@ -159,11 +261,12 @@ pub async fn handle_websocket(socket: WebSocket, manager: SessionManager, sessio
 
     // Clean up
     output_task.abort();
+    writer_task.abort();
 
-    // Mark session as detached
+    // Detach the client
     {
         let mut session_guard = session.write().await;
-        session_guard.attached = false;
+        session_guard.detach(&client_id);
     }
 
     tracing::info!("WebSocket disconnected for session {}", session_id);
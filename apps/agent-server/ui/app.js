@@ -6,6 +6,7 @@
     // DOM elements
     const terminalContainer = document.getElementById('terminal-container');
     const shellSelect = document.getElementById('shell-select');
+    const tokenInput = document.getElementById('token-input');
     const connectBtn = document.getElementById('connect-btn');
     const disconnectBtn = document.getElementById('disconnect-btn');
     const statusEl = document.getElementById('status');
@@ -16,6 +17,22 @@
     let fitAddon = null;
     let ws = null;
     let sessionId = null;
+    let exitCode = null;
+
+    // Restore saved token
+    tokenInput.value = localStorage.getItem('winpe-agent-token') || '';
+    tokenInput.addEventListener('change', () => {
+        localStorage.setItem('winpe-agent-token', tokenInput.value);
+    });
+
+    // Build request headers including the bearer token, if set
+    function authHeaders(extra = {}) {
+        const headers = { ...extra };
+        if (tokenInput.value) {
+            headers['Authorization'] = `Bearer ${tokenInput.value}`;
+        }
+        return headers;
+    }
 
     // Initialize terminal
     function initTerminal() {
@@ -94,6 +111,7 @@
         connectBtn.disabled = connected;
         disconnectBtn.disabled = !connected;
         shellSelect.disabled = connected;
+        tokenInput.disabled = connected;
 
         if (connected) {
             statusEl.textContent = 'Connected';
@@ -112,12 +130,13 @@
         const rows = terminal.rows;
 
         setStatus('Connecting...');
+        exitCode = null;
 
         try {
             // Create session
             const response = await fetch('/api/v1/sessions', {
                 method: 'POST',
-                headers: { 'Content-Type': 'application/json' },
+                headers: authHeaders({ 'Content-Type': 'application/json' }),
                 body: JSON.stringify({
                     shell,
                     cols,
@@ -135,9 +154,23 @@
             sessionId = session.id;
             sessionIdEl.textContent = `Session: ${sessionId}`;
 
+            // Browsers cannot set headers on WebSocket upgrades; use a one-time ticket
+            const ticketResponse = await fetch('/api/v1/auth/ticket', {
+                method: 'POST',
+                headers: authHeaders({ 'Content-Type': 'application/json' }),
+                body: JSON.stringify({ session_id: session.id })
+            });
+
+            if (!ticketResponse.ok) {
+                const error = await ticketResponse.json();
+                throw new Error(error.error?.message || 'Failed to obtain WebSocket ticket');
+            }
+
+            const { ticket } = await ticketResponse.json();
+
             // Connect WebSocket
             const protocol = location.protocol === 'https:' ? 'wss' : 'ws';
-            const wsUrl = `${protocol}://${location.host}${session.ws_url}`;
+            const wsUrl = `${protocol}://${location.host}${session.ws_url}?ticket=${encodeURIComponent(ticket)}`;
 
             ws = new WebSocket(wsUrl);
             ws.binaryType = 'arraybuffer';
@@ -154,7 +187,23 @@
                     // Control message
                     try {
                         const msg = JSON.parse(event.data);
-                        console.log('Control message:', msg);
+                        switch (msg.type) {
+                            case 'exit':
+                                terminal.write(`\r\n[Process exited with code ${msg.code}]\r\n`);
+                                exitCode = msg.code;
+                                break;
+                            case 'title':
+                                document.title = msg.title || 'WinPE Agent Terminal';
+                                break;
+                            case 'lagged':
+                                terminal.write(`\r\n[Output dropped: ${msg.dropped_chunks} chunks]\r\n`);
+                                break;
+                            case 'error':
+                                console.warn('Server error:', msg.message);
+                                break;
+                            default:
+                                console.log('Control message:', msg);
+                        }
                     } catch (e) {
                         // Not JSON, ignore
                     }
@@ -167,6 +216,9 @@
             ws.onclose = (event) => {
                 terminal.write('\r\n[Connection closed]\r\n');
                 setConnected(false);
+                if (exitCode !== null) {
+                    setStatus(`Exited (code ${exitCode})`);
+                }
                 ws = null;
             };
 
@@ -190,7 +242,10 @@
 
         if (sessionId) {
             try {
-                await fetch(`/api/v1/sessions/${sessionId}`, { method: 'DELETE' });
+                await fetch(`/api/v1/sessions/${sessionId}`, {
+                    method: 'DELETE',
+                    headers: authHeaders()
+                });
             } catch (e) {
                 console.error('Failed to delete session:', e);
             }
@@ -4,7 +4,7 @@
 //! the agent server and client.
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 // ============================================================================
 // Health API
@@ -32,6 +32,26 @@ pub struct HealthResponse {
     pub capabilities: Capabilities,
 }
 
+// ============================================================================
+// Auth API
+// ============================================================================
+
+/// Request body for `POST /api/v1/auth/ticket`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TicketRequest {
+    /// Session the ticket grants WebSocket access to.
+    pub session_id: String,
+}
+
+/// Response from `POST /api/v1/auth/ticket`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TicketResponse {
+    /// Single-use ticket, passed as `?ticket=` on the WebSocket URL.
+    pub ticket: String,
+    /// Seconds until the ticket expires.
+    pub expires_in_sec: u64,
+}
+
 // ============================================================================
 // Automation API
 // ============================================================================
@@ -43,6 +63,13 @@ pub enum Shell {
     #[default]
     Cmd,
     Powershell,
+    /// POSIX `/bin/sh` (non-Windows hosts).
+    Sh,
+    /// GNU Bash (non-Windows hosts).
+    Bash,
+    /// No shell: `command` is the executable and `args` are passed unmodified.
+    #[serde(alias = "none")]
+    Direct,
 }
 
 /// Request body for `POST /api/v1/automation/exec`.
@@ -63,15 +90,26 @@ pub struct ExecRequest {
     #[serde(default)]
     pub env: HashMap<String, String>,
     /// Timeout in milliseconds (server-enforced).
-    #[serde(default = "default_timeout")]
-    pub timeout_ms: u64,
-    /// Output encoding hint (default: utf-8).
+    ///
+    /// When omitted, `exec` and `exec_stream` stop the process after 10
+    /// minutes; jobs run until they exit unless the server sets a job timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Output encoding: `utf-8` (default), `cp437`, `gbk`, `utf-16le`, a code page number or `auto`.
     #[serde(default = "default_encoding")]
     pub encoding: String,
-}
-
-fn default_timeout() -> u64 {
-    600_000 // 10 minutes
+    /// Maximum captured bytes per output stream (capped by the server limit).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<u64>,
+    /// Text written to the process's stdin, which is then closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    /// Base64-encoded bytes written to stdin (alternative to `stdin`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin_base64: Option<String>,
+    /// For `exec_stream`: keep stdin open for `POST /automation/exec_stream/{id}/stdin`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stdin_stream: bool,
 }
 
 fn default_encoding() -> String {
@@ -89,6 +127,21 @@ pub struct ExecResponse {
     pub stderr: String,
     /// Execution duration in milliseconds.
     pub duration_ms: u64,
+    /// Whether the middle of stdout was dropped to stay within the capture limit.
+    #[serde(default)]
+    pub stdout_truncated: bool,
+    /// Whether the middle of stderr was dropped to stay within the capture limit.
+    #[serde(default)]
+    pub stderr_truncated: bool,
+    /// Total bytes written to stdout, including dropped bytes.
+    #[serde(default)]
+    pub stdout_bytes: u64,
+    /// Total bytes written to stderr, including dropped bytes.
+    #[serde(default)]
+    pub stderr_bytes: u64,
+    /// Encoding used to decode the output (resolved when `auto` was requested).
+    #[serde(default)]
+    pub encoding: String,
 }
 
 /// SSE event types for streaming execution.
@@ -103,7 +156,92 @@ pub enum ExecStreamEvent {
     Stderr { chunk: String },
     /// Process exited.
     #[serde(rename = "exit")]
-    Exit { exit_code: i32, duration_ms: u64 },
+    Exit {
+        exit_code: i32,
+        duration_ms: u64,
+        #[serde(default)]
+        encoding: String,
+    },
+}
+
+// ============================================================================
+// Jobs API
+// ============================================================================
+
+/// Response from `POST /api/v1/jobs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobCreateResponse {
+    /// Job ID (ULID format).
+    pub id: String,
+    /// Creation timestamp (ISO 8601).
+    pub created_at: String,
+}
+
+/// Job state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    /// The process exited on its own; see `exit_code`.
+    Exited,
+    /// The process tree was killed after `timeout_ms`.
+    TimedOut,
+    /// The process tree was killed by `DELETE /api/v1/jobs/{id}`.
+    Cancelled,
+}
+
+/// Job info returned by list/get endpoints.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    /// Job ID.
+    pub id: String,
+    /// Current state.
+    pub state: JobState,
+    /// Shell used to launch the command.
+    pub shell: Shell,
+    /// Command as submitted.
+    pub command: String,
+    /// Arguments as submitted.
+    pub args: Vec<String>,
+    /// Exit code, once the process has exited.
+    pub exit_code: Option<i32>,
+    /// Creation timestamp.
+    pub created_at: String,
+    /// Completion timestamp, once the job is no longer running.
+    pub finished_at: Option<String>,
+    /// Total stdout bytes (decoded UTF-8) produced so far.
+    pub stdout_bytes: u64,
+    /// Total stderr bytes (decoded UTF-8) produced so far.
+    pub stderr_bytes: u64,
+    /// Encoding used for stdout, once known.
+    pub encoding: Option<String>,
+}
+
+/// Output stream selector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    #[default]
+    Stdout,
+    Stderr,
+}
+
+/// Response from `GET /api/v1/jobs/{id}/output`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobOutputResponse {
+    /// Stream the data belongs to.
+    pub stream: OutputStream,
+    /// Offset of `data` within the stream.
+    pub offset: u64,
+    /// Offset to request next.
+    pub next_offset: u64,
+    /// Output text.
+    pub data: String,
+    /// Whether the requested offset had already been discarded; `offset` is then
+    /// the oldest retained position.
+    pub truncated: bool,
+    /// Whether the job has finished and no more output will follow.
+    pub complete: bool,
 }
 
 // ============================================================================
@@ -121,9 +259,15 @@ pub struct SessionInit {
 /// Request body for `POST /api/v1/sessions`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionCreateRequest {
-    /// Shell to spawn.
+    /// Shell to spawn; ignored when `program` is set.
     #[serde(default)]
     pub shell: Shell,
+    /// Program to run instead of a shell (e.g. `diskpart`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    /// Arguments for `program`, passed without shell interpretation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
     /// Working directory.
     #[serde(default)]
     pub cwd: Option<String>,
@@ -139,6 +283,15 @@ pub struct SessionCreateRequest {
     /// Idle timeout in seconds before auto-termination.
     #[serde(default = "default_idle_timeout")]
     pub idle_timeout_sec: u64,
+    /// Bytes of recent output kept and replayed when a client attaches (0 disables).
+    #[serde(default = "default_scrollback_bytes")]
+    pub scrollback_bytes: u64,
+    /// Record the session as an asciicast v2 file.
+    #[serde(default)]
+    pub record: bool,
+    /// Also record input when `record` is set.
+    #[serde(default)]
+    pub record_input: bool,
     /// Initialization options.
     #[serde(default)]
     pub init: SessionInit,
@@ -156,6 +309,10 @@ fn default_idle_timeout() -> u64 {
     600 // 10 minutes
 }
 
+fn default_scrollback_bytes() -> u64 {
+    256 * 1024
+}
+
 /// Response from `POST /api/v1/sessions`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionCreateResponse {
@@ -175,6 +332,28 @@ pub enum SessionState {
     Exited,
 }
 
+/// How a WebSocket client is attached to a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachMode {
+    /// Sends input, resizes and signals. At most one per session.
+    #[default]
+    Control,
+    /// Read-only observer; input and control messages are ignored.
+    View,
+}
+
+/// A WebSocket client attached to a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachedClient {
+    /// Client ID, unique per attachment.
+    pub id: String,
+    /// Attach mode.
+    pub mode: AttachMode,
+    /// Attach timestamp.
+    pub attached_at: String,
+}
+
 /// Session info returned by list/get endpoints.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionInfo {
@@ -182,12 +361,22 @@ pub struct SessionInfo {
     pub id: String,
     /// Shell type.
     pub shell: Shell,
+    /// Program run instead of the shell, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
     /// Process ID of the shell.
     pub pid: u32,
     /// Current state.
     pub state: SessionState,
-    /// Whether a client is currently attached via WebSocket.
-    pub attached: bool,
+    /// Clients currently attached via WebSocket.
+    pub clients: Vec<AttachedClient>,
+    /// Exit code once the shell has exited.
+    pub exit_code: Option<i32>,
+    /// Exit timestamp.
+    pub exited_at: Option<String>,
+    /// Whether the session is being recorded.
+    #[serde(default)]
+    pub recording: bool,
     /// Terminal columns.
     pub cols: u16,
     /// Terminal rows.
@@ -213,6 +402,507 @@ pub struct SignalRequest {
     pub signal: Signal,
 }
 
+/// Request body for `POST /api/v1/sessions/{id}/input`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInputRequest {
+    /// Text to type into the terminal; use `\r` for Enter.
+    pub data: String,
+}
+
+/// Response from `POST /api/v1/sessions/{id}/input`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInputResponse {
+    /// Output offset when the input was sent; output it causes starts at or after it.
+    pub offset: u64,
+}
+
+/// Request body for `POST /api/v1/sessions/{id}/expect`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionExpectRequest {
+    /// Regular expression to wait for.
+    pub pattern: String,
+    /// Output offset to search from; by default only output arriving after
+    /// the request is searched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    /// How long to wait for a match.
+    #[serde(default = "default_expect_timeout")]
+    pub timeout_ms: u64,
+}
+
+fn default_expect_timeout() -> u64 {
+    30_000
+}
+
+/// Response from `POST /api/v1/sessions/{id}/expect`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionExpectResponse {
+    /// Output offset the search started at.
+    pub start: u64,
+    /// Output between `start` and the match.
+    pub before: String,
+    /// The matched text.
+    pub matched: String,
+    /// Capture groups from group 1 on; `null` for groups that did not participate.
+    pub groups: Vec<Option<String>>,
+    /// Named capture groups that participated.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub named: HashMap<String, String>,
+    /// Output offset just past the match; pass as `since` to continue after it.
+    pub offset: u64,
+}
+
+/// Cursor position on a session screen (zero-based).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ScreenCursor {
+    /// Screen row, 0 being the top row.
+    pub row: u16,
+    /// Screen column, 0 being the leftmost column.
+    pub col: u16,
+    /// Whether the program has left the cursor visible.
+    pub visible: bool,
+}
+
+/// A run of styled text on a session screen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenSpan {
+    /// Zero-based screen row of the run.
+    pub row: u16,
+    /// Zero-based screen column of the run's first cell.
+    pub col: u16,
+    /// Text of the run; a wide character takes two columns.
+    pub text: String,
+    /// Foreground color: palette index (`"0"`-`"255"`) or `"#rrggbb"`; absent when default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fg: Option<String>,
+    /// Background color, in the same format as `fg`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg: Option<String>,
+    /// Bold text; omitted when false, like the other attributes.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bold: bool,
+    /// Italic text.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub italic: bool,
+    /// Underlined text.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub underline: bool,
+    /// Foreground and background swapped.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inverse: bool,
+}
+
+/// Response from `GET /api/v1/sessions/{id}/screen`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenSnapshot {
+    /// Screen width in columns.
+    pub cols: u16,
+    /// Screen height in rows.
+    pub rows: u16,
+    /// Visible rows as plain text, without trailing blanks.
+    pub lines: Vec<String>,
+    /// Whether a full-screen program switched to the alternate screen.
+    pub alternate_screen: bool,
+    /// Cursor position, when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<ScreenCursor>,
+    /// Styled text runs, when requested; unstyled text is omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attrs: Option<Vec<ScreenSpan>>,
+}
+
+// ============================================================================
+// Files API
+// ============================================================================
+
+/// Response from `PUT /api/v1/files`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileUploadResponse {
+    /// Destination path.
+    pub path: String,
+    /// Bytes written.
+    pub size: u64,
+    /// Whether the file was newly created rather than replaced.
+    pub created: bool,
+}
+
+/// Request body for `POST /api/v1/uploads`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadCreateRequest {
+    /// Destination path.
+    pub path: String,
+    /// Total file size in bytes.
+    pub size: u64,
+    /// Replace an existing file instead of failing.
+    #[serde(default)]
+    pub overwrite: bool,
+    /// Permission bits in octal (e.g. `755`); ignored on Windows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// Modification time to give the file, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+}
+
+/// A range of bytes an upload has received, as the half-open interval
+/// `start..end`.
+///
+/// Unlike an HTTP `Range` header, `end` is exclusive: `{start: 0, end: 10}`
+/// covers bytes 0 through 9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceivedRange {
+    /// Offset of the first received byte.
+    pub start: u64,
+    /// Offset just past the last received byte.
+    pub end: u64,
+}
+
+/// State of a resumable upload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadInfo {
+    /// Upload ID for the chunk, finalize and delete endpoints.
+    pub id: String,
+    /// Destination path.
+    pub path: String,
+    /// Total file size in bytes.
+    pub size: u64,
+    /// Whether finalizing may replace an existing file.
+    pub overwrite: bool,
+    /// Received ranges, sorted and merged.
+    pub received: Vec<ReceivedRange>,
+    /// Sum of the received ranges.
+    pub received_bytes: u64,
+    /// Creation timestamp.
+    pub created_at: String,
+    /// Time of the last received chunk.
+    pub updated_at: String,
+}
+
+/// Request body for `POST /api/v1/uploads/{id}/finalize`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadFinalizeRequest {
+    /// Expected SHA-256 of the whole file, in hex.
+    pub sha256: String,
+}
+
+/// Hash algorithm for `POST /api/v1/files/hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha1,
+    Md5,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Lowercase name, as used in requests and results.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha1 => "sha1",
+            Self::Md5 => "md5",
+            Self::Blake3 => "blake3",
+        }
+    }
+}
+
+/// Request body for `POST /api/v1/files/hash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashRequest {
+    /// Files to hash.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Absolute glob pattern; matching files are hashed after `paths`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(default = "default_hash_algorithms")]
+    pub algorithms: Vec<HashAlgorithm>,
+}
+
+fn default_hash_algorithms() -> Vec<HashAlgorithm> {
+    vec![HashAlgorithm::Sha256]
+}
+
+/// One line of the NDJSON response from `POST /api/v1/files/hash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashResult {
+    pub path: String,
+    /// Bytes hashed; absent on error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Lowercase hex digest per algorithm name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hashes: BTreeMap<String, String>,
+    /// Why the file could not be hashed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Kind of filesystem entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+/// Metadata of a file or directory.
+///
+/// Timestamps are RFC 3339 and omitted where the filesystem lacks them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// File name.
+    pub name: String,
+    /// Full path.
+    pub path: String,
+    /// Entry kind; symlinks are not followed.
+    pub kind: FileKind,
+    /// Size in bytes (0 for directories).
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accessed: Option<String>,
+    pub readonly: bool,
+    /// Hidden attribute on Windows, leading dot elsewhere.
+    pub hidden: bool,
+    /// Windows system attribute.
+    #[serde(default)]
+    pub system: bool,
+    /// Raw Windows `FILE_ATTRIBUTE_*` flags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<u32>,
+    /// Unix permission bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Depth below the listed directory (1 for direct children).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<u32>,
+}
+
+/// Response from `GET /api/v1/fs/list`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirListing {
+    /// Listed directory.
+    pub path: String,
+    /// Entries, directories before their contents, sorted by name.
+    pub entries: Vec<FileEntry>,
+    /// Whether the entry limit cut the listing short.
+    pub truncated: bool,
+}
+
+/// Request body for `POST /api/v1/fs/mkdir`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MkdirRequest {
+    pub path: String,
+    /// Create missing parents, and succeed if the directory exists.
+    #[serde(default)]
+    pub parents: bool,
+}
+
+/// Request body for `POST /api/v1/fs/rename`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenameRequest {
+    pub from: String,
+    pub to: String,
+    /// Replace an existing file at `to`.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// Request body for `POST /api/v1/fs/delete`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteRequest {
+    pub path: String,
+    /// Delete a directory with its contents.
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+/// Response from `GET /api/v1/fs/glob`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GlobResponse {
+    /// Matching entries in path order.
+    pub entries: Vec<FileEntry>,
+    /// Whether the match limit cut the results short.
+    pub truncated: bool,
+}
+
+/// A file in a directory manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the manifest root, `/`-separated.
+    pub path: String,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    /// Hex digest, when requested and the file could be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// Response from `GET /api/v1/fs/manifest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirManifest {
+    /// Root directory.
+    pub path: String,
+    /// Subdirectories, relative to the root, parents first.
+    pub dirs: Vec<String>,
+    /// Files, relative to the root.
+    pub files: Vec<ManifestEntry>,
+}
+
+// ============================================================================
+// Block Devices API
+// ============================================================================
+
+/// Response from `PUT /api/v1/block/{device}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockWriteResponse {
+    /// Device or image file that was written.
+    pub device: String,
+    /// Byte offset the image was written at.
+    pub offset: u64,
+    /// Bytes of (decompressed) image data written.
+    pub bytes_written: u64,
+}
+
+// ============================================================================
+// System Inventory API
+// ============================================================================
+
+/// Partition table format of a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionStyle {
+    Mbr,
+    Gpt,
+    /// No partition table.
+    Raw,
+}
+
+/// BitLocker protection of a volume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BitLockerStatus {
+    /// Not encrypted.
+    Off,
+    /// Encrypted and unlocked; the filesystem is readable.
+    Unlocked,
+    /// Encrypted and locked.
+    Locked,
+    /// Encrypted; whether it is unlocked is unknown (non-Windows hosts).
+    Encrypted,
+}
+
+/// A partition table entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionInfo {
+    /// Partition number; MBR logical partitions start at 5.
+    pub number: u32,
+    /// Byte offset on the disk.
+    pub offset: u64,
+    /// Size in bytes.
+    pub size: u64,
+    /// GPT type GUID (lowercase, no braces) or MBR type byte (`0x07`).
+    pub type_id: String,
+    /// Well-known name of the type, e.g. `EFI System`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub type_name: Option<String>,
+    /// GPT unique partition GUID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guid: Option<String>,
+    /// GPT partition name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// GPT attribute bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<u64>,
+    /// MBR active (boot) flag.
+    #[serde(default)]
+    pub active: bool,
+}
+
+/// A physical disk, from `GET /api/v1/system/disks`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskInfo {
+    /// Disk number (`N` in `\\.\PhysicalDriveN` on Windows).
+    pub number: u32,
+    /// Device name for `/api/v1/block/{device}`, e.g. `disk0` or `sda`.
+    pub device: String,
+    /// Size in bytes.
+    pub size: u64,
+    /// Logical sector size in bytes.
+    pub sector_size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Absent if the partition table could not be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_style: Option<PartitionStyle>,
+    /// GPT disk GUID, or MBR disk signature as 8 hex digits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_id: Option<String>,
+    /// Partitions in table order.
+    pub partitions: Vec<PartitionInfo>,
+    /// Why the partition table could not be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Response from `GET /api/v1/system/disks`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskList {
+    pub disks: Vec<DiskInfo>,
+}
+
+/// A volume, from `GET /api/v1/system/volumes`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeInfo {
+    /// Volume device: `\\?\Volume{GUID}\` on Windows, `/dev/sda1` elsewhere
+    /// (or the disk, when the kernel has no device for the partition).
+    pub path: String,
+    /// Drive letter, e.g. `C:`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub letter: Option<String>,
+    /// Paths the volume is mounted at, including the drive letter's root.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mount_points: Vec<String>,
+    /// Filesystem name, e.g. `NTFS` or `FAT32`; absent if unrecognized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filesystem: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Size in bytes.
+    pub size: u64,
+    /// Free bytes; known only for mounted volumes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub free: Option<u64>,
+    /// Disk holding the volume, if it is a partition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk: Option<u32>,
+    /// Partition number on `disk`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition: Option<u32>,
+    /// Byte offset on `disk`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    /// Absent if the volume's first sectors could not be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitlocker: Option<BitLockerStatus>,
+}
+
+/// Response from `GET /api/v1/system/volumes`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeList {
+    pub volumes: Vec<VolumeInfo>,
+}
+
 // ============================================================================
 // WebSocket Protocol
 // ============================================================================
@@ -239,6 +929,21 @@ pub enum WsServerMessage {
     /// Pong response to ping.
     #[serde(rename = "pong")]
     Pong { t: u64 },
+    /// The terminal was resized (sent to every client).
+    #[serde(rename = "resized")]
+    Resized { cols: u16, rows: u16 },
+    /// The session's process exited; the server closes the connection next.
+    #[serde(rename = "exit")]
+    Exit { code: i32 },
+    /// Output was dropped because this client fell behind.
+    #[serde(rename = "lagged")]
+    Lagged { dropped_chunks: u64 },
+    /// The window title changed (OSC 0/2).
+    #[serde(rename = "title")]
+    Title { title: String },
+    /// A control message failed or was rejected.
+    #[serde(rename = "error")]
+    Error { message: String },
 }
 
 // ============================================================================
@@ -250,8 +955,11 @@ pub enum WsServerMessage {
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ErrorCode {
     BadRequest,
+    Unauthorized,
     NotFound,
+    Forbidden,
     Timeout,
+    Conflict,
     Internal,
     NotSupported,
 }